@@ -6,10 +6,64 @@ use std::fmt;
 use std::net::IpAddr;
 use std::str::FromStr;
 
+#[cfg(not(test))]
 lazy_static! {
     pub static ref ARGS: Args = Args::parse();
 }
 
+// Tests keep their database and attachments in a data dir of their own
+// instead of the one of a local instance, removed again when they exit, and
+// have basic auth and an admin password set
+#[cfg(test)]
+lazy_static! {
+    pub static ref ARGS: Args = {
+        let data_dir = test_data_dir();
+        std::fs::create_dir_all(&data_dir).unwrap();
+        // SAFETY: atexit only stores the callback, which does not unwind
+        unsafe { atexit(remove_test_data_dir) };
+        Args::parse_from([
+            "microbin",
+            "--data-dir",
+            data_dir.to_str().unwrap(),
+            "--auth-basic-username",
+            "tester",
+            "--auth-basic-password",
+            "basic-password",
+            "--auth-admin-password",
+            "admin-password",
+        ])
+    };
+}
+
+#[cfg(test)]
+fn test_data_dir() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("microbin-test-{}", std::process::id()))
+}
+
+#[cfg(test)]
+extern "C" fn remove_test_data_dir() {
+    let _ = std::fs::remove_dir_all(test_data_dir());
+}
+
+#[cfg(test)]
+extern "C" {
+    fn atexit(callback: extern "C" fn()) -> std::os::raw::c_int;
+}
+
+/// Admin password of instances that do not set one
+pub const DEFAULT_ADMIN_PASSWORD: &str = "m1cr0b1n";
+
+/// Scripts only from MicroBin itself or with the page's nonce, nothing but
+/// stylesheets from elsewhere
+pub const DEFAULT_CONTENT_SECURITY_POLICY: &str = "default-src 'self'; \
+    script-src 'self' 'nonce-{nonce}'; style-src 'self' 'unsafe-inline' https:; \
+    img-src 'self' data: blob:; media-src 'self' blob:; object-src 'none'; \
+    base-uri 'none'; form-action 'self'; frame-ancestors 'self'";
+
+/// The Markdown extensions of GitHub Flavored Markdown, plus footnotes
+pub const DEFAULT_MARKDOWN_EXTENSIONS: &str =
+    "strikethrough,table,autolink,tasklist,tagfilter,footnotes";
+
 #[derive(Parser, Debug, Clone, Serialize)]
 #[clap(author, version, about, long_about = None)]
 pub struct Args {
@@ -22,7 +76,7 @@ pub struct Args {
     #[clap(long, env = "MICROBIN_ADMIN_USERNAME", default_value = "admin")]
     pub auth_admin_username: String,
 
-    #[clap(long, env = "MICROBIN_ADMIN_PASSWORD", default_value = "m1cr0b1n")]
+    #[clap(long, env = "MICROBIN_ADMIN_PASSWORD", default_value = DEFAULT_ADMIN_PASSWORD)]
     pub auth_admin_password: String,
 
     #[clap(long, env = "MICROBIN_EDITABLE")]
@@ -49,24 +103,90 @@ pub struct Args {
     #[clap(long, env = "MICROBIN_DEFAULT_SYNTAX", default_value = "md")]
     pub default_syntax: String,
 
+    /// Color theme of syntax highlighted code, one of the themes bundled
+    /// with syntect. Unknown names fall back to InspiredGitHub.
+    #[clap(long, env = "MICROBIN_HIGHLIGHT_THEME", default_value = "InspiredGitHub")]
+    pub highlight_theme: String,
+
     #[clap(long, env = "MICROBIN_RENDER_MARKDOWN", default_value_t = true)]
     pub render_markdown: bool,
 
+    /// Render `$...$` and `$$...$$` in Markdown pastas as math
+    #[clap(long, env = "MICROBIN_RENDER_MATH")]
+    pub render_math: bool,
+
+    /// Put a table of contents at the top of Markdown pastas. It always goes
+    /// where a pasta has a `[[TOC]]` line.
+    #[clap(long, env = "MICROBIN_MARKDOWN_TOC")]
+    pub markdown_toc: bool,
+
+    /// Comma separated Markdown extensions to enable, see
+    /// `contentrenderer::MARKDOWN_EXTENSIONS`. Unknown names are ignored.
+    #[clap(
+        long,
+        env = "MICROBIN_MARKDOWN_EXTENSIONS",
+        value_delimiter = ',',
+        default_value = DEFAULT_MARKDOWN_EXTENSIONS
+    )]
+    pub markdown_extensions: Vec<String>,
+
     #[clap(long, env = "MICROBIN_RENDER_HTML", default_value_t = true)]
     pub render_html: bool,
 
+    /// Strip scripts, event handlers and forms from HTML pastas before they
+    /// are rendered, on top of the sandbox of the iframe showing them
+    #[clap(long, env = "MICROBIN_SANITIZE_HTML_PASTES")]
+    pub sanitize_html_pastes: bool,
+
+    /// Content-Security-Policy header of pasta pages, where `{nonce}` is
+    /// replaced by the nonce of the page's own script. Rendered HTML pastas
+    /// are subject to it as well. Empty sends no header.
+    #[clap(
+        long,
+        env = "MICROBIN_CONTENT_SECURITY_POLICY",
+        default_value = DEFAULT_CONTENT_SECURITY_POLICY
+    )]
+    pub content_security_policy: String,
+
+    /// Default page theme for viewing uploads: auto, light or dark.
+    /// Default: auto, which follows the viewer's system setting.
+    #[clap(long, env = "MICROBIN_DEFAULT_THEME", default_value = "auto")]
+    pub default_theme: String,
+
     #[clap(short, long, env = "MICROBIN_PORT", default_value_t = 9018)]
     pub port: u16,
 
     #[clap(short, long, env="MICROBIN_BIND", default_value_t = IpAddr::from([0, 0, 0, 0]))]
     pub bind: IpAddr,
 
+    /// Path to a PEM certificate chain. Together with --tls-key, enables
+    /// serving HTTPS directly instead of plain HTTP.
+    #[clap(long, env = "MICROBIN_TLS_CERT")]
+    pub tls_cert: Option<String>,
+
+    /// Path to the PEM private key matching --tls-cert.
+    #[clap(long, env = "MICROBIN_TLS_KEY")]
+    pub tls_key: Option<String>,
+
+    /// Minimum accepted TLS version, either 1.2 or 1.3. Default: 1.2.
+    #[clap(long, env = "MICROBIN_TLS_MIN_VERSION", default_value = "1.2")]
+    pub tls_min_version: String,
+
+    /// Comma-separated list of allowed cipher suites, e.g.
+    /// TLS13_AES_256_GCM_SHA384. Default: all modern suites.
+    #[clap(long, env = "MICROBIN_TLS_CIPHERS")]
+    pub tls_ciphers: Option<String>,
+
     #[clap(long, env = "MICROBIN_PRIVATE", default_value_t = true)]
     pub private: bool,
 
     #[clap(long, env = "MICROBIN_DEFAULT_PRIVACY", default_value = "public")]
     pub default_privacy: String,
 
+    /// Privacy modes uploads may not use, comma separated, like `secret`
+    #[clap(long, env = "MICROBIN_DISABLED_PRIVACY_MODES", value_delimiter = ',')]
+    pub disabled_privacy_modes: Vec<String>,
+
     #[clap(long, env = "MICROBIN_PURE_HTML")]
     pub pure_html: bool,
 
@@ -82,6 +202,23 @@ pub struct Args {
     #[clap(long, env = "MICROBIN_UPLOADER_PASSWORD")]
     pub uploader_password: Option<String>,
 
+    /// Prefix added to the names of the cookies MicroBin sets, so that
+    /// several instances on one domain do not overwrite each other's
+    /// cookies. Default: empty.
+    #[clap(long, env = "MICROBIN_COOKIE_PREFIX", default_value = "")]
+    pub cookie_prefix: String,
+
+    /// Whether cookies get the `Secure` attribute. When unset, they do if
+    /// `public_path` starts with `https://`, which is wrong behind a proxy
+    /// terminating TLS in front of a plain `http://` public path.
+    #[clap(long, env = "MICROBIN_COOKIE_SECURE")]
+    pub cookie_secure: Option<bool>,
+
+    /// `SameSite` attribute of cookies: `strict`, `lax` or `none`. When
+    /// unset, secure cookies are `strict` and others `lax`.
+    #[clap(long, env = "MICROBIN_COOKIE_SAMESITE")]
+    pub cookie_samesite: Option<CookieSameSite>,
+
     #[clap(long, env = "MICROBIN_READONLY")]
     pub readonly: bool,
 
@@ -94,42 +231,180 @@ pub struct Args {
     #[clap(short, long, env = "MICROBIN_THREADS", default_value_t = 1)]
     pub threads: u8,
 
+    /// Log a warning for every request whose handler takes longer than this
+    /// many milliseconds. 0 disables slow request logging.
+    #[clap(long, env = "MICROBIN_SLOW_REQUEST_MS", default_value_t = 0)]
+    pub slow_request_ms: u64,
+
+    /// Log every request as a JSON object instead of the default request
+    /// log, for log pipelines
+    #[clap(long, env = "MICROBIN_JSON_LOGS")]
+    pub json_logs: bool,
+
+    /// Serve Prometheus metrics at /metrics
+    #[clap(long, env = "MICROBIN_ENABLE_METRICS")]
+    pub enable_metrics: bool,
+
+    /// Uploads and uploader logins allowed per client IP and minute, with
+    /// short bursts up to the same number. 0 disables rate limiting.
+    #[clap(long, env = "MICROBIN_RATE_LIMIT_PER_MINUTE", default_value_t = 0)]
+    pub rate_limit_per_minute: u32,
+
+    /// Failed uploader logins per client IP within 15 minutes before it is
+    /// locked out, for 30 seconds doubling with every further failure.
+    /// 0 disables the lockout.
+    #[clap(long, env = "MICROBIN_LOGIN_MAX_FAILURES", default_value_t = 5)]
+    pub login_max_failures: u32,
+
+    /// Comma separated addresses or CIDR ranges of reverse proxies, like
+    /// `127.0.0.1,10.0.0.0/8`. Only requests from these peers have their
+    /// `X-Forwarded-For` and `X-Real-IP` headers believed. Default: none.
+    #[clap(long, env = "MICROBIN_TRUSTED_PROXIES", value_delimiter = ',')]
+    pub trusted_proxies: Vec<String>,
+
     #[clap(short, long, env = "MICROBIN_GC_DAYS", default_value_t = 30)]
     pub gc_days: u16,
 
+    /// Seconds between background runs removing expired pastas and their
+    /// files. 0 only removes them when a request comes along.
+    #[clap(long, env = "MICROBIN_GC_INTERVAL_SECS", default_value_t = 300)]
+    pub gc_interval_secs: u64,
+
     #[clap(long, env = "MICROBIN_ENABLE_BURN_AFTER")]
     pub enable_burn_after: bool,
 
     #[clap(short, long, env = "MICROBIN_DEFAULT_BURN_AFTER", default_value_t = 0)]
     pub default_burn_after: u16,
 
+    /// Highest read limit an upload can have, larger ones are lowered to it.
+    /// 0 allows any number of reads.
+    #[clap(long, env = "MICROBIN_MAX_BURN_AFTER", default_value_t = 10000)]
+    pub max_burn_after: u64,
+
     #[clap(long, env = "MICROBIN_WIDE")]
     pub wide: bool,
 
     #[clap(long, env = "MICROBIN_QR", default_value_t = true)]
     pub qr: bool,
 
+    /// Error correction of QR codes: low, medium, quartile or high
+    #[clap(long, env = "MICROBIN_QR_ECC", default_value = "low")]
+    pub qr_ecc: String,
+
+    /// Width and height of QR codes in pixels, between 64 and 2048
+    #[clap(long, env = "MICROBIN_QR_SIZE", default_value_t = 256)]
+    pub qr_size: usize,
+
+    /// Serve an oEmbed endpoint at /oembed so public pastas can be embedded
+    /// in blogs and other oEmbed consumers
+    #[clap(long, env = "MICROBIN_ENABLE_OEMBED")]
+    pub enable_oembed: bool,
+
     #[clap(long, env = "MICROBIN_ETERNAL_PASTA", default_value_t = true)]
     pub eternal_pasta: bool,
 
+    /// Require uploads that never expire to be confirmed with a
+    /// `confirm_never=true` form field
+    #[clap(long, env = "MICROBIN_CONFIRM_ETERNAL")]
+    pub confirm_eternal: bool,
+
+    /// Reject uploads that would live forever, that is ones that never
+    /// expire and have no read limit
+    #[clap(long, env = "MICROBIN_FORCE_EXPIRY")]
+    pub force_expiry: bool,
+
     #[clap(long, env = "MICROBIN_ENABLE_READONLY")]
     pub enable_readonly: bool,
 
     #[clap(long, env = "MICROBIN_DEFAULT_EXPIRY", default_value = "1week")]
     pub default_expiry: String,
 
+    /// Longest allowed expiration, like `30d` or `6mo`. Longer ones, including never, are shortened to it
+    #[clap(long, env = "MICROBIN_MAX_EXPIRY")]
+    pub max_expiry: Option<String>,
+
     #[clap(long, env = "MICROBIN_DATA_DIR", default_value = "microbin_data")]
     pub data_dir: String,
 
+    /// Remove pastas whose attachment is missing from the data directory at startup
+    #[clap(long, env = "MICROBIN_PRUNE_MISSING_FILES")]
+    pub prune_missing_files: bool,
+
+    /// Accept resumable file uploads using the tus protocol at /tus
+    #[clap(long, env = "MICROBIN_ENABLE_TUS")]
+    pub enable_tus: bool,
+
     #[clap(short, long, env = "MICROBIN_NO_FILE_UPLOAD")]
     pub no_file_upload: bool,
 
+    /// Comma separated file extensions uploads are limited to, like
+    /// `txt,pdf`. Empty allows every file unless `allowed_mime_types` is set.
+    #[clap(long, env = "MICROBIN_ALLOWED_FILE_EXTENSIONS", value_delimiter = ',')]
+    pub allowed_file_extensions: Vec<String>,
+
+    /// Comma separated content types uploads are limited to, guessed from
+    /// the file name, like `text/plain,image/*`
+    #[clap(long, env = "MICROBIN_ALLOWED_MIME_TYPES", value_delimiter = ',')]
+    pub allowed_mime_types: Vec<String>,
+
+    /// Show attached images inline on the pasta page instead of only a
+    /// download button
+    #[clap(long, env = "MICROBIN_EMBED_IMAGES", default_value_t = true)]
+    pub embed_images: bool,
+
+    /// Show attached videos inline on the pasta page instead of only a
+    /// download button
+    #[clap(long, env = "MICROBIN_EMBED_VIDEOS", default_value_t = true)]
+    pub embed_videos: bool,
+
+    /// Let uploads name a `remote_url` the server downloads the pasta from.
+    /// Private and local addresses are refused.
+    #[clap(long, env = "MICROBIN_ALLOW_REMOTE_FETCH")]
+    pub allow_remote_fetch: bool,
+
     #[clap(long, env = "MICROBIN_CUSTOM_CSS")]
     pub custom_css: Option<String>,
 
+    /// Comma separated list of extension=content type pairs used instead of
+    /// the guessed content type when serving files, e.g. "md=text/plain"
+    #[clap(long, env = "MICROBIN_MIME_OVERRIDES")]
+    pub mime_overrides: Option<String>,
+
+    /// Regular expressions, one per line, rejecting text uploads whose
+    /// content matches any of them
+    #[clap(long, env = "MICROBIN_BLOCKED_CONTENT_PATTERNS", value_delimiter = '\n')]
+    pub blocked_content_patterns: Vec<String>,
+
+    /// Comma separated domains URL pastas may point to, subdomains
+    /// included. Empty allows every domain not in `url_blocklist`.
+    #[clap(long, env = "MICROBIN_URL_ALLOWLIST", value_delimiter = ',')]
+    pub url_allowlist: Vec<String>,
+
+    /// Comma separated domains URL pastas may not point to, subdomains
+    /// included. Takes precedence over `url_allowlist`.
+    #[clap(long, env = "MICROBIN_URL_BLOCKLIST", value_delimiter = ',')]
+    pub url_blocklist: Vec<String>,
+
     #[clap(long, env = "MICROBIN_HASH_IDS")]
     pub hash_ids: bool,
 
+    /// How pasta ids are written in links: `animals`, `hashids` or the
+    /// compact `base62`. `hash_ids` selects hashids when this is left at
+    /// its default. Default: animals.
+    #[clap(long, env = "MICROBIN_ID_SCHEME", default_value = "animals")]
+    pub id_scheme: IdScheme,
+
+    /// Store attachments under an opaque name so that storage paths and
+    /// file links do not reveal the original file name. Downloads still use
+    /// the original name. Default: false.
+    #[clap(long, env = "MICROBIN_HASH_FILENAMES_IN_URLS")]
+    pub hash_filenames_in_urls: bool,
+
+    /// What to add to the name of an attachment when a file of the pasta is
+    /// already stored under it: a `number` or a short `hash`. Default: number.
+    #[clap(long, env = "MICROBIN_FILENAME_COLLISIONS", default_value = "number")]
+    pub filename_collisions: FileNameCollisions,
+
     #[clap(long, env = "MICROBIN_LIST_SERVER")]
     pub list_server: bool,
 
@@ -145,6 +420,16 @@ pub struct Args {
     #[clap(long, env = "MICROBIN_ENCRYPTION_SERVER_SIDE", default_value_t = true)]
     pub encryption_server_side: bool,
 
+    /// Derive encryption keys from passwords with Argon2id instead of using them directly
+    #[clap(long, env = "MICROBIN_STRONG_KDF")]
+    pub strong_kdf: bool,
+
+    /// Shortest password accepted for server-side encryption. Passwords
+    /// that are long enough must still not be easy to guess. Default: 0,
+    /// which turns the check off.
+    #[clap(long, env = "MICROBIN_MIN_PASSWORD_LENGTH", default_value_t = 0)]
+    pub min_password_length: usize,
+
     #[clap(
         long,
         env = "MICROBIN_MAX_FILE_SIZE_ENCRYPTED_MB",
@@ -159,6 +444,32 @@ pub struct Args {
     )]
     pub max_file_size_unencrypted_mb: usize,
 
+    /// Percentage of the file size limit above which uploads get a soft
+    /// warning in the X-Size-Warning header. 0 disables the warning.
+    #[clap(long, env = "MICROBIN_SIZE_WARNING_PERCENT", default_value_t = 80)]
+    pub size_warning_percent: u8,
+
+    /// Maximum size (in KB) of a single text field in the upload form, e.g.
+    /// the pasta content. Default: 10240 (10 MB).
+    #[clap(long, env = "MICROBIN_MAX_TEXT_FIELD_SIZE_KB", default_value_t = 10240)]
+    pub max_text_field_size_kb: usize,
+
+    /// Text pastas of at least this many bytes are stored compressed.
+    /// Encrypted pastas never are. 0 turns compression off. Default: 65536.
+    #[clap(long, env = "MICROBIN_COMPRESS_MIN_BYTES", default_value_t = 65536)]
+    pub compress_min_bytes: usize,
+
+    /// Maximum size (in MB) of all pastas together, content and attachments.
+    /// Uploads that would go over it are rejected. 0 means no quota.
+    #[clap(long, env = "MICROBIN_MAX_TOTAL_STORAGE_MB", default_value_t = 0)]
+    pub max_total_storage_mb: u64,
+
+    /// Maximum total size (in MB) of an upload form, all its fields and
+    /// attachments together. Default: 0, which means the larger of the file
+    /// size limits plus the text field limit.
+    #[clap(long, env = "MICROBIN_MAX_FORM_SIZE_MB", default_value_t = 0)]
+    pub max_form_size_mb: usize,
+
     // S3 storage options
     #[clap(long, env = "MICROBIN_S3_ENDPOINT")]
     pub s3_endpoint: Option<String>,
@@ -174,6 +485,64 @@ pub struct Args {
 
     #[clap(long, env = "MICROBIN_S3_REGION", default_value = "us-west-000")]
     pub s3_region: String,
+
+    /// How many times to retry reading a freshly uploaded S3 object that
+    /// is not visible yet. 0 disables retries.
+    #[clap(long, env = "MICROBIN_S3_READ_RETRY", default_value_t = 3)]
+    pub s3_read_retry: u32,
+
+    // Google Cloud Storage options
+    /// Store attachments in this Google Cloud Storage bucket
+    #[clap(long, env = "MICROBIN_GCS_BUCKET")]
+    pub gcs_bucket: Option<String>,
+
+    /// Path to the JSON key of the service account used for Google Cloud
+    /// Storage. Without it, the service account of the GCP instance is used.
+    #[clap(long, env = "MICROBIN_GCS_CREDENTIALS")]
+    pub gcs_credentials: Option<String>,
+
+    /// POST a JSON notification to this URL whenever a pasta is created or
+    /// removed. It holds metadata only, never content or keys.
+    #[clap(long, env = "MICROBIN_WEBHOOK_URL")]
+    pub webhook_url: Option<String>,
+
+    /// Scan uploaded files with the ClamAV daemon listening on this address,
+    /// like 127.0.0.1:3310, and reject infected ones
+    #[clap(long, env = "MICROBIN_CLAMAV_ADDRESS")]
+    pub clamav_address: Option<String>,
+
+    // WebDAV storage options
+    /// Store attachments on this WebDAV server, like
+    /// https://cloud.example.com/remote.php/dav/files/microbin/attachments
+    #[clap(long, env = "MICROBIN_WEBDAV_URL")]
+    pub webdav_url: Option<String>,
+
+    /// User for HTTP Basic auth against the WebDAV server
+    #[clap(long, env = "MICROBIN_WEBDAV_USER")]
+    pub webdav_user: Option<String>,
+
+    /// Password for HTTP Basic auth against the WebDAV server
+    #[clap(long, env = "MICROBIN_WEBDAV_PASS")]
+    pub webdav_pass: Option<String>,
+
+    // Azure Blob Storage options
+    /// Store attachments in this Azure storage account
+    #[clap(long, env = "MICROBIN_AZURE_ACCOUNT")]
+    pub azure_account: Option<String>,
+
+    /// Base64 shared key of the Azure storage account
+    #[clap(long, env = "MICROBIN_AZURE_KEY")]
+    pub azure_key: Option<String>,
+
+    /// Blob container attachments are stored in
+    #[clap(long, env = "MICROBIN_AZURE_CONTAINER")]
+    pub azure_container: Option<String>,
+
+    /// Blob service endpoint, defaults to
+    /// https://<account>.blob.core.windows.net. Set it for the Azurite
+    /// emulator, like http://127.0.0.1:10000/devstoreaccount1
+    #[clap(long, env = "MICROBIN_AZURE_ENDPOINT")]
+    pub azure_endpoint: Option<String>,
 }
 
 impl Args {
@@ -195,6 +564,14 @@ impl Args {
         }
     }
 
+    pub fn uploader_cookie_name(&self) -> String {
+        format!("{}uploader_token", self.cookie_prefix)
+    }
+
+    pub fn owner_cookie_name(&self) -> String {
+        format!("{}owner_token", self.cookie_prefix)
+    }
+
     pub fn without_secrets(self) -> Args {
         Args {
             auth_basic_username: None,
@@ -209,45 +586,127 @@ impl Args {
             no_listing: self.no_listing,
             highlightsyntax: self.highlightsyntax,
             default_syntax: self.default_syntax,
+            highlight_theme: self.highlight_theme,
             render_markdown: self.render_markdown,
+            render_math: self.render_math,
+            markdown_toc: self.markdown_toc,
+            markdown_extensions: self.markdown_extensions,
             render_html: self.render_html,
+            sanitize_html_pastes: self.sanitize_html_pastes,
+            content_security_policy: self.content_security_policy,
+            default_theme: self.default_theme,
             port: self.port,
             bind: self.bind,
+            tls_cert: None,
+            tls_key: None,
+            tls_min_version: self.tls_min_version,
+            tls_ciphers: self.tls_ciphers,
             private: self.private,
             default_privacy: self.default_privacy,
+            disabled_privacy_modes: self.disabled_privacy_modes,
             pure_html: self.pure_html,
             json_db: self.json_db,
             public_path: self.public_path,
             short_path: self.short_path,
             uploader_password: None,
+            cookie_prefix: self.cookie_prefix,
+            cookie_secure: self.cookie_secure,
+            cookie_samesite: self.cookie_samesite,
             readonly: self.readonly,
             show_read_stats: self.show_read_stats,
             title: self.title,
             list_server: self.list_server,
             threads: self.threads,
+            slow_request_ms: self.slow_request_ms,
+            json_logs: self.json_logs,
+            enable_metrics: self.enable_metrics,
+            rate_limit_per_minute: self.rate_limit_per_minute,
+            login_max_failures: self.login_max_failures,
+            trusted_proxies: self.trusted_proxies,
             gc_days: self.gc_days,
+            gc_interval_secs: self.gc_interval_secs,
             enable_burn_after: self.enable_burn_after,
             default_burn_after: self.default_burn_after,
+            max_burn_after: self.max_burn_after,
             wide: self.wide,
             qr: self.qr,
+            qr_ecc: self.qr_ecc,
+            qr_size: self.qr_size,
+            enable_oembed: self.enable_oembed,
             eternal_pasta: self.eternal_pasta,
+            confirm_eternal: self.confirm_eternal,
+            force_expiry: self.force_expiry,
             enable_readonly: self.enable_readonly,
             default_expiry: self.default_expiry,
+            max_expiry: self.max_expiry,
             data_dir: String::from(""),
+            prune_missing_files: self.prune_missing_files,
             no_file_upload: self.no_file_upload,
+            allowed_file_extensions: self.allowed_file_extensions,
+            allowed_mime_types: self.allowed_mime_types,
+            embed_images: self.embed_images,
+            embed_videos: self.embed_videos,
+            allow_remote_fetch: self.allow_remote_fetch,
+            enable_tus: self.enable_tus,
             custom_css: self.custom_css,
+            mime_overrides: self.mime_overrides,
+            blocked_content_patterns: self.blocked_content_patterns,
+            url_allowlist: self.url_allowlist,
+            url_blocklist: self.url_blocklist,
             hash_ids: self.hash_ids,
+            id_scheme: self.id_scheme,
+            hash_filenames_in_urls: self.hash_filenames_in_urls,
+            filename_collisions: self.filename_collisions,
             disable_telemetry: self.disable_telemetry,
             encryption_client_side: self.encryption_client_side,
             encryption_server_side: self.encryption_server_side,
+            strong_kdf: self.strong_kdf,
+            min_password_length: self.min_password_length,
             max_file_size_encrypted_mb: self.max_file_size_encrypted_mb,
             max_file_size_unencrypted_mb: self.max_file_size_unencrypted_mb,
+            size_warning_percent: self.size_warning_percent,
+            max_text_field_size_kb: self.max_text_field_size_kb,
+            compress_min_bytes: self.compress_min_bytes,
+            max_form_size_mb: self.max_form_size_mb,
+            max_total_storage_mb: self.max_total_storage_mb,
             disable_update_checking: self.disable_update_checking,
             s3_endpoint: None,
             s3_bucket: None,
             s3_access_key: None,
             s3_secret_key: None,
             s3_region: String::from(""),
+            s3_read_retry: self.s3_read_retry,
+            gcs_bucket: None,
+            gcs_credentials: None,
+            webhook_url: None,
+            clamav_address: None,
+            webdav_url: None,
+            webdav_user: None,
+            webdav_pass: None,
+            azure_account: None,
+            azure_key: None,
+            azure_container: None,
+            azure_endpoint: None,
+        }
+    }
+
+    /// Whether the admin password was left at its well known default
+    pub fn default_admin_password(&self) -> bool {
+        self.auth_admin_password == DEFAULT_ADMIN_PASSWORD
+    }
+
+    pub fn max_text_field_size_bytes(&self) -> usize {
+        self.max_text_field_size_kb * 1024
+    }
+
+    pub fn max_form_size_bytes(&self) -> usize {
+        if self.max_form_size_mb > 0 {
+            self.max_form_size_mb * 1024 * 1024
+        } else {
+            let max_file_mb = self
+                .max_file_size_unencrypted_mb
+                .max(self.max_file_size_encrypted_mb);
+            max_file_mb * 1024 * 1024 + self.max_text_field_size_bytes()
         }
     }
 
@@ -257,6 +716,26 @@ impl Args {
             && self.s3_access_key.is_some()
             && self.s3_secret_key.is_some()
     }
+
+    pub fn gcs_enabled(&self) -> bool {
+        self.gcs_bucket.is_some()
+    }
+
+    pub fn webdav_enabled(&self) -> bool {
+        self.webdav_url.is_some()
+    }
+
+    pub fn azure_enabled(&self) -> bool {
+        self.azure_account.is_some() && self.azure_key.is_some() && self.azure_container.is_some()
+    }
+
+    /// Whether uploads may use the privacy mode `mode`, like `secret`
+    pub fn privacy_mode_enabled(&self, mode: &str) -> bool {
+        !self
+            .disabled_privacy_modes
+            .iter()
+            .any(|disabled| disabled.trim() == mode)
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -276,3 +755,105 @@ impl FromStr for PublicUrl {
         Ok(PublicUrl(uri))
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdScheme {
+    Animals,
+    HashIds,
+    Base62,
+}
+
+impl fmt::Display for IdScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            IdScheme::Animals => "animals",
+            IdScheme::HashIds => "hashids",
+            IdScheme::Base62 => "base62",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for IdScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "animals" => Ok(IdScheme::Animals),
+            "hashids" => Ok(IdScheme::HashIds),
+            "base62" => Ok(IdScheme::Base62),
+            other => Err(format!(
+                "unknown id scheme {:?}, expected animals, hashids or base62",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileNameCollisions {
+    Number,
+    Hash,
+}
+
+impl fmt::Display for FileNameCollisions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FileNameCollisions::Number => "number",
+            FileNameCollisions::Hash => "hash",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for FileNameCollisions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "number" => Ok(FileNameCollisions::Number),
+            "hash" => Ok(FileNameCollisions::Hash),
+            other => Err(format!(
+                "unknown file name collision handling {:?}, expected number or hash",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CookieSameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl fmt::Display for CookieSameSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CookieSameSite::Strict => "strict",
+            CookieSameSite::Lax => "lax",
+            CookieSameSite::None => "none",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for CookieSameSite {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "strict" => Ok(CookieSameSite::Strict),
+            "lax" => Ok(CookieSameSite::Lax),
+            "none" => Ok(CookieSameSite::None),
+            other => Err(format!(
+                "unknown SameSite value {:?}, expected strict, lax or none",
+                other
+            )),
+        }
+    }
+}
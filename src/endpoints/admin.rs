@@ -1,12 +1,27 @@
 use crate::args::{Args, ARGS};
-use crate::pasta::Pasta;
+use crate::pasta::{Pasta, PastaFile};
+use crate::util::auth;
+use crate::util::backup;
+use crate::util::db::{self, delete};
+use crate::util::ids;
+use crate::util::metrics;
 use crate::util::misc::remove_expired;
+use crate::util::storage;
+use crate::util::store::{MemoryStore, PastaStore};
 use crate::util::version::{fetch_latest_version, Version, CURRENT_VERSION};
+use crate::util::webhook;
 use crate::AppState;
 use actix_multipart::Multipart;
-use actix_web::{get, post, web, Error, HttpResponse};
+use actix_web::{get, post, web, Error, HttpRequest, HttpResponse};
 use askama::Template;
-use futures::TryStreamExt;
+use futures::{StreamExt, TryStreamExt};
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::json;
+use std::cmp::Reverse;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
 
 #[derive(Template)]
 #[template(path = "admin.html")]
@@ -74,7 +89,7 @@ pub async fn post_admin(
         message = "Warning: No public URL set with --public-path parameter. QR code and URL Copying functions have been disabled"
     }
 
-    if ARGS.auth_admin_username == "admin" && ARGS.auth_admin_password == "m1cr0b1n" {
+    if ARGS.auth_admin_username == "admin" && ARGS.default_admin_password() {
         status = "WARNING";
         message = "Warning: You are using the default admin login details. This is a security risk, please change them."
     }
@@ -112,3 +127,487 @@ pub async fn post_admin(
             .unwrap(),
         ))
 }
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminDelete {
+    /// Slugs of the pastas, as in their links
+    ids: Vec<String>,
+}
+
+/// What operators may see of a pasta: all of its metadata, but neither its
+/// content, nor its key, nor the title of encrypted pastas
+fn pasta_json(pasta: &Pasta) -> serde_json::Value {
+    let encrypted = pasta.encrypt_server || pasta.encrypt_client;
+    let file_json = |file: &PastaFile| {
+        json!({
+            "name": file.display_name(),
+            "size": file.size.as_u64(),
+        })
+    };
+
+    json!({
+        "id": pasta.id_as_animals(),
+        "custom_slug": pasta.custom_slug,
+        "created": pasta.created,
+        "expiration": pasta.expiration,
+        "last_read": pasta.last_read,
+        "read_count": pasta.read_count,
+        "burn_after_reads": pasta.burn_after_reads,
+        "download_limit": pasta.download_limit,
+        "download_count": pasta.download_count,
+        "one_time": pasta.one_time,
+        "pasta_type": pasta.pasta_type,
+        "extension": pasta.extension,
+        "title": pasta.title.as_ref().filter(|_| !encrypted),
+        "private": pasta.private,
+        "unlisted": pasta.unlisted,
+        "readonly": pasta.readonly,
+        "editable": pasta.editable,
+        "encrypt_server": pasta.encrypt_server,
+        "encrypt_client": pasta.encrypt_client,
+        "files": pasta.files().map(file_json).collect::<Vec<_>>(),
+        "size": pasta.total_size_bytes(),
+    })
+}
+
+/// Take the pastas matching `filter` out of the collection
+fn take_pastas(pastas: &mut MemoryStore, filter: impl Fn(&Pasta) -> bool) -> Vec<Pasta> {
+    let (removed, kept): (Vec<Pasta>, Vec<Pasta>) =
+        pastas.take().into_iter().partition(|pasta| filter(pasta));
+    pastas.extend(kept);
+    removed
+}
+
+/// Delete the pastas matching `filter` from the collection, the database
+/// and storage. Returns the pastas deleted.
+pub async fn delete_matching(
+    data: &web::Data<AppState>,
+    filter: impl Fn(&Pasta) -> bool,
+) -> Vec<Pasta> {
+    let removed = {
+        let mut pastas = data.lock_pastas();
+
+        remove_expired(&mut pastas);
+
+        let removed = take_pastas(&mut pastas, filter);
+        for pasta in removed.iter() {
+            delete(Some(&pastas), Some(pasta.id));
+            metrics::record(webhook::Event::Deleted);
+            webhook::notify(webhook::Event::Deleted, pasta);
+        }
+        removed
+    }; // Lock released here
+
+    for pasta in removed.iter() {
+        let pasta_id = pasta.id_as_animals();
+        for storage_path in pasta.file_storage_paths() {
+            if let Err(e) = storage::delete_file(&pasta_id, &storage_path).await {
+                log::error!("Failed to delete file {}: {}", storage_path, e);
+            }
+        }
+    }
+
+    removed
+}
+
+/// All pastas, private ones included, newest first
+#[get("/admin/pastas")]
+pub async fn list_pastas(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    if let Err(refused) = auth::check_admin(&req) {
+        return refused.response();
+    }
+
+    let mut pastas = data.lock_pastas();
+
+    remove_expired(&mut pastas);
+
+    let mut listed: Vec<&Pasta> = pastas.iter().collect();
+    listed.sort_by_key(|pasta| Reverse(pasta.created));
+
+    HttpResponse::Ok().json(listed.into_iter().map(pasta_json).collect::<Vec<_>>())
+}
+
+/// Delete pastas by their ids along with their files
+#[post("/admin/delete")]
+pub async fn delete_pastas(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    body: web::Json<AdminDelete>,
+) -> HttpResponse {
+    if let Err(refused) = auth::check_admin(&req) {
+        return refused.response();
+    }
+
+    // ids that decode to no pasta at all are simply not found
+    let mut requested: Vec<u64> = body
+        .ids
+        .iter()
+        .filter_map(|slug| ids::to_u64(slug).ok())
+        .collect();
+    let undecodable = body.ids.len() - requested.len();
+    requested.sort_unstable();
+    requested.dedup();
+
+    let removed = delete_matching(&data, |pasta| requested.contains(&pasta.id)).await;
+    let not_found = requested.len() - removed.len();
+
+    log::info!("Admin deleted {} pastas", removed.len());
+
+    HttpResponse::Ok().json(json!({
+        "deleted": removed.len(),
+        "not_found": not_found + undecodable,
+    }))
+}
+
+/// Backup of all pastas and their files as a tar archive, see
+/// `backup::export` for its layout
+#[get("/admin/export")]
+pub async fn export_backup(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    if let Err(refused) = auth::check_admin(&req) {
+        return refused.response();
+    }
+
+    let pastas = {
+        let mut pastas = data.lock_pastas();
+        remove_expired(&mut pastas);
+        pastas.to_vec()
+    };
+    log::info!("Admin exported a backup of {} pastas", pastas.len());
+
+    match backup::export(pastas) {
+        Ok(archive) => HttpResponse::Ok()
+            .content_type("application/x-tar")
+            .insert_header((
+                "Content-Disposition",
+                "attachment; filename=\"microbin-backup.tar\"",
+            ))
+            .streaming(archive.map(Ok::<_, Error>)),
+        Err(e) => {
+            log::error!("Failed to export backup: {}", e);
+            HttpResponse::InternalServerError().json(json!({ "error": "Failed to export backup." }))
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// Keep the pastas already here, skipping those in the backup with the
+    /// same id
+    #[default]
+    Merge,
+    /// Delete all pastas and their files before restoring the backup
+    Replace,
+}
+
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    mode: ImportMode,
+}
+
+/// Restore a backup made by `/admin/export`, sent as the request body
+#[post("/admin/import")]
+pub async fn import_backup(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    query: web::Query<ImportQuery>,
+    payload: web::Payload,
+) -> HttpResponse {
+    if let Err(refused) = auth::check_admin(&req) {
+        return refused.response();
+    }
+
+    let dir = format!(
+        "{}/import-{}",
+        ARGS.data_dir,
+        rand::thread_rng().gen::<u32>()
+    );
+    let archive_path = format!("{}.tar", dir);
+    let result = import(
+        &data,
+        query.mode,
+        payload,
+        Path::new(&archive_path),
+        Path::new(&dir),
+    )
+    .await;
+    let _ = fs::remove_file(&archive_path);
+    let _ = fs::remove_dir_all(&dir);
+
+    match result {
+        Ok(summary) => HttpResponse::Ok().json(summary),
+        Err(response) => response,
+    }
+}
+
+async fn import(
+    data: &web::Data<AppState>,
+    mode: ImportMode,
+    mut payload: web::Payload,
+    archive_path: &Path,
+    dir: &Path,
+) -> Result<serde_json::Value, HttpResponse> {
+    let failed = |e: String| {
+        log::error!("Failed to import backup: {}", e);
+        HttpResponse::InternalServerError().json(json!({ "error": "Failed to import backup." }))
+    };
+
+    // the archive goes to disk first instead of into memory
+    let mut file = fs::File::create(archive_path).map_err(|e| {
+        failed(format!(
+            "Failed to create {}: {}",
+            archive_path.display(),
+            e
+        ))
+    })?;
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| failed(e.to_string()))?;
+        file.write_all(&chunk)
+            .map_err(|e| failed(format!("Failed to write {}: {}", archive_path.display(), e)))?;
+    }
+    drop(file);
+
+    let (archive_path, unpack_dir) = (archive_path.to_path_buf(), dir.to_path_buf());
+    let mut incoming = web::block(move || {
+        let archive = fs::File::open(&archive_path).map_err(|e| e.to_string())?;
+        backup::unpack(archive, &unpack_dir)
+    })
+    .await
+    .unwrap_or_else(|e| Err(e.to_string()))
+    .map_err(|e| {
+        log::warn!("Rejected backup: {}", e);
+        HttpResponse::BadRequest().json(json!({ "error": "Not a valid backup." }))
+    })?;
+    let in_backup = incoming.len();
+
+    let replaced = {
+        let mut pastas = data.lock_pastas();
+        remove_expired(&mut pastas);
+        match mode {
+            ImportMode::Replace => {
+                let replaced = pastas.take();
+                db::update_all(&pastas);
+                replaced
+            }
+            ImportMode::Merge => {
+                incoming.retain(|pasta| pastas.position(pasta.id).is_none());
+                Vec::new()
+            }
+        }
+    }; // Lock released here
+
+    for pasta in replaced.iter() {
+        let pasta_id = pasta.id_as_animals();
+        for storage_path in pasta.file_storage_paths() {
+            if let Err(e) = storage::delete_file(&pasta_id, &storage_path).await {
+                log::error!("Failed to delete file {}: {}", storage_path, e);
+            }
+        }
+    }
+
+    let files = backup::restore_files(&incoming, dir)
+        .await
+        .map_err(failed)?;
+
+    let imported = {
+        let mut pastas = data.lock_pastas();
+        // pastas may have been created meanwhile
+        incoming.retain(|pasta| pastas.position(pasta.id).is_none());
+        let imported = incoming.len();
+        pastas.extend(incoming);
+        db::update_all(&pastas);
+        imported
+    };
+
+    log::info!(
+        "Admin imported {} pastas with {} files from a backup, replacing {}",
+        imported,
+        files,
+        replaced.len()
+    );
+
+    Ok(json!({
+        "imported": imported,
+        "skipped": in_backup - imported,
+        "replaced": replaced.len(),
+        "files": files,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::create::new_pasta;
+    use bytesize::ByteSize;
+
+    fn pasta(id: u64, file: Option<&str>) -> Pasta {
+        Pasta {
+            id,
+            content: String::from("secret content"),
+            file: file.map(|name| PastaFile {
+                name: name.to_string(),
+                size: ByteSize::b(11),
+                original_name: None,
+                sha256: None,
+                content_type: None,
+                stored_size: None,
+            }),
+            extension: String::from("txt"),
+            private: true,
+            editable: false,
+            encrypted_key: Some(String::from("key")),
+            created: id as i64,
+            expiration: 0,
+            custom_slug: Some(format!("pasta-{}", id)),
+            pasta_type: String::from("text"),
+            title: Some(String::from("Notes")),
+            ..new_pasta(0)
+        }
+    }
+
+    #[test]
+    fn test_pasta_json_has_no_secrets() {
+        let listed = pasta_json(&pasta(1, Some("notes.txt")));
+        assert_eq!(listed["id"], "pasta-1");
+        assert_eq!(listed["private"], true);
+        assert_eq!(listed["files"][0]["name"], "notes.txt");
+        assert_eq!(listed["title"], "Notes");
+        assert!(listed.get("content").is_none());
+        assert!(listed.get("encrypted_key").is_none());
+        assert!(!listed.to_string().contains("secret content"));
+
+        let mut encrypted = pasta(2, None);
+        encrypted.encrypt_server = true;
+        assert!(pasta_json(&encrypted)["title"].is_null());
+    }
+
+    #[actix_web::test]
+    async fn test_list_and_delete_need_the_admin_password() {
+        use crate::args::DEFAULT_ADMIN_PASSWORD;
+        use crate::util::rate_limit::{FailedLogins, RateLimiter};
+        use crate::util::store::MemoryStore;
+        use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+        use actix_web::App;
+        use std::sync::Mutex;
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let timenow = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        // ids no other test stores
+        let pastas = [4_067_890_101, 4_067_890_102].map(|id| Pasta {
+            last_read: timenow,
+            custom_slug: None,
+            ..pasta(id, None)
+        });
+        let slug = pastas[0].id_as_animals();
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(pastas.to_vec())),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .service(list_pastas)
+                .service(delete_pastas),
+        )
+        .await;
+        let delete = |password: &str| {
+            TestRequest::post()
+                .uri("/admin/delete")
+                .insert_header((auth::ADMIN_HEADER, password.to_string()))
+                .set_json(json!({ "ids": [slug] }))
+                .to_request()
+        };
+
+        for password in ["", DEFAULT_ADMIN_PASSWORD] {
+            let list = TestRequest::get()
+                .uri("/admin/pastas")
+                .insert_header((auth::ADMIN_HEADER, password))
+                .to_request();
+            assert_eq!(call_service(&app, list).await.status(), 401);
+            assert_eq!(call_service(&app, delete(password)).await.status(), 401);
+        }
+        assert_eq!(data.lock_pastas().len(), 2);
+
+        let list = TestRequest::get()
+            .uri("/admin/pastas")
+            .insert_header((auth::ADMIN_HEADER, ARGS.auth_admin_password.as_str()))
+            .to_request();
+        let listed: Vec<serde_json::Value> = read_body_json(call_service(&app, list).await).await;
+        assert_eq!(listed.len(), 2);
+
+        let response = call_service(&app, delete(&ARGS.auth_admin_password)).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(data.lock_pastas().len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_backups_need_the_admin_password() {
+        use crate::args::DEFAULT_ADMIN_PASSWORD;
+        use crate::util::rate_limit::{FailedLogins, RateLimiter};
+        use crate::util::store::MemoryStore;
+        use actix_web::test::{call_service, init_service, TestRequest};
+        use actix_web::App;
+        use std::sync::Mutex;
+
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(Vec::new())),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .service(export_backup)
+                .service(import_backup),
+        )
+        .await;
+
+        for password in ["", DEFAULT_ADMIN_PASSWORD] {
+            let export = TestRequest::get()
+                .uri("/admin/export")
+                .insert_header((auth::ADMIN_HEADER, password))
+                .to_request();
+            assert_eq!(call_service(&app, export).await.status(), 401);
+            let import = TestRequest::post()
+                .uri("/admin/import?mode=replace")
+                .insert_header((auth::ADMIN_HEADER, password))
+                .set_payload("not a backup")
+                .to_request();
+            assert_eq!(call_service(&app, import).await.status(), 401);
+        }
+
+        let export = TestRequest::get()
+            .uri("/admin/export")
+            .insert_header((auth::ADMIN_HEADER, ARGS.auth_admin_password.as_str()))
+            .to_request();
+        let response = call_service(&app, export).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            "application/x-tar"
+        );
+    }
+
+    #[test]
+    fn test_take_pastas_with_files() {
+        let mut pastas = MemoryStore::new(vec![
+            pasta(1, Some("notes.txt")),
+            pasta(2, None),
+            pasta(3, None),
+        ]);
+
+        let removed = take_pastas(&mut pastas, |pasta| [1, 3, 42].contains(&pasta.id));
+
+        assert_eq!(removed.iter().map(|p| p.id).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(pastas.iter().map(|p| p.id).collect::<Vec<_>>(), vec![2]);
+        // the files go along, so they can be deleted from storage
+        assert_eq!(removed[0].file_storage_paths(), vec!["notes.txt"]);
+        assert!(removed[1].file_storage_paths().is_empty());
+    }
+}
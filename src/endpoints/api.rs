@@ -0,0 +1,1830 @@
+use crate::args::ARGS;
+use crate::endpoints::admin;
+use crate::endpoints::create::{
+    check_bounded_lifetime, check_password_strength, check_storage_quota, clamp_reads,
+    detect_extension, expiration_to_timestamp, generate_uploader_token, new_pasta, persist_pasta,
+};
+use crate::pasta::{Pasta, PastaFile, PrivacyPreset};
+use crate::util::auth;
+use crate::util::content_filter;
+use crate::util::db::{delete, insert, try_update, update};
+use crate::util::expired::{self, ExpiryReason};
+use crate::util::ids;
+use crate::util::metrics;
+use crate::util::misc::{
+    decrypt_bytes_with_kdf, decrypt_with_kdf, encrypt_bytes_with_password, encrypt_with_password,
+    is_valid_url, parse_duration, remove_expired,
+};
+use crate::util::rate_limit;
+use crate::util::storage;
+use crate::util::store::PastaStore;
+use crate::util::syntaxhighlighter;
+use crate::AppState;
+use actix_web::{get, patch, post, put, web, HttpRequest, HttpResponse};
+use lazy_static::lazy_static;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Deserialize)]
+pub struct BulkDeleteFilter {
+    /// Only pastas created before this unix timestamp
+    created_before: Option<i64>,
+    /// Only pastas of this type: text, url or file
+    pasta_type: Option<String>,
+    /// Only pastas larger than this many bytes
+    size_over: Option<usize>,
+}
+
+impl BulkDeleteFilter {
+    fn is_empty(&self) -> bool {
+        self.created_before.is_none() && self.pasta_type.is_none() && self.size_over.is_none()
+    }
+
+    fn matches(&self, pasta: &Pasta) -> bool {
+        if let Some(created_before) = self.created_before {
+            if pasta.created >= created_before {
+                return false;
+            }
+        }
+        if let Some(pasta_type) = self.pasta_type.as_deref() {
+            let matches_type = match pasta_type {
+                "file" => pasta.has_file(),
+                other => !pasta.has_file() && pasta.pasta_type == other,
+            };
+            if !matches_type {
+                return false;
+            }
+        }
+        if let Some(size_over) = self.size_over {
+            if pasta.total_size_bytes() <= size_over {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BulkDeleteQuery {
+    #[serde(default)]
+    dry_run: u8,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct ListQuery {
+    /// At most 500, 50 when missing
+    limit: Option<usize>,
+    /// Pastas to skip
+    #[serde(default)]
+    offset: usize,
+    /// Only text pastas containing this
+    q: Option<String>,
+}
+
+/// Page size of the pasta list API when no `limit` is given
+const DEFAULT_LIST_LIMIT: usize = 50;
+/// Most pastas the list API returns at once
+const MAX_LIST_LIMIT: usize = 500;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetadataPatch {
+    /// A duration like `1hour` or `14d`, or `never`, counted from now
+    expiration: Option<String>,
+    /// Needed with `"expiration": "never"` when `confirm_eternal` is set
+    #[serde(default)]
+    confirm_never: bool,
+    /// An empty title removes it
+    title: Option<String>,
+    burn_after_reads: Option<u64>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct NewPaste {
+    content: String,
+    /// A duration like `1hour` or `14d`, or `never`, the instance default when missing
+    expiration: Option<String>,
+    /// Needed with `"expiration": "never"` when `confirm_eternal` is set
+    #[serde(default)]
+    confirm_never: bool,
+    burn_after: Option<u64>,
+    /// public, unlisted, readonly or private
+    privacy: Option<String>,
+    /// Required for readonly and private pastas
+    password: Option<String>,
+    syntax_highlight: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PasteEdit {
+    content: String,
+    /// Kept as it is when missing
+    syntax_highlight: Option<String>,
+    /// A duration like `1hour` or `14d`, or `never`, counted from now
+    expiration: Option<String>,
+    /// Needed with `"expiration": "never"` when `confirm_eternal` is set
+    #[serde(default)]
+    confirm_never: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PasteRekey {
+    old_password: String,
+    new_password: String,
+}
+
+/// Link to a pasta that was created or edited
+#[derive(Serialize, ToSchema)]
+pub struct PasteLink {
+    id: String,
+    url: String,
+    /// Unix timestamp, 0 for never
+    expiration: i64,
+}
+
+impl PasteLink {
+    fn new(pasta: &Pasta) -> Self {
+        let id = pasta.id_as_animals();
+        PasteLink {
+            url: format!("{}/upload/{}", ARGS.public_path_as_str(), id),
+            id,
+            expiration: pasta.expiration,
+        }
+    }
+}
+
+/// An attachment in `PasteInfo`
+#[derive(Serialize, ToSchema)]
+pub struct FileInfo {
+    /// Only for extra files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    size: u64,
+    /// Only for files stored unencrypted
+    sha256: Option<String>,
+}
+
+/// Status of a pasta, without its content
+#[derive(Serialize, ToSchema)]
+pub struct PasteInfo {
+    id: String,
+    created: i64,
+    created_string: String,
+    /// Unix timestamp, 0 for never
+    expiration: i64,
+    expiration_string: String,
+    read_count: u64,
+    burn_after_reads: u64,
+    download_limit: u64,
+    download_count: u64,
+    pasta_type: String,
+    encrypted: bool,
+    has_file: bool,
+    file: Option<FileInfo>,
+    extra_files: Vec<FileInfo>,
+    total_size: String,
+}
+
+/// A pasta in the public list
+#[derive(Serialize, ToSchema)]
+pub struct ListedPaste {
+    id: String,
+    created: i64,
+    /// Unix timestamp, 0 for never
+    expiration: i64,
+    /// Bytes of the content and all files
+    size: usize,
+    /// text, url or file
+    pasta_type: String,
+}
+
+/// Body of every error response of the API
+#[derive(Serialize, ToSchema)]
+pub struct ApiError {
+    error: String,
+}
+
+/// Why a pasta cannot be changed with `apply_edit`
+#[derive(Debug, PartialEq)]
+enum EditRefusal {
+    NotEditable,
+    Encrypted,
+    Unauthorized,
+    TooLarge,
+}
+
+/// Why the password of a pasta cannot be changed with `rekey_text`
+#[derive(Debug, PartialEq)]
+enum RekeyRefusal {
+    NotEncrypted,
+    ClientEncrypted,
+    WrongPassword,
+}
+
+/// Whether `expiration` is `never` on instances allowing it, or a duration like `14d`
+fn valid_expiration(expiration: &str) -> bool {
+    match expiration {
+        "never" => ARGS.eternal_pasta,
+        duration => parse_duration(duration).is_some(),
+    }
+}
+
+/// Same rule as the upload form, but with the uploader password sent as
+/// `Authorization: Bearer <password>` or in the `X-Microbin-Uploader` header
+/// instead of a form field
+fn is_uploader(req: &HttpRequest) -> bool {
+    if !ARGS.readonly || ARGS.uploader_password.is_none() {
+        return true;
+    }
+    let expected_password = ARGS.uploader_password.as_ref().unwrap().trim();
+    auth::secret_token(req, auth::UPLOADER_HEADER)
+        .is_some_and(|token| auth::constant_time_eq(&token, expected_password))
+        || req.cookie(&ARGS.uploader_cookie_name()).is_some_and(|c| {
+            auth::constant_time_eq(c.value(), &generate_uploader_token(expected_password))
+        })
+}
+
+fn pasta_id(slug: &str) -> u64 {
+    ids::to_u64(slug).unwrap_or(0)
+}
+
+fn json_error(mut builder: actix_web::HttpResponseBuilder, message: &str) -> HttpResponse {
+    builder.json(ApiError {
+        error: message.to_string(),
+    })
+}
+
+#[post("/api/admin/bulk-delete")]
+pub async fn bulk_delete(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    query: web::Query<BulkDeleteQuery>,
+    filter: web::Json<BulkDeleteFilter>,
+) -> HttpResponse {
+    // anyone could guess the default, and this deletes pastas by the thousand
+    if let Err(refused) = auth::check_admin(&req) {
+        return refused.response();
+    }
+
+    if filter.is_empty() {
+        return json_error(
+            HttpResponse::BadRequest(),
+            "At least one filter is required.",
+        );
+    }
+
+    if let Some(pasta_type) = filter.pasta_type.as_deref() {
+        if !matches!(pasta_type, "text" | "url" | "file") {
+            return json_error(
+                HttpResponse::BadRequest(),
+                "Invalid pasta_type, expected text, url or file.",
+            );
+        }
+    }
+
+    if query.dry_run != 0 {
+        let mut pastas = data.lock_pastas();
+        remove_expired(&mut pastas);
+        let matched = pastas.iter().filter(|p| filter.matches(p)).count();
+        return HttpResponse::Ok().json(json!({ "dry_run": true, "matched": matched }));
+    }
+
+    let count = admin::delete_matching(&data, |p| filter.matches(p))
+        .await
+        .len();
+
+    log::info!("Bulk deleted {} pastas", count);
+
+    HttpResponse::Ok().json(json!({ "dry_run": false, "deleted": count }))
+}
+
+/// Limits and upload options of this instance, for clients to adapt to
+#[get("/api/info")]
+pub async fn info() -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "max_file_size_encrypted_mb": ARGS.max_file_size_encrypted_mb,
+        "max_file_size_unencrypted_mb": ARGS.max_file_size_unencrypted_mb,
+        "max_text_field_size_kb": ARGS.max_text_field_size_kb,
+        "size_warning_percent": ARGS.size_warning_percent,
+        "file_upload": !ARGS.no_file_upload,
+        "eternal_pasta": ARGS.eternal_pasta,
+        "confirm_eternal": ARGS.confirm_eternal,
+        "default_expiry": ARGS.default_expiry,
+        "max_expiry": ARGS.max_expiry,
+    }))
+}
+
+/// Syntax highlighting themes, for clients to offer a selection
+#[get("/api/v1/themes")]
+pub async fn themes() -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "current": syntaxhighlighter::theme_name(),
+        "themes": syntaxhighlighter::theme_names(),
+    }))
+}
+
+/// Aggregate numbers about `pastas`, never anything about a single one
+fn stats_json(pastas: &[Pasta]) -> serde_json::Value {
+    let mut types: BTreeMap<&str, usize> = BTreeMap::new();
+    for pasta in pastas {
+        *types.entry(pasta.pasta_type.as_str()).or_default() += 1;
+    }
+
+    json!({
+        "total": pastas.len(),
+        "types": types,
+        "encrypted": pastas.iter().filter(|p| p.encrypt_server || p.encrypt_client).count(),
+        "with_files": pastas.iter().filter(|p| p.has_file()).count(),
+        "stored_bytes": pastas.iter().map(|p| p.stored_size_bytes() as u64).sum::<u64>(),
+        "oldest_created": pastas.iter().map(|p| p.created).min(),
+        "newest_created": pastas.iter().map(|p| p.created).max(),
+    })
+}
+
+/// Counts of the pastas on this instance, for status pages
+#[get("/api/v1/stats")]
+pub async fn stats(data: web::Data<AppState>) -> HttpResponse {
+    let mut pastas = data.lock_pastas();
+    remove_expired(&mut pastas);
+
+    HttpResponse::Ok().json(stats_json(&pastas))
+}
+
+/// Point remote attachments at `paths`, given in the order of `Pasta::files`
+fn set_remote_file_names(pasta: &mut Pasta, paths: &[String]) {
+    let files = pasta.file.iter_mut().chain(pasta.extra_files.iter_mut());
+    for (file, path) in files.zip(paths) {
+        if file.is_remote() {
+            file.name = path.clone();
+        }
+    }
+}
+
+/// Give a pasta a new random id, so links to the old one stop working
+#[post("/rotate-slug/{id}")]
+pub async fn rotate_slug(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+) -> HttpResponse {
+    if let Err(refused) = auth::check_admin(&req) {
+        return refused.response();
+    }
+
+    let old_id = pasta_id(&id);
+
+    // take the pasta out of the collection while its files are moved
+    let (mut pasta, new_id) = {
+        let mut pastas = data.lock_pastas();
+
+        remove_expired(&mut pastas);
+
+        let index = match pastas.position(old_id) {
+            Some(index) => index,
+            None => return json_error(HttpResponse::NotFound(), "Pasta not found."),
+        };
+
+        let mut new_id = rand::thread_rng().gen::<u16>() as u64;
+        while new_id == old_id || pastas.position(new_id).is_some() {
+            new_id = rand::thread_rng().gen::<u16>() as u64;
+        }
+
+        (pastas.remove(index), new_id)
+    };
+
+    let old_slug = pasta.id_as_animals();
+    let old_storage_paths = pasta.file_storage_paths();
+    // a rotated pasta must not be reachable under its custom slug anymore
+    let custom_slug = pasta.custom_slug.take();
+    pasta.id = new_id;
+    let new_slug = pasta.id_as_animals();
+
+    // local attachments share a directory and move together, remote objects
+    // are copied one by one
+    let paths_to_move: Vec<&String> = match old_storage_paths.first() {
+        Some(first) if storage::is_remote(first) => old_storage_paths.iter().collect(),
+        Some(first) => vec![first],
+        None => Vec::new(),
+    };
+
+    let mut new_storage_paths: Vec<String> = Vec::new();
+    for storage_path in paths_to_move {
+        match storage::move_file(&old_slug, &new_slug, storage_path).await {
+            Ok(path) => new_storage_paths.push(path),
+            Err(e) => {
+                log::error!("Failed to move files of pasta {}: {}", old_slug, e);
+                for copied in &new_storage_paths {
+                    if let Err(e) = storage::delete_file(&new_slug, copied).await {
+                        log::error!("Failed to delete file {}: {}", copied, e);
+                    }
+                }
+                pasta.id = old_id;
+                pasta.custom_slug = custom_slug;
+                data.lock_pastas().push(pasta);
+                return json_error(HttpResponse::InternalServerError(), "Failed to move files.");
+            }
+        }
+    }
+
+    // non-encrypted remote files are referenced by their full path
+    set_remote_file_names(&mut pasta, &new_storage_paths);
+
+    let persisted = {
+        let mut pastas = data.lock_pastas();
+        pastas.push(pasta.clone());
+        match insert(Some(&pastas), Some(&pasta)) {
+            Ok(()) => {
+                delete(Some(&pastas), Some(old_id));
+                true
+            }
+            Err(e) => {
+                log::error!("Failed to persist rotated pasta {}: {}", new_slug, e);
+                pastas.retain(|p| p.id != new_id);
+                false
+            }
+        }
+    };
+
+    if !persisted {
+        // put the files back where the old pasta expects them
+        for (old_path, new_path) in old_storage_paths.iter().zip(&new_storage_paths) {
+            let restored = if storage::is_remote(new_path) {
+                storage::delete_file(&new_slug, new_path).await
+            } else {
+                storage::move_file(&new_slug, &old_slug, new_path)
+                    .await
+                    .map(|_| ())
+            };
+            if let Err(e) = restored {
+                log::error!(
+                    "Failed to restore files of pasta {}: {} ({})",
+                    old_slug,
+                    e,
+                    old_path
+                );
+            }
+        }
+        set_remote_file_names(&mut pasta, &old_storage_paths);
+        pasta.id = old_id;
+        pasta.custom_slug = custom_slug;
+        data.lock_pastas().push(pasta);
+        return json_error(HttpResponse::InternalServerError(), "Failed to save pasta.");
+    }
+
+    // the copied remote objects are in use now, the old ones can go
+    for (old_path, new_path) in old_storage_paths.iter().zip(&new_storage_paths) {
+        if old_path != new_path && storage::is_remote(old_path) {
+            if let Err(e) = storage::delete_file(&old_slug, old_path).await {
+                log::error!("Failed to delete file {}: {}", old_path, e);
+            }
+        }
+    }
+
+    expired::record(old_id, ExpiryReason::Rotated);
+    log::info!("Rotated pasta {} to {}", old_slug, new_slug);
+
+    HttpResponse::Ok().json(json!({
+        "id": new_slug,
+        "url": format!("{}/upload/{}", ARGS.public_path_as_str(), new_slug),
+    }))
+}
+
+fn metadata_json(pasta: &Pasta) -> serde_json::Value {
+    json!({
+        "id": pasta.id_as_animals(),
+        "title": pasta.title,
+        "created": pasta.created,
+        "expiration": pasta.expiration,
+        "burn_after_reads": pasta.burn_after_reads,
+        "read_count": pasta.read_count,
+    })
+}
+
+/// Apply the fields present in `patch`, with the same limits on the lifetime
+/// of a pasta as when creating it
+fn apply_metadata(
+    pasta: &mut Pasta,
+    patch: &MetadataPatch,
+    timenow: i64,
+    force_expiry: bool,
+) -> Result<(), &'static str> {
+    let expiration = patch
+        .expiration
+        .as_deref()
+        .map_or(pasta.expiration, |expiration| {
+            expiration_to_timestamp(expiration, timenow)
+        });
+    let burn_after_reads = patch
+        .burn_after_reads
+        .map_or(pasta.burn_after_reads, |reads| {
+            clamp_reads(reads, ARGS.max_burn_after)
+        });
+    check_bounded_lifetime(expiration, burn_after_reads, pasta.one_time, force_expiry)?;
+
+    pasta.expiration = expiration;
+    pasta.burn_after_reads = burn_after_reads;
+    if let Some(title) = patch.title.as_deref() {
+        pasta.title = Pasta::clean_title(title);
+    }
+    Ok(())
+}
+
+/// Change the expiration, title or read limit of a pasta, leaving its
+/// content alone. Needs the admin password or the owner token of the pasta.
+#[patch("/api/v1/pastas/{id}")]
+pub async fn patch_metadata(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    patch: web::Json<MetadataPatch>,
+) -> HttpResponse {
+    let id = pasta_id(&id);
+
+    if !auth::is_owner(&req, id) {
+        if let Err(refused) = auth::check_admin(&req) {
+            return refused.response();
+        }
+    }
+
+    if let Some(expiration) = patch.expiration.as_deref() {
+        if !valid_expiration(expiration) {
+            return json_error(HttpResponse::BadRequest(), "Invalid expiration.");
+        }
+        if expiration == "never" && ARGS.confirm_eternal && !patch.confirm_never {
+            return json_error(
+                HttpResponse::BadRequest(),
+                "Pastas that never expire must be confirmed with confirm_never.",
+            );
+        }
+    }
+
+    let mut pastas = data.lock_pastas();
+
+    remove_expired(&mut pastas);
+
+    let index = match pastas.position(id) {
+        Some(index) => index,
+        None => return json_error(HttpResponse::NotFound(), "Pasta not found."),
+    };
+
+    let timenow = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    if let Err(message) = apply_metadata(&mut pastas[index], &patch, timenow, ARGS.force_expiry) {
+        return json_error(HttpResponse::BadRequest(), message);
+    }
+
+    update(Some(&pastas), Some(&pastas[index]));
+
+    HttpResponse::Ok().json(metadata_json(&pastas[index]))
+}
+
+/// Replace the content of an editable pasta, and its extension and
+/// expiration when given. Read-only and private pastas need the request to
+/// be `authorized`, encrypted ones cannot be changed without their password.
+fn apply_edit(
+    pasta: &mut Pasta,
+    content: String,
+    extension: Option<String>,
+    expiration: Option<i64>,
+    authorized: bool,
+) -> Result<(), EditRefusal> {
+    if !pasta.editable {
+        return Err(EditRefusal::NotEditable);
+    }
+    if pasta.encrypt_server || pasta.encrypt_client {
+        return Err(EditRefusal::Encrypted);
+    }
+    if (pasta.readonly || pasta.private) && !authorized {
+        return Err(EditRefusal::Unauthorized);
+    }
+
+    if let Some(extension) = extension {
+        pasta.extension = extension;
+    }
+    if let Some(expiration) = expiration {
+        pasta.expiration = expiration;
+    }
+    pasta.pasta_type = if is_valid_url(&content) {
+        String::from("url")
+    } else {
+        String::from("text")
+    };
+    pasta.title = Pasta::extract_title(&content, &pasta.extension);
+    pasta.content = content;
+    Ok(())
+}
+
+/// Add `addition` to the end of the content of an editable pasta, with the
+/// same rules as `apply_edit`, as long as the content stays within `max_bytes`
+fn apply_append(
+    pasta: &mut Pasta,
+    addition: &str,
+    max_bytes: usize,
+    authorized: bool,
+) -> Result<(), EditRefusal> {
+    let content = pasta.text().into_owned() + addition;
+    if pasta.editable && content.len() > max_bytes {
+        return Err(EditRefusal::TooLarge);
+    }
+    apply_edit(pasta, content, None, None, authorized)
+}
+
+/// Append the request body to an editable pasta, for pastas used as a log
+#[post("/api/v1/paste/{id}/append")]
+pub async fn append_paste(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    body: String,
+) -> HttpResponse {
+    if body.is_empty() {
+        return json_error(HttpResponse::BadRequest(), "Content is required.");
+    }
+    if let Some(pattern) = content_filter::blocked_pattern(&body) {
+        log::warn!(
+            "Rejected append from {} matching blocked content pattern {}",
+            rate_limit::client_ip(&req),
+            pattern
+        );
+        return json_error(HttpResponse::BadRequest(), "Append rejected.");
+    }
+
+    let id = pasta_id(&id);
+
+    let mut pastas = data.lock_pastas();
+
+    remove_expired(&mut pastas);
+
+    let index = match pastas.position(id) {
+        Some(index) => index,
+        None => return json_error(HttpResponse::NotFound(), "Pasta not found."),
+    };
+
+    if check_storage_quota(&pastas, body.len() as u64, ARGS.max_total_storage_mb).is_err() {
+        return json_error(
+            HttpResponse::PayloadTooLarge(),
+            "The storage quota of this instance is used up.",
+        );
+    }
+
+    // changed on a copy, so a refused append leaves the pasta alone
+    let mut pasta = pastas[index].clone();
+    let authorized = auth::is_admin(&req) || auth::is_owner(&req, id);
+    let appended = apply_append(
+        &mut pasta,
+        &body,
+        ARGS.max_file_size_unencrypted_mb * 1024 * 1024,
+        authorized,
+    );
+    match appended {
+        Ok(()) => {}
+        Err(EditRefusal::NotEditable) => {
+            return json_error(HttpResponse::Forbidden(), "Pasta is not editable.")
+        }
+        Err(EditRefusal::Encrypted) => {
+            return json_error(
+                HttpResponse::Forbidden(),
+                "Encrypted pastas cannot be appended to.",
+            )
+        }
+        Err(EditRefusal::Unauthorized) => {
+            return json_error(
+                HttpResponse::Unauthorized(),
+                "Invalid admin password or owner token.",
+            )
+        }
+        Err(EditRefusal::TooLarge) => {
+            return json_error(
+                HttpResponse::PayloadTooLarge(),
+                "Pasta exceeded size limit.",
+            )
+        }
+    }
+    if pasta.pasta_type == "url" && !content_filter::url_allowed(&pasta.content) {
+        return json_error(
+            HttpResponse::BadRequest(),
+            "Links to this domain are not allowed.",
+        );
+    }
+
+    pasta.last_read = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    pasta.compress_content(ARGS.compress_min_bytes);
+    pastas[index] = pasta;
+    update(Some(&pastas), Some(&pastas[index]));
+
+    let pasta = &pastas[index];
+    HttpResponse::Ok().json(json!({
+        "id": pasta.id_as_animals(),
+        "url": format!("{}/upload/{}", ARGS.public_path_as_str(), pasta.id_as_animals()),
+        "size": pasta.total_size_bytes(),
+    }))
+}
+
+/// Replace the content of an editable pasta under the same id
+#[utoipa::path(
+    params(("id" = String, Path, description = "Id of the pasta")),
+    request_body = PasteEdit,
+    responses(
+        (status = 200, description = "The pasta was edited", body = PasteLink),
+        (status = 400, description = "Invalid or rejected content", body = ApiError),
+        (status = 401, description = "Invalid admin password or owner token", body = ApiError),
+        (status = 403, description = "The pasta is not editable", body = ApiError),
+        (status = 404, description = "No such pasta", body = ApiError),
+        (status = 413, description = "Too large or over the storage quota", body = ApiError),
+    ),
+    security((), ("bearer" = []), ("admin_header" = []), ("owner_token" = []))
+)]
+#[put("/api/v1/paste/{id}")]
+pub async fn edit_paste(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    edit: web::Json<PasteEdit>,
+) -> HttpResponse {
+    let edit = edit.into_inner();
+
+    if edit.content.is_empty() {
+        return json_error(HttpResponse::BadRequest(), "Content is required.");
+    }
+    if edit.content.len() > ARGS.max_text_field_size_bytes() {
+        return json_error(HttpResponse::PayloadTooLarge(), "Text exceeded size limit.");
+    }
+    if let Some(expiration) = edit.expiration.as_deref() {
+        if !valid_expiration(expiration) {
+            return json_error(HttpResponse::BadRequest(), "Invalid expiration.");
+        }
+        if expiration == "never" && ARGS.confirm_eternal && !edit.confirm_never {
+            return json_error(
+                HttpResponse::BadRequest(),
+                "Pastas that never expire must be confirmed with confirm_never.",
+            );
+        }
+    }
+    if let Some(pattern) = content_filter::blocked_pattern(&edit.content) {
+        log::warn!(
+            "Rejected edit from {} matching blocked content pattern {}",
+            rate_limit::client_ip(&req),
+            pattern
+        );
+        return json_error(HttpResponse::BadRequest(), "Edit rejected.");
+    }
+    if is_valid_url(&edit.content) && !content_filter::url_allowed(&edit.content) {
+        return json_error(
+            HttpResponse::BadRequest(),
+            "Links to this domain are not allowed.",
+        );
+    }
+
+    let id = pasta_id(&id);
+
+    let mut pastas = data.lock_pastas();
+
+    remove_expired(&mut pastas);
+
+    let index = match pastas.position(id) {
+        Some(index) => index,
+        None => return json_error(HttpResponse::NotFound(), "Pasta not found."),
+    };
+
+    let growth = edit
+        .content
+        .len()
+        .saturating_sub(pastas[index].content.len()) as u64;
+    if check_storage_quota(&pastas, growth, ARGS.max_total_storage_mb).is_err() {
+        return json_error(
+            HttpResponse::PayloadTooLarge(),
+            "The storage quota of this instance is used up.",
+        );
+    }
+
+    let timenow = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let expiration = edit
+        .expiration
+        .as_deref()
+        .map(|expiration| expiration_to_timestamp(expiration, timenow));
+    let authorized = auth::is_admin(&req) || auth::is_owner(&req, id);
+
+    let edited = apply_edit(
+        &mut pastas[index],
+        edit.content,
+        edit.syntax_highlight,
+        expiration,
+        authorized,
+    );
+    match edited {
+        Ok(()) => {}
+        Err(EditRefusal::NotEditable) => {
+            return json_error(HttpResponse::Forbidden(), "Pasta is not editable.")
+        }
+        Err(EditRefusal::Encrypted) => {
+            return json_error(
+                HttpResponse::Forbidden(),
+                "Encrypted pastas cannot be edited through the API.",
+            )
+        }
+        Err(EditRefusal::Unauthorized) => {
+            return json_error(
+                HttpResponse::Unauthorized(),
+                "Invalid admin password or owner token.",
+            )
+        }
+        Err(EditRefusal::TooLarge) => {
+            return json_error(HttpResponse::PayloadTooLarge(), "Text exceeded size limit.")
+        }
+    }
+
+    pastas[index].compress_content(ARGS.compress_min_bytes);
+    update(Some(&pastas), Some(&pastas[index]));
+
+    HttpResponse::Ok().json(PasteLink::new(&pastas[index]))
+}
+
+/// Encrypt the content and key of `pasta` with `new_password` instead of
+/// `old_password`. An attached file is left to the caller, so is checking the
+/// old password of file pastas without content.
+fn rekey_text(
+    pasta: &mut Pasta,
+    old_password: &str,
+    new_password: &str,
+) -> Result<(), RekeyRefusal> {
+    if pasta.encrypt_client {
+        return Err(RekeyRefusal::ClientEncrypted);
+    }
+    // pastas made without a password have an empty key
+    let encrypted_key = pasta.encrypted_key.as_deref().filter(|key| !key.is_empty());
+    if !pasta.encrypt_server && encrypted_key.is_none() {
+        return Err(RekeyRefusal::NotEncrypted);
+    }
+
+    // read-only pastas keep their id encrypted to check edits against
+    if let Some(encrypted_key) = encrypted_key {
+        match decrypt_with_kdf(encrypted_key, old_password) {
+            Ok(key) if key == pasta.id.to_string() => {}
+            _ => return Err(RekeyRefusal::WrongPassword),
+        }
+    }
+    let content = if pasta.encrypt_server && !pasta.content.is_empty() {
+        let content = decrypt_with_kdf(&pasta.content, old_password)
+            .map_err(|_| RekeyRefusal::WrongPassword)?;
+        Some(content)
+    } else {
+        None
+    };
+
+    if encrypted_key.is_some() {
+        pasta.encrypted_key = Some(encrypt_with_password(&pasta.id.to_string(), new_password));
+    }
+    if let Some(content) = content {
+        pasta.content = encrypt_with_password(&content, new_password);
+    }
+    Ok(())
+}
+
+lazy_static! {
+    /// Pastas whose password is being changed, see `RekeyGuard`
+    static ref REKEYING: Mutex<HashSet<u64>> = Mutex::new(HashSet::new());
+}
+
+/// Marks a pasta as having its password changed until dropped, so that two
+/// rekeys of it cannot leave its content and file under different passwords
+struct RekeyGuard(u64);
+
+impl RekeyGuard {
+    fn acquire(id: u64) -> Option<RekeyGuard> {
+        let inserted = REKEYING
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id);
+        inserted.then(|| RekeyGuard(id))
+    }
+}
+
+impl Drop for RekeyGuard {
+    fn drop(&mut self) {
+        REKEYING
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.0);
+    }
+}
+
+/// Change the password of a pasta, keeping its id and link. The content and
+/// an encrypted file are decrypted with the old password and encrypted again
+/// with the new one. Needs the owner token of the pasta or the admin
+/// password, and wrong old passwords count towards the login lockout.
+#[post("/api/v1/paste/{id}/rekey")]
+pub async fn rekey_paste(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    rekey: web::Json<PasteRekey>,
+) -> HttpResponse {
+    if let Some(response) = rate_limit::limit(&req, &data, "rekey") {
+        return response;
+    }
+
+    let id = pasta_id(&id);
+
+    if !auth::is_owner(&req, id) {
+        if let Err(refused) = auth::check_admin(&req) {
+            return refused.response();
+        }
+    }
+
+    // wrong old passwords are counted apart from uploader logins
+    let client = format!("rekey {}", rate_limit::client_ip(&req));
+    if ARGS.login_max_failures != 0 {
+        if let Some(retry_after) = data.failed_logins.locked_out(&client, Instant::now()) {
+            log::warn!("Rekey from {} refused, locked out", client);
+            return rate_limit::too_many_requests(retry_after);
+        }
+    }
+
+    let rekey = rekey.into_inner();
+
+    if rekey.new_password.is_empty() {
+        return json_error(HttpResponse::BadRequest(), "A new password is required.");
+    }
+    if let Err(message) = check_password_strength(&rekey.new_password, ARGS.min_password_length) {
+        return json_error(HttpResponse::BadRequest(), &message);
+    }
+
+    let _guard = match RekeyGuard::acquire(id) {
+        Some(guard) => guard,
+        None => {
+            return json_error(
+                HttpResponse::Conflict(),
+                "The password of this pasta is being changed already.",
+            )
+        }
+    };
+
+    let wrong_password = || {
+        metrics::record_decrypt_failure();
+        if ARGS.login_max_failures != 0 {
+            data.failed_logins
+                .failed(&client, ARGS.login_max_failures, Instant::now());
+        }
+        json_error(HttpResponse::Unauthorized(), "Incorrect password.")
+    };
+
+    // work on a copy, the pasta only changes once its file is encrypted again
+    let mut pasta = {
+        let mut pastas = data.lock_pastas();
+        remove_expired(&mut pastas);
+        match pastas.position(id) {
+            Some(index) => pastas[index].clone(),
+            None => return json_error(HttpResponse::NotFound(), "Pasta not found."),
+        }
+    };
+
+    match rekey_text(&mut pasta, &rekey.old_password, &rekey.new_password) {
+        Ok(()) => {}
+        Err(RekeyRefusal::NotEncrypted) => {
+            return json_error(HttpResponse::BadRequest(), "Pasta has no password.")
+        }
+        Err(RekeyRefusal::ClientEncrypted) => {
+            return json_error(
+                HttpResponse::BadRequest(),
+                "Pastas encrypted in the browser cannot be rekeyed on the server.",
+            )
+        }
+        Err(RekeyRefusal::WrongPassword) => return wrong_password(),
+    }
+
+    let slug = pasta.id_as_animals();
+
+    // only server side encrypted pastas have their file stored as data.enc,
+    // kept as it was until the new password is saved
+    let mut previous_file: Option<(String, Vec<u8>)> = None;
+    if let Some(storage_path) = pasta.file_storage_path().filter(|_| pasta.encrypt_server) {
+        let encrypted = match storage::get_file(&slug, &storage_path).await {
+            Ok(encrypted) => encrypted,
+            Err(e) => {
+                log::error!("Failed to read file {} of {}: {}", storage_path, slug, e);
+                return json_error(HttpResponse::InternalServerError(), "Failed to read file.");
+            }
+        };
+        let decrypted = match decrypt_bytes_with_kdf(&encrypted, &rekey.old_password) {
+            Ok(decrypted) => decrypted,
+            Err(_) => return wrong_password(),
+        };
+        let rekeyed = encrypt_bytes_with_password(&decrypted, &rekey.new_password);
+        if let Err(e) = storage::save_file(&slug, &storage_path, &rekeyed).await {
+            log::error!("Failed to save file {} of {}: {}", storage_path, slug, e);
+            return json_error(HttpResponse::InternalServerError(), "Failed to save file.");
+        }
+        previous_file = Some((storage_path, encrypted));
+    }
+
+    let saved = {
+        let mut pastas = data.lock_pastas();
+        match pastas.position(id) {
+            Some(index) => {
+                let previous = (
+                    std::mem::replace(&mut pastas[index].content, pasta.content),
+                    std::mem::replace(&mut pastas[index].encrypted_key, pasta.encrypted_key),
+                );
+                let saved = try_update(Some(&pastas), Some(&pastas[index]));
+                if saved.is_err() {
+                    (pastas[index].content, pastas[index].encrypted_key) = previous;
+                }
+                saved.map_err(|e| (HttpResponse::InternalServerError(), e))
+            }
+            None => Err((HttpResponse::NotFound(), String::from("Pasta not found."))),
+        }
+    };
+
+    if let Err((builder, error)) = saved {
+        log::error!("Failed to change the password of pasta {}: {}", slug, error);
+        // put the file back under the old password
+        if let Some((storage_path, encrypted)) = previous_file {
+            if let Err(e) = storage::save_file(&slug, &storage_path, &encrypted).await {
+                log::error!("Failed to restore file {} of {}: {}", storage_path, slug, e);
+            }
+        }
+        return json_error(builder, "Failed to change the password.");
+    }
+
+    if ARGS.login_max_failures != 0 {
+        data.failed_logins.succeeded(&client);
+    }
+    log::info!("Changed the password of pasta {}", slug);
+
+    HttpResponse::Ok().json(json!({
+        "id": slug,
+        "url": format!("{}/upload/{}", ARGS.public_path_as_str(), slug),
+    }))
+}
+
+/// Create a pasta from JSON, for clients that do not want to deal with the upload form
+#[utoipa::path(
+    request_body = NewPaste,
+    responses(
+        (status = 201, description = "The pasta was created", body = PasteLink),
+        (status = 400, description = "Invalid or rejected pasta", body = ApiError),
+        (status = 401, description = "Invalid uploader password", body = ApiError),
+        (status = 413, description = "Too large or over the storage quota", body = ApiError),
+        (status = 429, description = "Too many requests"),
+    ),
+    security((), ("bearer" = []), ("uploader_header" = []))
+)]
+#[post("/api/v1/paste")]
+pub async fn create_paste(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    paste: web::Json<NewPaste>,
+) -> HttpResponse {
+    if let Some(response) = rate_limit::limit(&req, &data, "create") {
+        return response;
+    }
+
+    if !is_uploader(&req) {
+        return json_error(HttpResponse::Unauthorized(), "Invalid uploader password.");
+    }
+
+    let paste = paste.into_inner();
+
+    if paste.content.is_empty() {
+        return json_error(HttpResponse::BadRequest(), "Content is required.");
+    }
+    if paste.content.len() > ARGS.max_text_field_size_bytes() {
+        return json_error(HttpResponse::PayloadTooLarge(), "Text exceeded size limit.");
+    }
+
+    let expiration = paste.expiration.as_deref().unwrap_or(&ARGS.default_expiry);
+    if !valid_expiration(expiration) {
+        return json_error(HttpResponse::BadRequest(), "Invalid expiration.");
+    }
+    if expiration == "never" && ARGS.confirm_eternal && !paste.confirm_never {
+        return json_error(
+            HttpResponse::BadRequest(),
+            "Pastas that never expire must be confirmed with confirm_never.",
+        );
+    }
+
+    let burn_after = clamp_reads(paste.burn_after.unwrap_or(0), ARGS.max_burn_after);
+
+    let preset = match paste.privacy.as_deref().unwrap_or(&ARGS.default_privacy) {
+        "public" => PrivacyPreset::Public,
+        "unlisted" if ARGS.private => PrivacyPreset::Unlisted,
+        "readonly" if ARGS.enable_readonly => PrivacyPreset::Readonly,
+        "private" if ARGS.encryption_server_side => PrivacyPreset::Private,
+        _ => {
+            return json_error(
+                HttpResponse::BadRequest(),
+                "Invalid privacy, expected one of the options enabled on this instance.",
+            )
+        }
+    };
+    if !ARGS.privacy_mode_enabled(preset.as_str()) {
+        return json_error(
+            HttpResponse::BadRequest(),
+            "Invalid privacy, expected one of the options enabled on this instance.",
+        );
+    }
+    let password = paste.password.unwrap_or_default();
+    if matches!(preset, PrivacyPreset::Readonly | PrivacyPreset::Private) && password.is_empty() {
+        return json_error(
+            HttpResponse::BadRequest(),
+            "A password is required for readonly and private pastas.",
+        );
+    }
+    if preset == PrivacyPreset::Private {
+        if let Err(message) = check_password_strength(&password, ARGS.min_password_length) {
+            return json_error(HttpResponse::BadRequest(), &message);
+        }
+    }
+
+    if let Some(pattern) = content_filter::blocked_pattern(&paste.content) {
+        log::warn!(
+            "Rejected upload from {} matching blocked content pattern {}",
+            rate_limit::client_ip(&req),
+            pattern
+        );
+        return json_error(HttpResponse::BadRequest(), "Upload rejected.");
+    }
+    if is_valid_url(&paste.content) && !content_filter::url_allowed(&paste.content) {
+        return json_error(
+            HttpResponse::BadRequest(),
+            "Links to this domain are not allowed.",
+        );
+    }
+
+    {
+        let mut pastas = data.lock_pastas();
+        remove_expired(&mut pastas);
+        let size = paste.content.len() as u64;
+        if check_storage_quota(&pastas, size, ARGS.max_total_storage_mb).is_err() {
+            return json_error(
+                HttpResponse::PayloadTooLarge(),
+                "The storage quota of this instance is used up.",
+            );
+        }
+    }
+
+    let timenow: i64 = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(_) => {
+            log::error!("SystemTime before UNIX EPOCH!");
+            0
+        }
+    } as i64;
+
+    let mut pasta = new_pasta(timenow);
+    pasta.expiration = expiration_to_timestamp(expiration, timenow);
+    pasta.burn_after_reads = burn_after;
+    if let Err(message) =
+        check_bounded_lifetime(pasta.expiration, burn_after, false, ARGS.force_expiry)
+    {
+        return json_error(HttpResponse::BadRequest(), message);
+    }
+    pasta.apply_privacy(preset);
+    pasta.extension = paste.syntax_highlight.unwrap_or_default();
+    pasta.pasta_type = if is_valid_url(&paste.content) {
+        String::from("url")
+    } else {
+        String::from("text")
+    };
+    pasta.content = paste.content;
+    detect_extension(&mut pasta);
+    pasta.title = Pasta::extract_title(&pasta.content, &pasta.extension);
+
+    if pasta.readonly {
+        pasta.encrypted_key = Some(encrypt_with_password(&pasta.id.to_string(), &password));
+    }
+    if pasta.encrypt_server && !pasta.readonly {
+        pasta.content = encrypt_with_password(&pasta.content, &password);
+    }
+    pasta.compress_content(ARGS.compress_min_bytes);
+
+    let slug = pasta.id_as_animals();
+    let link = PasteLink::new(&pasta);
+
+    if let Err(error) = persist_pasta(&data, pasta, &[]).await {
+        log::error!("Failed to persist pasta {}: {}", slug, error);
+        return json_error(HttpResponse::InternalServerError(), "Failed to save upload.");
+    }
+
+    HttpResponse::Created().json(link)
+}
+
+/// Status of a pasta for scripts. Never contains content or keys, and the
+/// file digest only for files stored unencrypted.
+#[utoipa::path(
+    params(("id" = String, Path, description = "Id of the pasta")),
+    responses(
+        (status = 200, description = "Status of the pasta", body = PasteInfo),
+        (status = 404, description = "No such pasta", body = ApiError),
+    )
+)]
+#[get("/api/v1/paste/{id}/info")]
+pub async fn paste_info(data: web::Data<AppState>, id: web::Path<String>) -> HttpResponse {
+    let id = pasta_id(&id);
+
+    let mut pastas = data.lock_pastas();
+
+    remove_expired(&mut pastas);
+
+    let pasta = match pastas.get(id) {
+        Some(pasta) => pasta,
+        None => return json_error(HttpResponse::NotFound(), "Pasta not found."),
+    };
+
+    let encrypted = pasta.encrypt_server || pasta.encrypt_client;
+    let file_info = |file: &PastaFile| FileInfo {
+        name: None,
+        size: file.size.as_u64(),
+        sha256: file.sha256.clone().filter(|_| !encrypted),
+    };
+    let extra_files = pasta
+        .extra_files
+        .iter()
+        .map(|extra| FileInfo {
+            name: Some(extra.display_name().to_string()),
+            ..file_info(extra)
+        })
+        .collect();
+
+    HttpResponse::Ok().json(PasteInfo {
+        id: pasta.id_as_animals(),
+        created: pasta.created,
+        created_string: pasta.created_as_string(),
+        expiration: pasta.expiration,
+        expiration_string: pasta.expiration_as_string(),
+        read_count: pasta.read_count,
+        burn_after_reads: pasta.burn_after_reads,
+        download_limit: pasta.download_limit,
+        download_count: pasta.download_count,
+        pasta_type: pasta.pasta_type.clone(),
+        encrypted,
+        has_file: pasta.has_file(),
+        file: pasta.file.as_ref().map(file_info),
+        extra_files,
+        total_size: pasta.total_size_as_string(),
+    })
+}
+
+/// Public pastas, newest first, for dashboards. Private, unlisted and
+/// encrypted pastas are never included, and content only ever searched.
+#[utoipa::path(
+    params(ListQuery),
+    responses(
+        (status = 200, description = "Public pastas, newest first", body = [ListedPaste]),
+        (status = 404, description = "Listing is disabled", body = ApiError),
+    )
+)]
+#[get("/api/v1/pastas")]
+pub async fn list_pastas(data: web::Data<AppState>, query: web::Query<ListQuery>) -> HttpResponse {
+    if ARGS.no_listing {
+        return json_error(HttpResponse::NotFound(), "Listing is disabled.");
+    }
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .min(MAX_LIST_LIMIT);
+    let search = query.q.as_deref().filter(|q| !q.is_empty());
+
+    let mut pastas = data.lock_pastas();
+
+    remove_expired(&mut pastas);
+
+    let mut listed: Vec<&Pasta> = pastas
+        .iter()
+        .filter(|p| p.is_listed() && !p.encrypt_server && !p.encrypt_client)
+        .filter(|p| {
+            search.map_or(true, |q| {
+                !p.has_file() && p.pasta_type == "text" && p.text().contains(q)
+            })
+        })
+        .collect();
+    listed.sort_by_key(|p| Reverse(p.created));
+
+    let list: Vec<ListedPaste> = listed
+        .into_iter()
+        .skip(query.offset)
+        .take(limit)
+        .map(|pasta| ListedPaste {
+            id: pasta.id_as_animals(),
+            created: pasta.created,
+            expiration: pasta.expiration,
+            size: pasta.total_size_bytes(),
+            pasta_type: if pasta.has_file() {
+                String::from("file")
+            } else {
+                pasta.pasta_type.clone()
+            },
+        })
+        .collect();
+
+    HttpResponse::Ok().json(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::create::new_pasta;
+    use crate::util::rate_limit::{FailedLogins, RateLimiter};
+    use crate::util::store::MemoryStore;
+    use actix_web::http::header;
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::App;
+    use base64::prelude::{Engine, BASE64_STANDARD};
+    use bytesize::ByteSize;
+    use std::sync::Mutex;
+
+    fn pasta(editable: bool) -> Pasta {
+        Pasta {
+            id: 1,
+            content: String::from("old content"),
+            extension: String::from("txt"),
+            editable,
+            encrypted_key: None,
+            expiration: 100,
+            pasta_type: String::from("text"),
+            ..new_pasta(0)
+        }
+    }
+
+    #[test]
+    fn test_apply_edit() {
+        let mut editable = pasta(true);
+        apply_edit(
+            &mut editable,
+            String::from("# Notes\n\nnew content"),
+            Some(String::from("md")),
+            Some(200),
+            false,
+        )
+        .unwrap();
+        assert_eq!(editable.id, 1);
+        assert_eq!(editable.content, "# Notes\n\nnew content");
+        assert_eq!(editable.extension, "md");
+        assert_eq!(editable.expiration, 200);
+        assert_eq!(editable.pasta_type, "text");
+
+        // a URL turns the pasta into a redirect, the rest is kept
+        apply_edit(
+            &mut editable,
+            String::from("https://example.com"),
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(editable.pasta_type, "url");
+        assert_eq!(editable.extension, "md");
+        assert_eq!(editable.expiration, 200);
+
+        // read-only pastas need the admin password or owner token
+        editable.readonly = true;
+        assert_eq!(
+            apply_edit(&mut editable, String::from("x"), None, None, false),
+            Err(EditRefusal::Unauthorized)
+        );
+        assert!(apply_edit(&mut editable, String::from("x"), None, None, true).is_ok());
+        assert_eq!(editable.content, "x");
+    }
+
+    #[test]
+    fn test_apply_edit_refuses_immutable_pastas() {
+        let mut immutable = pasta(false);
+        assert_eq!(
+            apply_edit(&mut immutable, String::from("new"), None, Some(200), true),
+            Err(EditRefusal::NotEditable)
+        );
+        assert_eq!(immutable.content, "old content");
+        assert_eq!(immutable.expiration, 100);
+
+        let mut encrypted = pasta(true);
+        encrypted.encrypt_server = true;
+        assert_eq!(
+            apply_edit(&mut encrypted, String::from("new"), None, None, true),
+            Err(EditRefusal::Encrypted)
+        );
+        assert_eq!(encrypted.content, "old content");
+    }
+
+    #[test]
+    fn test_apply_append() {
+        let mut log = pasta(true);
+        apply_append(&mut log, "\nline 2", 100, false).unwrap();
+        apply_append(&mut log, "\nline 3", 100, false).unwrap();
+        assert_eq!(log.id, 1);
+        assert_eq!(log.content, "old content\nline 2\nline 3");
+        assert_eq!(log.pasta_type, "text");
+
+        // the whole content counts against the limit
+        assert_eq!(
+            apply_append(&mut log, "\nline 4", 30, false),
+            Err(EditRefusal::TooLarge)
+        );
+        assert_eq!(log.content, "old content\nline 2\nline 3");
+        assert!(apply_append(&mut log, "\n4", 30, false).is_ok());
+
+        let mut immutable = pasta(false);
+        assert_eq!(
+            apply_append(&mut immutable, "more", 100, true),
+            Err(EditRefusal::NotEditable)
+        );
+        let mut encrypted = pasta(true);
+        encrypted.encrypt_server = true;
+        assert_eq!(
+            apply_append(&mut encrypted, "more", 100, true),
+            Err(EditRefusal::Encrypted)
+        );
+        assert_eq!(immutable.content, "old content");
+        assert_eq!(encrypted.content, "old content");
+    }
+
+    #[actix_web::test]
+    async fn test_rekey_paste() {
+        let timenow = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let encrypted = Pasta {
+            // an id no other test stores files under
+            id: 4_012_345_679,
+            content: encrypt_with_password("secret notes", "old password"),
+            file: Some(PastaFile {
+                name: String::from("notes.txt"),
+                size: ByteSize::b(11),
+                original_name: None,
+                sha256: None,
+                content_type: None,
+                stored_size: None,
+            }),
+            private: true,
+            encrypt_server: true,
+            ..new_pasta(timenow)
+        };
+        let slug = encrypted.id_as_animals();
+        let file = encrypt_bytes_with_password(b"hello world", "old password");
+        storage::save_file(&slug, "data.enc", &file).await.unwrap();
+        insert(None, Some(&encrypted)).unwrap();
+
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(vec![encrypted])),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(App::new().app_data(data.clone()).service(rekey_paste)).await;
+        let request = |old_password: &str, new_password: &str| {
+            TestRequest::post()
+                .uri(&format!("/api/v1/paste/{}/rekey", slug))
+                .set_json(json!({ "old_password": old_password, "new_password": new_password }))
+        };
+        let rekey = |old_password: &str, new_password: &str| {
+            request(old_password, new_password)
+                .insert_header((auth::ADMIN_HEADER, ARGS.auth_admin_password.as_str()))
+                .to_request()
+        };
+
+        // only the owner or admin may try passwords
+        let response =
+            call_service(&app, request("old password", "new password").to_request()).await;
+        assert_eq!(response.status(), 401);
+
+        let response = call_service(&app, rekey("wrong password", "new password")).await;
+        assert_eq!(response.status(), 401);
+
+        let response = call_service(&app, rekey("old password", "new password")).await;
+        assert_eq!(response.status(), 200);
+
+        // the id stays, only the new password opens the pasta and its file
+        let content = data.lock_pastas()[0].content.clone();
+        assert_eq!(data.lock_pastas()[0].id, 4_012_345_679);
+        assert_eq!(data.lock_pastas()[0].encrypted_key.as_deref(), Some(""));
+        assert!(decrypt_with_kdf(&content, "old password").is_err());
+        assert_eq!(
+            decrypt_with_kdf(&content, "new password").unwrap(),
+            "secret notes"
+        );
+        let file = storage::get_file(&slug, "data.enc").await.unwrap();
+        assert!(decrypt_bytes_with_kdf(&file, "old password").is_err());
+        assert_eq!(
+            decrypt_bytes_with_kdf(&file, "new password").unwrap(),
+            b"hello world"
+        );
+
+        // a rekey of the same pasta running already is not interleaved
+        let guard = RekeyGuard::acquire(4_012_345_679).unwrap();
+        let response = call_service(&app, rekey("new password", "other password")).await;
+        assert_eq!(response.status(), 409);
+        drop(guard);
+
+        // wrong passwords lock the client out like failed logins
+        for _ in 0..ARGS.login_max_failures {
+            let response = call_service(&app, rekey("old password", "other password")).await;
+            assert_eq!(response.status(), 401);
+        }
+        let response = call_service(&app, rekey("new password", "other password")).await;
+        assert_eq!(response.status(), 429);
+
+        storage::delete_file(&slug, "data.enc").await.unwrap();
+        delete(None, Some(4_012_345_679));
+    }
+
+    #[test]
+    fn test_apply_metadata_keeps_lifetime_bounded() {
+        let patch = |json| serde_json::from_value::<MetadataPatch>(json).unwrap();
+
+        let mut limited = pasta(false);
+        apply_metadata(
+            &mut limited,
+            &patch(json!({ "expiration": "1hour", "title": "Notes" })),
+            1000,
+            true,
+        )
+        .unwrap();
+        assert_eq!(limited.expiration, 1000 + 3600);
+        assert_eq!(limited.title.as_deref(), Some("Notes"));
+
+        // dropping the last limit is refused and changes nothing
+        let mut never = Pasta {
+            expiration: 0,
+            burn_after_reads: 5,
+            ..pasta(false)
+        };
+        let refused = apply_metadata(
+            &mut never,
+            &patch(json!({ "burn_after_reads": 0, "title": "Notes" })),
+            1000,
+            true,
+        );
+        assert!(refused.is_err());
+        assert_eq!(never.burn_after_reads, 5);
+        assert_eq!(never.title, None);
+        assert!(apply_metadata(
+            &mut never,
+            &patch(json!({ "burn_after_reads": 0 })),
+            1000,
+            false
+        )
+        .is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_patch_metadata_as_owner() {
+        let timenow = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let owned = Pasta {
+            id: 4_012_345_681,
+            ..new_pasta(timenow as i64)
+        };
+        let uri = format!("/api/v1/pastas/{}", owned.id_as_animals());
+        let token = crate::util::hashids::HARSH.encode(&[timenow + 15, owned.id]);
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(vec![owned])),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(App::new().app_data(data.clone()).service(patch_metadata)).await;
+        let request = |cookie: Option<&str>| {
+            let mut request = TestRequest::patch()
+                .uri(&uri)
+                .set_json(json!({ "title": "Release notes" }));
+            if let Some(cookie) = cookie {
+                request = request.cookie(actix_web::cookie::Cookie::new(
+                    ARGS.owner_cookie_name(),
+                    cookie.to_string(),
+                ));
+            }
+            request.to_request()
+        };
+
+        let response = call_service(&app, request(None)).await;
+        assert_eq!(response.status(), 401);
+
+        let response = call_service(&app, request(Some(&token))).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            data.lock_pastas()[0].title.as_deref(),
+            Some("Release notes")
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_patch_metadata_as_admin() {
+        use crate::args::DEFAULT_ADMIN_PASSWORD;
+
+        let timenow = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let managed = Pasta {
+            id: 4_012_345_689,
+            ..new_pasta(timenow)
+        };
+        let uri = format!("/api/v1/pastas/{}", managed.id_as_animals());
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(vec![managed])),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(App::new().app_data(data.clone()).service(patch_metadata)).await;
+        let request = |password: &str, patch: serde_json::Value| {
+            TestRequest::patch()
+                .uri(&uri)
+                .insert_header((auth::ADMIN_HEADER, password.to_string()))
+                .set_json(patch)
+                .to_request()
+        };
+
+        let response = call_service(
+            &app,
+            request(DEFAULT_ADMIN_PASSWORD, json!({ "title": "Release notes" })),
+        )
+        .await;
+        assert_eq!(response.status(), 401);
+        assert_eq!(data.lock_pastas()[0].title, None);
+
+        // pastas have no tags to change
+        let response = call_service(
+            &app,
+            request(&ARGS.auth_admin_password, json!({ "tags": ["release"] })),
+        )
+        .await;
+        assert_eq!(response.status(), 400);
+
+        let response = call_service(
+            &app,
+            request(
+                &ARGS.auth_admin_password,
+                json!({ "title": "Release notes" }),
+            ),
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            data.lock_pastas()[0].title.as_deref(),
+            Some("Release notes")
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_bulk_delete_behind_basic_auth() {
+        use actix_web_httpauth::middleware::HttpAuthentication;
+
+        let timenow = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let listed = Pasta {
+            // an id no other test stores
+            id: 4_012_345_685,
+            content: String::from("old content"),
+            pasta_type: String::from("text"),
+            ..new_pasta(timenow)
+        };
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(vec![listed])),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(
+            App::new().app_data(data.clone()).service(
+                web::scope("")
+                    .wrap(HttpAuthentication::basic(auth::auth_validator))
+                    .service(bulk_delete),
+            ),
+        )
+        .await;
+        let login = format!("Basic {}", BASE64_STANDARD.encode("tester:basic-password"));
+        let request = |uri: &str, authorization: &str, admin: Option<&str>| {
+            let mut request = TestRequest::post()
+                .uri(uri)
+                .insert_header((header::AUTHORIZATION, authorization.to_string()))
+                .set_json(json!({ "pasta_type": "text" }));
+            if let Some(admin) = admin {
+                request = request.insert_header((auth::ADMIN_HEADER, admin.to_string()));
+            }
+            request.to_request()
+        };
+
+        // the admin password as bearer token has no basic auth login
+        let bearer = format!("Bearer {}", ARGS.auth_admin_password);
+        let response = call_service(&app, request("/api/admin/bulk-delete", &bearer, None)).await;
+        assert!(!response.status().is_success());
+
+        let response = call_service(
+            &app,
+            request("/api/admin/bulk-delete", &login, Some("wrong password")),
+        )
+        .await;
+        assert_eq!(response.status(), 401);
+
+        let admin = Some(ARGS.auth_admin_password.as_str());
+        let response = call_service(
+            &app,
+            request("/api/admin/bulk-delete?dry_run=1", &login, admin),
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body["matched"], 1);
+        assert_eq!(data.lock_pastas().len(), 1);
+
+        let response = call_service(&app, request("/api/admin/bulk-delete", &login, admin)).await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body["deleted"], 1);
+        assert!(data.lock_pastas().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_rotate_slug_needs_the_admin_password() {
+        use crate::args::DEFAULT_ADMIN_PASSWORD;
+
+        let timenow = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let shared = Pasta {
+            // an id no other test stores
+            id: 4_012_345_687,
+            ..new_pasta(timenow)
+        };
+        let uri = format!("/rotate-slug/{}", shared.id_as_animals());
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(vec![shared])),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(App::new().app_data(data.clone()).service(rotate_slug)).await;
+        let rotate = |password: &str| {
+            TestRequest::post()
+                .uri(&uri)
+                .insert_header((auth::ADMIN_HEADER, password.to_string()))
+                .to_request()
+        };
+
+        for password in ["", DEFAULT_ADMIN_PASSWORD] {
+            assert_eq!(call_service(&app, rotate(password)).await.status(), 401);
+            assert_eq!(data.lock_pastas()[0].id, 4_012_345_687);
+        }
+
+        let response = call_service(&app, rotate(&ARGS.auth_admin_password)).await;
+        assert_eq!(response.status(), 200);
+        assert_ne!(data.lock_pastas()[0].id, 4_012_345_687);
+    }
+
+    #[actix_web::test]
+    async fn test_stats_count_types() {
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(Vec::new())),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .service(create_paste)
+                .service(stats),
+        )
+        .await;
+
+        for content in ["https://example.com", "just some text"] {
+            let request = TestRequest::post()
+                .uri("/api/v1/paste")
+                .set_json(json!({ "content": content, "privacy": "public" }))
+                .to_request();
+            assert_eq!(call_service(&app, request).await.status(), 201);
+        }
+
+        let request = TestRequest::get().uri("/api/v1/stats").to_request();
+        let counts: serde_json::Value = read_body_json(call_service(&app, request).await).await;
+        assert_eq!(counts["total"], 2);
+        assert_eq!(counts["types"]["url"], 1);
+        assert_eq!(counts["types"]["text"], 1);
+        assert_eq!(counts["encrypted"], 0);
+        assert_eq!(counts["with_files"], 0);
+        assert_eq!(counts["oldest_created"], data.lock_pastas()[0].created);
+
+        for pasta in data.lock_pastas().iter() {
+            delete(None, Some(pasta.id));
+        }
+    }
+}
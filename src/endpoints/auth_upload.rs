@@ -1,7 +1,6 @@
 use crate::args::{Args, ARGS};
 use crate::endpoints::errors::ErrorTemplate;
-use crate::util::animalnumbers::to_u64;
-use crate::util::hashids::to_u64 as hashid_to_u64;
+use crate::util::ids;
 use crate::util::misc::remove_expired;
 use crate::AppState;
 use actix_web::{get, web, HttpResponse};
@@ -16,6 +15,8 @@ struct AuthPasta<'a> {
     encrypted_key: String,
     encrypt_client: bool,
     path: String,
+    /// Never part of any key, only shown
+    label: String,
 }
 
 #[get("/auth/{id}")]
@@ -25,11 +26,7 @@ pub async fn auth_upload(data: web::Data<AppState>, id: web::Path<String>) -> Ht
 
     remove_expired(&mut pastas);
 
-    let intern_id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id).unwrap_or(0)
-    };
+    let intern_id = ids::to_u64(&id).unwrap_or(0);
 
     for (_i, pasta) in pastas.iter().enumerate() {
         if pasta.id == intern_id {
@@ -42,6 +39,7 @@ pub async fn auth_upload(data: web::Data<AppState>, id: web::Path<String>) -> Ht
                         status: String::from(""),
                         encrypted_key: pasta.encrypted_key.to_owned().unwrap_or_default(),
                         encrypt_client: pasta.encrypt_client,
+                        label: pasta.label.clone(),
                         path: String::from("upload"),
                     }
                     .render()
@@ -67,11 +65,7 @@ pub async fn auth_upload_with_status(
 
     let (id, status) = param.into_inner();
 
-    let intern_id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id).unwrap_or(0)
-    };
+    let intern_id = ids::to_u64(&id).unwrap_or(0);
 
     for (_i, pasta) in pastas.iter().enumerate() {
         if pasta.id == intern_id {
@@ -84,6 +78,7 @@ pub async fn auth_upload_with_status(
                         status,
                         encrypted_key: pasta.encrypted_key.to_owned().unwrap_or_default(),
                         encrypt_client: pasta.encrypt_client,
+                        label: pasta.label.clone(),
                         path: String::from("upload"),
                     }
                     .render()
@@ -104,11 +99,7 @@ pub async fn auth_raw_pasta(data: web::Data<AppState>, id: web::Path<String>) ->
 
     remove_expired(&mut pastas);
 
-    let intern_id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id).unwrap_or(0)
-    };
+    let intern_id = ids::to_u64(&id).unwrap_or(0);
 
     for (_i, pasta) in pastas.iter().enumerate() {
         if pasta.id == intern_id {
@@ -121,6 +112,7 @@ pub async fn auth_raw_pasta(data: web::Data<AppState>, id: web::Path<String>) ->
                         status: String::from(""),
                         encrypted_key: pasta.encrypted_key.to_owned().unwrap_or_default(),
                         encrypt_client: pasta.encrypt_client,
+                        label: pasta.label.clone(),
                         path: String::from("raw"),
                     }
                     .render()
@@ -146,11 +138,7 @@ pub async fn auth_raw_pasta_with_status(
 
     let (id, status) = param.into_inner();
 
-    let intern_id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id).unwrap_or(0)
-    };
+    let intern_id = ids::to_u64(&id).unwrap_or(0);
 
     for (_i, pasta) in pastas.iter().enumerate() {
         if pasta.id == intern_id {
@@ -163,6 +151,7 @@ pub async fn auth_raw_pasta_with_status(
                         status,
                         encrypted_key: pasta.encrypted_key.to_owned().unwrap_or_default(),
                         encrypt_client: pasta.encrypt_client,
+                        label: pasta.label.clone(),
                         path: String::from("raw"),
                     }
                     .render()
@@ -183,11 +172,7 @@ pub async fn auth_edit_private(data: web::Data<AppState>, id: web::Path<String>)
 
     remove_expired(&mut pastas);
 
-    let intern_id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id).unwrap_or(0)
-    };
+    let intern_id = ids::to_u64(&id).unwrap_or(0);
 
     for (_, pasta) in pastas.iter().enumerate() {
         if pasta.id == intern_id {
@@ -200,6 +185,7 @@ pub async fn auth_edit_private(data: web::Data<AppState>, id: web::Path<String>)
                         status: String::from(""),
                         encrypted_key: pasta.encrypted_key.to_owned().unwrap_or_default(),
                         encrypt_client: pasta.encrypt_client,
+                        label: pasta.label.clone(),
                         path: String::from("edit_private"),
                     }
                     .render()
@@ -225,11 +211,7 @@ pub async fn auth_edit_private_with_status(
 
     let (id, status) = param.into_inner();
 
-    let intern_id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id).unwrap_or(0)
-    };
+    let intern_id = ids::to_u64(&id).unwrap_or(0);
 
     for (_i, pasta) in pastas.iter().enumerate() {
         if pasta.id == intern_id {
@@ -242,6 +224,7 @@ pub async fn auth_edit_private_with_status(
                         status,
                         encrypted_key: pasta.encrypted_key.to_owned().unwrap_or_default(),
                         encrypt_client: pasta.encrypt_client,
+                        label: pasta.label.clone(),
                         path: String::from("edit_private"),
                     }
                     .render()
@@ -262,11 +245,7 @@ pub async fn auth_file(data: web::Data<AppState>, id: web::Path<String>) -> Http
 
     remove_expired(&mut pastas);
 
-    let intern_id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id).unwrap_or(0)
-    };
+    let intern_id = ids::to_u64(&id).unwrap_or(0);
 
     for (_, pasta) in pastas.iter().enumerate() {
         if pasta.id == intern_id {
@@ -279,6 +258,7 @@ pub async fn auth_file(data: web::Data<AppState>, id: web::Path<String>) -> Http
                         status: String::from(""),
                         encrypted_key: pasta.encrypted_key.to_owned().unwrap_or_default(),
                         encrypt_client: pasta.encrypt_client,
+                        label: pasta.label.clone(),
                         path: String::from("secure_file"),
                     }
                     .render()
@@ -304,11 +284,7 @@ pub async fn auth_file_with_status(
 
     let (id, status) = param.into_inner();
 
-    let intern_id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id).unwrap_or(0)
-    };
+    let intern_id = ids::to_u64(&id).unwrap_or(0);
 
     for (_i, pasta) in pastas.iter().enumerate() {
         if pasta.id == intern_id {
@@ -321,6 +297,7 @@ pub async fn auth_file_with_status(
                         status,
                         encrypted_key: pasta.encrypted_key.to_owned().unwrap_or_default(),
                         encrypt_client: pasta.encrypt_client,
+                        label: pasta.label.clone(),
                         path: String::from("secure_file"),
                     }
                     .render()
@@ -341,11 +318,7 @@ pub async fn auth_remove_private(data: web::Data<AppState>, id: web::Path<String
 
     remove_expired(&mut pastas);
 
-    let intern_id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id).unwrap_or(0)
-    };
+    let intern_id = ids::to_u64(&id).unwrap_or(0);
 
     for (_, pasta) in pastas.iter().enumerate() {
         if pasta.id == intern_id {
@@ -358,6 +331,7 @@ pub async fn auth_remove_private(data: web::Data<AppState>, id: web::Path<String
                         status: String::from(""),
                         encrypted_key: pasta.encrypted_key.to_owned().unwrap_or_default(),
                         encrypt_client: pasta.encrypt_client,
+                        label: pasta.label.clone(),
                         path: String::from("remove"),
                     }
                     .render()
@@ -383,11 +357,7 @@ pub async fn auth_remove_private_with_status(
 
     let (id, status) = param.into_inner();
 
-    let intern_id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id).unwrap_or(0)
-    };
+    let intern_id = ids::to_u64(&id).unwrap_or(0);
 
     for (_i, pasta) in pastas.iter().enumerate() {
         if pasta.id == intern_id {
@@ -400,6 +370,7 @@ pub async fn auth_remove_private_with_status(
                         status,
                         encrypted_key: pasta.encrypted_key.to_owned().unwrap_or_default(),
                         encrypt_client: pasta.encrypt_client,
+                        label: pasta.label.clone(),
                         path: String::from("remove"),
                     }
                     .render()
@@ -412,3 +383,25 @@ pub async fn auth_remove_private_with_status(
         .content_type("text/html; charset=utf-8")
         .body(ErrorTemplate { args: &ARGS }.render().unwrap())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label_is_escaped() {
+        let page = AuthPasta {
+            args: &ARGS,
+            id: String::from("cat-dog-bird"),
+            status: String::from(""),
+            encrypted_key: String::from(""),
+            encrypt_client: false,
+            path: String::from("upload"),
+            label: String::from("<script>alert(1)</script> notes"),
+        }
+        .render()
+        .unwrap();
+        assert!(page.contains("&lt;script&gt;alert(1)&lt;/script&gt; notes"));
+        assert!(!page.contains("<script>alert(1)"));
+    }
+}
@@ -1,23 +1,40 @@
-use crate::pasta::PastaFile;
-use crate::util::animalnumbers::to_animal_names;
+use crate::args::CookieSameSite;
+use crate::pasta::{PastaFile, PrivacyPreset};
+use crate::util::auth;
+use crate::util::clamav::{self, Verdict};
+use crate::util::content_filter;
+use crate::util::contentrenderer::{detect_content_type, ContentType};
+use crate::util::custom_slug;
 use crate::util::db::insert;
-use crate::util::hashids::to_hashids;
-use crate::util::misc::{encrypt, encrypt_bytes, is_valid_url};
-use crate::util::storage;
+use crate::util::ids;
+use crate::util::metrics;
+use crate::util::misc::{
+    encrypt_bytes_with_password, encrypt_with_password, is_valid_url, parse_duration,
+    remove_expired, SNIFF_LENGTH,
+};
+use crate::util::rate_limit;
+use crate::util::remote_fetch::{self, Fetched};
+use crate::util::storage::{self, BackendKind};
+use crate::util::syntaxhighlighter::detect_language;
+use crate::util::webhook;
 use crate::{AppState, Pasta, ARGS};
-use actix_multipart::Multipart;
+use actix_multipart::{Field, Multipart};
 use actix_web::cookie::time::Duration;
 use actix_web::cookie::{Cookie, SameSite};
-use actix_web::error::{ErrorBadRequest, ErrorInternalServerError};
+use actix_web::error::{
+    ErrorBadRequest, ErrorConflict, ErrorInternalServerError, ErrorPayloadTooLarge,
+};
+use actix_web::http::header;
 use actix_web::{get, post, web, Error, HttpRequest, HttpResponse, Responder};
 use askama::Template;
 use bytesize::ByteSize;
-use futures::TryStreamExt;
+use futures::{StreamExt, TryStreamExt};
 use log::warn;
 use rand::Rng;
 use serde::Deserialize;
+use serde_json::json;
 use sha2::{Digest, Sha256};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 #[derive(Template)]
 #[template(path = "index.html")]
@@ -39,8 +56,8 @@ fn check_uploader_cookie(req: &HttpRequest) -> bool {
         return false;
     }
     let expected_token = generate_uploader_token(ARGS.uploader_password.as_ref().unwrap().trim());
-    req.cookie("uploader_token")
-        .map(|c| c.value() == expected_token)
+    req.cookie(&ARGS.uploader_cookie_name())
+        .map(|c| auth::constant_time_eq(c.value(), &expected_token))
         .unwrap_or(false)
 }
 
@@ -73,36 +90,349 @@ pub async fn index_with_status(req: HttpRequest, _param: web::Path<String>) -> H
         )
 }
 
-pub fn expiration_to_timestamp(expiration: &str, timenow: i64) -> i64 {
-    match expiration {
-        "1min" => timenow + 60,
-        "10min" => timenow + 60 * 10,
-        "1hour" => timenow + 60 * 60,
-        "24hour" => timenow + 60 * 60 * 24,
-        "3days" => timenow + 60 * 60 * 24 * 3,
-        "1week" => timenow + 60 * 60 * 24 * 7,
-        "never" => {
-            if ARGS.eternal_pasta {
-                0
-            } else {
-                timenow + 60 * 60 * 24 * 7
-            }
+const DEFAULT_EXPIRATION_SECONDS: i64 = 60 * 60 * 24 * 7;
+
+/// Longest `remote_url` accepted by the upload form
+const MAX_REMOTE_URL_LENGTH: usize = 2048;
+
+/// Longest `label` accepted by the upload form, in characters
+const MAX_LABEL_LENGTH: usize = 100;
+
+/// Answer to uploads of files outside `allowed_file_extensions` and
+/// `allowed_mime_types`
+pub const FILE_TYPE_NOT_ALLOWED: &str = "Files of this type are not allowed on this instance.";
+
+/// Whether uploads may attach `file`, as limited by `allowed_file_extensions`
+/// and `allowed_mime_types`
+pub fn file_type_allowed(file: &PastaFile) -> bool {
+    file.is_allowed_type(&ARGS.allowed_file_extensions, &ARGS.allowed_mime_types)
+}
+
+/// Longest value accepted for the other small form fields, like keys and
+/// options
+const MAX_FIELD_LENGTH: usize = 4096;
+
+/// The text of form field `name`, refusing bytes that are not UTF-8
+fn field_text(name: &str, bytes: Vec<u8>) -> Result<String, Error> {
+    String::from_utf8(bytes)
+        .map_err(|_| ErrorBadRequest(format!("The {} field is not valid UTF-8.", name)))
+}
+
+/// Read a whole text form field of at most `max_bytes`
+async fn read_text_field(
+    field: &mut Field,
+    max_bytes: usize,
+    form_size: &mut usize,
+) -> Result<String, Error> {
+    let name = field.name().unwrap_or_default().to_string();
+    let mut bytes: Vec<u8> = Vec::new();
+    while let Some(chunk) = field.try_next().await? {
+        count_form_bytes(form_size, chunk.len())?;
+        bytes.extend_from_slice(&chunk);
+        if bytes.len() > max_bytes {
+            return Err(ErrorBadRequest(format!("The {} field is too long.", name)));
         }
-        _ => {
+    }
+    field_text(&name, bytes)
+}
+
+/// Add `len` bytes to the size of the upload form so far, rejecting forms
+/// larger than `max_form_size_bytes` whichever fields make them up
+fn count_form_bytes(form_size: &mut usize, len: usize) -> Result<(), Error> {
+    *form_size += len;
+    if *form_size > ARGS.max_form_size_bytes() {
+        return Err(ErrorBadRequest("Upload exceeded size limit."));
+    }
+    Ok(())
+}
+
+/// Expiration timestamp for an expiration like `1hour`, `14d` or `never`
+pub fn expiration_to_timestamp(expiration: &str, timenow: i64) -> i64 {
+    let max_expiry = ARGS.max_expiry.as_deref().and_then(parse_duration);
+    clamped_expiration(expiration, timenow, ARGS.eternal_pasta, max_expiry)
+}
+
+/// Whether `expiration` is `never` or a duration like `14d`. Unknown values
+/// are rejected instead of silently falling back to the default.
+pub fn is_known_expiration(expiration: &str) -> bool {
+    expiration == "never" || parse_duration(expiration).is_some()
+}
+
+/// Reject pastas that never expire and have no read limit when
+/// `force_expiry` is set, one-time pastas count as limited to one read
+pub fn check_bounded_lifetime(
+    expiration: i64,
+    burn_after_reads: u64,
+    one_time: bool,
+    force_expiry: bool,
+) -> Result<(), &'static str> {
+    if force_expiry && expiration == 0 && burn_after_reads == 0 && !one_time {
+        return Err("Uploads that never expire need a burn after read limit on this instance.");
+    }
+    Ok(())
+}
+
+/// Expiration timestamp, 0 for never, with durations capped at `max_expiry` seconds
+fn clamped_expiration(
+    expiration: &str,
+    timenow: i64,
+    eternal_pasta: bool,
+    max_expiry: Option<i64>,
+) -> i64 {
+    let seconds = match expiration {
+        "never" if eternal_pasta => None,
+        "never" => Some(DEFAULT_EXPIRATION_SECONDS),
+        _ => Some(parse_duration(expiration).unwrap_or_else(|| {
             log::error!("{}", "Unexpected expiration time!");
-            timenow + 60 * 60 * 24 * 7
+            DEFAULT_EXPIRATION_SECONDS
+        })),
+    };
+
+    match (seconds, max_expiry) {
+        (None, None) => 0,
+        (None, Some(max)) => timenow + max,
+        (Some(seconds), Some(max)) => timenow + seconds.min(max),
+        (Some(seconds), None) => timenow + seconds,
+    }
+}
+
+/// Read limit for a burn after value like `7`, 0 being unlimited
+pub fn burn_after_to_reads(burn_after: &str) -> u64 {
+    parsed_reads(burn_after, ARGS.max_burn_after)
+}
+
+/// Lower `reads` to `max_reads` unless that is 0, 0 reads stays unlimited
+pub fn clamp_reads(reads: u64, max_reads: u64) -> u64 {
+    if max_reads == 0 {
+        reads
+    } else {
+        reads.min(max_reads)
+    }
+}
+
+fn parsed_reads(burn_after: &str, max_reads: u64) -> u64 {
+    match burn_after.trim().parse::<u64>() {
+        Ok(reads) => clamp_reads(reads, max_reads),
+        Err(_) => {
+            log::warn!(
+                "Unexpected burn after value {:?}, using no limit",
+                burn_after
+            );
+            0
         }
     }
 }
 
+/// Privacy of uploads that do not choose one, `public` if `configured` is
+/// not a privacy mode
+pub fn default_privacy(configured: &str) -> PrivacyPreset {
+    PrivacyPreset::parse(configured.trim()).unwrap_or(PrivacyPreset::Public)
+}
+
+/// Reject uploads asking for a privacy mode listed in `disabled_modes`
+fn check_privacy(preset: PrivacyPreset, disabled_modes: &[String]) -> Result<(), Error> {
+    if disabled_modes
+        .iter()
+        .any(|mode| mode.trim() == preset.as_str())
+    {
+        log::warn!(
+            "Rejected upload with disabled privacy mode {}",
+            preset.as_str()
+        );
+        return Err(ErrorBadRequest(format!(
+            "The {} privacy mode is disabled on this instance.",
+            preset.as_str()
+        )));
+    }
+    Ok(())
+}
+
+/// Reject an upload of `size` bytes that would take all pastas together
+/// over `max_total_mb`, 0 meaning no quota. The total is summed up from the
+/// collection every time, so expired and deleted pastas free their space
+/// right away.
+pub fn check_storage_quota(pastas: &[Pasta], size: u64, max_total_mb: u64) -> Result<(), Error> {
+    if max_total_mb == 0 {
+        return Ok(());
+    }
+
+    let stored: u64 = pastas
+        .iter()
+        .map(|pasta| pasta.stored_size_bytes() as u64)
+        .sum();
+    if stored + size > max_total_mb * 1024 * 1024 {
+        log::warn!(
+            "Rejected upload of {} bytes, {} of {} MB stored",
+            size,
+            stored,
+            max_total_mb
+        );
+        return Err(ErrorPayloadTooLarge(
+            "The storage quota of this instance is used up, please try again later.",
+        ));
+    }
+    Ok(())
+}
+
+/// Estimated entropy below which a password is too easy to guess
+const MIN_PASSWORD_ENTROPY_BITS: f64 = 40.0;
+
+/// Rough entropy of a password: its distinct characters, each worth as much
+/// as the character classes it uses allow. Repeats add nothing.
+fn password_entropy_bits(password: &str) -> f64 {
+    let mut pool = 0;
+    if password.chars().any(|c| c.is_ascii_lowercase()) {
+        pool += 26;
+    }
+    if password.chars().any(|c| c.is_ascii_uppercase()) {
+        pool += 26;
+    }
+    if password.chars().any(|c| c.is_ascii_digit()) {
+        pool += 10;
+    }
+    if password.chars().any(|c| !c.is_ascii_alphanumeric()) {
+        pool += 33;
+    }
+    let mut distinct: Vec<char> = password.chars().collect();
+    distinct.sort_unstable();
+    distinct.dedup();
+    distinct.len() as f64 * f64::from(pool.max(1)).log2()
+}
+
+/// Reject a password server-side encryption would protect little with,
+/// shorter or guessable ones, once `min_length` is set. 0 turns the check
+/// off, callers refuse empty passwords themselves.
+pub fn check_password_strength(password: &str, min_length: usize) -> Result<(), String> {
+    if min_length == 0 {
+        return Ok(());
+    }
+    if password.chars().count() < min_length {
+        return Err(format!(
+            "Passwords must be at least {} characters long.",
+            min_length
+        ));
+    }
+    if password_entropy_bits(password) < MIN_PASSWORD_ENTROPY_BITS {
+        return Err(String::from(
+            "This password is too easy to guess, please use a longer one or mix in capitals, digits and symbols.",
+        ));
+    }
+    Ok(())
+}
+
+/// A new pasta with the instance defaults, created at `timenow`
+pub fn new_pasta(timenow: i64) -> Pasta {
+    Pasta {
+        id: rand::thread_rng().gen::<u16>() as u64,
+        content: String::from(""),
+        file: None,
+        extra_files: Vec::new(),
+        extension: String::from(""),
+        private: false,
+        unlisted: false,
+        readonly: false,
+        editable: ARGS.editable,
+        encrypt_server: false,
+        encrypted_key: Some(String::from("")),
+        encrypt_client: false,
+        created: timenow,
+        read_count: 0,
+        burn_after_reads: 0,
+        download_limit: 0,
+        download_count: 0,
+        one_time: false,
+        custom_slug: None,
+        last_read: timenow,
+        pasta_type: String::from(""),
+        expiration: expiration_to_timestamp(&ARGS.default_expiry, timenow),
+        title: None,
+        theme: String::from(""),
+        label: String::from(""),
+    }
+}
+
+/// Give text pastas without a chosen syntax the `md` or `html` extension
+/// when their content looks like it, so they get rendered, or the extension
+/// of the language of code. Encrypted content is never rendered and left
+/// alone.
+pub fn detect_extension(pasta: &mut Pasta) {
+    let chosen = !pasta.extension.is_empty() && pasta.extension != "auto";
+    if chosen || pasta.encrypt_server || pasta.encrypt_client {
+        return;
+    }
+
+    match detect_content_type(&pasta.content) {
+        ContentType::Markdown => pasta.extension = String::from("md"),
+        ContentType::Html => pasta.extension = String::from("html"),
+        ContentType::PlainText => {
+            if let Some(extension) = detect_language(&pasta.content) {
+                pasta.extension = String::from(extension);
+            }
+        }
+    }
+}
+
+/// Soft warning for files past `size_warning_percent` of the hard size limit
+fn size_warning(size: usize, encrypted: bool) -> Option<String> {
+    if ARGS.size_warning_percent == 0 {
+        return None;
+    }
+
+    let limit_mb = if encrypted {
+        ARGS.max_file_size_encrypted_mb
+    } else {
+        ARGS.max_file_size_unencrypted_mb
+    };
+    let limit = limit_mb * 1024 * 1024;
+
+    if limit == 0 || size * 100 < limit * ARGS.size_warning_percent as usize {
+        return None;
+    }
+
+    Some(format!(
+        "File uses {}% of the {} MB size limit",
+        size * 100 / limit,
+        limit_mb
+    ))
+}
+
 /// Helper function to generate uploader token from password
-fn generate_uploader_token(password: &str) -> String {
+pub fn generate_uploader_token(password: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(password.as_bytes());
     hasher.update(b"microbin_uploader_salt_2024");
     format!("{:x}", hasher.finalize())
 }
 
+/// `Secure` and `SameSite` attributes of the cookies MicroBin sets. The
+/// `cookie_secure` and `cookie_samesite` settings win; without them cookies
+/// are secure when the public path is `https://`, and secure cookies are
+/// `SameSite=Strict`.
+fn cookie_attributes(
+    public_path: &str,
+    secure: Option<bool>,
+    same_site: Option<CookieSameSite>,
+) -> (bool, SameSite) {
+    let secure = secure.unwrap_or_else(|| public_path.starts_with("https://"));
+    let same_site = match same_site {
+        Some(CookieSameSite::Strict) => SameSite::Strict,
+        Some(CookieSameSite::Lax) => SameSite::Lax,
+        Some(CookieSameSite::None) => SameSite::None,
+        None if secure => SameSite::Strict,
+        None => SameSite::Lax,
+    };
+    (secure, same_site)
+}
+
+/// `cookie_attributes` for the running instance
+fn cookie_policy() -> (bool, SameSite) {
+    cookie_attributes(
+        &ARGS.public_path_as_str(),
+        ARGS.cookie_secure,
+        ARGS.cookie_samesite,
+    )
+}
+
 #[derive(Deserialize)]
 pub struct UploaderLoginForm {
     password: String,
@@ -141,36 +471,52 @@ pub async fn login_page_with_status(param: web::Path<String>) -> HttpResponse {
 
 /// Handle login form submission
 #[post("/login")]
-pub async fn login_submit(form: web::Form<UploaderLoginForm>) -> HttpResponse {
+pub async fn login_submit(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    form: web::Form<UploaderLoginForm>,
+) -> HttpResponse {
+    if let Some(response) = rate_limit::limit(&req, &data, "login") {
+        return response;
+    }
+
     if !ARGS.readonly || ARGS.uploader_password.is_none() {
         return HttpResponse::Found()
             .append_header(("Location", format!("{}/", ARGS.public_path_as_str())))
             .finish();
     }
 
+    let client = rate_limit::client_ip(&req);
+    let now = Instant::now();
+
+    if ARGS.login_max_failures != 0 {
+        if let Some(retry_after) = data.failed_logins.locked_out(&client, now) {
+            log::warn!("Uploader login from {} refused, locked out", client);
+            return rate_limit::too_many_requests(retry_after);
+        }
+    }
+
     let expected_password = ARGS.uploader_password.as_ref().unwrap().trim();
 
-    if form.password.trim() == expected_password {
+    if auth::constant_time_eq(form.password.trim(), expected_password) {
+        data.failed_logins.succeeded(&client);
+
         // Password correct, set cookie and redirect to home
         let token = generate_uploader_token(expected_password);
 
-        // Determine if we should use secure cookies based on public_path
-        let use_secure = ARGS.public_path_as_str().starts_with("https://");
+        let (use_secure, same_site) = cookie_policy();
         log::info!(
-            "Uploader login successful, setting cookie (secure={}, public_path={})",
+            "Uploader login successful, setting cookie (secure={}, same_site={}, public_path={})",
             use_secure,
+            same_site,
             ARGS.public_path_as_str()
         );
 
-        let cookie = Cookie::build("uploader_token", token)
+        let cookie = Cookie::build(ARGS.uploader_cookie_name(), token)
             .path("/")
             .max_age(Duration::days(365 * 3))
             .secure(use_secure)
-            .same_site(if use_secure {
-                SameSite::Strict
-            } else {
-                SameSite::Lax
-            })
+            .same_site(same_site)
             .http_only(true)
             .finish();
         HttpResponse::Found()
@@ -179,7 +525,19 @@ pub async fn login_submit(form: web::Form<UploaderLoginForm>) -> HttpResponse {
             .finish()
     } else {
         // Password incorrect, show login page with error
-        log::warn!("Uploader login failed: incorrect password");
+        log::warn!("Uploader login from {} failed: incorrect password", client);
+        if ARGS.login_max_failures != 0 {
+            let failures = data
+                .failed_logins
+                .failed(&client, ARGS.login_max_failures, now);
+            if failures >= ARGS.login_max_failures {
+                log::warn!(
+                    "Locking {} out of uploader login after {} failed attempts",
+                    client,
+                    failures
+                );
+            }
+        }
         HttpResponse::Found()
             .append_header((
                 "Location",
@@ -189,6 +547,267 @@ pub async fn login_submit(form: web::Form<UploaderLoginForm>) -> HttpResponse {
     }
 }
 
+/// Data of an uploaded file that is not attached to its pasta yet
+pub enum UploadedData {
+    /// Held in memory
+    Buffered(Vec<u8>),
+    /// Already streamed to local storage under this temporary name, with its
+    /// digest set on the `PastaFile`
+    Streamed(String),
+}
+
+/// Removes files streamed to local storage when an upload is abandoned
+/// before its pasta was saved
+struct StreamedUploads {
+    pasta_id: String,
+    temp_names: Vec<String>,
+}
+
+impl Drop for StreamedUploads {
+    fn drop(&mut self) {
+        for temp_name in self.temp_names.iter() {
+            storage::delete_local_file(&self.pasta_id, temp_name);
+        }
+    }
+}
+
+/// Scan an uploaded file with the clamd at `address`, rejecting infected
+/// files. Files that could not be scanned are rejected as well. Hands the
+/// data back once it is found clean.
+async fn scan_upload(
+    address: String,
+    pasta_id: &str,
+    display_name: &str,
+    data: UploadedData,
+) -> Result<UploadedData, Error> {
+    let id = pasta_id.to_string();
+    let (data, verdict) = web::block(move || {
+        let verdict = match &data {
+            UploadedData::Buffered(file_data) => clamav::scan(&address, file_data.as_slice()),
+            UploadedData::Streamed(temp_name) => {
+                std::fs::File::open(storage::local_file_path(&id, temp_name))
+                    .map_err(|e| format!("Failed to open file for scanning: {}", e))
+                    .and_then(|file| clamav::scan(&address, file))
+            }
+        };
+        (data, verdict)
+    })
+    .await?;
+
+    match verdict {
+        Ok(Verdict::Clean) => Ok(data),
+        Ok(Verdict::Infected(signature)) => {
+            log::warn!(
+                "Rejected upload of {} in pasta {}: {} found",
+                display_name,
+                pasta_id,
+                signature
+            );
+            Err(ErrorBadRequest(
+                "The file was rejected by the virus scanner.",
+            ))
+        }
+        Err(e) => {
+            log::error!(
+                "Failed to scan {} in pasta {}: {}",
+                display_name,
+                pasta_id,
+                e
+            );
+            Err(ErrorInternalServerError(
+                "The file could not be scanned for viruses.",
+            ))
+        }
+    }
+}
+
+/// Save an uploaded file to storage and attach it to the pasta, as its first
+/// or as an extra attachment. The file is encrypted with `key` first if the
+/// pasta is server side encrypted. Returns the storage path the file was saved to.
+pub async fn save_pasta_file(
+    pasta: &mut Pasta,
+    mut file: PastaFile,
+    data: UploadedData,
+    key: &str,
+) -> Result<String, Error> {
+    let pasta_id = pasta.id_as_animals();
+    let display_name = file.display_name().to_string();
+
+    let data = match data {
+        // the privacy field came after the file, so it was streamed before
+        // anyone knew it has to be encrypted
+        UploadedData::Streamed(temp_name) if pasta.encrypt_server || pasta.encrypt_client => {
+            let file_data = storage::get_file(&pasta_id, &temp_name).await;
+            storage::delete_local_file(&pasta_id, &temp_name);
+            let file_data = file_data.map_err(ErrorInternalServerError)?;
+            if pasta.encrypt_server
+                && file_data.len() > ARGS.max_file_size_encrypted_mb * 1024 * 1024
+            {
+                return Err(ErrorBadRequest("File exceeded size limit."));
+            }
+            file.sha256 = None;
+            UploadedData::Buffered(file_data)
+        }
+        data => data,
+    };
+
+    // Client side encrypted files only ever reach us as ciphertext
+    if let UploadedData::Buffered(file_data) = &data {
+        if !pasta.encrypt_client {
+            file.sha256 = Some(format!("{:x}", Sha256::digest(file_data)));
+        }
+    }
+
+    // Server side encrypted files are scanned before they are encrypted
+    let data = match ARGS.clamav_address.clone() {
+        Some(address) if !pasta.encrypt_client => {
+            scan_upload(address, &pasta_id, &display_name, data).await?
+        }
+        _ => data,
+    };
+
+    let storage_path = if pasta.encrypt_server && !pasta.readonly {
+        let UploadedData::Buffered(file_data) = &data else {
+            unreachable!("streamed files of encrypted pastas are loaded above");
+        };
+
+        // Encrypt file data in memory
+        let encrypted_data = encrypt_bytes_with_password(file_data, key);
+
+        // Save encrypted file directly as data.enc
+        let storage_path = storage::generate_storage_path(&pasta_id, "data.enc");
+        storage::save_file(&pasta_id, &storage_path, &encrypted_data)
+            .await
+            .map_err(ErrorInternalServerError)?;
+        file.stored_size = Some(ByteSize::b(encrypted_data.len() as u64));
+
+        // Set file name with appropriate prefix for encrypted files
+        file.name = match BackendKind::configured().scheme() {
+            Some(scheme) => format!("{}:{}", scheme, display_name),
+            None => display_name,
+        };
+        storage_path
+    } else {
+        // Store under an opaque name if the original one should not be exposed
+        let storage_name = if ARGS.hash_filenames_in_urls {
+            file.original_name = Some(display_name.clone());
+            PastaFile::hashed_name(&pasta_id, &file.name)
+        } else {
+            file.name.clone()
+        };
+
+        // Save unencrypted file directly, renamed if an earlier attachment
+        // of the pasta is stored under the same name
+        let storage_path = storage::free_storage_path(&pasta_id, &storage_name)
+            .await
+            .map_err(|e| {
+                if let UploadedData::Streamed(temp_name) = &data {
+                    storage::delete_local_file(&pasta_id, temp_name);
+                }
+                ErrorInternalServerError(e)
+            })?;
+        let storage_name = storage_path
+            .rsplit('/')
+            .next()
+            .unwrap_or(&storage_path)
+            .to_string();
+        match &data {
+            UploadedData::Buffered(file_data) => {
+                storage::save_file(&pasta_id, &storage_path, file_data)
+                    .await
+                    .map_err(ErrorInternalServerError)?
+            }
+            UploadedData::Streamed(temp_name) => {
+                storage::rename_local_file(&pasta_id, temp_name, &storage_path).map_err(|e| {
+                    storage::delete_local_file(&pasta_id, temp_name);
+                    ErrorInternalServerError(e)
+                })?
+            }
+        }
+
+        // Client side encrypted files are ciphertext, nothing to sniff there
+        if !pasta.encrypt_client {
+            match &data {
+                UploadedData::Buffered(file_data) => file.sniff(file_data),
+                UploadedData::Streamed(_) => {
+                    match storage::read_local_file_head(&pasta_id, &storage_path, SNIFF_LENGTH) {
+                        Ok(head) => file.sniff(&head),
+                        Err(e) => log::warn!("Failed to sniff {}: {}", storage_path, e),
+                    }
+                }
+            }
+        }
+
+        // Update file name with the object path if using remote storage
+        if storage::is_remote(&storage_path) {
+            file.name = storage_path.clone();
+        } else {
+            file.name = storage_name;
+        }
+        storage_path
+    };
+
+    if pasta.file.is_none() {
+        pasta.file = Some(file);
+    } else {
+        pasta.extra_files.push(file);
+    }
+    Ok(storage_path)
+}
+
+/// Persist a new pasta and add it to the collection. If it cannot be
+/// persisted, its already saved files are deleted again.
+pub async fn persist_pasta(
+    data: &web::Data<AppState>,
+    pasta: Pasta,
+    saved_storage_paths: &[String],
+) -> Result<(), String> {
+    let pasta_id = pasta.id_as_animals();
+
+    let mut pastas = data.lock_pastas();
+
+    // another upload may have taken the custom slug in the meantime
+    let persisted = match &pasta.custom_slug {
+        Some(slug) if custom_slug::is_taken(&pastas, slug) => {
+            Err(format!("custom slug {} is already taken", slug))
+        }
+        _ => {
+            // saved along with the others, taken back out if that fails
+            pastas.push(pasta.clone());
+            let inserted = insert(Some(&pastas), Some(&pasta));
+            if inserted.is_err() {
+                pastas.pop();
+            }
+            inserted
+        }
+    };
+
+    if let Err(error) = persisted {
+        drop(pastas);
+
+        for storage_path in saved_storage_paths {
+            if let Err(cleanup_error) = storage::delete_file(&pasta_id, storage_path).await {
+                log::error!(
+                    "Failed to clean up file for unsaved pasta {}: {}",
+                    pasta_id,
+                    cleanup_error
+                );
+            }
+        }
+
+        return Err(error);
+    }
+
+    if let Some(slug) = &pasta.custom_slug {
+        custom_slug::register(slug, pasta.id);
+    }
+
+    metrics::record(webhook::Event::Created);
+
+    webhook::notify(webhook::Event::Created, &pasta);
+    Ok(())
+}
+
 /// receives a file through http Post on url /upload/a-b-c with a, b and c
 /// different animals. The client sends the post in response to a form.
 // TODO: form field order might need to be changed. In my testing the attachment
@@ -198,6 +817,10 @@ pub async fn create(
     data: web::Data<AppState>,
     mut payload: Multipart,
 ) -> Result<HttpResponse, Error> {
+    if let Some(response) = rate_limit::limit(&req, &data, "create") {
+        return Ok(response);
+    }
+
     let timenow: i64 = match SystemTime::now().duration_since(UNIX_EPOCH) {
         Ok(n) => n.as_secs(),
         Err(_) => {
@@ -206,31 +829,23 @@ pub async fn create(
         }
     } as i64;
 
-    let mut new_pasta = Pasta {
-        id: rand::thread_rng().gen::<u16>() as u64,
-        content: String::from(""),
-        file: None,
-        extension: String::from(""),
-        private: false,
-        readonly: false,
-        editable: ARGS.editable,
-        encrypt_server: false,
-        encrypted_key: Some(String::from("")),
-        encrypt_client: false,
-        created: timenow,
-        read_count: 0,
-        burn_after_reads: 0,
-        last_read: timenow,
-        pasta_type: String::from(""),
-        expiration: expiration_to_timestamp(&ARGS.default_expiry, timenow),
-        title: None,
-    };
+    let mut new_pasta = new_pasta(timenow);
 
     let mut random_key: String = String::from("");
     let mut plain_key: String = String::from("");
     let mut uploader_password = String::from("");
-    let mut pending_file_data: Option<(PastaFile, Vec<u8>)> = None;
-    let mut saved_storage_path: Option<String> = None;
+    let mut pending_files: Vec<(PastaFile, UploadedData)> = Vec::new();
+    let mut streamed_uploads = StreamedUploads {
+        pasta_id: new_pasta.id_as_animals(),
+        temp_names: Vec::new(),
+    };
+    let mut saved_storage_paths: Vec<String> = Vec::new();
+    let mut form_size: usize = 0;
+    let mut confirm_never = false;
+    // the form may leave the privacy out, then the instance default applies
+    let mut privacy = default_privacy(&ARGS.default_privacy);
+    new_pasta.apply_privacy(privacy);
+    let mut remote_url = String::from("");
 
     while let Some(mut field) = payload.try_next().await? {
         let Some(field_name) = field.name() else {
@@ -238,86 +853,72 @@ pub async fn create(
         };
         match field_name {
             "uploader_password" => {
-                while let Some(chunk) = field.try_next().await? {
-                    uploader_password
-                        .push_str(std::str::from_utf8(&chunk).unwrap().to_string().as_str());
-                }
+                uploader_password =
+                    read_text_field(&mut field, MAX_FIELD_LENGTH, &mut form_size).await?;
                 continue;
             }
             "random_key" => {
-                while let Some(chunk) = field.try_next().await? {
-                    random_key = std::str::from_utf8(&chunk).unwrap().to_string();
-                }
+                random_key = read_text_field(&mut field, MAX_FIELD_LENGTH, &mut form_size).await?;
                 continue;
             }
             "privacy" => {
-                while let Some(chunk) = field.try_next().await? {
-                    let privacy = std::str::from_utf8(&chunk).unwrap();
-                    new_pasta.private = match privacy {
-                        "public" => false,
-                        _ => true,
-                    };
-                    new_pasta.readonly = match privacy {
-                        "readonly" => true,
-                        _ => false,
-                    };
-                    new_pasta.encrypt_client = match privacy {
-                        "secret" => true,
-                        _ => false,
-                    };
-                    new_pasta.encrypt_server = match privacy {
-                        "private" => true,
-                        "secret" => true,
-                        _ => false,
-                    };
-                }
+                let value = read_text_field(&mut field, MAX_FIELD_LENGTH, &mut form_size).await?;
+                // unknown values are refused rather than guessed at
+                let preset = PrivacyPreset::parse(&value).ok_or_else(|| {
+                    warn!("Rejected upload with unknown privacy {:?}", value);
+                    ErrorBadRequest(
+                        "Invalid privacy, expected public, unlisted, readonly, private or secret.",
+                    )
+                })?;
+                new_pasta.apply_privacy(preset);
+                privacy = preset;
             }
             "plain_key" => {
-                while let Some(chunk) = field.try_next().await? {
-                    plain_key = std::str::from_utf8(&chunk).unwrap().to_string();
-                }
+                plain_key = read_text_field(&mut field, MAX_FIELD_LENGTH, &mut form_size).await?;
                 continue;
             }
             "encrypted_random_key" => {
-                while let Some(chunk) = field.try_next().await? {
-                    new_pasta.encrypted_key =
-                        Some(std::str::from_utf8(&chunk).unwrap().to_string());
-                }
+                new_pasta.encrypted_key =
+                    Some(read_text_field(&mut field, MAX_FIELD_LENGTH, &mut form_size).await?);
                 continue;
             }
             "expiration" => {
-                while let Some(chunk) = field.try_next().await? {
-                    new_pasta.expiration =
-                        expiration_to_timestamp(std::str::from_utf8(&chunk).unwrap(), timenow);
+                let value = read_text_field(&mut field, MAX_FIELD_LENGTH, &mut form_size).await?;
+                if !is_known_expiration(&value) {
+                    return Err(ErrorBadRequest("Invalid expiration."));
                 }
-
+                new_pasta.expiration = expiration_to_timestamp(&value, timenow);
                 continue;
             }
             "burn_after" => {
-                while let Some(chunk) = field.try_next().await? {
-                    new_pasta.burn_after_reads = match std::str::from_utf8(&chunk).unwrap() {
-                        "1" => 1,
-                        "10" => 10,
-                        "100" => 100,
-                        "1000" => 1000,
-                        "10000" => 10000,
-                        "0" => 0,
-                        _ => {
-                            log::error!("{}", "Unexpected burn after value!");
-                            0
-                        }
-                    };
+                let value = read_text_field(&mut field, MAX_FIELD_LENGTH, &mut form_size).await?;
+                new_pasta.burn_after_reads = burn_after_to_reads(&value);
+                continue;
+            }
+            "download_limit" => {
+                let value = read_text_field(&mut field, MAX_FIELD_LENGTH, &mut form_size).await?;
+                // left empty for no limit
+                new_pasta.download_limit = value.trim().parse().unwrap_or(0);
+                continue;
+            }
+            "label" => {
+                let value = read_text_field(&mut field, MAX_FIELD_LENGTH, &mut form_size).await?;
+                if value.trim().chars().count() > MAX_LABEL_LENGTH {
+                    return Err(ErrorBadRequest("Label is too long."));
                 }
-
+                new_pasta.label = value.trim().to_string();
                 continue;
             }
             "content" => {
                 let mut content_bytes: Vec<u8> = Vec::new();
                 while let Some(chunk) = field.try_next().await? {
+                    count_form_bytes(&mut form_size, chunk.len())?;
                     content_bytes.extend_from_slice(&chunk);
+                    if content_bytes.len() > ARGS.max_text_field_size_bytes() {
+                        return Err(ErrorBadRequest("Text exceeded size limit."));
+                    }
                 }
-                let content = String::from_utf8(content_bytes)
-                    .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned());
+                let content = field_text("content", content_bytes)?;
                 if !content.is_empty() {
                     new_pasta.content = content;
 
@@ -329,10 +930,60 @@ pub async fn create(
                 }
                 continue;
             }
-            "syntax_highlight" => {
-                while let Some(chunk) = field.try_next().await? {
-                    new_pasta.extension = std::str::from_utf8(&chunk).unwrap().to_string();
+            "confirm_never" => {
+                confirm_never =
+                    read_text_field(&mut field, MAX_FIELD_LENGTH, &mut form_size).await? == "true";
+                continue;
+            }
+            "remote_url" => {
+                remote_url =
+                    read_text_field(&mut field, MAX_REMOTE_URL_LENGTH, &mut form_size).await?;
+                continue;
+            }
+            "custom_slug" => {
+                let slug = read_text_field(&mut field, custom_slug::MAX_LENGTH * 4, &mut form_size)
+                    .await?;
+                if slug.trim().is_empty() {
+                    continue;
+                }
+                let slug = custom_slug::validate(&slug).map_err(ErrorBadRequest)?;
+                // streamed files are already stored under the generated id
+                if !streamed_uploads.temp_names.is_empty() {
+                    return Err(ErrorBadRequest(
+                        "The custom slug must be sent before any files.",
+                    ));
+                }
+                if custom_slug::is_taken(&data.lock_pastas(), &slug) {
+                    return Err(ErrorConflict("This custom slug is already taken."));
                 }
+                new_pasta.custom_slug = Some(slug);
+                streamed_uploads.pasta_id = new_pasta.id_as_animals();
+                continue;
+            }
+            "one_time" => {
+                new_pasta.one_time =
+                    read_text_field(&mut field, MAX_FIELD_LENGTH, &mut form_size).await? == "true";
+                continue;
+            }
+            "theme" => {
+                new_pasta.theme =
+                    match read_text_field(&mut field, MAX_FIELD_LENGTH, &mut form_size)
+                        .await?
+                        .as_str()
+                    {
+                        "auto" => String::from("auto"),
+                        "light" => String::from("light"),
+                        "dark" => String::from("dark"),
+                        _ => {
+                            log::error!("{}", "Unexpected theme value!");
+                            String::from("")
+                        }
+                    };
+                continue;
+            }
+            "syntax_highlight" => {
+                new_pasta.extension =
+                    read_text_field(&mut field, MAX_FIELD_LENGTH, &mut form_size).await?;
                 continue;
             }
             "file" => {
@@ -355,22 +1006,81 @@ pub async fn create(
                         continue;
                     }
                 };
+                if !file_type_allowed(&file) {
+                    return Err(ErrorBadRequest(FILE_TYPE_NOT_ALLOWED));
+                }
 
-                let mut file_data: Vec<u8> = Vec::new();
-                while let Some(chunk) = field.try_next().await? {
-                    file_data.extend_from_slice(&chunk);
-                    if (new_pasta.encrypt_server
-                        && file_data.len() > ARGS.max_file_size_encrypted_mb * 1024 * 1024)
-                        || file_data.len() > ARGS.max_file_size_unencrypted_mb * 1024 * 1024
-                    {
-                        return Err(ErrorBadRequest("File exceeded size limit."));
+                // Unencrypted files for local storage go straight to disk,
+                // everything else is kept in memory until the pasta is saved
+                if !new_pasta.encrypt_server
+                    && !new_pasta.encrypt_client
+                    && BackendKind::configured() == BackendKind::Local
+                {
+                    // random, so it cannot clash with the name of another attachment
+                    let temp_name = format!(".upload-{:016x}", rand::thread_rng().gen::<u64>());
+                    let mut hasher = Sha256::new();
+                    let mut file_size: usize = 0;
+                    let mut stream_error: Option<Error> = None;
+
+                    let chunks = (&mut field).map(|chunk| {
+                        let chunk = chunk.map_err(|e| {
+                            let message = e.to_string();
+                            stream_error = Some(e.into());
+                            message
+                        })?;
+                        form_size += chunk.len();
+                        file_size += chunk.len();
+                        let limit_error = if form_size > ARGS.max_form_size_bytes() {
+                            Some("Upload exceeded size limit.")
+                        } else if file_size > ARGS.max_file_size_unencrypted_mb * 1024 * 1024 {
+                            Some("File exceeded size limit.")
+                        } else {
+                            None
+                        };
+                        if let Some(message) = limit_error {
+                            stream_error = Some(ErrorBadRequest(message));
+                            return Err(message.to_string());
+                        }
+                        hasher.update(&chunk);
+                        Ok(chunk)
+                    });
+
+                    streamed_uploads.temp_names.push(temp_name.clone());
+                    let written = storage::save_file_streaming(
+                        &streamed_uploads.pasta_id,
+                        &temp_name,
+                        chunks,
+                    )
+                    .await
+                    .map_err(|e| {
+                        stream_error
+                            .take()
+                            .unwrap_or_else(|| ErrorInternalServerError(e))
+                    })?;
+
+                    file.size = ByteSize::b(written);
+                    file.sha256 = Some(format!("{:x}", hasher.finalize()));
+                    pending_files.push((file, UploadedData::Streamed(temp_name)));
+                } else {
+                    let mut file_data: Vec<u8> = Vec::new();
+                    let max_file_mb = if new_pasta.encrypt_server {
+                        ARGS.max_file_size_encrypted_mb
+                    } else {
+                        ARGS.max_file_size_unencrypted_mb
+                    };
+                    while let Some(chunk) = field.try_next().await? {
+                        count_form_bytes(&mut form_size, chunk.len())?;
+                        file_data.extend_from_slice(&chunk);
+                        if file_data.len() > max_file_mb * 1024 * 1024 {
+                            return Err(ErrorBadRequest("File exceeded size limit."));
+                        }
                     }
-                }
 
-                file.size = ByteSize::b(file_data.len() as u64);
+                    file.size = ByteSize::b(file_data.len() as u64);
 
-                // Store file data temporarily for later processing (after we know encryption settings)
-                pending_file_data = Some((file, file_data));
+                    // Store file data temporarily for later processing (after we know encryption settings)
+                    pending_files.push((file, UploadedData::Buffered(file_data)));
+                }
                 new_pasta.pasta_type = String::from("text");
             }
             field => {
@@ -388,14 +1098,14 @@ pub async fn create(
 
         // Check if valid cookie exists
         let has_valid_cookie = req
-            .cookie("uploader_token")
-            .map(|c| c.value() == expected_token)
+            .cookie(&ARGS.uploader_cookie_name())
+            .map(|c| auth::constant_time_eq(c.value(), &expected_token))
             .unwrap_or(false);
 
         if has_valid_cookie {
             // Cookie is valid, allow upload
             log::info!("Uploader authenticated via cookie");
-        } else if uploader_password.trim() == expected_password {
+        } else if auth::constant_time_eq(uploader_password.trim(), expected_password) {
             // Password matches, set cookie for future requests
             should_set_uploader_cookie = true;
             log::info!("Uploader authenticated via password, will set cookie");
@@ -414,109 +1124,184 @@ pub async fn create(
         }
     }
 
+    check_privacy(privacy, &ARGS.disabled_privacy_modes)?;
+
+    // the server never sees the key of client-side encrypted pastas
+    if new_pasta.encrypt_server && !new_pasta.encrypt_client {
+        if plain_key.is_empty() {
+            return Err(ErrorBadRequest("Encrypted uploads need a password."));
+        }
+        check_password_strength(&plain_key, ARGS.min_password_length).map_err(ErrorBadRequest)?;
+    }
+
+    let remote_url = remote_url.trim();
+    if !remote_url.is_empty() {
+        if !ARGS.allow_remote_fetch {
+            return Err(ErrorBadRequest("Fetching remote URLs is disabled."));
+        }
+        if !new_pasta.content.is_empty() {
+            return Err(ErrorBadRequest(
+                "Send either content or a remote URL, not both.",
+            ));
+        }
+
+        let max_file_mb = if new_pasta.encrypt_server || new_pasta.encrypt_client {
+            ARGS.max_file_size_encrypted_mb
+        } else {
+            ARGS.max_file_size_unencrypted_mb
+        };
+        let fetched = remote_fetch::fetch(
+            remote_url,
+            ARGS.max_text_field_size_bytes(),
+            max_file_mb * 1024 * 1024,
+        )
+        .await
+        .map_err(|e| {
+            log::warn!("Failed to fetch remote URL {}: {}", remote_url, e);
+            ErrorBadRequest(e)
+        })?;
+
+        match fetched {
+            Fetched::Text(text) => {
+                new_pasta.content = text;
+                new_pasta.pasta_type = String::from("text");
+            }
+            Fetched::File { .. } if ARGS.no_file_upload => {
+                return Err(ErrorBadRequest("File uploads are disabled."));
+            }
+            Fetched::File { name, data } => {
+                let mut file = PastaFile::from_unsanitized(&name)
+                    .or_else(|_| PastaFile::from_unsanitized("download"))
+                    .map_err(ErrorBadRequest)?;
+                if !file_type_allowed(&file) {
+                    return Err(ErrorBadRequest(FILE_TYPE_NOT_ALLOWED));
+                }
+                file.size = ByteSize::b(data.len() as u64);
+                pending_files.push((file, UploadedData::Buffered(data)));
+                new_pasta.pasta_type = String::from("text");
+            }
+        }
+    }
+
+    if let Some(pattern) = content_filter::blocked_pattern(&new_pasta.content) {
+        log::warn!(
+            "Rejected upload from {} matching blocked content pattern {}",
+            rate_limit::client_ip(&req),
+            pattern
+        );
+        return Err(ErrorBadRequest("Upload rejected."));
+    }
+    if new_pasta.pasta_type == "url" && !content_filter::url_allowed(&new_pasta.content) {
+        return Err(ErrorBadRequest("Links to this domain are not allowed."));
+    }
+
+    // encrypted attachments are all stored as data.enc, so there is room for one
+    if pending_files.len() > 1 && (new_pasta.encrypt_server || new_pasta.encrypt_client) {
+        return Err(ErrorBadRequest(
+            "Only one file can be attached to encrypted uploads.",
+        ));
+    }
+
+    if ARGS.confirm_eternal && new_pasta.expiration == 0 && !confirm_never {
+        log::warn!("Rejected never-expiring upload without confirmation");
+        return Err(ErrorBadRequest(
+            "Uploads that never expire must be confirmed with confirm_never=true.",
+        ));
+    }
+
+    check_bounded_lifetime(
+        new_pasta.expiration,
+        new_pasta.burn_after_reads,
+        new_pasta.one_time,
+        ARGS.force_expiry,
+    )
+    .map_err(ErrorBadRequest)?;
+
+    let upload_size = new_pasta.content.len() as u64
+        + pending_files
+            .iter()
+            .map(|(file, _)| file.size.as_u64())
+            .sum::<u64>();
+    {
+        let mut pastas = data.lock_pastas();
+        remove_expired(&mut pastas);
+        check_storage_quota(&pastas, upload_size, ARGS.max_total_storage_mb)?;
+    }
+
+    detect_extension(&mut new_pasta);
     new_pasta.title = Pasta::extract_title(&new_pasta.content, &new_pasta.extension);
 
     let id = new_pasta.id;
 
     if plain_key != *"" && new_pasta.readonly {
-        new_pasta.encrypted_key = Some(encrypt(id.to_string().as_str(), &plain_key));
+        new_pasta.encrypted_key = Some(encrypt_with_password(id.to_string().as_str(), &plain_key));
     }
 
     if new_pasta.encrypt_server && !new_pasta.readonly && new_pasta.content != *"" {
         if new_pasta.encrypt_client {
-            new_pasta.content = encrypt(&new_pasta.content, &random_key);
+            new_pasta.content = encrypt_with_password(&new_pasta.content, &random_key);
         } else {
-            new_pasta.content = encrypt(&new_pasta.content, &plain_key);
+            new_pasta.content = encrypt_with_password(&new_pasta.content, &plain_key);
         }
     }
 
-    // Process pending file data - encrypt in memory if needed, then save
-    if let Some((mut file, file_data)) = pending_file_data {
-        let pasta_id = new_pasta.id_as_animals();
-        let display_name = file.display_name().to_string();
-
-        if new_pasta.encrypt_server && !new_pasta.readonly {
-            // Encrypt file data in memory
-            let key = if new_pasta.encrypt_client {
-                &random_key
-            } else {
-                &plain_key
-            };
-            let encrypted_data = encrypt_bytes(&file_data, key);
-
-            // Save encrypted file directly as data.enc
-            let storage_path = storage::generate_storage_path(&pasta_id, "data.enc");
-            storage::save_file(&pasta_id, &storage_path, &encrypted_data)
-                .await
-                .map_err(ErrorInternalServerError)?;
-            saved_storage_path = Some(storage_path.clone());
-
-            // Set file name with appropriate prefix for encrypted files
-            if ARGS.s3_enabled() {
-                file.name = format!("s3:{}", display_name);
-            } else {
-                file.name = display_name;
-            }
-        } else {
-            // Save unencrypted file directly
-            let storage_path = storage::generate_storage_path(&pasta_id, &file.name);
-            storage::save_file(&pasta_id, &storage_path, &file_data)
-                .await
-                .map_err(ErrorInternalServerError)?;
-            saved_storage_path = Some(storage_path.clone());
+    new_pasta.compress_content(ARGS.compress_min_bytes);
+
+    let size_warning = pending_files
+        .iter()
+        .map(|(file, _)| file.size.as_u64() as usize)
+        .max()
+        .and_then(|largest| size_warning(largest, new_pasta.encrypt_server));
 
-            // Update file name with S3 path if using S3
-            if ARGS.s3_enabled() {
-                file.name = storage_path;
+    // Process pending file data - encrypt in memory if needed, then save
+    let key = if new_pasta.encrypt_client {
+        &random_key
+    } else {
+        &plain_key
+    };
+    for (file, file_data) in pending_files {
+        if let UploadedData::Streamed(temp_name) = &file_data {
+            // from here on, save_pasta_file takes care of the temporary file
+            streamed_uploads.temp_names.retain(|name| name != temp_name);
+        }
+        match save_pasta_file(&mut new_pasta, file, file_data, key).await {
+            Ok(storage_path) => saved_storage_paths.push(storage_path),
+            Err(error) => {
+                let pasta_id = new_pasta.id_as_animals();
+                for storage_path in saved_storage_paths.iter() {
+                    if let Err(e) = storage::delete_file(&pasta_id, storage_path).await {
+                        log::error!("Failed to clean up file {}: {}", storage_path, e);
+                    }
+                }
+                return Err(error);
             }
         }
-
-        new_pasta.file = Some(file);
     }
 
     let encrypt_server = new_pasta.encrypt_server;
-    let pasta_id = new_pasta.id_as_animals();
+    let expiration = new_pasta.expiration;
+    let custom_slug = new_pasta.custom_slug.clone();
 
-    let mut pastas = data.lock_pastas();
-    let mut persisted_pastas = pastas.clone();
-    persisted_pastas.push(new_pasta.clone());
-
-    if let Err(error) = insert(Some(&persisted_pastas), Some(&new_pasta)) {
+    if let Err(error) = persist_pasta(&data, new_pasta, &saved_storage_paths).await {
         log::error!("Failed to persist pasta {}: {}", id, error);
-        drop(pastas);
-
-        if let Some(storage_path) = saved_storage_path.as_deref() {
-            if let Err(cleanup_error) = storage::delete_file(&pasta_id, storage_path).await {
-                log::error!(
-                    "Failed to clean up file for unsaved pasta {}: {}",
-                    pasta_id,
-                    cleanup_error
-                );
-            }
-        }
-
         return Ok(HttpResponse::InternalServerError()
             .content_type("text/plain; charset=utf-8")
             .body("Failed to save upload."));
     }
 
-    pastas.push(new_pasta);
+    let slug = custom_slug.unwrap_or_else(|| ids::to_slug(id));
 
-    let slug = if ARGS.hash_ids {
-        to_hashids(id)
-    } else {
-        to_animal_names(id)
-    };
+    let (use_secure, same_site) = cookie_policy();
 
-    // Build uploader cookie if needed (valid for 3 years, HTTPS only, SameSite Strict)
+    // Build uploader cookie if needed (valid for 3 years)
     let uploader_cookie = if should_set_uploader_cookie {
         let token = generate_uploader_token(ARGS.uploader_password.as_ref().unwrap().trim());
         Some(
-            Cookie::build("uploader_token", token)
+            Cookie::build(ARGS.uploader_cookie_name(), token)
                 .path("/")
                 .max_age(Duration::days(365 * 3))
-                .secure(true)
-                .same_site(SameSite::Strict)
+                .secure(use_secure)
+                .same_site(same_site)
                 .http_only(true)
                 .finish(),
         )
@@ -524,13 +1309,27 @@ pub async fn create(
         None
     };
 
+    // clients asking for JSON get the link instead of being redirected to it
+    let json = wants_json(&req);
+    let mut builder = if json {
+        HttpResponse::Created()
+    } else {
+        HttpResponse::Found()
+    };
+    let body = json!({
+        "id": slug,
+        "url": format!("{}/upload/{}", ARGS.public_path_as_str(), slug),
+        "expiration": expiration,
+    });
+
     if encrypt_server {
-        let mut builder = HttpResponse::Found();
         builder.append_header(("Location", format!("/auth/{}/success", slug)));
         if let Some(cookie) = uploader_cookie {
             builder.cookie(cookie);
         }
-        Ok(builder.finish())
+        if let Some(warning) = size_warning {
+            builder.append_header(("X-Size-Warning", warning));
+        }
     } else {
         // Generate time-limited token for initial view using Hashids
         let timenow = SystemTime::now()
@@ -542,20 +1341,410 @@ pub async fn create(
         // Use global HARSH instance
         let encoded_token = crate::util::hashids::HARSH.encode(&[expiry, id]);
 
-        let mut builder = HttpResponse::Found();
         builder.append_header((
             "Location",
             format!("{}/upload/{}", ARGS.public_path_as_str(), slug),
         ));
         builder.cookie(
-            Cookie::build("owner_token", encoded_token)
+            Cookie::build(ARGS.owner_cookie_name(), encoded_token)
                 .path("/")
                 .max_age(Duration::seconds(15))
+                .secure(use_secure)
+                .same_site(same_site)
                 .finish(),
         );
         if let Some(cookie) = uploader_cookie {
             builder.cookie(cookie);
         }
+        if let Some(warning) = size_warning {
+            builder.append_header(("X-Size-Warning", warning));
+        }
+    }
+
+    if json {
+        Ok(builder.json(body))
+    } else {
         Ok(builder.finish())
     }
 }
+
+/// Whether the uploader asked for the link of the new pasta as JSON, with
+/// `Accept: application/json` or `?format=json`, instead of a redirect
+fn wants_json(req: &HttpRequest) -> bool {
+    let accepts_json = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| {
+            accept
+                .split(',')
+                .any(|media| media.split(';').next().unwrap_or("").trim() == "application/json")
+        });
+    accepts_json
+        || req
+            .query_string()
+            .split('&')
+            .any(|pair| pair == "format=json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cookie_attributes_default_to_public_path() {
+        assert_eq!(
+            cookie_attributes("https://paste.example.com", None, None),
+            (true, SameSite::Strict)
+        );
+        assert_eq!(
+            cookie_attributes("http://paste.internal", None, None),
+            (false, SameSite::Lax)
+        );
+    }
+
+    #[test]
+    fn test_cookie_attributes_overrides() {
+        // TLS terminated by a proxy in front of a plain http public path
+        assert_eq!(
+            cookie_attributes("http://paste.internal", Some(true), None),
+            (true, SameSite::Strict)
+        );
+        assert_eq!(
+            cookie_attributes("https://paste.example.com", Some(false), None),
+            (false, SameSite::Lax)
+        );
+        assert_eq!(
+            cookie_attributes(
+                "http://paste.internal",
+                Some(true),
+                Some(CookieSameSite::None)
+            ),
+            (true, SameSite::None)
+        );
+        assert_eq!(
+            cookie_attributes("https://paste.example.com", None, Some(CookieSameSite::Lax)),
+            (true, SameSite::Lax)
+        );
+        assert_eq!("Strict".parse(), Ok(CookieSameSite::Strict));
+        assert!("sometimes".parse::<CookieSameSite>().is_err());
+    }
+
+    #[test]
+    fn test_clamped_expiration() {
+        let now = 1_000_000;
+        let day = 60 * 60 * 24;
+
+        assert_eq!(clamped_expiration("1hour", now, false, None), now + 3600);
+        assert_eq!(clamped_expiration("14d", now, false, None), now + 14 * day);
+        assert_eq!(clamped_expiration("bogus", now, false, None), now + 7 * day);
+        assert_eq!(clamped_expiration("never", now, true, None), 0);
+        assert_eq!(clamped_expiration("never", now, false, None), now + 7 * day);
+
+        let max = Some(30 * day);
+        assert_eq!(clamped_expiration("1hour", now, false, max), now + 3600);
+        assert_eq!(clamped_expiration("6mo", now, false, max), now + 30 * day);
+        assert_eq!(clamped_expiration("never", now, true, max), now + 30 * day);
+    }
+
+    #[test]
+    fn test_expiration_validation() {
+        assert!(is_known_expiration("never"));
+        assert!(is_known_expiration("14d"));
+        assert!(!is_known_expiration("bogus"));
+        assert!(!is_known_expiration(""));
+
+        assert!(check_bounded_lifetime(0, 0, false, true).is_err());
+        assert!(check_bounded_lifetime(0, 3, false, true).is_ok());
+        assert!(check_bounded_lifetime(0, 0, true, true).is_ok());
+        assert!(check_bounded_lifetime(1_000_000, 0, false, true).is_ok());
+        assert!(check_bounded_lifetime(0, 0, false, false).is_ok());
+    }
+
+    #[test]
+    fn test_check_privacy() {
+        let disabled = vec![String::from("secret"), String::from(" readonly")];
+
+        let error = check_privacy(PrivacyPreset::Secret, &disabled).unwrap_err();
+        assert_eq!(
+            error.as_response_error().status_code(),
+            actix_web::http::StatusCode::BAD_REQUEST
+        );
+        assert!(check_privacy(PrivacyPreset::Readonly, &disabled).is_err());
+        assert!(check_privacy(PrivacyPreset::Public, &disabled).is_ok());
+        assert!(check_privacy(PrivacyPreset::Private, &disabled).is_ok());
+        assert!(check_privacy(PrivacyPreset::Secret, &[]).is_ok());
+    }
+
+    #[test]
+    fn test_default_privacy() {
+        assert_eq!(default_privacy("public"), PrivacyPreset::Public);
+        assert_eq!(default_privacy("readonly"), PrivacyPreset::Readonly);
+        assert_eq!(default_privacy("secret"), PrivacyPreset::Secret);
+        assert_eq!(default_privacy("everything"), PrivacyPreset::Public);
+
+        // an upload without a privacy field gets the default's flags
+        let mut pasta = new_pasta(0);
+        pasta.apply_privacy(default_privacy("private"));
+        assert!(pasta.private);
+        assert!(pasta.encrypt_server);
+        assert!(!pasta.encrypt_client);
+        assert!(!pasta.is_listed());
+    }
+
+    #[actix_web::test]
+    async fn test_same_file_name_twice() {
+        let mut pasta = new_pasta(0);
+        pasta.id = 918_273_645_501;
+        let pasta_id = pasta.id_as_animals();
+
+        for content in ["first", "second"] {
+            let file = PastaFile::from_unsanitized("a.txt").unwrap();
+            let data = UploadedData::Buffered(content.as_bytes().to_vec());
+            save_pasta_file(&mut pasta, file, data, "").await.unwrap();
+        }
+
+        let names: Vec<String> = pasta.files().map(|f| f.name.clone()).collect();
+        assert_eq!(names, ["a.txt", "a_1.txt"]);
+        for (name, content) in names.iter().zip(["first", "second"]) {
+            let stored = storage::get_file(&pasta_id, name).await.unwrap();
+            assert_eq!(stored, content.as_bytes());
+        }
+
+        for name in &names {
+            storage::delete_file(&pasta_id, name).await.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_check_storage_quota() {
+        let mb = 1024 * 1024;
+        assert!(check_storage_quota(&[], mb, 1).is_ok());
+        assert!(check_storage_quota(&[], mb + 1, 1).is_err());
+
+        // content and attachment filling the quota of 1 MB exactly
+        let pasta = Pasta {
+            id: 1,
+            content: String::from("0123456789"),
+            file: Some(PastaFile {
+                name: String::from("a.bin"),
+                size: ByteSize::b(mb - 10),
+                original_name: None,
+                sha256: None,
+                content_type: None,
+                stored_size: None,
+            }),
+            editable: false,
+            encrypted_key: None,
+            expiration: 0,
+            pasta_type: String::from("text"),
+            ..new_pasta(0)
+        };
+        let pastas = vec![pasta];
+
+        let error = check_storage_quota(&pastas, 1, 1).unwrap_err();
+        assert_eq!(
+            error.as_response_error().status_code(),
+            actix_web::http::StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert!(check_storage_quota(&pastas, 1, 2).is_ok());
+        assert!(check_storage_quota(&pastas, 1, 0).is_ok());
+    }
+
+    #[test]
+    fn test_check_password_strength() {
+        // 0 turns the check off
+        assert!(check_password_strength("", 0).is_ok());
+        assert!(check_password_strength("1234", 0).is_ok());
+        assert!(check_password_strength("", 10).is_err());
+
+        let error = check_password_strength("Tr0ub4d&", 10).unwrap_err();
+        assert_eq!(error, "Passwords must be at least 10 characters long.");
+        // long enough, but nothing to guess
+        assert!(check_password_strength("aaaaaaaaaaaa", 10).is_err());
+        assert!(check_password_strength("1212121212", 10).is_err());
+
+        assert!(check_password_strength("correct horse battery", 10).is_ok());
+        assert!(check_password_strength("Tr0ub4d&3xyz", 10).is_ok());
+    }
+
+    #[test]
+    fn test_detect_extension() {
+        let markdown = "# Release notes\n\n- **faster** uploads\n- fewer bugs\n";
+        let mut pasta = Pasta {
+            id: 1,
+            content: String::from(markdown),
+            pasta_type: String::from("text"),
+            ..new_pasta(0)
+        };
+        detect_extension(&mut pasta);
+        assert_eq!(pasta.extension, "md");
+
+        pasta.extension = String::from("auto");
+        detect_extension(&mut pasta);
+        assert_eq!(pasta.extension, "md");
+
+        // explicit choices win
+        pasta.extension = String::from("txt");
+        detect_extension(&mut pasta);
+        assert_eq!(pasta.extension, "txt");
+
+        pasta.content = String::from("<!DOCTYPE html><html></html>");
+        pasta.extension = String::from("");
+        detect_extension(&mut pasta);
+        assert_eq!(pasta.extension, "html");
+
+        pasta.content = String::from("just a note");
+        pasta.extension = String::from("");
+        detect_extension(&mut pasta);
+        assert_eq!(pasta.extension, "");
+
+        pasta.content = String::from("#!/bin/sh\necho hi\n");
+        detect_extension(&mut pasta);
+        assert_eq!(pasta.extension, "sh");
+        pasta.extension = String::from("");
+
+        pasta.content = String::from(markdown);
+        pasta.encrypt_server = true;
+        detect_extension(&mut pasta);
+        assert_eq!(pasta.extension, "");
+    }
+
+    #[test]
+    fn test_parsed_reads() {
+        for preset in ["0", "1", "10", "100", "1000", "10000"] {
+            assert_eq!(parsed_reads(preset, 10000), preset.parse::<u64>().unwrap());
+        }
+        assert_eq!(parsed_reads("7", 10000), 7);
+        assert_eq!(parsed_reads("50000", 10000), 10000);
+        assert_eq!(parsed_reads("50000", 0), 50000);
+        assert_eq!(parsed_reads("-3", 10000), 0);
+        assert_eq!(parsed_reads("seven", 10000), 0);
+        assert_eq!(parsed_reads("", 10000), 0);
+    }
+
+    #[actix_web::test]
+    async fn test_invalid_utf8_field() {
+        let mut headers = actix_web::http::header::HeaderMap::new();
+        headers.insert(
+            actix_web::http::header::CONTENT_TYPE,
+            "multipart/form-data; boundary=xyz".parse().unwrap(),
+        );
+        let mut body =
+            b"--xyz\r\nContent-Disposition: form-data; name=\"content\"\r\n\r\n".to_vec();
+        body.extend_from_slice(&[0x68, 0x69, 0xff, 0xfe]);
+        body.extend_from_slice(b"\r\n--xyz--\r\n");
+        let stream = futures::stream::once(async move {
+            Ok::<_, actix_web::error::PayloadError>(actix_web::web::Bytes::from(body))
+        });
+        let mut payload = Multipart::new(&headers, stream);
+
+        let mut field = payload.try_next().await.unwrap().unwrap();
+        let mut form_size = 0;
+        let error = read_text_field(&mut field, MAX_FIELD_LENGTH, &mut form_size)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.as_response_error().status_code(),
+            actix_web::http::StatusCode::BAD_REQUEST
+        );
+        assert_eq!(field_text("content", b"hi".to_vec()).unwrap(), "hi");
+        assert_eq!(form_size, 4);
+
+        // short fields count towards the size of the form too
+        let mut form_size = ARGS.max_form_size_bytes() - 1;
+        assert!(count_form_bytes(&mut form_size, 1).is_ok());
+        assert!(count_form_bytes(&mut form_size, 1).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_create_answers_json_when_asked() {
+        use crate::util::db::delete;
+        use crate::util::rate_limit::{FailedLogins, RateLimiter};
+        use crate::util::store::MemoryStore;
+        use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+        use actix_web::App;
+        use std::sync::Mutex;
+
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(Vec::new())),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/upload", web::post().to(create)),
+        )
+        .await;
+        let upload = |uri: &str| {
+            TestRequest::post()
+                .uri(uri)
+                .insert_header((header::CONTENT_TYPE, "multipart/form-data; boundary=xyz"))
+                .set_payload(
+                    "--xyz\r\nContent-Disposition: form-data; name=\"privacy\"\r\n\r\npublic\r\n\
+                     --xyz\r\nContent-Disposition: form-data; name=\"content\"\r\n\r\nhello\r\n\
+                     --xyz--\r\n",
+                )
+        };
+
+        let response = call_service(&app, upload("/upload").to_request()).await;
+        assert_eq!(response.status(), 302);
+        let location = response.headers().get(header::LOCATION).unwrap();
+        assert!(location.to_str().unwrap().contains("/upload/"));
+
+        for request in [
+            upload("/upload")
+                .insert_header((header::ACCEPT, "application/json"))
+                .to_request(),
+            upload("/upload?format=json").to_request(),
+        ] {
+            let response = call_service(&app, request).await;
+            assert_eq!(response.status(), 201);
+            let body: serde_json::Value = read_body_json(response).await;
+            let slug = body["id"].as_str().unwrap();
+            assert!(body["url"]
+                .as_str()
+                .unwrap()
+                .ends_with(&format!("/upload/{}", slug)));
+        }
+
+        for pasta in data.lock_pastas().iter() {
+            delete(None, Some(pasta.id));
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_unknown_privacy_is_rejected() {
+        use crate::util::rate_limit::{FailedLogins, RateLimiter};
+        use crate::util::store::MemoryStore;
+        use actix_web::test::{call_service, init_service, TestRequest};
+        use actix_web::App;
+        use std::sync::Mutex;
+
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(Vec::new())),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/upload", web::post().to(create)),
+        )
+        .await;
+        let request = TestRequest::post()
+            .uri("/upload")
+            .insert_header((header::CONTENT_TYPE, "multipart/form-data; boundary=xyz"))
+            .set_payload(
+                "--xyz\r\nContent-Disposition: form-data; name=\"privacy\"\r\n\r\nhidden\r\n\
+                 --xyz\r\nContent-Disposition: form-data; name=\"content\"\r\n\r\nhello\r\n\
+                 --xyz--\r\n",
+            )
+            .to_request();
+
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), 400);
+        assert!(data.lock_pastas().is_empty());
+    }
+}
@@ -1,12 +1,13 @@
 use crate::args::Args;
 use crate::endpoints::errors::ErrorTemplate;
-use crate::util::animalnumbers::to_u64;
+use crate::util::auth;
 use crate::util::db::update;
-use crate::util::hashids::to_u64 as hashid_to_u64;
-use crate::util::misc::{decrypt, encrypt, remove_expired};
+use crate::util::ids;
+use crate::util::misc::{decrypt_with_kdf, encrypt_with_password, remove_expired};
+use crate::util::store::PastaStore;
 use crate::{AppState, Pasta, ARGS};
 use actix_multipart::Multipart;
-use actix_web::{get, post, web, Error, HttpResponse};
+use actix_web::{get, post, web, Error, HttpRequest, HttpResponse};
 use askama::Template;
 use futures::TryStreamExt;
 
@@ -23,11 +24,7 @@ struct EditTemplate<'a> {
 pub async fn get_edit(data: web::Data<AppState>, id: web::Path<String>) -> HttpResponse {
     let mut pastas = data.lock_pastas();
 
-    let id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id.into_inner()).unwrap_or(0)
-    };
+    let id = ids::to_u64(&id).unwrap_or(0);
 
     remove_expired(&mut pastas);
 
@@ -81,11 +78,7 @@ pub async fn get_edit_with_status(
 
     let (id, status) = param.into_inner();
 
-    let intern_id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id).unwrap_or(0)
-    };
+    let intern_id = ids::to_u64(&id).unwrap_or(0);
 
     remove_expired(&mut pastas);
 
@@ -139,11 +132,7 @@ pub async fn post_edit_private(
     // get access to the pasta collection
     let mut pastas = data.lock_pastas();
 
-    let id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id.into_inner()).unwrap_or(0)
-    };
+    let id = ids::to_u64(&id).unwrap_or(0);
 
     let mut password = String::from("");
 
@@ -159,22 +148,14 @@ pub async fn post_edit_private(
     remove_expired(&mut pastas);
 
     // find the index of the pasta in the collection based on u64 id
-    let mut index: usize = 0;
-    let mut found: bool = false;
-    for (i, pasta) in pastas.iter().enumerate() {
-        if pasta.id == id {
-            index = i;
-            found = true;
-            break;
-        }
-    }
+    let index = pastas.position(id);
 
-    if found && !pastas[index].encrypt_client {
+    if let Some(index) = index.filter(|&i| !pastas[i].encrypt_client) {
         let original_content = pastas[index].content.to_owned();
 
         // decrypt content temporarily
         if password != *"" {
-            let res = decrypt(&original_content, &password);
+            let res = decrypt_with_kdf(&original_content, &password);
             if res.is_ok() {
                 pastas[index]
                     .content
@@ -229,11 +210,7 @@ pub async fn post_submit_edit_private(
     // get access to the pasta collection
     let mut pastas = data.lock_pastas();
 
-    let id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id.into_inner()).unwrap_or(0)
-    };
+    let id = ids::to_u64(&id).unwrap_or(0);
 
     let mut password = String::from("");
     let mut new_content = String::from("");
@@ -255,23 +232,15 @@ pub async fn post_submit_edit_private(
     remove_expired(&mut pastas);
 
     // find the index of the pasta in the collection based on u64 id
-    let mut index: usize = 0;
-    let mut found: bool = false;
-    for (i, pasta) in pastas.iter().enumerate() {
-        if pasta.id == id {
-            index = i;
-            found = true;
-            break;
-        }
-    }
+    let index = pastas.position(id);
 
-    if found && pastas[index].editable && !pastas[index].encrypt_client {
+    if let Some(index) = index.filter(|&i| pastas[i].editable && !pastas[i].encrypt_client) {
         if pastas[index].readonly {
-            let res = decrypt(pastas[index].encrypted_key.as_ref().unwrap(), &password);
+            let res = decrypt_with_kdf(pastas[index].encrypted_key.as_ref().unwrap(), &password);
             if res.is_ok() {
                 pastas[index]
                     .content
-                    .replace_range(.., &encrypt(&new_content, &password));
+                    .replace_range(.., &encrypt_with_password(&new_content, &password));
                 pastas[index].title = Pasta::extract_title(&new_content, &pastas[index].extension);
             } else {
                 return Ok(HttpResponse::Found()
@@ -286,11 +255,11 @@ pub async fn post_submit_edit_private(
                     .finish());
             }
         } else if pastas[index].private {
-            let res = decrypt(&pastas[index].content, &password);
+            let res = decrypt_with_kdf(&pastas[index].content, &password);
             if res.is_ok() {
                 pastas[index]
                     .content
-                    .replace_range(.., &encrypt(&new_content, &password));
+                    .replace_range(.., &encrypt_with_password(&new_content, &password));
                 pastas[index].title = Pasta::extract_title(&new_content, &pastas[index].extension);
                 // save pasta in database
                 update(Some(&pastas), Some(&pastas[index]));
@@ -326,15 +295,12 @@ pub async fn post_submit_edit_private(
 
 #[post("/edit/{id}")]
 pub async fn post_edit(
+    req: HttpRequest,
     data: web::Data<AppState>,
     id: web::Path<String>,
     mut payload: Multipart,
 ) -> Result<HttpResponse, Error> {
-    let id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id.into_inner()).unwrap_or(0)
-    };
+    let id = ids::to_u64(&id).unwrap_or(0);
 
     let mut pastas = data.lock_pastas();
 
@@ -356,30 +322,21 @@ pub async fn post_edit(
         }
     }
 
-    for (i, pasta) in pastas.iter().enumerate() {
-        if pasta.id == id {
-            if pasta.editable && !pasta.encrypt_client {
-                if pastas[i].readonly || pastas[i].encrypt_server {
-                    if password != *"" {
-                        let res = decrypt(pastas[i].encrypted_key.as_ref().unwrap(), &password);
-                        if res.is_ok() {
-                            pastas[i].content.replace_range(.., &new_content);
-                            pastas[i].title =
-                                Pasta::extract_title(&pastas[i].content, &pastas[i].extension);
-                            // save pasta in database
-                            update(Some(&pastas), Some(&pastas[i]));
-                        } else {
-                            return Ok(HttpResponse::Found()
-                                .append_header((
-                                    "Location",
-                                    format!(
-                                        "{}/edit/{}/incorrect",
-                                        ARGS.public_path_as_str(),
-                                        pasta.id_as_animals()
-                                    ),
-                                ))
-                                .finish());
-                        }
+    if let Some(i) = pastas.position(id) {
+        let pasta = &pastas[i];
+        if pasta.editable && !pasta.encrypt_client {
+            // the uploader may fix a read-only pasta with their owner token,
+            // encrypted content still needs the password to be re-encrypted
+            if pastas[i].encrypt_server || (pastas[i].readonly && !auth::is_owner(&req, id)) {
+                if password != *"" {
+                    let res =
+                        decrypt_with_kdf(pastas[i].encrypted_key.as_ref().unwrap(), &password);
+                    if res.is_ok() {
+                        pastas[i].content.replace_range(.., &new_content);
+                        pastas[i].title =
+                            Pasta::extract_title(&pastas[i].content, &pastas[i].extension);
+                        // save pasta in database
+                        update(Some(&pastas), Some(&pastas[i]));
                     } else {
                         return Ok(HttpResponse::Found()
                             .append_header((
@@ -393,26 +350,36 @@ pub async fn post_edit(
                             .finish());
                     }
                 } else {
-                    pastas[i].content.replace_range(.., &new_content);
-                    pastas[i].title =
-                        Pasta::extract_title(&pastas[i].content, &pastas[i].extension);
-                    // save pasta in database
-                    update(Some(&pastas), Some(&pastas[i]));
+                    return Ok(HttpResponse::Found()
+                        .append_header((
+                            "Location",
+                            format!(
+                                "{}/edit/{}/incorrect",
+                                ARGS.public_path_as_str(),
+                                pasta.id_as_animals()
+                            ),
+                        ))
+                        .finish());
                 }
-
-                return Ok(HttpResponse::Found()
-                    .append_header((
-                        "Location",
-                        format!(
-                            "{}/upload/{}",
-                            ARGS.public_path_as_str(),
-                            pastas[i].id_as_animals()
-                        ),
-                    ))
-                    .finish());
             } else {
-                break;
+                pastas[i].content.replace_range(.., &new_content);
+                pastas[i].title =
+                    Pasta::extract_title(&pastas[i].content, &pastas[i].extension);
+                pastas[i].compress_content(ARGS.compress_min_bytes);
+                // save pasta in database
+                update(Some(&pastas), Some(&pastas[i]));
             }
+
+            return Ok(HttpResponse::Found()
+                .append_header((
+                    "Location",
+                    format!(
+                        "{}/upload/{}",
+                        ARGS.public_path_as_str(),
+                        pastas[i].id_as_animals()
+                    ),
+                ))
+                .finish());
         }
     }
 
@@ -2,6 +2,7 @@ use actix_web::{Error, HttpResponse};
 use askama::Template;
 
 use crate::args::{Args, ARGS};
+use crate::util::expired::{self, ExpiryReason};
 
 #[derive(Template)]
 #[template(path = "error.html")]
@@ -14,3 +15,30 @@ pub async fn not_found() -> Result<HttpResponse, Error> {
         .content_type("text/html; charset=utf-8")
         .body(ErrorTemplate { args: &ARGS }.render().unwrap()))
 }
+
+#[derive(Template)]
+#[template(path = "expired.html")]
+pub struct ExpiredTemplate<'a> {
+    pub args: &'a Args,
+    pub reason: ExpiryReason,
+}
+
+/// Error page for a pasta that could not be found, telling apart recently
+/// expired pastas from ones that never existed
+pub fn not_found_or_expired(id: u64) -> HttpResponse {
+    match expired::reason(id) {
+        Some(reason) => HttpResponse::Gone()
+            .content_type("text/html; charset=utf-8")
+            .body(
+                ExpiredTemplate {
+                    args: &ARGS,
+                    reason,
+                }
+                .render()
+                .unwrap(),
+            ),
+        None => HttpResponse::NotFound()
+            .content_type("text/html; charset=utf-8")
+            .body(ErrorTemplate { args: &ARGS }.render().unwrap()),
+    }
+}
@@ -1,18 +1,338 @@
+use std::fs;
 use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::args::ARGS;
-use crate::util::animalnumbers::to_u64;
+use crate::endpoints::pasta::insert_lifetime_headers;
+use crate::pasta::Pasta;
 use crate::util::auth;
-use crate::util::hashids::to_u64 as hashid_to_u64;
-use crate::util::misc::{decrypt_bytes, remove_expired};
+use crate::util::db::{delete, update};
+use crate::util::expired::{self, ExpiryReason};
+use crate::util::ids;
+use crate::util::metrics;
+use crate::util::misc::{
+    content_type_for, decrypt_bytes_with_kdf, remove_expired, sniff_content_type, SNIFF_LENGTH,
+};
+use crate::util::pending;
 use crate::util::storage;
+use crate::util::store::{MemoryStore, PastaStore};
+use crate::util::thumbnail;
+use crate::util::webhook;
 use crate::AppState;
+use actix_files::HttpRange;
 use actix_multipart::Multipart;
-use actix_web::http::header;
-use actix_web::{get, post, web, Error, HttpResponse};
+use actix_web::body::{BodySize, MessageBody, SizedStream};
+use actix_web::http::{header, StatusCode};
+use actix_web::web::Bytes;
+use actix_web::{get, head, post, web, Error, HttpRequest, HttpResponse, HttpResponseBuilder};
+use futures::stream;
+use mime_guess::{mime, Mime};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// Count a download of the attached file, as a read and against the
+/// download limit. When this is the last read or download allowed, the pasta
+/// is taken out of the collection right away so no one else can download it,
+/// and returned to be deleted once the file was sent. One-time pastas are
+/// deleted from the database right away as well.
+fn count_download(pastas: &mut MemoryStore, index: usize) -> Option<Pasta> {
+    let timenow: i64 = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(_) => {
+            log::error!("SystemTime before UNIX EPOCH!");
+            0
+        }
+    } as i64;
+
+    let pasta = &pastas[index];
+    if pasta.one_time {
+        let pasta = pastas.remove(index);
+        delete(Some(pastas), Some(pasta.id));
+        metrics::record(webhook::Event::ReadLimit);
+        webhook::notify(webhook::Event::ReadLimit, &pasta);
+        return Some(pasta);
+    }
+    if pasta.burn_after_reads != 0 && pasta.read_count + 1 >= pasta.burn_after_reads {
+        return Some(pastas.remove(index));
+    }
+    if pasta.download_limit != 0 && pasta.download_count + 1 >= pasta.download_limit {
+        return Some(pastas.remove(index));
+    }
+
+    pastas[index].read_count += 1;
+    pastas[index].download_count += 1;
+    pastas[index].last_read = timenow;
+    update(Some(pastas), Some(&pastas[index]));
+    None
+}
+
+/// Response body that deletes a burnt pasta and its file once the body was
+/// fully sent. If the download is aborted, the pasta is put back unread,
+/// unless it is a one-time pasta.
+struct BurnAfterSent<B> {
+    body: B,
+    burn: Option<(Pasta, web::Data<AppState>)>,
+}
+
+impl<B: MessageBody + Unpin> MessageBody for BurnAfterSent<B> {
+    type Error = B::Error;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.body).poll_next(cx);
+
+        if let Poll::Ready(None) = poll {
+            if let Some((pasta, data)) = this.burn.take() {
+                let pastas = data.lock_pastas();
+                delete(Some(&pastas), Some(pasta.id));
+                drop(pastas);
+
+                if !pasta.one_time {
+                    expired::record(pasta.id, ExpiryReason::ReadLimit);
+                    metrics::record(webhook::Event::ReadLimit);
+                    webhook::notify(webhook::Event::ReadLimit, &pasta);
+                }
+                delete_files(pasta);
+            }
+        }
+
+        poll
+    }
+}
+
+/// Delete the attachments of a burnt pasta in the background
+fn delete_files(pasta: Pasta) {
+    let storage_paths = pasta.file_storage_paths();
+    let pasta_id = pasta.id_as_animals();
+    pending::spawn(async move {
+        for storage_path in storage_paths {
+            if let Err(e) = storage::delete_file(&pasta_id, &storage_path).await {
+                log::error!("Failed to delete file {}: {}", storage_path, e);
+            }
+        }
+    });
+}
+
+impl<B> Drop for BurnAfterSent<B> {
+    fn drop(&mut self) {
+        if let Some((pasta, data)) = self.burn.take() {
+            if pasta.one_time {
+                log::warn!(
+                    "Download of one-time pasta {} did not complete, deleting it anyway",
+                    pasta.id_as_animals()
+                );
+                delete_files(pasta);
+                return;
+            }
+            log::warn!(
+                "Download of pasta {} did not complete, keeping it",
+                pasta.id_as_animals()
+            );
+            data.lock_pastas().push(pasta);
+        }
+    }
+}
+
+/// `insert_lifetime_headers` for a download `count_download` just counted
+fn insert_download_lifetime_headers(
+    response: &mut HttpResponse,
+    pastas: &[Pasta],
+    index: usize,
+    burn: Option<&Pasta>,
+) {
+    match burn {
+        Some(pasta) => insert_lifetime_headers(response, pasta, true),
+        None => insert_lifetime_headers(response, &pastas[index], false),
+    }
+}
+
+fn burn_after_sent(
+    response: HttpResponse,
+    burn: Option<Pasta>,
+    data: web::Data<AppState>,
+) -> HttpResponse {
+    match burn {
+        Some(pasta) => response
+            .map_body(|_, body| BurnAfterSent {
+                body,
+                burn: Some((pasta, data)),
+            })
+            .map_into_boxed_body(),
+        None => response,
+    }
+}
+
+/// Strong `ETag` of a file: its SHA-256 when known, or that of `data`
+fn etag(sha256: Option<&str>, data: &[u8]) -> String {
+    match sha256 {
+        Some(sha256) => format!("\"{}\"", sha256),
+        None => format!("\"{:x}\"", Sha256::digest(data)),
+    }
+}
+
+/// Whether the `If-None-Match` header of the request lists `etag`, so the
+/// client already has the file. Weak tags match too, as the RFC asks of
+/// `If-None-Match`.
+fn if_none_match(request: &HttpRequest, etag: &str) -> bool {
+    request
+        .headers()
+        .get_all(header::IF_NONE_MATCH)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// 304 response for a client that has the file with `etag` already
+fn not_modified(etag: &str) -> HttpResponse {
+    HttpResponse::NotModified()
+        .insert_header((header::ETAG, etag))
+        .finish()
+}
+
+/// The range asked for with the `Range` header of the request, in a file of
+/// `size` bytes. Only the first of several ranges is served, like `NamedFile`
+/// does. Ranges outside the file get a 416 response to send instead.
+fn requested_range(request: &HttpRequest, size: u64) -> Result<Option<HttpRange>, HttpResponse> {
+    let Some(value) = request.headers().get(header::RANGE) else {
+        return Ok(None);
+    };
+    let ranges = value
+        .to_str()
+        .ok()
+        .and_then(|value| HttpRange::parse(value, size).ok());
+    match ranges {
+        Some(ranges) => Ok(ranges.first().copied()),
+        None => Err(HttpResponse::RangeNotSatisfiable()
+            .insert_header((header::CONTENT_RANGE, format!("bytes */{}", size)))
+            .finish()),
+    }
+}
+
+/// Turn a response into a 206 holding `range` of a file of `size` bytes
+fn partial_content(builder: &mut HttpResponseBuilder, range: HttpRange, size: u64) {
+    builder.status(StatusCode::PARTIAL_CONTENT).insert_header((
+        header::CONTENT_RANGE,
+        format!(
+            "bytes {}-{}/{}",
+            range.start,
+            range.start + range.length - 1,
+            size
+        ),
+    ));
+}
+
+/// Respond with `data`, or the range of it the request asked for
+fn ranged_body(
+    request: &HttpRequest,
+    mut builder: HttpResponseBuilder,
+    data: Vec<u8>,
+) -> HttpResponse {
+    let size = data.len() as u64;
+    builder.insert_header((header::ACCEPT_RANGES, "bytes"));
+    match requested_range(request, size) {
+        Ok(Some(range)) => {
+            partial_content(&mut builder, range, size);
+            let start = range.start as usize;
+            builder
+                .insert_header((header::CONTENT_LENGTH, range.length))
+                .body(data[start..start + range.length as usize].to_vec())
+        }
+        Ok(None) => builder
+            .insert_header((header::CONTENT_LENGTH, size))
+            .body(data),
+        Err(response) => response,
+    }
+}
+
+/// Content type of a download of `display_name`, guessed from the name.
+/// Names that tell none fall back to the type `sniffed` on upload, or else
+/// to sniffing the first bytes of the file, which `head` reads. Encrypted
+/// files are never sniffed, so callers pass nothing for those.
+fn download_content_type(
+    display_name: &str,
+    sniffed: Option<&str>,
+    head: impl FnOnce() -> Option<Vec<u8>>,
+) -> Mime {
+    let guessed = content_type_for(display_name);
+    if guessed != mime::APPLICATION_OCTET_STREAM {
+        return guessed;
+    }
+    let sniffed = match sniffed {
+        Some(sniffed) => sniffed.parse().ok(),
+        None => head()
+            .as_deref()
+            .and_then(sniff_content_type)
+            .and_then(|sniffed| sniffed.parse().ok()),
+    };
+    sniffed.unwrap_or(guessed)
+}
+
+/// Content type, disposition and digest of a download of `display_name`
+fn download_headers(
+    builder: &mut HttpResponseBuilder,
+    display_name: &str,
+    content_type: Mime,
+    digest: Option<String>,
+) {
+    builder.content_type(content_type).append_header((
+        "Content-Disposition",
+        format!("attachment; filename=\"{}\"", display_name),
+    ));
+    if let Some(digest) = digest {
+        builder.append_header(("Digest", digest));
+    }
+}
+
+/// The headers a GET of a file of `size` bytes gets, without the body. The
+/// body size still sets `Content-Length`, and nothing is sent for HEAD.
+fn head_body(mut builder: HttpResponseBuilder, size: u64) -> HttpResponse {
+    builder
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .insert_header((header::CONTENT_LENGTH, size))
+        .body(SizedStream::new(
+            size,
+            stream::empty::<Result<Bytes, Error>>(),
+        ))
+}
+
+/// A locally stored attachment, streamed by `NamedFile`, which answers HEAD
+/// requests and ranges by itself
+fn local_file(
+    request: &HttpRequest,
+    file_path: PathBuf,
+    display_name: String,
+    content_type: Mime,
+    digest: Option<String>,
+) -> Result<HttpResponse, Error> {
+    let file_response = actix_files::NamedFile::open(file_path)?.set_content_type(content_type);
+    let file_response = file_response.set_content_disposition(header::ContentDisposition {
+        disposition: header::DispositionType::Attachment,
+        parameters: vec![header::DispositionParam::Filename(display_name)],
+    });
+    let mut response = file_response.into_response(request);
+    if let Some(digest) = digest {
+        if let Ok(value) = header::HeaderValue::from_str(&digest) {
+            response
+                .headers_mut()
+                .insert(header::HeaderName::from_static("digest"), value);
+        }
+    }
+    Ok(response)
+}
 
 #[post("/secure_file/{id}")]
 pub async fn post_secure_file(
+    request: HttpRequest,
     data: web::Data<AppState>,
     id: web::Path<String>,
     payload: Multipart,
@@ -20,37 +340,22 @@ pub async fn post_secure_file(
     // get access to the pasta collection
     let mut pastas = data.lock_pastas();
 
-    let id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id.into_inner()).unwrap_or(0)
-    };
+    let id = ids::to_u64(&id).unwrap_or(0);
 
     // remove expired pastas (including this one if needed)
     remove_expired(&mut pastas);
 
     // find the index of the pasta in the collection based on u64 id
-    let mut index: usize = 0;
-    let mut found: bool = false;
-    for (i, pasta) in pastas.iter().enumerate() {
-        if pasta.id == id {
-            index = i;
-            found = true;
-            break;
-        }
-    }
+    let index = pastas.position(id);
 
     let password = auth::password_from_multipart(payload).await?;
-    log::info!(
-        "Received password/key length: {}, first chars: {}...",
-        password.len(),
-        password.chars().take(8).collect::<String>()
-    );
 
-    if found {
+    if let Some(index) = index {
         if let Some(ref pasta_file) = pastas[index].file {
             let pasta_id = pastas[index].id_as_animals();
             let display_name = pasta_file.display_name().to_string();
+            let digest = pasta_file.digest_header();
+            let sha256 = pasta_file.sha256.clone();
 
             log::info!(
                 "Secure file download: pasta_id={}, file_name={}, is_s3_encrypted={}",
@@ -63,6 +368,15 @@ pub async fn post_secure_file(
             let storage_path = if pasta_file.is_s3_encrypted() {
                 // Encrypted file stored in S3
                 format!("s3://attachments/{}/data.enc", pasta_id)
+            } else if pasta_file.is_gcs_encrypted() {
+                // Encrypted file stored in GCS
+                format!("gcs://attachments/{}/data.enc", pasta_id)
+            } else if pasta_file.is_webdav_encrypted() {
+                // Encrypted file stored on WebDAV
+                format!("webdav://attachments/{}/data.enc", pasta_id)
+            } else if pasta_file.is_azure_encrypted() {
+                // Encrypted file stored in Azure
+                format!("azure://attachments/{}/data.enc", pasta_id)
             } else {
                 // Encrypted file stored locally
                 "data.enc".to_string()
@@ -85,61 +399,328 @@ pub async fn post_secure_file(
             );
 
             // Decrypt the data
-            let decrypted_data = decrypt_bytes(&encrypted_data, &password).map_err(|e| {
-                log::error!("Failed to decrypt: {:?}", e);
-                actix_web::error::ErrorUnauthorized("Failed to decrypt file")
+            let decrypted_data = decrypt_bytes_with_kdf(&encrypted_data, &password).map_err(|e| {
+                log::error!("Failed to decrypt: {}", e);
+                metrics::record_decrypt_failure();
+                actix_web::error::ErrorUnauthorized(format!("Failed to decrypt file: {}", e))
             })?;
 
-            // Set the content type based on the file extension
-            let content_type = mime_guess::from_path(&display_name)
-                .first_or_octet_stream()
-                .to_string();
+            // Only answer conditional requests once the password was proven
+            // correct, and without counting a read as nothing is sent
+            let etag = etag(sha256.as_deref(), &decrypted_data);
+            if if_none_match(&request, &etag) {
+                return Ok(not_modified(&etag));
+            }
+
+            // Create a response with the decrypted data. Its size and digest
+            // are only revealed once the password was proven correct.
+            let mut builder = HttpResponse::Ok();
+            download_headers(
+                &mut builder,
+                &display_name,
+                content_type_for(&display_name),
+                digest,
+            );
+            builder.append_header((header::ETAG, etag));
 
-            // Create a response with the decrypted data
-            let response = HttpResponse::Ok()
-                .content_type(content_type)
-                .append_header((
-                    "Content-Disposition",
-                    format!("attachment; filename=\"{}\"", display_name),
-                ))
-                .body(decrypted_data);
-            return Ok(response);
+            // Decrypted files are in memory anyway, so ranges are cut from them
+            let response = ranged_body(&request, builder, decrypted_data);
+            if !response.status().is_success() {
+                return Ok(response);
+            }
+
+            let burn = count_download(&mut pastas, index);
+            return Ok(burn_after_sent(response, burn, data.clone()));
         }
     }
+
+    if let Some(reason) = expired::reason(id) {
+        return Ok(HttpResponse::Gone()
+            .content_type("text/plain; charset=utf-8")
+            .body(reason.message()));
+    }
+
     Ok(HttpResponse::NotFound().finish())
 }
 
+/// Pasta id, storage path and display name of the file of the client side
+/// encrypted pasta `id`, or the response refusing it
+fn client_encrypted_file(
+    data: &web::Data<AppState>,
+    id: u64,
+) -> Result<(String, String, String), HttpResponse> {
+    let mut pastas = data.lock_pastas();
+
+    remove_expired(&mut pastas);
+
+    let Some(pasta) = pastas.get(id) else {
+        if let Some(reason) = expired::reason(id) {
+            return Err(HttpResponse::Gone()
+                .content_type("text/plain; charset=utf-8")
+                .body(reason.message()));
+        }
+        return Err(HttpResponse::NotFound().finish());
+    };
+
+    if !pasta.encrypt_client {
+        return Err(HttpResponse::Forbidden()
+            .content_type("text/plain; charset=utf-8")
+            .body("Only client side encrypted files can be downloaded encrypted."));
+    }
+    match (&pasta.file, pasta.file_storage_path()) {
+        (Some(pasta_file), Some(storage_path)) => Ok((
+            pasta.id_as_animals(),
+            storage_path,
+            pasta_file.display_name().to_string(),
+        )),
+        _ => Err(HttpResponse::NotFound().finish()),
+    }
+}
+
+/// The stored ciphertext of a client side encrypted file, for the browser to
+/// decrypt with a key the server never sees. Other pastas are refused, as
+/// their files are decrypted by the server or not encrypted at all.
+#[get("/raw_encrypted_file/{id}")]
+pub async fn get_raw_encrypted_file(
+    id: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let id = ids::to_u64(&id).unwrap_or(0);
+
+    let (pasta_id, storage_path, display_name) = match client_encrypted_file(&data, id) {
+        Ok(file) => file,
+        Err(response) => return Ok(response),
+    };
+
+    // the pastas are not locked while the file is fetched
+    let encrypted_data = storage::get_file(&pasta_id, &storage_path)
+        .await
+        .map_err(|e| {
+            log::error!("Failed to get file: {}", e);
+            actix_web::error::ErrorNotFound(e)
+        })?;
+
+    // someone else may have used up the last read in the meantime
+    let mut pastas = data.lock_pastas();
+    let Some(index) = pastas.position(id) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let mut builder = HttpResponse::Ok();
+    download_headers(
+        &mut builder,
+        &display_name,
+        mime::APPLICATION_OCTET_STREAM,
+        None,
+    );
+    let response = builder.body(encrypted_data);
+
+    let burn = count_download(&mut pastas, index);
+    Ok(burn_after_sent(response, burn, data.clone()))
+}
+
+#[derive(Deserialize)]
+pub struct ThumbnailQuery {
+    /// Width in pixels, clamped to what `thumbnail::clamp_width` allows
+    w: Option<u32>,
+}
+
+/// Pasta id and storage path of the image attached to pasta `id`, when it
+/// may be shown as a thumbnail: unencrypted, and without a read or download
+/// limit a thumbnail would get around
+fn thumbnail_source(data: &web::Data<AppState>, id: u64, width: u32) -> Option<(String, String)> {
+    let mut pastas = data.lock_pastas();
+
+    remove_expired(&mut pastas);
+
+    let pasta = pastas.get(id)?;
+    let file = pasta.file.as_ref()?;
+    let limited = pasta.one_time || pasta.burn_after_reads != 0 || pasta.download_limit != 0;
+    // an attachment stored under the name of the cached thumbnail is not one
+    if pasta.encrypt_server
+        || pasta.encrypt_client
+        || limited
+        || !file.is_image()
+        || file.name() == thumbnail::cache_name(width)
+    {
+        return None;
+    }
+    Some((pasta.id_as_animals(), file.name().to_string()))
+}
+
+/// A downscaled JPEG of the image attached to a pasta, for previews. It is
+/// made on the first request for a width and cached on disk after that.
+#[get("/thumb/{id}")]
+pub async fn get_thumbnail(
+    id: web::Path<String>,
+    query: web::Query<ThumbnailQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let id = ids::to_u64(&id).unwrap_or(0);
+    let width = thumbnail::clamp_width(query.w);
+
+    let Some((pasta_id, storage_path)) = thumbnail_source(&data, id, width) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let cache_path = thumbnail::cache_path(&pasta_id, width);
+    let thumb = match fs::read(&cache_path) {
+        Ok(cached) => cached,
+        Err(_) => {
+            let image = storage::get_file(&pasta_id, &storage_path)
+                .await
+                .map_err(actix_web::error::ErrorNotFound)?;
+            let thumb = match web::block(move || thumbnail::generate(&image, width)).await? {
+                Ok(thumb) => thumb,
+                Err(e) => {
+                    log::warn!("No thumbnail for pasta {}: {}", pasta_id, e);
+                    return Ok(HttpResponse::NotFound().finish());
+                }
+            };
+
+            // a pasta removed in the meantime would leave the cached copy behind
+            if data.lock_pastas().position(id).is_some() {
+                let dir_path = format!("{}/attachments/{}", ARGS.data_dir, pasta_id);
+                let cached =
+                    fs::create_dir_all(&dir_path).and_then(|_| fs::write(&cache_path, &thumb));
+                if let Err(e) = cached {
+                    log::error!("Failed to cache thumbnail {}: {}", cache_path, e);
+                }
+            }
+            thumb
+        }
+    };
+
+    Ok(HttpResponse::Ok().content_type("image/jpeg").body(thumb))
+}
+
 #[get("/file/{id}")]
 pub async fn get_file(
     request: actix_web::HttpRequest,
     id: web::Path<String>,
     data: web::Data<AppState>,
 ) -> Result<HttpResponse, Error> {
-    // get access to the pasta collection
+    serve_file(request, &id, 0, data).await
+}
+
+/// Download one of the attachments after the first one of a pasta
+#[get("/file/{id}/{index:\\d+}")]
+pub async fn get_extra_file(
+    request: actix_web::HttpRequest,
+    path: web::Path<(String, usize)>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let (id, file_index) = path.into_inner();
+    serve_file(request, &id, file_index, data).await
+}
+
+/// The headers of a download, to learn the size of a file before fetching
+/// it. Nothing is read, so this counts as no read.
+#[head("/file/{id}")]
+pub async fn head_file(
+    request: actix_web::HttpRequest,
+    id: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    file_head(request, &id, 0, data)
+}
+
+#[head("/file/{id}/{index:\\d+}")]
+pub async fn head_extra_file(
+    request: actix_web::HttpRequest,
+    path: web::Path<(String, usize)>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let (id, file_index) = path.into_inner();
+    file_head(request, &id, file_index, data)
+}
+
+fn file_head(
+    request: actix_web::HttpRequest,
+    id: &str,
+    file_index: usize,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
     let mut pastas = data.lock_pastas();
 
-    let id_intern = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id.into_inner()).unwrap_or(0)
-    };
+    let id_intern = ids::to_u64(id).unwrap_or(0);
 
-    // remove expired pastas (including this one if needed)
     remove_expired(&mut pastas);
 
-    // find the index of the pasta in the collection based on u64 id
-    let mut index: usize = 0;
-    let mut found: bool = false;
-    for (i, pasta) in pastas.iter().enumerate() {
-        if pasta.id == id_intern {
-            index = i;
-            found = true;
-            break;
+    let Some(pasta) = pastas.get(id_intern) else {
+        if let Some(reason) = expired::reason(id_intern) {
+            return Ok(HttpResponse::Gone()
+                .content_type("text/plain; charset=utf-8")
+                .body(reason.message()));
         }
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let Some(pasta_file) = pasta.file_at(file_index) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    // the size of encrypted files is only told along with their content
+    if pasta.encrypt_server {
+        return Ok(HttpResponse::Found()
+            .append_header(("Location", format!("/auth_file/{}", pasta.id_as_animals())))
+            .finish());
     }
 
-    if found {
-        if let Some(ref pasta_file) = pastas[index].file {
+    let display_name = pasta_file.display_name().to_string();
+    let digest = pasta_file.digest_header();
+    let sniffed = pasta_file.content_type.as_deref();
+    // client side encrypted files are stored as ciphertext
+    let sniff = !pasta.encrypt_client;
+
+    if pasta_file.is_remote() {
+        let mut builder = HttpResponse::Ok();
+        let content_type = download_content_type(&display_name, sniffed, || None);
+        download_headers(&mut builder, &display_name, content_type, digest);
+        // without a digest, the ETag of a GET is hashed from the content
+        if let Some(sha256) = pasta_file.sha256.as_deref() {
+            builder.insert_header((header::ETAG, etag(Some(sha256), &[])));
+        }
+        Ok(head_body(builder, pasta_file.size.as_u64()))
+    } else {
+        let pasta_id = pasta.id_as_animals();
+        let file_path = format!(
+            "{}/attachments/{}/{}",
+            ARGS.data_dir,
+            pasta_id,
+            pasta_file.name()
+        );
+        let content_type = download_content_type(&display_name, sniffed, || {
+            sniff
+                .then(|| storage::read_local_file_head(&pasta_id, pasta_file.name(), SNIFF_LENGTH))
+                .and_then(Result::ok)
+        });
+        local_file(
+            &request,
+            PathBuf::from(file_path),
+            display_name,
+            content_type,
+            digest,
+        )
+    }
+}
+
+async fn serve_file(
+    request: actix_web::HttpRequest,
+    id: &str,
+    file_index: usize,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    // get access to the pasta collection
+    let mut pastas = data.lock_pastas();
+
+    let id_intern = ids::to_u64(id).unwrap_or(0);
+
+    // remove expired pastas (including this one if needed)
+    remove_expired(&mut pastas);
+
+    // find the index of the pasta in the collection based on u64 id
+    if let Some(index) = pastas.position(id_intern) {
+        if let Some(pasta_file) = pastas[index].file_at(file_index) {
             if pastas[index].encrypt_server {
                 return Ok(HttpResponse::Found()
                     .append_header((
@@ -152,42 +733,493 @@ pub async fn get_file(
             let pasta_id = pastas[index].id_as_animals();
             let storage_path = pasta_file.name().to_string();
             let display_name = pasta_file.display_name().to_string();
+            let digest = pasta_file.digest_header();
+            let sniffed = pasta_file.content_type.clone();
+            // client side encrypted files are stored as ciphertext
+            let sniff = !pastas[index].encrypt_client;
+
+            if pasta_file.is_remote() {
+                // With a known digest, cached copies are confirmed without
+                // fetching the file. A 304 sends nothing, so it is no read.
+                let sha256 = pasta_file.sha256.clone();
+                if let Some(sha256) = sha256.as_deref() {
+                    let etag = etag(Some(sha256), &[]);
+                    if if_none_match(&request, &etag) {
+                        return Ok(not_modified(&etag));
+                    }
+                }
 
-            if pasta_file.is_s3() {
-                // File is stored in S3
-                let file_data = storage::get_file(&pasta_id, &storage_path)
+                let mut builder = HttpResponse::Ok();
+                let content_type =
+                    download_content_type(&display_name, sniffed.as_deref(), || None);
+                let unknown_type = content_type == mime::APPLICATION_OCTET_STREAM;
+                download_headers(&mut builder, &display_name, content_type, digest);
+                builder.insert_header((header::ACCEPT_RANGES, "bytes"));
+
+                // With a known digest and size, a requested range is fetched
+                // alone instead of the whole file
+                let size = pasta_file.size.as_u64();
+                let range = match sha256.as_deref() {
+                    Some(_) if size > 0 => match requested_range(&request, size) {
+                        Ok(range) => range,
+                        Err(response) => return Ok(response),
+                    },
+                    _ => None,
+                };
+
+                // File is stored in S3, GCS, WebDAV or Azure
+                let mut response = if let Some(range) = range {
+                    let file_data = storage::get_file_range(
+                        &pasta_id,
+                        &storage_path,
+                        range.start,
+                        range.length,
+                    )
                     .await
-                    .map_err(|e| actix_web::error::ErrorNotFound(e))?;
+                    .map_err(actix_web::error::ErrorNotFound)?;
 
-                let content_type = mime_guess::from_path(&display_name)
-                    .first_or_octet_stream()
-                    .to_string();
+                    builder.insert_header((header::ETAG, etag(sha256.as_deref(), &[])));
+                    partial_content(&mut builder, range, size);
+                    builder
+                        .insert_header((header::CONTENT_LENGTH, file_data.len()))
+                        .body(file_data)
+                } else {
+                    let file_data = storage::get_file(&pasta_id, &storage_path)
+                        .await
+                        .map_err(|e| actix_web::error::ErrorNotFound(e))?;
 
-                return Ok(HttpResponse::Ok()
-                    .content_type(content_type)
-                    .append_header((
-                        "Content-Disposition",
-                        format!("attachment; filename=\"{}\"", display_name),
-                    ))
-                    .body(file_data));
+                    let etag = etag(sha256.as_deref(), &file_data);
+                    if if_none_match(&request, &etag) {
+                        return Ok(not_modified(&etag));
+                    }
+
+                    // files fetched whole can still be sniffed
+                    if unknown_type && sniff {
+                        let content_type = download_content_type(&display_name, None, || {
+                            Some(file_data[..file_data.len().min(SNIFF_LENGTH)].to_vec())
+                        });
+                        builder.content_type(content_type);
+                    }
+
+                    builder.insert_header((header::ETAG, etag));
+                    let response = ranged_body(&request, builder, file_data);
+                    if !response.status().is_success() {
+                        return Ok(response);
+                    }
+                    response
+                };
+
+                let burn = count_download(&mut pastas, index);
+                insert_download_lifetime_headers(&mut response, &pastas, index, burn.as_ref());
+                return Ok(burn_after_sent(response, burn, data.clone()));
             } else {
                 // File is stored locally - use NamedFile for streaming
                 let file_path = format!(
                     "{}/attachments/{}/{}",
                     ARGS.data_dir, pasta_id, storage_path
                 );
-                let file_path = PathBuf::from(file_path);
-
-                let file_response = actix_files::NamedFile::open(file_path)?;
-                let file_response =
-                    file_response.set_content_disposition(header::ContentDisposition {
-                        disposition: header::DispositionType::Attachment,
-                        parameters: vec![header::DispositionParam::Filename(display_name)],
-                    });
-                return Ok(file_response.into_response(&request));
+                let content_type = download_content_type(&display_name, sniffed.as_deref(), || {
+                    sniff
+                        .then(|| {
+                            storage::read_local_file_head(&pasta_id, &storage_path, SNIFF_LENGTH)
+                        })
+                        .and_then(Result::ok)
+                });
+                let mut response = local_file(
+                    &request,
+                    PathBuf::from(file_path),
+                    display_name,
+                    content_type,
+                    digest,
+                )?;
+
+                let burn = count_download(&mut pastas, index);
+                insert_download_lifetime_headers(&mut response, &pastas, index, burn.as_ref());
+                return Ok(burn_after_sent(response, burn, data.clone()));
             }
         }
     }
 
+    if let Some(reason) = expired::reason(id_intern) {
+        return Ok(HttpResponse::Gone()
+            .content_type("text/plain; charset=utf-8")
+            .body(reason.message()));
+    }
+
     Ok(HttpResponse::NotFound().finish())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::create::new_pasta;
+    use crate::pasta::{PastaFile, PrivacyPreset};
+    use crate::util::rate_limit::{FailedLogins, RateLimiter};
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::{middleware, App};
+    use bytesize::ByteSize;
+    use std::sync::Mutex;
+
+    fn pasta(download_limit: u64) -> Pasta {
+        Pasta {
+            id: 4_023_456_789,
+            editable: false,
+            encrypted_key: None,
+            expiration: 0,
+            download_limit,
+            pasta_type: String::from("text"),
+            ..new_pasta(0)
+        }
+    }
+
+    #[test]
+    fn test_download_limit() {
+        let mut pastas = MemoryStore::new(vec![pasta(3)]);
+
+        assert!(count_download(&mut pastas, 0).is_none());
+        assert!(count_download(&mut pastas, 0).is_none());
+        assert_eq!(pastas[0].download_count, 2);
+
+        // the last download allowed takes the pasta out
+        let burnt = count_download(&mut pastas, 0).unwrap();
+        assert_eq!(burnt.download_limit, 3);
+        assert!(pastas.is_empty());
+
+        // views do not use up downloads
+        let mut viewed = pasta(1);
+        viewed.read_count = 10;
+        let mut pastas = MemoryStore::new(vec![viewed, pasta(0)]);
+        assert!(count_download(&mut pastas, 1).is_none());
+        assert!(count_download(&mut pastas, 0).is_some());
+        assert_eq!(pastas.len(), 1);
+        assert_eq!(pastas[0].download_count, 1);
+    }
+
+    #[test]
+    fn test_conditional_get() {
+        let tag = etag(None, b"hello");
+        assert_eq!(
+            tag,
+            "\"2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824\""
+        );
+        assert_eq!(etag(Some("abc"), b"hello"), "\"abc\"");
+
+        let request = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, format!("\"other\", W/{}", tag)))
+            .to_http_request();
+        assert!(if_none_match(&request, &tag));
+
+        let response = not_modified(&tag);
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers().get(header::ETAG).unwrap(), tag.as_str());
+        assert!(response.into_body().try_into_bytes().unwrap().is_empty());
+
+        let request = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, "\"other\""))
+            .to_http_request();
+        assert!(!if_none_match(&request, &tag));
+        assert!(!if_none_match(&TestRequest::default().to_http_request(), &tag));
+    }
+
+    #[test]
+    fn test_ranged_body() {
+        let data = b"hello world".to_vec();
+
+        let request = TestRequest::default()
+            .insert_header((header::RANGE, "bytes=6-10"))
+            .to_http_request();
+        let response = ranged_body(&request, HttpResponse::Ok(), data.clone());
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers().get(header::CONTENT_RANGE).unwrap(),
+            "bytes 6-10/11"
+        );
+        assert_eq!(response.into_body().try_into_bytes().unwrap(), "world");
+
+        let request = TestRequest::default()
+            .insert_header((header::RANGE, "bytes=-5"))
+            .to_http_request();
+        let response = ranged_body(&request, HttpResponse::Ok(), data.clone());
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.into_body().try_into_bytes().unwrap(), "world");
+
+        let response = ranged_body(
+            &TestRequest::default().to_http_request(),
+            HttpResponse::Ok(),
+            data.clone(),
+        );
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(header::ACCEPT_RANGES).unwrap(), "bytes");
+        assert_eq!(response.into_body().try_into_bytes().unwrap(), "hello world");
+
+        let request = TestRequest::default()
+            .insert_header((header::RANGE, "bytes=20-30"))
+            .to_http_request();
+        let response = ranged_body(&request, HttpResponse::Ok(), data);
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(
+            response.headers().get(header::CONTENT_RANGE).unwrap(),
+            "bytes */11"
+        );
+    }
+
+    #[test]
+    fn test_download_content_type_sniffs_unknown_names() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+
+        assert_eq!(
+            download_content_type("screenshot", None, || Some(png.clone())),
+            mime::IMAGE_PNG
+        );
+        assert_eq!(
+            download_content_type("screenshot", Some("image/gif"), || Some(png.clone())),
+            mime::IMAGE_GIF
+        );
+        // names that tell a type are trusted, without reading the file
+        assert_eq!(
+            download_content_type("notes.txt", None, || panic!("sniffed")),
+            mime::TEXT_PLAIN
+        );
+        assert_eq!(
+            download_content_type("screenshot", None, || None),
+            mime::APPLICATION_OCTET_STREAM
+        );
+    }
+
+    #[test]
+    fn test_head_matches_get() {
+        let data = b"hello world".to_vec();
+        let digest = Some(String::from("sha-256=abc"));
+
+        let mut builder = HttpResponse::Ok();
+        download_headers(&mut builder, "notes.txt", mime::TEXT_PLAIN, digest.clone());
+        let get = ranged_body(&TestRequest::default().to_http_request(), builder, data);
+
+        let mut builder = HttpResponse::Ok();
+        download_headers(&mut builder, "notes.txt", mime::TEXT_PLAIN, digest);
+        let head = head_body(builder, 11);
+
+        assert_eq!(head.status(), get.status());
+        for name in [
+            header::CONTENT_TYPE,
+            header::CONTENT_DISPOSITION,
+            header::CONTENT_LENGTH,
+            header::ACCEPT_RANGES,
+            header::HeaderName::from_static("digest"),
+        ] {
+            assert_eq!(head.headers().get(&name), get.headers().get(&name));
+        }
+        assert_eq!(get.headers().get(header::CONTENT_LENGTH).unwrap(), "11");
+        assert_eq!(head.body().size(), BodySize::Sized(11));
+
+        let request = TestRequest::default()
+            .insert_header((header::RANGE, "bytes=6-10"))
+            .to_http_request();
+        let ranged = ranged_body(&request, HttpResponse::Ok(), b"hello world".to_vec());
+        assert_eq!(ranged.headers().get(header::CONTENT_LENGTH).unwrap(), "5");
+    }
+
+    #[test]
+    fn test_local_file_head_matches_get() {
+        let dir = std::env::temp_dir().join(format!("microbin-head-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("stored-name");
+        std::fs::write(&path, "hello world").unwrap();
+
+        let get_request = TestRequest::default().to_http_request();
+        let get = local_file(
+            &get_request,
+            path.clone(),
+            String::from("notes.txt"),
+            mime::TEXT_PLAIN,
+            None,
+        )
+        .unwrap();
+        let head_request = TestRequest::default()
+            .method(actix_web::http::Method::HEAD)
+            .to_http_request();
+        let head = local_file(
+            &head_request,
+            path,
+            String::from("notes.txt"),
+            mime::TEXT_PLAIN,
+            None,
+        )
+        .unwrap();
+
+        for name in [
+            header::CONTENT_TYPE,
+            header::CONTENT_DISPOSITION,
+            header::ACCEPT_RANGES,
+            header::ETAG,
+        ] {
+            assert!(get.headers().contains_key(&name));
+            assert_eq!(head.headers().get(&name), get.headers().get(&name));
+        }
+        assert_eq!(head.body().size(), BodySize::Sized(11));
+        assert_eq!(get.body().size(), BodySize::Sized(11));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_download_lifetime_headers() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let mut pasta = pasta(0);
+        pasta.id = 4_091_234_569;
+        pasta.created = now;
+        pasta.last_read = now;
+        pasta.burn_after_reads = 2;
+        pasta.file = Some(PastaFile {
+            name: String::from("notes.txt"),
+            size: ByteSize::b(5),
+            original_name: None,
+            sha256: None,
+            content_type: None,
+            stored_size: None,
+        });
+        let pasta_id = pasta.id_as_animals();
+        storage::save_file(&pasta_id, "notes.txt", b"hello")
+            .await
+            .unwrap();
+
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(vec![pasta])),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(App::new().app_data(data).service(get_file)).await;
+        let get = || {
+            TestRequest::get()
+                .uri(&format!("/file/{}", pasta_id))
+                .to_request()
+        };
+
+        for remaining in ["1", "0"] {
+            let response = call_service(&app, get()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let headers = response.headers();
+            assert_eq!(
+                headers.get("X-Microbin-Reads-Remaining").unwrap(),
+                remaining
+            );
+            assert_eq!(headers.get("X-Microbin-Expires-In").unwrap(), "never");
+            assert_eq!(read_body(response).await, "hello");
+        }
+
+        // the last download burnt the pasta
+        let response = call_service(&app, get()).await;
+        assert!(!response.status().is_success());
+        pending::wait_for_pending().await;
+    }
+
+    #[actix_web::test]
+    async fn test_slug_case_and_trailing_slash() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let mut pasta = pasta(0);
+        pasta.id = 4_091_234_570;
+        pasta.created = now;
+        pasta.last_read = now;
+        pasta.file = Some(PastaFile {
+            name: String::from("notes.txt"),
+            size: ByteSize::b(5),
+            original_name: None,
+            sha256: None,
+            content_type: None,
+            stored_size: None,
+        });
+        let pasta_id = pasta.id_as_animals();
+        storage::save_file(&pasta_id, "notes.txt", b"hello")
+            .await
+            .unwrap();
+
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(vec![pasta])),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(
+            App::new()
+                .app_data(data)
+                .wrap(middleware::NormalizePath::trim())
+                .service(get_file),
+        )
+        .await;
+
+        for uri in [
+            format!("/file/{}", pasta_id.to_uppercase()),
+            format!("/file/{}/", pasta_id),
+        ] {
+            let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+            assert_eq!(read_body(response).await, "hello");
+        }
+
+        storage::delete_file(&pasta_id, "notes.txt").await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_raw_encrypted_file() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let encrypted_pasta = |id, preset| {
+            let mut pasta = pasta(0);
+            pasta.id = id;
+            pasta.created = now;
+            pasta.last_read = now;
+            pasta.apply_privacy(preset);
+            pasta.file = Some(PastaFile {
+                name: String::from("notes.txt"),
+                size: ByteSize::b(10),
+                original_name: None,
+                sha256: None,
+                content_type: None,
+                stored_size: None,
+            });
+            pasta
+        };
+        let secret = encrypted_pasta(4_091_234_567, PrivacyPreset::Secret);
+        let private = encrypted_pasta(4_091_234_568, PrivacyPreset::Private);
+        let (secret_id, private_id) = (secret.id_as_animals(), private.id_as_animals());
+        for id in [&secret_id, &private_id] {
+            storage::save_file(id, "data.enc", b"ciphertext")
+                .await
+                .unwrap();
+        }
+
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(vec![secret, private])),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(App::new().app_data(data).service(get_raw_encrypted_file)).await;
+        let get = |id: &str| {
+            TestRequest::get()
+                .uri(&format!("/raw_encrypted_file/{}", id))
+                .to_request()
+        };
+
+        let response = call_service(&app, get(&secret_id)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/octet-stream"
+        );
+        assert_eq!(read_body(response).await, "ciphertext");
+
+        let response = call_service(&app, get(&private_id)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        for id in [&secret_id, &private_id] {
+            storage::delete_file(id, "data.enc").await.unwrap();
+        }
+    }
+}
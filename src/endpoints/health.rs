@@ -0,0 +1,71 @@
+use crate::util::db;
+use crate::util::storage;
+use actix_web::{get, web, HttpResponse};
+use serde_json::json;
+
+/// Liveness probe, answering as long as the server handles requests
+#[get("/healthz")]
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+/// Readiness probe, checking the database is writable and the storage
+/// backend reachable. Like the liveness probe, it needs no authentication.
+#[get("/readyz")]
+pub async fn readyz() -> HttpResponse {
+    let database = web::block(db::check_writable)
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+    if let Err(e) = database {
+        log::error!("Readiness check failed: {}", e);
+        return readiness(Err("database not writable"));
+    }
+
+    if let Err(e) = storage::check().await {
+        log::error!("Readiness check failed: {}", e);
+        return readiness(Err("storage unreachable"));
+    }
+
+    readiness(Ok(()))
+}
+
+/// Answer of the readiness probe. Only a short reason is given, the details
+/// of a failed check are logged.
+fn readiness(result: Result<(), &str>) -> HttpResponse {
+    match result {
+        Ok(()) => HttpResponse::Ok().json(json!({ "status": "ok" })),
+        Err(reason) => HttpResponse::ServiceUnavailable()
+            .json(json!({ "status": "unavailable", "reason": reason })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+
+    #[actix_web::test]
+    async fn test_healthz() {
+        let app = test::init_service(App::new().service(healthz)).await;
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri("/healthz").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn test_readiness() {
+        let ready = readiness(Ok(()));
+        assert_eq!(ready.status(), StatusCode::OK);
+
+        let unavailable = readiness(Err("storage unreachable"));
+        assert_eq!(unavailable.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = actix_web::body::to_bytes(unavailable.into_body())
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            json!({ "status": "unavailable", "reason": "storage unreachable" })
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use actix_web::{get, web, HttpResponse};
 use askama::Template;
+use serde::Deserialize;
+use std::cmp::Reverse;
 
 use crate::args::{Args, ARGS};
 use crate::pasta::Pasta;
@@ -9,33 +11,202 @@ use crate::AppState;
 #[derive(Template)]
 #[template(path = "list.html")]
 struct ListTemplate<'a> {
-    pastas: &'a Vec<Pasta>,
+    pastas: &'a [&'a Pasta],
+    kind: &'a str,
     args: &'a Args,
+    /// The page shown, counted from 1
+    page: usize,
+    pages: usize,
+    per_page: usize,
+    /// Listed pastas on all pages together
+    total: usize,
+}
+
+impl ListTemplate<'_> {
+    /// Link to another page of the same list
+    fn page_url(&self, page: &usize) -> String {
+        let mut url = format!("{}/list?page={}", self.args.public_path_as_str(), page);
+        if !self.kind.is_empty() {
+            url.push_str(&format!("&type={}", self.kind));
+        }
+        if self.per_page != DEFAULT_PER_PAGE {
+            url.push_str(&format!("&per_page={}", self.per_page));
+        }
+        url
+    }
+}
+
+/// Pastas on a page of the list when no `per_page` is given
+const DEFAULT_PER_PAGE: usize = 50;
+/// Most pastas shown on one page
+const MAX_PER_PAGE: usize = 500;
+
+#[derive(Deserialize)]
+pub struct ListQuery {
+    /// Only show pastas of this type: text, markdown, file or url
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    /// Page to show, counted from 1
+    page: Option<usize>,
+    per_page: Option<usize>,
+}
+
+/// Page `page` of `items`, counted from 1, with `per_page` items on a page.
+/// Pages past the end are empty.
+fn paginate<T>(items: &[T], page: usize, per_page: usize) -> &[T] {
+    let start = page
+        .saturating_sub(1)
+        .saturating_mul(per_page)
+        .min(items.len());
+    let end = start.saturating_add(per_page).min(items.len());
+    &items[start..end]
+}
+
+/// Whether `pasta` belongs in the list filtered by `kind`, an empty `kind` shows everything
+fn matches_kind(pasta: &Pasta, kind: &str) -> bool {
+    match kind {
+        "file" => pasta.has_file(),
+        "markdown" => {
+            !pasta.has_file()
+                && pasta.pasta_type == "text"
+                && (pasta.extension == "md" || pasta.extension == "markdown")
+        }
+        "text" | "url" => !pasta.has_file() && pasta.pasta_type == kind,
+        _ => true,
+    }
 }
 
 #[get("/list")]
-pub async fn list(data: web::Data<AppState>) -> HttpResponse {
+pub async fn list(data: web::Data<AppState>, query: web::Query<ListQuery>) -> HttpResponse {
     if ARGS.no_listing {
         return HttpResponse::Found()
             .append_header(("Location", format!("{}/", ARGS.public_path_as_str())))
             .finish();
     }
 
+    // unknown types show the whole list
+    let kind = query
+        .kind
+        .as_deref()
+        .filter(|kind| matches!(*kind, "text" | "markdown" | "file" | "url"))
+        .unwrap_or_default();
+
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+
     let mut pastas = data.lock_pastas();
 
     remove_expired(&mut pastas);
 
+    let mut listed: Vec<&Pasta> = pastas
+        .iter()
+        .filter(|p| p.is_listed() && matches_kind(p, kind))
+        .collect();
+
     // sort pastas in reverse-chronological order of creation time
-    pastas.sort_by(|a, b| b.created.cmp(&a.created));
+    listed.sort_by_key(|p| Reverse(p.created));
+
+    // pages past the end show the last one
+    let total = listed.len();
+    let pages = total.div_ceil(per_page).max(1);
+    let page = query.page.unwrap_or(1).clamp(1, pages);
 
     HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
         .body(
             ListTemplate {
-                pastas: &pastas,
+                pastas: paginate(&listed, page, per_page),
+                kind,
                 args: &ARGS,
+                page,
+                pages,
+                per_page,
+                total,
             }
             .render()
             .unwrap(),
         )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::create::new_pasta;
+    use crate::endpoints::{api, pasta as pasta_endpoint};
+    use crate::pasta::PrivacyPreset;
+    use crate::util::rate_limit::{FailedLogins, RateLimiter};
+    use crate::util::store::MemoryStore;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::App;
+    use std::sync::Mutex;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn pasta(id: u64, created: i64) -> Pasta {
+        Pasta {
+            id,
+            content: String::from("listed"),
+            editable: false,
+            encrypted_key: None,
+            expiration: 0,
+            pasta_type: String::from("text"),
+            ..new_pasta(created)
+        }
+    }
+
+    #[test]
+    fn test_paginate_second_page() {
+        let pastas: Vec<Pasta> = (0..50).map(|i| pasta(i, 1000 + i as i64)).collect();
+        let mut listed: Vec<&Pasta> = pastas.iter().collect();
+        listed.sort_by_key(|p| Reverse(p.created));
+
+        // newest first, so page 2 of 20 holds ids 29 down to 10
+        let page: Vec<u64> = paginate(&listed, 2, 20).iter().map(|p| p.id).collect();
+        assert_eq!(page, (10..30).rev().collect::<Vec<u64>>());
+
+        assert_eq!(paginate(&listed, 3, 20).len(), 10);
+        assert!(paginate(&listed, 4, 20).is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_unlisted_pasta_is_reachable_but_not_listed() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let public = pasta(4_078_901_234, now);
+        let mut unlisted = pasta(4_078_901_235, now);
+        unlisted.apply_privacy(PrivacyPreset::Unlisted);
+        let (public_id, unlisted_id) = (public.id_as_animals(), unlisted.id_as_animals());
+
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(vec![public, unlisted])),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(
+            App::new()
+                .app_data(data)
+                .service(list)
+                .service(api::list_pastas)
+                .service(pasta_endpoint::getpasta),
+        )
+        .await;
+        let get = |uri: String| TestRequest::get().uri(&uri).to_request();
+
+        let page = read_body(call_service(&app, get(String::from("/list"))).await).await;
+        let page = String::from_utf8(page.to_vec()).unwrap();
+        assert!(page.contains(&public_id));
+        assert!(!page.contains(&unlisted_id));
+
+        let listing =
+            read_body(call_service(&app, get(String::from("/api/v1/pastas"))).await).await;
+        let listing = String::from_utf8(listing.to_vec()).unwrap();
+        assert!(listing.contains(&public_id));
+        assert!(!listing.contains(&unlisted_id));
+
+        let response = call_service(&app, get(format!("/upload/{}", unlisted_id))).await;
+        assert!(response.status().is_success());
+    }
+}
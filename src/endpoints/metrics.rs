@@ -0,0 +1,20 @@
+use crate::args::ARGS;
+use crate::util::metrics;
+use crate::util::misc::remove_expired;
+use crate::AppState;
+use actix_web::{get, web, HttpResponse};
+
+/// Counters and gauges for Prometheus, when `enable_metrics` is set
+#[get("/metrics")]
+pub async fn get_metrics(data: web::Data<AppState>) -> HttpResponse {
+    if !ARGS.enable_metrics {
+        return HttpResponse::NotFound().finish();
+    }
+
+    let mut pastas = data.lock_pastas();
+    remove_expired(&mut pastas);
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(metrics::render(&pastas))
+}
@@ -0,0 +1,190 @@
+use crate::args::ARGS;
+use crate::pasta::Pasta;
+use crate::util::ids;
+use crate::util::misc::remove_expired;
+use crate::util::store::PastaStore;
+use crate::AppState;
+use actix_web::{get, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_WIDTH: u32 = 800;
+const DEFAULT_HEIGHT: u32 = 600;
+
+#[derive(Deserialize)]
+pub struct OEmbedQuery {
+    url: String,
+    format: Option<String>,
+    maxwidth: Option<u32>,
+    maxheight: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct OEmbedResponse {
+    version: &'static str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: String,
+    provider_name: String,
+    provider_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    html: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+}
+
+impl OEmbedResponse {
+    fn new(pasta: &Pasta, query: &OEmbedQuery) -> Self {
+        let slug = pasta.id_as_animals();
+        let title = pasta.title.clone().unwrap_or_else(|| slug.clone());
+        let provider_name = ARGS
+            .title
+            .clone()
+            .unwrap_or_else(|| String::from("MicroBin"));
+        let provider_url = format!("{}/", ARGS.public_path_as_str());
+
+        // url pastas are links to somewhere else, there is nothing to render
+        if pasta.pasta_type == "url" && !pasta.has_file() {
+            return Self {
+                version: "1.0",
+                kind: "link",
+                title,
+                provider_name,
+                provider_url,
+                html: None,
+                width: None,
+                height: None,
+            };
+        }
+
+        let width = query
+            .maxwidth
+            .map_or(DEFAULT_WIDTH, |w| w.min(DEFAULT_WIDTH));
+        let height = query
+            .maxheight
+            .map_or(DEFAULT_HEIGHT, |h| h.min(DEFAULT_HEIGHT));
+        let html = format!(
+            "<iframe src=\"{}/upload/{}\" width=\"{}\" height=\"{}\" title=\"{}\" frameborder=\"0\"></iframe>",
+            ARGS.public_path_as_str(),
+            slug,
+            width,
+            height,
+            html_escape::encode_double_quoted_attribute(&title)
+        );
+
+        Self {
+            version: "1.0",
+            kind: "rich",
+            title,
+            provider_name,
+            provider_url,
+            html: Some(html),
+            width: Some(width),
+            height: Some(height),
+        }
+    }
+
+    fn to_xml(&self) -> String {
+        let mut fields = vec![
+            ("version", self.version.to_string()),
+            ("type", self.kind.to_string()),
+            ("title", self.title.clone()),
+            ("provider_name", self.provider_name.clone()),
+            ("provider_url", self.provider_url.clone()),
+        ];
+        if let Some(html) = self.html.as_ref() {
+            fields.push(("html", html.clone()));
+        }
+        if let Some(width) = self.width {
+            fields.push(("width", width.to_string()));
+        }
+        if let Some(height) = self.height {
+            fields.push(("height", height.to_string()));
+        }
+
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n<oembed>\n",
+        );
+        for (name, value) in fields {
+            xml.push_str(&format!(
+                "  <{}>{}</{}>\n",
+                name,
+                html_escape::encode_text(&value),
+                name
+            ));
+        }
+        xml.push_str("</oembed>\n");
+        xml
+    }
+}
+
+/// Extract the pasta slug from a pasta URL of this instance
+fn slug_from_url(url: &str) -> Option<&str> {
+    let url = url.split(['?', '#']).next()?;
+    let public_path = ARGS.public_path_as_str();
+    let short_path = ARGS.short_path_as_str();
+
+    let path = if public_path.is_empty() {
+        // without a public path, accept any host and only look at the path
+        match url.split_once("://") {
+            Some((_, rest)) => &rest[rest.find('/')?..],
+            None => url,
+        }
+    } else {
+        url.strip_prefix(public_path.as_str())
+            .or_else(|| url.strip_prefix(short_path.as_str()))?
+    };
+
+    let mut segments = path.trim_matches('/').split('/');
+    match (segments.next(), segments.next(), segments.next()) {
+        (Some("upload" | "p" | "raw" | "url" | "u"), Some(slug), None) if !slug.is_empty() => {
+            Some(slug)
+        }
+        _ => None,
+    }
+}
+
+#[get("/oembed")]
+pub async fn oembed(data: web::Data<AppState>, query: web::Query<OEmbedQuery>) -> HttpResponse {
+    if !ARGS.enable_oembed {
+        return HttpResponse::NotFound().finish();
+    }
+
+    // as per the oEmbed spec, unsupported formats are answered with 501
+    let format = query.format.as_deref().unwrap_or("json");
+    if format != "json" && format != "xml" {
+        return HttpResponse::NotImplemented().finish();
+    }
+
+    let slug = match slug_from_url(&query.url) {
+        Some(slug) => slug,
+        None => return HttpResponse::NotFound().finish(),
+    };
+
+    let id = ids::to_u64(slug).unwrap_or(0);
+
+    let mut pastas = data.lock_pastas();
+
+    remove_expired(&mut pastas);
+
+    let pasta = match pastas.get(id) {
+        Some(pasta) => pasta,
+        None => return HttpResponse::NotFound().finish(),
+    };
+
+    // never leak anything about pastas that are not meant to be public
+    if pasta.private || pasta.encrypt_server || pasta.encrypt_client {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let response = OEmbedResponse::new(pasta, &query);
+
+    if format == "xml" {
+        HttpResponse::Ok()
+            .content_type("text/xml; charset=utf-8")
+            .body(response.to_xml())
+    } else {
+        HttpResponse::Ok().json(response)
+    }
+}
@@ -0,0 +1,102 @@
+use crate::args::ARGS;
+use crate::endpoints::api;
+use crate::util::auth;
+use actix_web::{get, HttpResponse};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+/// OpenAPI document of the JSON API, generated from the handlers
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "MicroBin API"),
+    paths(
+        api::create_paste,
+        api::paste_info,
+        api::list_pastas,
+        api::edit_paste
+    ),
+    components(schemas(
+        api::NewPaste,
+        api::PasteEdit,
+        api::PasteLink,
+        api::PasteInfo,
+        api::FileInfo,
+        api::ListedPaste,
+        api::ApiError
+    )),
+    modifiers(&Authentication)
+)]
+struct ApiDoc;
+
+/// Adds how requests authenticate, the owner cookie name depends on the instance
+struct Authentication;
+
+impl Modify for Authentication {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some(
+                        "The uploader password to create pastas, the admin password to edit them",
+                    ))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "admin_header",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                auth::ADMIN_HEADER.to_string(),
+                "The admin password, for instances behind basic auth".to_string(),
+            ))),
+        );
+        components.add_security_scheme(
+            "uploader_header",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                auth::UPLOADER_HEADER.to_string(),
+                "The uploader password, for instances behind basic auth".to_string(),
+            ))),
+        );
+        components.add_security_scheme(
+            "owner_token",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::with_description(
+                ARGS.owner_cookie_name(),
+                "Owner token handed out with the upload of the pasta".to_string(),
+            ))),
+        );
+    }
+}
+
+/// The OpenAPI document, for clients to generate bindings from
+#[get("/api/openapi.json")]
+pub async fn get_openapi() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::App;
+
+    #[actix_web::test]
+    async fn test_openapi_document() {
+        let app = init_service(App::new().service(get_openapi)).await;
+        let request = TestRequest::get().uri("/api/openapi.json").to_request();
+        let response = call_service(&app, request).await;
+        assert!(response.status().is_success());
+
+        let document: utoipa::openapi::OpenApi = read_body_json(response).await;
+        assert!(document.paths.paths.contains_key("/api/v1/paste"));
+        assert!(document.paths.paths.contains_key("/api/v1/paste/{id}"));
+        assert!(document.paths.paths.contains_key("/api/v1/paste/{id}/info"));
+        assert!(document.paths.paths.contains_key("/api/v1/pastas"));
+
+        let components = document.components.unwrap();
+        assert!(components.schemas.contains_key("NewPaste"));
+        assert!(components.security_schemes.contains_key("bearer"));
+        assert!(components.security_schemes.contains_key("admin_header"));
+    }
+}
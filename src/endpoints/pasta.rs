@@ -1,16 +1,19 @@
 use crate::args::{Args, ARGS};
-use crate::endpoints::errors::ErrorTemplate;
+use crate::endpoints::errors::{not_found_or_expired, ErrorTemplate};
 use crate::pasta::Pasta;
-use crate::util::animalnumbers::to_u64;
 use crate::util::auth;
 use crate::util::db::update;
-use crate::util::hashids::to_u64 as hashid_to_u64;
-use crate::util::misc::remove_expired;
+use crate::util::expired;
+use crate::util::ids;
+use crate::util::misc::{decrypt_with_kdf, remove_expired, save_or_burn};
+use crate::util::store::PastaStore;
 use crate::AppState;
 use actix_multipart::Multipart;
+use actix_web::http::header::{self, HeaderName, HeaderValue};
 use actix_web::{get, post, web, Error, HttpRequest, HttpResponse};
 use askama::Template;
-use magic_crypt::{new_magic_crypt, MagicCryptTrait};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use rand::Rng;
 
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -19,6 +22,46 @@ use std::time::{SystemTime, UNIX_EPOCH};
 struct PastaTemplate<'a> {
     pasta: &'a Pasta,
     args: &'a Args,
+    /// Allows the page's own script under the Content-Security-Policy
+    csp_nonce: &'a str,
+}
+
+/// A fresh nonce for the script of a pasta page
+fn csp_nonce() -> String {
+    let bytes: [u8; 16] = rand::thread_rng().gen();
+    BASE64_STANDARD.encode(bytes)
+}
+
+/// `policy` for a page whose script has `nonce`, unless it is empty
+fn content_security_policy(policy: &str, nonce: &str) -> Option<String> {
+    let policy = policy.trim();
+    (!policy.is_empty()).then(|| policy.replace("{nonce}", nonce))
+}
+
+/// Tell scripts how close a pasta is to being deleted, in the
+/// `X-Microbin-Reads-Remaining` and `X-Microbin-Expires-In` headers. A
+/// `burnt` pasta was just read for the last time.
+pub fn insert_lifetime_headers(response: &mut HttpResponse, pasta: &Pasta, burnt: bool) {
+    let timenow = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |n| n.as_secs() as i64);
+
+    let reads_remaining = match pasta.reads_remaining() {
+        _ if burnt => HeaderValue::from(0u64),
+        Some(reads) => HeaderValue::from(reads),
+        None => HeaderValue::from_static("unlimited"),
+    };
+    let expires_in = match pasta.expires_in(timenow) {
+        Some(seconds) => HeaderValue::from(seconds),
+        None => HeaderValue::from_static("never"),
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(
+        HeaderName::from_static("x-microbin-reads-remaining"),
+        reads_remaining,
+    );
+    headers.insert(HeaderName::from_static("x-microbin-expires-in"), expires_in);
 }
 
 fn pastaresponse(
@@ -30,27 +73,13 @@ fn pastaresponse(
     // get access to the pasta collection
     let mut pastas = data.lock_pastas();
 
-    let id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id.into_inner()).unwrap_or(0)
-    };
+    let id = ids::to_u64(&id).unwrap_or(0);
 
     // remove expired pastas (including this one if needed)
     remove_expired(&mut pastas);
 
     // find the index of the pasta in the collection based on u64 id
-    let mut index: usize = 0;
-    let mut found: bool = false;
-    for (i, pasta) in pastas.iter().enumerate() {
-        if pasta.id == id {
-            index = i;
-            found = true;
-            break;
-        }
-    }
-
-    if found {
+    if let Some(index) = pastas.position(id) {
         if pastas[index].encrypt_server && password == *"" {
             return HttpResponse::Found()
                 .append_header((
@@ -76,7 +105,7 @@ fn pastaresponse(
 
         // decrypt content temporarily
         if password != *"" && !original_content.is_empty() {
-            let res = decrypt(&original_content, &password);
+            let res = decrypt_with_kdf(&original_content, &password);
             if let Ok(..) = res {
                 pastas[index]
                     .content
@@ -96,16 +125,21 @@ fn pastaresponse(
         }
 
         // serve pasta in template
-        let response = HttpResponse::Ok()
-            .content_type("text/html; charset=utf-8")
-            .body(
-                PastaTemplate {
-                    pasta: &pastas[index],
-                    args: &ARGS,
-                }
-                .render()
-                .unwrap(),
-            );
+        let nonce = csp_nonce();
+        let mut builder = HttpResponse::Ok();
+        builder.content_type("text/html; charset=utf-8");
+        if let Some(policy) = content_security_policy(&ARGS.content_security_policy, &nonce) {
+            builder.insert_header((header::CONTENT_SECURITY_POLICY, policy));
+        }
+        let mut response = builder.body(
+            PastaTemplate {
+                pasta: &pastas[index],
+                args: &ARGS,
+                csp_nonce: &nonce,
+            }
+            .render()
+            .unwrap(),
+        );
 
         if pastas[index].content != original_content {
             pastas[index].content = original_content;
@@ -122,17 +156,16 @@ fn pastaresponse(
 
         // update last read time
         pastas[index].last_read = timenow;
+        insert_lifetime_headers(&mut response, &pastas[index], false);
 
-        // save the updated read count
-        update(Some(&pastas), Some(&pastas[index]));
+        // save the updated read count, or burn the pasta if this was its last read
+        save_or_burn(&mut pastas, index);
 
         return response;
     }
 
-    // otherwise send pasta not found error
-    HttpResponse::Ok()
-        .content_type("text/html; charset=utf-8")
-        .body(ErrorTemplate { args: &ARGS }.render().unwrap())
+    // otherwise send pasta not found or expired error
+    not_found_or_expired(id)
 }
 
 #[post("/upload/{id}")]
@@ -165,46 +198,16 @@ pub async fn getpasta(
 
     // the user attached an owner_token. likely they're the same user that created the pasta
     // but let's verify it just in case
-    if let Some(cookie) = req.cookie("owner_token") {
-        if verify_owner_token(cookie.value(), &id) {
-            // yay, it really is the same user and their cookie isn't expired
-            // so let's skip incrementing the read count
-            skip_increment = true;
-        }
+    let target_id = ids::to_u64(&id).unwrap_or(0);
+    if auth::is_owner(&req, target_id) {
+        // yay, it really is the same user and their cookie isn't expired
+        // so let's skip incrementing the read count
+        skip_increment = true;
     }
 
     pastaresponse(data, id, String::from(""), skip_increment)
 }
 
-// when creating a pasta, the owner is issued a token with a 15-second expiration
-// this token is used to avoid incrementing the read count of the pasta when the owner views it
-fn verify_owner_token(token: &str, id: &str) -> bool {
-    // decode the token
-    if let Ok(numbers) = crate::util::hashids::HARSH.decode(token) {
-        if numbers.len() == 2 {
-            let expiry = numbers[0];
-            let token_id = numbers[1];
-            let timenow = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-
-            // verify the token is valid
-            let target_id = if ARGS.hash_ids {
-                hashid_to_u64(id).unwrap_or(0)
-            } else {
-                to_u64(id).unwrap_or(0)
-            };
-
-            if token_id == target_id && expiry > timenow {
-                // yay, it's valid
-                return true;
-            }
-        }
-    }
-    false
-}
-
 #[get("/p/{id}")]
 pub async fn getshortpasta(data: web::Data<AppState>, id: web::Path<String>) -> HttpResponse {
     pastaresponse(data, id, String::from(""), false)
@@ -214,28 +217,13 @@ fn urlresponse(data: web::Data<AppState>, id: web::Path<String>) -> HttpResponse
     // get access to the pasta collection
     let mut pastas = data.lock_pastas();
 
-    let id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id.into_inner()).unwrap_or(0)
-    };
+    let id = ids::to_u64(&id).unwrap_or(0);
 
     // remove expired pastas (including this one if needed)
     remove_expired(&mut pastas);
 
     // find the index of the pasta in the collection based on u64 id
-    let mut index: usize = 0;
-    let mut found: bool = false;
-
-    for (i, pasta) in pastas.iter().enumerate() {
-        if pasta.id == id {
-            index = i;
-            found = true;
-            break;
-        }
-    }
-
-    if found {
+    if let Some(index) = pastas.position(id) {
         // increment read count
         pastas[index].read_count += 1;
 
@@ -245,7 +233,7 @@ fn urlresponse(data: web::Data<AppState>, id: web::Path<String>) -> HttpResponse
         // send redirect if it's a url pasta
         if pastas[index].pasta_type == "url" {
             let response = HttpResponse::Found()
-                .append_header(("Location", String::from(&pastas[index].content)))
+                .append_header(("Location", pastas[index].text().into_owned()))
                 .finish();
 
             // get current unix time in seconds
@@ -260,8 +248,8 @@ fn urlresponse(data: web::Data<AppState>, id: web::Path<String>) -> HttpResponse
             // update last read time
             pastas[index].last_read = timenow;
 
-            // save the updated read count
-            update(Some(&pastas), Some(&pastas[index]));
+            // save the updated read count, or burn the pasta if this was its last read
+            save_or_burn(&mut pastas, index);
 
             return response;
         // send error if we're trying to open a non-url pasta as a redirect
@@ -272,10 +260,8 @@ fn urlresponse(data: web::Data<AppState>, id: web::Path<String>) -> HttpResponse
         }
     }
 
-    // otherwise send pasta not found error
-    HttpResponse::Ok()
-        .content_type("text/html; charset=utf-8")
-        .body(ErrorTemplate { args: &ARGS }.render().unwrap())
+    // otherwise send pasta not found or expired error
+    not_found_or_expired(id)
 }
 
 #[get("/url/{id}")]
@@ -296,27 +282,13 @@ pub async fn getrawpasta(
     // get access to the pasta collection
     let mut pastas = data.lock_pastas();
 
-    let id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id.into_inner()).unwrap_or(0)
-    };
+    let id = ids::to_u64(&id).unwrap_or(0);
 
     // remove expired pastas (including this one if needed)
     remove_expired(&mut pastas);
 
     // find the index of the pasta in the collection based on u64 id
-    let mut index: usize = 0;
-    let mut found: bool = false;
-    for (i, pasta) in pastas.iter().enumerate() {
-        if pasta.id == id {
-            index = i;
-            found = true;
-            break;
-        }
-    }
-
-    if found {
+    if let Some(index) = pastas.position(id) {
         if pastas[index].encrypt_server {
             return Ok(HttpResponse::Found()
                 .append_header((
@@ -351,12 +323,21 @@ pub async fn getrawpasta(
         // send raw content of pasta
         let response = Ok(HttpResponse::Ok()
             .content_type("text/plain; charset=utf-8")
-            .body(pastas[index].content.to_owned()));
+            .body(pastas[index].text().into_owned()));
+
+        // save the updated read count, or burn the pasta if this was its last read
+        save_or_burn(&mut pastas, index);
 
         return response;
     }
 
-    // otherwise send pasta not found error as raw text
+    // otherwise send pasta expired or not found error as raw text
+    if let Some(reason) = expired::reason(id) {
+        return Ok(HttpResponse::Gone()
+            .content_type("text/plain; charset=utf-8")
+            .body(reason.message()));
+    }
+
     Ok(HttpResponse::NotFound()
         .content_type("text/html; charset=utf-8")
         .body(String::from("Upload not found! :-(")))
@@ -373,27 +354,13 @@ pub async fn postrawpasta(
     // get access to the pasta collection
     let mut pastas = data.lock_pastas();
 
-    let id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id.into_inner()).unwrap_or(0)
-    };
+    let id = ids::to_u64(&id).unwrap_or(0);
 
     // remove expired pastas (including this one if needed)
     remove_expired(&mut pastas);
 
     // find the index of the pasta in the collection based on u64 id
-    let mut index: usize = 0;
-    let mut found: bool = false;
-    for (i, pasta) in pastas.iter().enumerate() {
-        if pasta.id == id {
-            index = i;
-            found = true;
-            break;
-        }
-    }
-
-    if found {
+    if let Some(index) = pastas.position(id) {
         if pastas[index].encrypt_server && password == *"" {
             return Ok(HttpResponse::Found()
                 .append_header((
@@ -417,7 +384,7 @@ pub async fn postrawpasta(
 
         // decrypt content temporarily
         if password != *"" {
-            let res = decrypt(&original_content, &password);
+            let res = decrypt_with_kdf(&original_content, &password);
             if res.is_ok() {
                 pastas[index]
                     .content
@@ -448,29 +415,256 @@ pub async fn postrawpasta(
         // update last read time
         pastas[index].last_read = timenow;
 
-        // save the updated read count
-        update(Some(&pastas), Some(&pastas[index]));
-
         // send raw content of pasta
         let response = Ok(HttpResponse::Ok()
             .content_type("text/plain; charset=utf-8")
-            .body(pastas[index].content.to_owned()));
+            .body(pastas[index].text().into_owned()));
 
         if pastas[index].content != original_content {
             pastas[index].content = original_content;
         }
 
+        // save the updated read count, or burn the pasta if this was its last read
+        save_or_burn(&mut pastas, index);
+
         return response;
     }
 
-    // otherwise send pasta not found error as raw text
+    // otherwise send pasta expired or not found error as raw text
+    if let Some(reason) = expired::reason(id) {
+        return Ok(HttpResponse::Gone()
+            .content_type("text/plain; charset=utf-8")
+            .body(reason.message()));
+    }
+
     Ok(HttpResponse::NotFound()
         .content_type("text/html; charset=utf-8")
         .body(String::from("Upload not found! :-(")))
 }
 
-fn decrypt(text_str: &str, key_str: &str) -> Result<String, magic_crypt::MagicCryptError> {
-    let mc = new_magic_crypt!(key_str, 256);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::create::new_pasta;
+    use crate::util::rate_limit::{FailedLogins, RateLimiter};
+    use crate::util::store::MemoryStore;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::{middleware, App};
+    use std::sync::Mutex;
+
+    fn html_pasta(id: u64) -> Pasta {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        Pasta {
+            id,
+            content: String::from("<p>hello</p><script>alert(1)</script>"),
+            extension: String::from("html"),
+            editable: false,
+            encrypted_key: None,
+            expiration: 0,
+            pasta_type: String::from("text"),
+            ..new_pasta(now)
+        }
+    }
+
+    #[test]
+    fn test_content_security_policy() {
+        assert_eq!(
+            content_security_policy("script-src 'nonce-{nonce}'", "abc"),
+            Some(String::from("script-src 'nonce-abc'"))
+        );
+        assert_eq!(content_security_policy("  ", "abc"), None);
+    }
+
+    #[actix_web::test]
+    async fn test_rendered_html_pasta_has_csp() {
+        let pasta = html_pasta(4_034_567_890);
+        let uri = format!("/upload/{}", pasta.id_as_animals());
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(vec![pasta])),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(App::new().app_data(data).service(getpasta)).await;
+
+        let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+        assert!(response.status().is_success());
+        let policy = response
+            .headers()
+            .get(header::CONTENT_SECURITY_POLICY)
+            .expect("pasta pages have a Content-Security-Policy")
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(policy.contains("script-src 'self' 'nonce-"));
+
+        // the page's own script carries the nonce, the pasta's does not
+        let nonce = policy
+            .split("'nonce-")
+            .nth(1)
+            .and_then(|rest| rest.split('\'').next())
+            .unwrap();
+        let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+        assert!(body.contains(&format!("<script nonce=\"{}\">", nonce)));
+        assert!(body.contains("sandbox=\"\""));
+    }
+
+    /// Read a burn after one read pasta with 16 concurrent requests to
+    /// `route`, each through a server of its own sharing the pastas, and
+    /// count the requests that were served its content
+    fn concurrent_reads(route: &str, id: u64) -> usize {
+        use std::sync::{Arc, Barrier};
+        use std::thread;
+
+        let pasta = Pasta {
+            content: String::from("one-time secret"),
+            extension: String::from(""),
+            burn_after_reads: 1,
+            ..html_pasta(id)
+        };
+        let uri = format!("{}/{}", route, pasta.id_as_animals());
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(vec![pasta])),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let barrier = Arc::new(Barrier::new(16));
+
+        let readers: Vec<_> = (0..16)
+            .map(|_| {
+                let data = data.clone();
+                let barrier = Arc::clone(&barrier);
+                let uri = uri.clone();
+                thread::spawn(move || {
+                    actix_web::rt::System::new().block_on(async move {
+                        let app = init_service(
+                            App::new()
+                                .app_data(data)
+                                .service(getpasta)
+                                .service(getrawpasta),
+                        )
+                        .await;
+                        barrier.wait();
+                        let response =
+                            call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+                        let served = response.status().is_success();
+                        let body = read_body(response).await;
+                        served && String::from_utf8_lossy(&body).contains("one-time secret")
+                    })
+                })
+            })
+            .collect();
+
+        let served = readers
+            .into_iter()
+            .map(|reader| reader.join().unwrap())
+            .filter(|&served| served)
+            .count();
+        assert!(data.lock_pastas().is_empty());
+        served
+    }
+
+    #[test]
+    fn test_burn_after_one_concurrent_reads() {
+        assert_eq!(concurrent_reads("/upload", 4_034_567_896), 1);
+        assert_eq!(concurrent_reads("/raw", 4_034_567_897), 1);
+    }
 
-    mc.decrypt_base64_to_string(text_str)
+    #[actix_web::test]
+    async fn test_slug_case_and_trailing_slash() {
+        let pasta = html_pasta(4_034_567_894);
+        let slug = pasta.id_as_animals();
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(vec![pasta])),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(
+            App::new()
+                .app_data(data)
+                .wrap(middleware::NormalizePath::trim())
+                .service(getpasta),
+        )
+        .await;
+
+        for uri in [
+            format!("/upload/{}", slug.to_uppercase()),
+            format!("/upload/{}/", slug),
+        ] {
+            let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            assert!(response.status().is_success(), "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_lifetime_headers_count_down() {
+        let header = |response: &actix_web::dev::ServiceResponse, name: &str| {
+            response
+                .headers()
+                .get(name)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let mut limited = html_pasta(4_034_567_892);
+        limited.burn_after_reads = 3;
+        limited.expiration = limited.created + 3600;
+        let unlimited = html_pasta(4_034_567_893);
+        let (limited_uri, unlimited_uri) = (
+            format!("/p/{}", limited.id_as_animals()),
+            format!("/p/{}", unlimited.id_as_animals()),
+        );
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(vec![limited, unlimited])),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(App::new().app_data(data).service(getshortpasta)).await;
+
+        for remaining in ["2", "1", "0"] {
+            let request = TestRequest::get().uri(&limited_uri).to_request();
+            let response = call_service(&app, request).await;
+            assert!(response.status().is_success());
+            assert_eq!(header(&response, "X-Microbin-Reads-Remaining"), remaining);
+            let expires_in: i64 = header(&response, "X-Microbin-Expires-In").parse().unwrap();
+            assert!((3590..=3600).contains(&expires_in));
+        }
+        let request = TestRequest::get().uri(&limited_uri).to_request();
+        assert!(!call_service(&app, request).await.status().is_success());
+
+        let request = TestRequest::get().uri(&unlimited_uri).to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(header(&response, "X-Microbin-Reads-Remaining"), "unlimited");
+        assert_eq!(header(&response, "X-Microbin-Expires-In"), "never");
+    }
+
+    #[actix_web::test]
+    async fn test_poisoned_pastas_still_served() {
+        let pasta = html_pasta(4_034_567_891);
+        let uri = format!("/upload/{}", pasta.id_as_animals());
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(vec![pasta])),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+
+        // a handler panicking with the lock held poisons it
+        let poisoner = data.clone();
+        let _ = std::thread::spawn(move || {
+            let _pastas = poisoner.lock_pastas();
+            panic!("request failed while holding the pastas");
+        })
+        .join();
+        assert!(data.pastas.is_poisoned());
+
+        let app = init_service(App::new().app_data(data.clone()).service(getpasta)).await;
+        for _ in 0..2 {
+            let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            assert!(response.status().is_success());
+        }
+    }
 }
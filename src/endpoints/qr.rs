@@ -1,12 +1,13 @@
 use crate::args::{Args, ARGS};
 use crate::endpoints::errors::ErrorTemplate;
 use crate::pasta::Pasta;
-use crate::util::animalnumbers::to_u64;
-use crate::util::hashids::to_u64 as hashid_to_u64;
+use crate::util::ids;
 use crate::util::misc::{self, remove_expired};
+use crate::util::store::PastaStore;
 use crate::AppState;
 use actix_web::{get, web, HttpResponse};
 use askama::Template;
+use serde::Deserialize;
 
 #[derive(Template)]
 #[template(path = "qr.html", escape = "none")]
@@ -16,43 +17,112 @@ struct QRTemplate<'a> {
     args: &'a Args,
 }
 
+#[derive(Deserialize)]
+pub struct QrQuery {
+    size: Option<usize>,
+    ecc: Option<String>,
+}
+
+/// Where the QR code of a pasta leads: the redirect for URL pastas, the
+/// pasta page otherwise
+fn qr_target(pasta: &Pasta, id: &str) -> String {
+    match pasta.pasta_type.as_str() {
+        "url" => match ARGS.short_path.as_ref() {
+            Some(short) => format!("{short}/u/{id}"),
+            _ => format!("{}/url/{}", &ARGS.public_path_as_str(), &id),
+        },
+        _ => match ARGS.short_path.as_ref() {
+            Some(short) => format!("{short}/p/{id}"),
+            _ => format!("{}/upload/{}", &ARGS.public_path_as_str(), &id),
+        },
+    }
+}
+
+/// Format of QR code images
+enum QrImage {
+    Svg,
+    Png,
+}
+
+/// The QR code of a pasta as an image, with the size and error correction
+/// optionally given in the query, e.g. for printing
+fn qr_image(
+    data: &web::Data<AppState>,
+    id: &str,
+    query: &QrQuery,
+    format: QrImage,
+) -> HttpResponse {
+    let ecc = match query.ecc.as_deref() {
+        Some(ecc) => match misc::parse_qr_ecc(ecc) {
+            Some(ecc) => ecc,
+            None => {
+                return HttpResponse::BadRequest()
+                    .body("Invalid ecc, expected low, medium, quartile or high.")
+            }
+        },
+        None => misc::default_qr_ecc(),
+    };
+    let size = query.size.unwrap_or(ARGS.qr_size);
+
+    let u64_id = ids::to_u64(id).unwrap_or(0);
+
+    let mut pastas = data.lock_pastas();
+
+    remove_expired(&mut pastas);
+
+    let Some(pasta) = pastas.get(u64_id) else {
+        return HttpResponse::NotFound()
+            .content_type("text/html; charset=utf-8")
+            .body(ErrorTemplate { args: &ARGS }.render().unwrap());
+    };
+    let target = qr_target(pasta, id);
+
+    match format {
+        QrImage::Svg => HttpResponse::Ok()
+            .content_type("image/svg+xml")
+            .body(misc::string_to_qr_svg(&target, ecc, size)),
+        QrImage::Png => HttpResponse::Ok()
+            .content_type("image/png")
+            .body(misc::string_to_qr_png(&target, ecc, size)),
+    }
+}
+
+#[get("/qr/{id}.svg")]
+pub async fn get_qr_svg(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    query: web::Query<QrQuery>,
+) -> HttpResponse {
+    qr_image(&data, &id, &query, QrImage::Svg)
+}
+
+#[get("/qr/{id}.png")]
+pub async fn get_qr_png(
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+    query: web::Query<QrQuery>,
+) -> HttpResponse {
+    qr_image(&data, &id, &query, QrImage::Png)
+}
+
 #[get("/qr/{id}")]
 pub async fn getqr(data: web::Data<AppState>, id: web::Path<String>) -> HttpResponse {
     // get access to the pasta collection
     let mut pastas = data.lock_pastas();
 
-    let u64_id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id).unwrap_or(0)
-    };
+    let u64_id = ids::to_u64(&id).unwrap_or(0);
 
     // remove expired pastas (including this one if needed)
     remove_expired(&mut pastas);
 
     // find the index of the pasta in the collection based on u64 id
-    let mut index: usize = 0;
-    let mut found: bool = false;
-    for (i, pasta) in pastas.iter().enumerate() {
-        if pasta.id == u64_id {
-            index = i;
-            found = true;
-            break;
-        }
-    }
-
-    if found {
+    if let Some(index) = pastas.position(u64_id) {
         // generate the QR code as an SVG - if its a file or text pastas, this will point to the /upload endpoint, otherwise to the /url endpoint, essentially directly taking the user to the url stored in the pasta
-        let svg = misc::string_to_qr_svg(&match pastas[index].pasta_type.as_str() {
-            "url" => match ARGS.short_path.as_ref() {
-                Some(short) => format!("{short}/u/{id}"),
-                _ => format!("{}/url/{}", &ARGS.public_path_as_str(), &id),
-            },
-            _ => match ARGS.short_path.as_ref() {
-                Some(short) => format!("{short}/p/{id}"),
-                _ => format!("{}/upload/{}", &ARGS.public_path_as_str(), &id),
-            },
-        });
+        let svg = misc::string_to_qr_svg(
+            &qr_target(&pastas[index], &id),
+            misc::default_qr_ecc(),
+            ARGS.qr_size,
+        );
 
         // serve qr code in template
         return HttpResponse::Ok()
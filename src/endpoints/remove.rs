@@ -1,114 +1,110 @@
 use actix_multipart::Multipart;
-use actix_web::{get, post, web, Error, HttpResponse};
+use actix_web::{get, post, web, Error, HttpRequest, HttpResponse};
 
 use crate::args::ARGS;
-use crate::endpoints::errors::ErrorTemplate;
-use crate::pasta::PastaFile;
-use crate::util::animalnumbers::to_u64;
+use crate::endpoints::errors::not_found_or_expired;
 use crate::util::auth;
 use crate::util::db::delete;
-use crate::util::hashids::to_u64 as hashid_to_u64;
-use crate::util::misc::{decrypt, remove_expired};
+use crate::util::ids;
+use crate::util::metrics;
+use crate::util::misc::{decrypt_with_kdf, remove_expired};
 use crate::util::storage;
+use crate::util::store::PastaStore;
+use crate::util::webhook;
 use crate::AppState;
-use askama::Template;
 
 #[get("/remove/{id}")]
-pub async fn remove(data: web::Data<AppState>, id: web::Path<String>) -> HttpResponse {
+pub async fn remove(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    id: web::Path<String>,
+) -> HttpResponse {
     let mut pastas = data.lock_pastas();
 
-    let id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id.into_inner()).unwrap_or(0)
-    };
-
-    for (i, pasta) in pastas.iter().enumerate() {
-        if pasta.id == id {
-            // if it's encrypted or read-only, it needs password to be deleted
-            // OR if it is not editable (public immutable), it needs admin password to be deleted
-            if pasta.encrypt_server || pasta.readonly || !pasta.editable {
-                return HttpResponse::Found()
-                    .append_header((
-                        "Location",
-                        format!(
-                            "{}/auth_remove_private/{}",
-                            ARGS.public_path_as_str(),
-                            pasta.id_as_animals()
-                        ),
-                    ))
-                    .finish();
-            }
+    let id = ids::to_u64(&id).unwrap_or(0);
+
+    if let Some(i) = pastas.position(id) {
+        let pasta = &pastas[i];
+        // if it's encrypted or read-only, it needs password to be deleted
+        // OR if it is not editable (public immutable), it needs admin password to be deleted
+        // unless the uploader still holds the owner token from creating it
+        if (pasta.encrypt_server || pasta.readonly || !pasta.editable)
+            && !auth::is_owner(&req, id)
+        {
+            return HttpResponse::Found()
+                .append_header((
+                    "Location",
+                    format!(
+                        "{}/auth_remove_private/{}",
+                        ARGS.public_path_as_str(),
+                        pasta.id_as_animals()
+                    ),
+                ))
+                .finish();
+        }
 
-            let pasta_id = pasta.id_as_animals();
+        let pasta_id = pasta.id_as_animals();
 
-            // remove the file using storage abstraction
-            if let Some(PastaFile { name, .. }) = &pasta.file {
-                let filename = name.clone();
-                // Need to drop the lock before await
-                drop(pastas);
+        // remove the files using storage abstraction
+        let filenames = pasta.file_storage_paths();
+        if !filenames.is_empty() {
+            // Need to drop the lock before await
+            drop(pastas);
 
+            for filename in filenames {
                 if let Err(e) = storage::delete_file(&pasta_id, &filename).await {
                     log::error!("Failed to delete file {}: {}", filename, e);
                 }
+            }
 
-                // Re-acquire lock
-                pastas = data.lock_pastas();
-
-                // Find the pasta again (index may have changed)
-                let mut new_index = None;
-                for (j, p) in pastas.iter().enumerate() {
-                    if p.id == id {
-                        new_index = Some(j);
-                        break;
-                    }
-                }
-
-                if let Some(idx) = new_index {
-                    pastas.remove(idx);
-                }
-
-                delete(Some(&pastas), Some(id));
+            // Re-acquire lock
+            pastas = data.lock_pastas();
 
-                return HttpResponse::Found()
-                    .append_header(("Location", format!("{}/list", ARGS.public_path_as_str())))
-                    .finish();
+            // Find the pasta again (index may have changed)
+            if let Some(idx) = pastas.position(id) {
+                let pasta = pastas.remove(idx);
+                metrics::record(webhook::Event::Deleted);
+                webhook::notify(webhook::Event::Deleted, &pasta);
             }
 
-            // remove it from in-memory pasta list
-            pastas.remove(i);
-
             delete(Some(&pastas), Some(id));
 
             return HttpResponse::Found()
                 .append_header(("Location", format!("{}/list", ARGS.public_path_as_str())))
                 .finish();
         }
+
+        // remove it from in-memory pasta list
+        let pasta = pastas.remove(i);
+        metrics::record(webhook::Event::Deleted);
+        webhook::notify(webhook::Event::Deleted, &pasta);
+
+        delete(Some(&pastas), Some(id));
+
+        return HttpResponse::Found()
+            .append_header(("Location", format!("{}/list", ARGS.public_path_as_str())))
+            .finish();
     }
 
     remove_expired(&mut pastas);
 
-    HttpResponse::Ok()
-        .content_type("text/html; charset=utf-8")
-        .body(ErrorTemplate { args: &ARGS }.render().unwrap())
+    not_found_or_expired(id)
 }
 
 #[post("/remove/{id}")]
 pub async fn post_remove(
+    req: HttpRequest,
     data: web::Data<AppState>,
     id: web::Path<String>,
     payload: Multipart,
 ) -> Result<HttpResponse, Error> {
-    let id = if ARGS.hash_ids {
-        hashid_to_u64(&id).unwrap_or(0)
-    } else {
-        to_u64(&id.into_inner()).unwrap_or(0)
-    };
+    let id = ids::to_u64(&id).unwrap_or(0);
 
     let password = auth::password_from_multipart(payload).await?;
+    let is_owner = auth::is_owner(&req, id);
 
     // First, check if we need to delete a file and collect the info we need
-    let file_to_delete: Option<(String, String)>;
+    let files_to_delete: Vec<String>;
     let pasta_animals: String;
     let should_delete: bool;
     let is_protected: bool;
@@ -118,12 +114,10 @@ pub async fn post_remove(
         let mut pastas = data.lock_pastas();
         remove_expired(&mut pastas);
 
-        let pasta = pastas.iter().find(|p| p.id == id);
+        let pasta = pastas.get(id);
 
         if pasta.is_none() {
-            return Ok(HttpResponse::Ok()
-                .content_type("text/html; charset=utf-8")
-                .body(ErrorTemplate { args: &ARGS }.render().unwrap()));
+            return Ok(not_found_or_expired(id));
         }
 
         let pasta = pasta.unwrap();
@@ -134,26 +128,26 @@ pub async fn post_remove(
             // Not protected, redirect to upload page
             redirect_to_upload = true;
             should_delete = false;
-            file_to_delete = None;
-        } else if password.is_empty() {
+            files_to_delete = Vec::new();
+        } else if password.is_empty() && !is_owner {
             // Protected but no password provided
             redirect_to_upload = false;
             should_delete = false;
-            file_to_delete = None;
+            files_to_delete = Vec::new();
         } else {
             // Check password
-            let mut is_password_correct = password == ARGS.auth_admin_password;
+            let mut is_password_correct = is_owner || password == ARGS.auth_admin_password;
 
             if !is_password_correct && pasta.readonly {
                 if let Some(ref encrypted_key) = pasta.encrypted_key {
-                    if let Ok(decrypted_key) = decrypt(encrypted_key, &password) {
+                    if let Ok(decrypted_key) = decrypt_with_kdf(encrypted_key, &password) {
                         if decrypted_key == id.to_string() {
                             is_password_correct = true;
                         }
                     }
                 }
             } else if !is_password_correct && pasta.encrypt_server {
-                if decrypt(&pasta.content, &password).is_ok() {
+                if decrypt_with_kdf(&pasta.content, &password).is_ok() {
                     is_password_correct = true;
                 }
             }
@@ -161,24 +155,11 @@ pub async fn post_remove(
             if is_password_correct {
                 redirect_to_upload = false;
                 should_delete = true;
-                file_to_delete = pasta.file.as_ref().map(|f| {
-                    let storage_path = if pasta.encrypt_server {
-                        // Encrypted file - determine if S3 or local
-                        if f.is_s3_encrypted() {
-                            format!("s3://attachments/{}/data.enc", pasta_animals)
-                        } else {
-                            "data.enc".to_string()
-                        }
-                    } else {
-                        // Non-encrypted - use stored path directly
-                        f.name.clone()
-                    };
-                    (pasta_animals.clone(), storage_path)
-                });
+                files_to_delete = pasta.file_storage_paths();
             } else {
                 redirect_to_upload = false;
                 should_delete = false;
-                file_to_delete = None;
+                files_to_delete = Vec::new();
             }
         }
     } // Lock released here
@@ -205,9 +186,9 @@ pub async fn post_remove(
             .finish());
     }
 
-    // Delete file if exists
-    if let Some((pasta_id, filename)) = file_to_delete {
-        if let Err(e) = storage::delete_file(&pasta_id, &filename).await {
+    // Delete files if any
+    for filename in files_to_delete {
+        if let Err(e) = storage::delete_file(&pasta_animals, &filename).await {
             log::error!("Failed to delete file {}: {}", filename, e);
         }
     }
@@ -215,8 +196,10 @@ pub async fn post_remove(
     // Re-acquire lock and remove from list
     {
         let mut pastas = data.lock_pastas();
-        if let Some(idx) = pastas.iter().position(|p| p.id == id) {
-            pastas.remove(idx);
+        if let Some(idx) = pastas.position(id) {
+            let pasta = pastas.remove(idx);
+            metrics::record(webhook::Event::Deleted);
+            webhook::notify(webhook::Event::Deleted, &pasta);
         }
         delete(Some(&pastas), Some(id));
     }
@@ -225,3 +208,61 @@ pub async fn post_remove(
         .append_header(("Location", format!("{}/list", ARGS.public_path_as_str())))
         .finish())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::create::new_pasta;
+    use crate::pasta::Pasta;
+    use crate::util::rate_limit::{FailedLogins, RateLimiter};
+    use crate::util::store::MemoryStore;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{middleware, App};
+    use std::sync::Mutex;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[actix_web::test]
+    async fn test_slug_case_and_trailing_slash() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let pastas: Vec<Pasta> = [4_056_789_112, 4_056_789_113]
+            .into_iter()
+            .map(|id| Pasta {
+                id,
+                editable: true,
+                ..new_pasta(now)
+            })
+            .collect();
+        let uris = [
+            format!("/remove/{}", pastas[0].id_as_animals().to_uppercase()),
+            format!("/remove/{}/", pastas[1].id_as_animals()),
+        ];
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(pastas)),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .wrap(middleware::NormalizePath::trim())
+                .service(remove),
+        )
+        .await;
+
+        for uri in uris {
+            let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            assert_eq!(response.status(), actix_web::http::StatusCode::FOUND);
+            assert!(response
+                .headers()
+                .get("Location")
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .ends_with("/list"));
+        }
+        assert!(data.lock_pastas().is_empty());
+    }
+}
@@ -0,0 +1,795 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::{delete, patch, post, route, web, HttpRequest, HttpResponse};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use bytesize::ByteSize;
+use futures::TryStreamExt;
+use lazy_static::lazy_static;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::args::ARGS;
+use crate::endpoints::create::{
+    burn_after_to_reads, check_bounded_lifetime, check_password_strength, check_storage_quota,
+    expiration_to_timestamp, file_type_allowed, generate_uploader_token, is_known_expiration,
+    new_pasta, persist_pasta, save_pasta_file, UploadedData, FILE_TYPE_NOT_ALLOWED,
+};
+use crate::pasta::{Pasta, PastaFile, PrivacyPreset};
+use crate::util::auth;
+use crate::util::misc::remove_expired;
+use crate::util::storage::{self, BackendKind};
+use crate::AppState;
+
+const TUS_VERSION: &str = "1.0.0";
+
+/// Unfinished uploads untouched for this long are removed
+const STALE_AFTER: Duration = Duration::from_secs(60 * 60 * 24);
+
+lazy_static! {
+    /// Uploads currently receiving a PATCH, so that concurrent requests
+    /// cannot append to the same upload at the same time
+    static ref IN_PROGRESS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+
+    /// Passwords of unfinished private uploads. They are never written to
+    /// disk, so these uploads cannot be finished after a restart.
+    static ref PASSWORDS: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+}
+
+/// State of an unfinished upload, stored next to its partial data
+#[derive(Serialize, Deserialize)]
+struct TusUpload {
+    length: u64,
+    /// The upload metadata, without the password and uploader_password
+    metadata: HashMap<String, String>,
+}
+
+fn upload_dir() -> PathBuf {
+    PathBuf::from(format!("{}/tus", ARGS.data_dir))
+}
+
+fn info_path(upload_id: &str) -> PathBuf {
+    upload_dir().join(format!("{}.info", upload_id))
+}
+
+fn data_path(upload_id: &str) -> PathBuf {
+    upload_dir().join(format!("{}.part", upload_id))
+}
+
+fn is_valid_upload_id(upload_id: &str) -> bool {
+    upload_id.len() == 32 && upload_id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn read_upload(upload_id: &str) -> Option<(TusUpload, u64)> {
+    if !is_valid_upload_id(upload_id) {
+        return None;
+    }
+    let info = fs::read(info_path(upload_id)).ok()?;
+    let upload = serde_json::from_slice(&info).ok()?;
+    let offset = fs::metadata(data_path(upload_id)).ok()?.len();
+    Some((upload, offset))
+}
+
+/// Bytes announced by the unfinished uploads, counted against the storage
+/// quota before they arrive
+fn reserved_bytes() -> u64 {
+    let Ok(entries) = fs::read_dir(upload_dir()) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .filter(|entry| entry.path().extension().is_some_and(|e| e == "info"))
+        .filter_map(|entry| fs::read(entry.path()).ok())
+        .filter_map(|info| serde_json::from_slice::<TusUpload>(&info).ok())
+        .map(|upload| upload.length)
+        .sum()
+}
+
+fn take_password(upload_id: &str) -> Option<String> {
+    PASSWORDS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(upload_id)
+}
+
+fn remove_upload(upload_id: &str) {
+    take_password(upload_id);
+    let _ = fs::remove_file(info_path(upload_id));
+    let _ = fs::remove_file(data_path(upload_id));
+}
+
+/// Remove unfinished uploads that were abandoned by their clients
+fn remove_stale_uploads() {
+    let Ok(entries) = fs::read_dir(upload_dir()) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let stale = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age > STALE_AFTER);
+        if stale {
+            log::info!("Removing stale tus upload {:?}", entry.file_name());
+            let _ = fs::remove_file(entry.path());
+            if let Some(upload_id) = entry.path().file_stem().and_then(|s| s.to_str()) {
+                take_password(upload_id);
+            }
+        }
+    }
+}
+
+/// Parse the Upload-Metadata header: comma separated "key base64value" pairs
+fn parse_metadata(header: &str) -> Option<HashMap<String, String>> {
+    let mut metadata = HashMap::new();
+    for pair in header.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (key, value) = match pair.split_once(' ') {
+            Some((key, value)) => (key, BASE64_STANDARD.decode(value.trim()).ok()?),
+            None => (pair, Vec::new()),
+        };
+        metadata.insert(key.to_string(), String::from_utf8(value).ok()?);
+    }
+    Some(metadata)
+}
+
+fn header<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+fn tus_response(mut builder: actix_web::HttpResponseBuilder) -> actix_web::HttpResponseBuilder {
+    builder
+        .append_header(("Tus-Resumable", TUS_VERSION))
+        .append_header(("Cache-Control", "no-store"));
+    builder
+}
+
+fn error(builder: actix_web::HttpResponseBuilder, message: &str) -> HttpResponse {
+    tus_response(builder)
+        .content_type("text/plain; charset=utf-8")
+        .body(message.to_string())
+}
+
+fn check_enabled() -> Option<HttpResponse> {
+    if !ARGS.enable_tus || ARGS.no_file_upload {
+        return Some(HttpResponse::NotFound().finish());
+    }
+    None
+}
+
+/// Check the client speaks our protocol version
+fn check_version(req: &HttpRequest) -> Option<HttpResponse> {
+    if header(req, "Tus-Resumable") != Some(TUS_VERSION) {
+        return Some(
+            tus_response(HttpResponse::PreconditionFailed())
+                .append_header(("Tus-Version", TUS_VERSION))
+                .finish(),
+        );
+    }
+    None
+}
+
+/// Same rules as the upload form: with an uploader password, either the
+/// uploader cookie or the password in the upload metadata is required
+fn is_authorized(req: &HttpRequest, metadata: &HashMap<String, String>) -> bool {
+    if !ARGS.readonly || ARGS.uploader_password.is_none() {
+        return true;
+    }
+    let expected_password = ARGS.uploader_password.as_ref().unwrap().trim();
+    let expected_token = generate_uploader_token(expected_password);
+
+    req.cookie(&ARGS.uploader_cookie_name())
+        .is_some_and(|c| auth::constant_time_eq(c.value(), &expected_token))
+        || metadata
+            .get("uploader_password")
+            .is_some_and(|p| auth::constant_time_eq(p.trim(), expected_password))
+}
+
+fn max_size(metadata: &HashMap<String, String>) -> u64 {
+    let limit_mb = if metadata.get("privacy").map(String::as_str) == Some("private") {
+        ARGS.max_file_size_encrypted_mb
+    } else {
+        ARGS.max_file_size_unencrypted_mb
+    };
+    limit_mb as u64 * 1024 * 1024
+}
+
+#[route("/tus", method = "OPTIONS")]
+pub async fn options() -> HttpResponse {
+    if let Some(response) = check_enabled() {
+        return response;
+    }
+    tus_response(HttpResponse::NoContent())
+        .append_header(("Tus-Version", TUS_VERSION))
+        .append_header(("Tus-Extension", "creation,termination"))
+        .append_header((
+            "Tus-Max-Size",
+            (ARGS.max_file_size_unencrypted_mb as u64 * 1024 * 1024).to_string(),
+        ))
+        .finish()
+}
+
+/// Create a new upload. The pasta settings are passed as upload metadata:
+/// filename (required), expiration, burn_after, privacy (public, unlisted
+/// or private), password (for private uploads) and uploader_password.
+#[post("/tus")]
+pub async fn create_upload(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    if let Some(response) = check_enabled() {
+        return response;
+    }
+    start_upload(req, data).await
+}
+
+async fn start_upload(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    if let Some(response) = check_version(&req) {
+        return response;
+    }
+
+    let length = match header(&req, "Upload-Length").and_then(|l| l.parse::<u64>().ok()) {
+        Some(length) => length,
+        None => {
+            return error(
+                HttpResponse::BadRequest(),
+                "Missing or invalid Upload-Length.",
+            )
+        }
+    };
+
+    let metadata = match parse_metadata(header(&req, "Upload-Metadata").unwrap_or("")) {
+        Some(metadata) => metadata,
+        None => return error(HttpResponse::BadRequest(), "Invalid Upload-Metadata."),
+    };
+
+    if !is_authorized(&req, &metadata) {
+        return error(HttpResponse::Unauthorized(), "Incorrect uploader password.");
+    }
+
+    if metadata.get("filename").map_or(true, |f| f.is_empty()) {
+        return error(HttpResponse::BadRequest(), "Missing filename metadata.");
+    }
+    match PastaFile::from_unsanitized(&metadata["filename"]) {
+        Ok(file) if file_type_allowed(&file) => {}
+        Ok(_) => return error(HttpResponse::BadRequest(), FILE_TYPE_NOT_ALLOWED),
+        Err(_) => return error(HttpResponse::BadRequest(), "Invalid file name."),
+    }
+
+    match metadata.get("privacy").map(String::as_str) {
+        None | Some("public") | Some("unlisted") => {}
+        Some("private") => {
+            if !ARGS.encryption_server_side
+                || metadata.get("password").map_or(true, |p| p.is_empty())
+            {
+                return error(
+                    HttpResponse::BadRequest(),
+                    "Private uploads need server side encryption and a password.",
+                );
+            }
+            if let Err(message) =
+                check_password_strength(&metadata["password"], ARGS.min_password_length)
+            {
+                return error(HttpResponse::BadRequest(), &message);
+            }
+        }
+        Some(_) => return error(HttpResponse::BadRequest(), "Invalid privacy metadata."),
+    }
+    if !ARGS.privacy_mode_enabled(metadata.get("privacy").map_or("public", String::as_str)) {
+        return error(
+            HttpResponse::BadRequest(),
+            "This privacy mode is disabled on this instance.",
+        );
+    }
+
+    if ARGS.confirm_eternal
+        && metadata.get("expiration").map(String::as_str) == Some("never")
+        && metadata.get("confirm_never").map(String::as_str) != Some("true")
+    {
+        return error(
+            HttpResponse::BadRequest(),
+            "Uploads that never expire must be confirmed with confirm_never=true.",
+        );
+    }
+
+    let expiration = metadata
+        .get("expiration")
+        .map_or(ARGS.default_expiry.as_str(), String::as_str);
+    if !is_known_expiration(expiration) {
+        return error(HttpResponse::BadRequest(), "Invalid expiration metadata.");
+    }
+    let timenow = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |n| n.as_secs() as i64);
+    if let Err(message) = check_bounded_lifetime(
+        expiration_to_timestamp(expiration, timenow),
+        metadata
+            .get("burn_after")
+            .map_or(0, |b| burn_after_to_reads(b)),
+        false,
+        ARGS.force_expiry,
+    ) {
+        return error(HttpResponse::BadRequest(), message);
+    }
+
+    if length == 0 || length > max_size(&metadata) {
+        return error(HttpResponse::PayloadTooLarge(), "File exceeded size limit.");
+    }
+
+    remove_stale_uploads();
+
+    {
+        let reserved = reserved_bytes();
+        let mut pastas = data.lock_pastas();
+        remove_expired(&mut pastas);
+        if check_storage_quota(&pastas, reserved + length, ARGS.max_total_storage_mb).is_err() {
+            return error(
+                HttpResponse::PayloadTooLarge(),
+                "The storage quota of this instance is used up.",
+            );
+        }
+    }
+
+    let upload_id = format!("{:032x}", rand::thread_rng().gen::<u128>());
+    let mut metadata = metadata;
+    let password = metadata.remove("password");
+    metadata.remove("uploader_password");
+    let upload = TusUpload { length, metadata };
+
+    let created = fs::create_dir_all(upload_dir())
+        .and_then(|_| fs::write(data_path(&upload_id), []))
+        .and_then(|_| {
+            fs::write(
+                info_path(&upload_id),
+                serde_json::to_vec(&upload).unwrap_or_default(),
+            )
+        });
+    if let Err(e) = created {
+        log::error!("Failed to create tus upload {}: {}", upload_id, e);
+        remove_upload(&upload_id);
+        return error(
+            HttpResponse::InternalServerError(),
+            "Failed to create upload.",
+        );
+    }
+    if let Some(password) = password.filter(|p| !p.is_empty()) {
+        PASSWORDS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(upload_id.clone(), password);
+    }
+
+    tus_response(HttpResponse::Created())
+        .append_header((
+            "Location",
+            format!("{}/tus/{}", ARGS.public_path_as_str(), upload_id),
+        ))
+        .finish()
+}
+
+#[route("/tus/{id}", method = "HEAD")]
+pub async fn upload_offset(req: HttpRequest, id: web::Path<String>) -> HttpResponse {
+    if let Some(response) = check_enabled() {
+        return response;
+    }
+    current_offset(req, id).await
+}
+
+async fn current_offset(req: HttpRequest, id: web::Path<String>) -> HttpResponse {
+    if let Some(response) = check_version(&req) {
+        return response;
+    }
+
+    match read_upload(&id) {
+        Some((upload, offset)) => tus_response(HttpResponse::Ok())
+            .append_header(("Upload-Offset", offset.to_string()))
+            .append_header(("Upload-Length", upload.length.to_string()))
+            .finish(),
+        None => tus_response(HttpResponse::NotFound()).finish(),
+    }
+}
+
+#[patch("/tus/{id}")]
+pub async fn append(
+    req: HttpRequest,
+    id: web::Path<String>,
+    data: web::Data<AppState>,
+    payload: web::Payload,
+) -> HttpResponse {
+    if let Some(response) = check_enabled() {
+        return response;
+    }
+    append_chunk(req, id, data, payload).await
+}
+
+async fn append_chunk(
+    req: HttpRequest,
+    id: web::Path<String>,
+    data: web::Data<AppState>,
+    payload: web::Payload,
+) -> HttpResponse {
+    if let Some(response) = check_version(&req) {
+        return response;
+    }
+
+    if header(&req, "Content-Type") != Some("application/offset+octet-stream") {
+        return error(
+            HttpResponse::UnsupportedMediaType(),
+            "Expected application/offset+octet-stream.",
+        );
+    }
+
+    let upload_id = id.into_inner();
+
+    if !IN_PROGRESS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(upload_id.clone())
+    {
+        return error(HttpResponse::Conflict(), "Upload is already in progress.");
+    }
+
+    let response = append_locked(&req, &upload_id, &data, payload).await;
+
+    IN_PROGRESS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(&upload_id);
+
+    response
+}
+
+async fn append_locked(
+    req: &HttpRequest,
+    upload_id: &str,
+    data: &web::Data<AppState>,
+    mut payload: web::Payload,
+) -> HttpResponse {
+    let (upload, mut offset) = match read_upload(upload_id) {
+        Some(upload) => upload,
+        None => return tus_response(HttpResponse::NotFound()).finish(),
+    };
+
+    if header(req, "Upload-Offset").and_then(|o| o.parse::<u64>().ok()) != Some(offset) {
+        return tus_response(HttpResponse::Conflict())
+            .append_header(("Upload-Offset", offset.to_string()))
+            .finish();
+    }
+
+    let mut file = match OpenOptions::new().append(true).open(data_path(upload_id)) {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!("Failed to open tus upload {}: {}", upload_id, e);
+            return error(
+                HttpResponse::InternalServerError(),
+                "Failed to open upload.",
+            );
+        }
+    };
+
+    // keep whatever arrived before the connection dropped, so the client can resume
+    loop {
+        let chunk = match payload.try_next().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                log::warn!("tus upload {} interrupted at {}: {}", upload_id, offset, e);
+                break;
+            }
+        };
+        if offset + chunk.len() as u64 > upload.length {
+            return error(HttpResponse::BadRequest(), "Upload exceeded its length.");
+        }
+        if let Err(e) = file.write_all(&chunk) {
+            log::error!("Failed to write tus upload {}: {}", upload_id, e);
+            return error(
+                HttpResponse::InternalServerError(),
+                "Failed to write upload.",
+            );
+        }
+        offset += chunk.len() as u64;
+    }
+
+    if offset < upload.length {
+        return tus_response(HttpResponse::NoContent())
+            .append_header(("Upload-Offset", offset.to_string()))
+            .finish();
+    }
+
+    match finish_upload(upload_id, upload, data).await {
+        Ok(location) => tus_response(HttpResponse::NoContent())
+            .append_header(("Upload-Offset", offset.to_string()))
+            .append_header(("X-Pasta-Url", location))
+            .finish(),
+        Err(message) => error(HttpResponse::InternalServerError(), &message),
+    }
+}
+
+/// The data of a completed upload for `save_pasta_file`. Like streamed
+/// form uploads, unencrypted files for local storage are moved next to the
+/// attachments of the pasta, only the rest is read into memory.
+async fn uploaded_data(
+    upload_id: &str,
+    pasta: &Pasta,
+    file: &mut PastaFile,
+) -> Result<UploadedData, String> {
+    let path = data_path(upload_id);
+    let failed = |e: String| {
+        log::error!("Failed to read tus upload {}: {}", upload_id, e);
+        String::from("Failed to read upload.")
+    };
+
+    if pasta.encrypt_server || BackendKind::configured() != BackendKind::Local {
+        return fs::read(&path)
+            .map(UploadedData::Buffered)
+            .map_err(|e| failed(e.to_string()));
+    }
+
+    let hashed_path = path.clone();
+    let sha256 = web::block(move || {
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut fs::File::open(hashed_path)?, &mut hasher)?;
+        Ok::<_, std::io::Error>(format!("{:x}", hasher.finalize()))
+    })
+    .await
+    .map_err(|e| failed(e.to_string()))?
+    .map_err(|e| failed(e.to_string()))?;
+
+    // random, so it cannot clash with the name of another attachment
+    let temp_name = format!(".upload-{:016x}", rand::thread_rng().gen::<u64>());
+    storage::move_to_local_file(&pasta.id_as_animals(), &path, &temp_name).map_err(failed)?;
+    file.sha256 = Some(sha256);
+    Ok(UploadedData::Streamed(temp_name))
+}
+
+/// Turn a completed upload into a pasta, going through the same storage
+/// path as uploads from the form
+async fn finish_upload(
+    upload_id: &str,
+    upload: TusUpload,
+    data: &web::Data<AppState>,
+) -> Result<String, String> {
+    let password = take_password(upload_id);
+
+    let timenow: i64 = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(_) => {
+            log::error!("SystemTime before UNIX EPOCH!");
+            0
+        }
+    } as i64;
+
+    let metadata = upload.metadata;
+    let mut pasta = new_pasta(timenow);
+    pasta.pasta_type = String::from("text");
+    if let Some(expiration) = metadata.get("expiration") {
+        pasta.expiration = expiration_to_timestamp(expiration, timenow);
+    }
+    if let Some(burn_after) = metadata.get("burn_after") {
+        pasta.burn_after_reads = burn_after_to_reads(burn_after);
+    }
+    // the privacy metadata was validated when the upload was created
+    let preset = metadata
+        .get("privacy")
+        .and_then(|p| PrivacyPreset::parse(p))
+        .unwrap_or(PrivacyPreset::Public);
+    pasta.apply_privacy(preset);
+    // the password is only used by the presets that encrypt on the server
+    let password = match password {
+        _ if !pasta.encrypt_server => String::new(),
+        Some(password) => password,
+        None => {
+            remove_upload(upload_id);
+            return Err(String::from(
+                "The password of this upload was lost in a restart, please upload it again.",
+            ));
+        }
+    };
+
+    let file = PastaFile::from_unsanitized(&metadata["filename"]).map_err(|e| {
+        log::warn!("Unsafe file name: {e:?}");
+        String::from("Invalid file name.")
+    });
+    let mut file = match file {
+        Ok(file) => file,
+        Err(message) => {
+            remove_upload(upload_id);
+            return Err(message);
+        }
+    };
+    file.size = ByteSize::b(upload.length);
+
+    let uploaded = uploaded_data(upload_id, &pasta, &mut file).await;
+    remove_upload(upload_id);
+    let uploaded = uploaded?;
+
+    let slug = pasta.id_as_animals();
+    let temp_name = match &uploaded {
+        UploadedData::Streamed(temp_name) => Some(temp_name.clone()),
+        UploadedData::Buffered(_) => None,
+    };
+    let storage_path = save_pasta_file(&mut pasta, file, uploaded, &password)
+        .await
+        .map_err(|e| {
+            if let Some(temp_name) = temp_name {
+                storage::delete_local_file(&slug, &temp_name);
+            }
+            e.to_string()
+        })?;
+
+    let location = if pasta.encrypt_server {
+        format!("{}/auth/{}", ARGS.public_path_as_str(), slug)
+    } else {
+        format!("{}/upload/{}", ARGS.public_path_as_str(), slug)
+    };
+
+    persist_pasta(data, pasta, &[storage_path])
+        .await
+        .map_err(|e| {
+            log::error!("Failed to persist pasta {}: {}", slug, e);
+            String::from("Failed to save upload.")
+        })?;
+
+    log::info!("Finished tus upload {} as pasta {}", upload_id, slug);
+    Ok(location)
+}
+
+#[delete("/tus/{id}")]
+pub async fn terminate(req: HttpRequest, id: web::Path<String>) -> HttpResponse {
+    if let Some(response) = check_enabled().or_else(|| check_version(&req)) {
+        return response;
+    }
+
+    if read_upload(&id).is_none() {
+        return tus_response(HttpResponse::NotFound()).finish();
+    }
+    if IN_PROGRESS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .contains(id.as_str())
+    {
+        return error(HttpResponse::Conflict(), "Upload is in progress.");
+    }
+
+    remove_upload(&id);
+    tus_response(HttpResponse::NoContent()).finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::rate_limit::{FailedLogins, RateLimiter};
+    use crate::util::store::MemoryStore;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+
+    #[actix_web::test]
+    async fn test_upload_offset_flow() {
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(Vec::new())),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+        let app = init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/tus", web::post().to(start_upload))
+                .route("/tus/{id}", web::head().to(current_offset))
+                .route("/tus/{id}", web::patch().to(append_chunk)),
+        )
+        .await;
+
+        let response = call_service(
+            &app,
+            TestRequest::post()
+                .uri("/tus")
+                .insert_header(("Upload-Length", "11"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), 412);
+
+        let response = call_service(
+            &app,
+            TestRequest::post()
+                .uri("/tus")
+                .insert_header(("Tus-Resumable", TUS_VERSION))
+                .insert_header(("Upload-Length", "11"))
+                .insert_header((
+                    "Upload-Metadata",
+                    "filename aGVsbG8udHh0,uploader_password c2VjcmV0,password c2VjcmV0",
+                ))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), 201);
+        let location = response
+            .headers()
+            .get("Location")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        let upload_id = location.rsplit('/').next().unwrap().to_string();
+
+        // secrets in the metadata are not written to disk
+        let info = fs::read_to_string(info_path(&upload_id)).unwrap();
+        assert!(info.contains("hello.txt"));
+        assert!(!info.contains("uploader_password") && !info.contains("secret"));
+        let uri = format!("/tus/{}", upload_id);
+
+        let offset = |response: &actix_web::dev::ServiceResponse| {
+            response
+                .headers()
+                .get("Upload-Offset")
+                .map(|o| o.to_str().unwrap().to_string())
+        };
+        let head = || {
+            TestRequest::default()
+                .method(actix_web::http::Method::HEAD)
+                .uri(&uri)
+                .insert_header(("Tus-Resumable", TUS_VERSION))
+                .to_request()
+        };
+        let patch = |offset: u64, chunk: &'static str| {
+            TestRequest::patch()
+                .uri(&uri)
+                .insert_header(("Tus-Resumable", TUS_VERSION))
+                .insert_header(("Content-Type", "application/offset+octet-stream"))
+                .insert_header(("Upload-Offset", offset.to_string()))
+                .set_payload(chunk)
+                .to_request()
+        };
+
+        let response = call_service(&app, head()).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(offset(&response).as_deref(), Some("0"));
+        assert_eq!(response.headers().get("Upload-Length").unwrap(), "11");
+
+        let response = call_service(&app, patch(0, "hello")).await;
+        assert_eq!(response.status(), 204);
+        assert_eq!(offset(&response).as_deref(), Some("5"));
+
+        // a chunk sent for an offset the server is not at is refused
+        let response = call_service(&app, patch(0, "hello")).await;
+        assert_eq!(response.status(), 409);
+        assert_eq!(offset(&response).as_deref(), Some("5"));
+
+        let response = call_service(&app, head()).await;
+        assert_eq!(offset(&response).as_deref(), Some("5"));
+
+        // nothing beyond the announced length is accepted
+        let response = call_service(&app, patch(5, " world and more")).await;
+        assert_eq!(response.status(), 400);
+
+        let response = call_service(&app, patch(5, " world")).await;
+        assert_eq!(response.status(), 204);
+        assert_eq!(offset(&response).as_deref(), Some("11"));
+        assert!(response.headers().contains_key("X-Pasta-Url"));
+
+        // the finished upload became a pasta and is gone
+        let response = call_service(&app, head()).await;
+        assert_eq!(response.status(), 404);
+        let (id, pasta_id) = {
+            let pastas = data.lock_pastas();
+            let pasta = pastas.iter().find(|p| p.file.is_some()).unwrap();
+            assert_eq!(pasta.file.as_ref().unwrap().name(), "hello.txt");
+            assert_eq!(pasta.file.as_ref().unwrap().size.as_u64(), 11);
+            assert!(pasta.file.as_ref().unwrap().sha256.is_some());
+            (pasta.id, pasta.id_as_animals())
+        };
+
+        // the upload was moved into storage, not copied, and a password does
+        // not encrypt public uploads
+        assert!(!data_path(&upload_id).exists());
+        let stored = storage::get_file(&pasta_id, "hello.txt").await.unwrap();
+        assert_eq!(stored, b"hello world");
+
+        // the pasta was saved like any other, take it out again
+        storage::delete_file(&pasta_id, "hello.txt").await.unwrap();
+        let mut pastas = data.lock_pastas();
+        pastas.retain(|p| p.id != id);
+        crate::util::db::delete(Some(&pastas), Some(id));
+    }
+}
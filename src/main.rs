@@ -2,11 +2,13 @@ extern crate core;
 
 use crate::args::ARGS;
 use crate::endpoints::{
-    admin, auth_admin, auth_upload, create, edit, errors, file, guide, list,
-    pasta as pasta_endpoint, qr, remove, static_resources,
+    admin, api, auth_admin, auth_upload, create, edit, errors, file, guide, health, list, metrics,
+    oembed, openapi, pasta as pasta_endpoint, qr, remove, static_resources, tus,
 };
 use crate::pasta::Pasta;
 use crate::util::db::read_all;
+use crate::util::rate_limit::{FailedLogins, RateLimiter};
+use crate::util::store::MemoryStore;
 use crate::util::telemetry::start_telemetry_thread;
 use actix_web::middleware::Condition;
 use actix_web::{middleware, web, App, HttpServer};
@@ -16,30 +18,56 @@ use env_logger::Builder;
 use log::LevelFilter;
 use std::fs;
 use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Mutex, MutexGuard};
 
 pub mod args;
 pub mod pasta;
 
 pub mod util {
+    pub mod access_log;
     pub mod animalnumbers;
     pub mod auth;
+    pub mod azure;
+    pub mod backup;
+    pub mod base62;
+    pub mod clamav;
+    pub mod compression;
+    pub mod content_filter;
     pub mod contentrenderer;
+    pub mod custom_slug;
     pub mod db;
     pub mod db_json;
     #[cfg(feature = "default")]
     pub mod db_sqlite;
+    pub mod expired;
+    pub mod gcs;
     pub mod hashids;
     pub mod http_client;
+    pub mod ids;
+    pub mod integrity;
+    pub mod math;
+    pub mod metrics;
     pub mod misc;
+    pub mod pending;
+    pub mod rate_limit;
+    pub mod remote_fetch;
+    pub mod slow_requests;
     pub mod storage;
+    pub mod store;
+    pub mod sweeper;
     pub mod syntaxhighlighter;
     pub mod telemetry;
+    pub mod thumbnail;
+    pub mod tls;
     pub mod version;
+    pub mod webdav;
+    pub mod webhook;
 }
 
 pub mod endpoints {
     pub mod admin;
+    pub mod api;
     pub mod auth_admin;
     pub mod auth_upload;
     pub mod create;
@@ -47,24 +75,38 @@ pub mod endpoints {
     pub mod errors;
     pub mod file;
     pub mod guide;
+    pub mod health;
     pub mod list;
+    pub mod metrics;
+    pub mod oembed;
+    pub mod openapi;
     pub mod pasta;
     pub mod qr;
     pub mod remove;
     pub mod static_resources;
+    pub mod tus;
 }
 
+/// Whether recovering the pastas from a poisoned lock has been logged
+static POISON_LOGGED: AtomicBool = AtomicBool::new(false);
+
 pub struct AppState {
-    pub pastas: Mutex<Vec<Pasta>>,
+    pub pastas: Mutex<MemoryStore>,
+    pub rate_limiter: RateLimiter,
+    pub failed_logins: FailedLogins,
 }
 
 impl AppState {
-    pub fn lock_pastas(&self) -> MutexGuard<'_, Vec<Pasta>> {
+    /// Lock the pastas, also after a request panicked while holding them,
+    /// which is only logged the first time
+    pub fn lock_pastas(&self) -> MutexGuard<'_, MemoryStore> {
         self.pastas.lock().unwrap_or_else(|error| {
-            log::error!(
-                "Pasta store mutex poisoned; recovering in-memory state: {}",
-                error
-            );
+            if !POISON_LOGGED.swap(true, Ordering::Relaxed) {
+                log::warn!(
+                    "Pasta store mutex poisoned; recovering in-memory state: {}",
+                    error
+                );
+            }
             error.into_inner()
         })
     }
@@ -74,6 +116,10 @@ impl AppState {
 async fn main() -> std::io::Result<()> {
     Builder::new()
         .format(|buf, record| {
+            // access log entries are JSON objects on their own line
+            if record.target() == util::access_log::TARGET {
+                return writeln!(buf, "{}", record.args());
+            }
             writeln!(
                 buf,
                 "{} [{}] - {}",
@@ -91,6 +137,18 @@ async fn main() -> std::io::Result<()> {
         ARGS.port.to_string()
     );
 
+    let remote_backends = [
+        ARGS.s3_enabled(),
+        ARGS.gcs_enabled(),
+        ARGS.webdav_enabled(),
+        ARGS.azure_enabled(),
+    ];
+    if remote_backends.iter().filter(|enabled| **enabled).count() > 1 {
+        panic!(
+            "More than one of S3, GCS, WebDAV and Azure storage are configured, only one of them can be used."
+        );
+    }
+
     // Log S3 storage status
     if ARGS.s3_enabled() {
         log::info!(
@@ -98,6 +156,26 @@ async fn main() -> std::io::Result<()> {
             ARGS.s3_endpoint.as_ref().unwrap(),
             ARGS.s3_bucket.as_ref().unwrap()
         );
+    } else if ARGS.gcs_enabled() {
+        log::info!(
+            "GCS storage enabled: bucket={}",
+            ARGS.gcs_bucket.as_ref().unwrap()
+        );
+    } else if ARGS.webdav_enabled() {
+        log::info!(
+            "WebDAV storage enabled: url={}",
+            ARGS.webdav_url.as_ref().unwrap()
+        );
+    } else if ARGS.azure_enabled() {
+        if let Err(error) = util::azure::AzureBlob::from_args() {
+            log::error!("{}", error);
+            panic!("{}", error);
+        }
+        log::info!(
+            "Azure storage enabled: account={}, container={}",
+            ARGS.azure_account.as_ref().unwrap(),
+            ARGS.azure_container.as_ref().unwrap()
+        );
     } else {
         log::info!(
             "S3 storage disabled, using local filesystem: {}",
@@ -120,19 +198,77 @@ async fn main() -> std::io::Result<()> {
         }
     };
 
+    let mut pastas = read_all();
+    util::integrity::check_files(&mut pastas);
+    util::custom_slug::load(&pastas);
+
     let data = web::Data::new(AppState {
-        pastas: Mutex::new(read_all()),
+        pastas: Mutex::new(MemoryStore::new(pastas)),
+        rate_limiter: RateLimiter::default(),
+        failed_logins: FailedLogins::default(),
     });
 
     if !ARGS.disable_telemetry {
         start_telemetry_thread();
     }
 
-    HttpServer::new(move || {
+    util::sweeper::start(data.clone());
+
+    if let Some(max_expiry) = ARGS.max_expiry.as_deref() {
+        if util::misc::parse_duration(max_expiry).is_none() {
+            log::error!("Invalid MICROBIN_MAX_EXPIRY: {}", max_expiry);
+            panic!("Invalid MICROBIN_MAX_EXPIRY: {}", max_expiry);
+        }
+    }
+
+    if !create::is_known_expiration(&ARGS.default_expiry) {
+        log::error!("Invalid MICROBIN_DEFAULT_EXPIRY: {}", ARGS.default_expiry);
+        panic!("Invalid MICROBIN_DEFAULT_EXPIRY: {}", ARGS.default_expiry);
+    }
+
+    if pasta::PrivacyPreset::parse(ARGS.default_privacy.trim()).is_none() {
+        log::error!("Invalid MICROBIN_DEFAULT_PRIVACY: {}", ARGS.default_privacy);
+        panic!("Invalid MICROBIN_DEFAULT_PRIVACY: {}", ARGS.default_privacy);
+    }
+
+    util::syntaxhighlighter::check_theme();
+    util::contentrenderer::check_markdown_extensions();
+
+    if util::misc::parse_qr_ecc(&ARGS.qr_ecc).is_none() {
+        let error = format!(
+            "Invalid QR error correction {:?}, expected low, medium, quartile or high",
+            ARGS.qr_ecc
+        );
+        log::error!("{}", error);
+        panic!("{}", error);
+    }
+
+    if let Err(error) = util::content_filter::init() {
+        log::error!("{}", error);
+        panic!("{}", error);
+    }
+
+    let tls_config = match util::tls::server_config() {
+        Ok(config) => config,
+        Err(error) => {
+            log::error!("Couldn't set up TLS: {}", error);
+            panic!("Couldn't set up TLS: {}", error);
+        }
+    };
+
+    let server = HttpServer::new(move || {
         App::new()
             .app_data(data.clone())
             .wrap(middleware::NormalizePath::trim())
-            .wrap(middleware::Logger::default())
+            .wrap(Condition::new(!ARGS.json_logs, middleware::Logger::default()))
+            .wrap(Condition::new(
+                ARGS.json_logs,
+                middleware::from_fn(util::access_log::log_requests),
+            ))
+            .wrap(Condition::new(
+                ARGS.slow_request_ms > 0,
+                middleware::from_fn(util::slow_requests::log_slow_requests),
+            ))
             // Conditional / Public Services
             .service(pasta_endpoint::getpasta)
             .service(pasta_endpoint::postpasta)
@@ -142,9 +278,17 @@ async fn main() -> std::io::Result<()> {
             .service(pasta_endpoint::postrawpasta)
             .service(pasta_endpoint::redirecturl)
             .service(pasta_endpoint::shortredirecturl)
+            .service(qr::get_qr_svg)
+            .service(qr::get_qr_png)
             .service(qr::getqr)
+            .service(oembed::oembed)
             .service(file::get_file)
+            .service(file::get_extra_file)
+            .service(file::head_file)
+            .service(file::head_extra_file)
             .service(file::post_secure_file)
+            .service(file::get_raw_encrypted_file)
+            .service(file::get_thumbnail)
             .service(static_resources::static_resources)
             .service(static_resources::robots_txt)
             .service(guide::guide)
@@ -158,6 +302,17 @@ async fn main() -> std::io::Result<()> {
             .service(auth_upload::auth_raw_pasta)
             .service(auth_upload::auth_edit_private)
             .service(auth_upload::auth_remove_private)
+            // API services authenticate requests themselves
+            .service(api::info)
+            .service(api::patch_metadata)
+            .service(api::paste_info)
+            .service(api::themes)
+            .service(api::stats)
+            .service(openapi::get_openapi)
+            .service(metrics::get_metrics)
+            // Probes stay open even behind basic auth
+            .service(health::healthz)
+            .service(health::readyz)
             // Protected Services (Require Login)
             .service(
                 web::scope("")
@@ -183,13 +338,74 @@ async fn main() -> std::io::Result<()> {
                     .service(remove::remove)
                     .service(remove::post_remove)
                     .service(list::list)
+                    .service(api::list_pastas)
+                    .service(admin::list_pastas)
+                    .service(admin::delete_pastas)
+                    .service(admin::export_backup)
+                    .service(admin::import_backup)
+                    .service(api::bulk_delete)
+                    .service(api::rotate_slug)
                     .service(web::resource("/upload").route(web::post().to(create::create)))
+                    .service(api::create_paste)
+                    .service(api::edit_paste)
+                    .service(api::rekey_paste)
+                    .service(api::append_paste)
+                    .service(tus::options)
+                    .service(tus::create_upload)
+                    .service(tus::upload_offset)
+                    .service(tus::append)
+                    .service(tus::terminate)
                     .service(create::index_with_status),
             )
             .default_service(web::route().to(errors::not_found))
-    })
-    .bind((ARGS.bind, ARGS.port))?
-    .workers(ARGS.threads as usize)
-    .run()
-    .await
+    });
+
+    let server = match tls_config {
+        Some(config) => {
+            log::info!("TLS enabled, minimum version {}", ARGS.tls_min_version);
+            server.bind_rustls_0_23((ARGS.bind, ARGS.port), config)?
+        }
+        None => server.bind((ARGS.bind, ARGS.port))?,
+    };
+
+    // signals are handled here, so background storage operations run on the
+    // workers can complete before the workers stop
+    let server = server
+        .workers(ARGS.threads as usize)
+        .disable_signals()
+        .run();
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        wait_for_stop_signal().await;
+        log::info!("Shutting down");
+        util::pending::wait_for_pending().await;
+        handle.stop(true).await;
+    });
+
+    server.await?;
+    // requests that were still being served may have started more
+    util::pending::wait_for_pending().await;
+    Ok(())
+}
+
+/// Wait for Ctrl-C, or SIGTERM on Unix
+async fn wait_for_stop_signal() {
+    #[cfg(unix)]
+    {
+        use actix_web::rt::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                let ctrl_c = Box::pin(actix_web::rt::signal::ctrl_c());
+                let terminate = Box::pin(terminate.recv());
+                futures::future::select(ctrl_c, terminate).await;
+                return;
+            }
+            Err(error) => log::error!("Couldn't listen for SIGTERM: {}", error),
+        }
+    }
+
+    if let Err(error) = actix_web::rt::signal::ctrl_c().await {
+        log::error!("Couldn't listen for Ctrl-C: {}", error);
+    }
 }
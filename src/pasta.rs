@@ -1,20 +1,39 @@
+use base64::prelude::{Engine, BASE64_STANDARD};
 use bytesize::ByteSize;
 use chrono::{Datelike, Local, TimeZone, Timelike};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
 use std::fmt;
 use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::args::ARGS;
-use crate::util::animalnumbers::to_animal_names;
+use crate::util::compression;
 use crate::util::contentrenderer::{prepare_html_for_iframe, render_markdown};
-use crate::util::hashids::to_hashids;
+use crate::util::ids;
+use crate::util::misc::sniff_content_type;
 use crate::util::syntaxhighlighter::html_highlight;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Eq, Clone)]
 pub struct PastaFile {
     pub name: String,
+    /// Size of the file as uploaded
     pub size: ByteSize,
+    /// Original file name when the file is stored under an opaque name
+    #[serde(default)]
+    pub original_name: Option<String>,
+    /// Hex encoded SHA-256 of the plaintext file contents
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Content type sniffed from the first bytes of the file, for names that
+    /// tell none. Encrypted files are never sniffed.
+    #[serde(default)]
+    pub content_type: Option<String>,
+    /// Size of the file as stored, when that differs from `size`, as for
+    /// files encrypted by the server
+    #[serde(default)]
+    pub stored_size: Option<ByteSize>,
 }
 
 impl PastaFile {
@@ -25,6 +44,10 @@ impl PastaFile {
         Ok(Self {
             name,
             size: ByteSize::b(0),
+            original_name: None,
+            sha256: None,
+            content_type: None,
+            stored_size: None,
         })
     }
 
@@ -32,6 +55,62 @@ impl PastaFile {
         &self.name
     }
 
+    /// Size of the file as stored, which for encrypted files includes the
+    /// overhead of the encryption
+    pub fn stored_size(&self) -> ByteSize {
+        self.stored_size.unwrap_or(self.size)
+    }
+
+    /// Whether the file's extension is in `extensions`, or the content type
+    /// guessed from its name in `mime_types`, where `image/*` matches every
+    /// image. Every file is allowed when both lists are empty.
+    pub fn is_allowed_type(&self, extensions: &[String], mime_types: &[String]) -> bool {
+        let extensions: Vec<String> = extensions
+            .iter()
+            .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+            .filter(|e| !e.is_empty())
+            .collect();
+        let mime_types: Vec<&str> = mime_types
+            .iter()
+            .map(|m| m.trim())
+            .filter(|m| !m.is_empty())
+            .collect();
+        if extensions.is_empty() && mime_types.is_empty() {
+            return true;
+        }
+
+        let name = self.display_name();
+        let extension = Path::new(name)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase());
+        if extension.is_some_and(|extension| extensions.contains(&extension)) {
+            return true;
+        }
+
+        let mime = mime_guess::from_path(name).first_or_octet_stream();
+        mime_types
+            .iter()
+            .any(|allowed| match allowed.split_once('/') {
+                Some((type_, "*")) => mime.type_().as_str().eq_ignore_ascii_case(type_),
+                _ => mime.essence_str().eq_ignore_ascii_case(allowed),
+            })
+    }
+
+    /// Remember the content type sniffed from `head`, the first bytes of the
+    /// file, when its name does not tell one
+    pub fn sniff(&mut self, head: &[u8]) {
+        if mime_guess::from_path(self.display_name()).first().is_none() {
+            self.content_type = sniff_content_type(head).map(String::from);
+        }
+    }
+
+    /// Whether sniffing found the file to be of the `family/` content type
+    fn sniffed_as(&self, family: &str) -> bool {
+        self.content_type
+            .as_deref()
+            .is_some_and(|content_type| content_type.starts_with(family))
+    }
+
     /// Check if this file is stored in S3 (non-encrypted)
     pub fn is_s3(&self) -> bool {
         self.name.starts_with("s3://")
@@ -43,19 +122,88 @@ impl PastaFile {
         self.name.starts_with("s3:") && !self.name.starts_with("s3://")
     }
 
+    /// Check if this file is stored in Google Cloud Storage (non-encrypted)
+    pub fn is_gcs(&self) -> bool {
+        self.name.starts_with("gcs://")
+    }
+
+    /// Check if this encrypted file's data.enc is stored in Google Cloud Storage
+    /// Format: "gcs:originalname.ext"
+    pub fn is_gcs_encrypted(&self) -> bool {
+        self.name.starts_with("gcs:") && !self.name.starts_with("gcs://")
+    }
+
+    /// Check if this file is stored on a WebDAV server (non-encrypted)
+    pub fn is_webdav(&self) -> bool {
+        self.name.starts_with("webdav://")
+    }
+
+    /// Check if this encrypted file's data.enc is stored on a WebDAV server
+    /// Format: "webdav:originalname.ext"
+    pub fn is_webdav_encrypted(&self) -> bool {
+        self.name.starts_with("webdav:") && !self.name.starts_with("webdav://")
+    }
+
+    /// Check if this file is stored in Azure Blob Storage (non-encrypted)
+    pub fn is_azure(&self) -> bool {
+        self.name.starts_with("azure://")
+    }
+
+    /// Check if this encrypted file's data.enc is stored in Azure Blob Storage
+    /// Format: "azure:originalname.ext"
+    pub fn is_azure_encrypted(&self) -> bool {
+        self.name.starts_with("azure:") && !self.name.starts_with("azure://")
+    }
+
+    /// Check if this file is stored in a remote backend (non-encrypted)
+    pub fn is_remote(&self) -> bool {
+        self.is_s3() || self.is_gcs() || self.is_webdav() || self.is_azure()
+    }
+
     /// Get the display filename (for Content-Disposition header)
     pub fn display_name(&self) -> &str {
-        if self.name.starts_with("s3://") {
+        if let Some(original_name) = self.original_name.as_deref() {
+            original_name
+        } else if self.is_remote() {
             // s3://attachments/pasta-id/filename.ext -> filename.ext
             self.name.rsplit('/').next().unwrap_or(&self.name)
         } else if let Some(name) = self.name.strip_prefix("s3:") {
             // s3:filename.ext -> filename.ext (encrypted in S3)
             name
+        } else if let Some(name) = self.name.strip_prefix("gcs:") {
+            // gcs:filename.ext -> filename.ext (encrypted in GCS)
+            name
+        } else if let Some(name) = self.name.strip_prefix("webdav:") {
+            // webdav:filename.ext -> filename.ext (encrypted on WebDAV)
+            name
+        } else if let Some(name) = self.name.strip_prefix("azure:") {
+            // azure:filename.ext -> filename.ext (encrypted in Azure)
+            name
         } else {
             &self.name
         }
     }
 
+    /// Opaque storage name used instead of the original file name when
+    /// `hash_filenames_in_urls` is enabled
+    pub fn hashed_name(pasta_id: &str, name: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(pasta_id.as_bytes());
+        hasher.update(b"/");
+        hasher.update(name.as_bytes());
+        format!("{:x}", hasher.finalize())[..32].to_string()
+    }
+
+    /// Value for the `Digest` response header (RFC 3230), if the digest is known
+    pub fn digest_header(&self) -> Option<String> {
+        let hex = self.sha256.as_deref()?;
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        Some(format!("sha-256={}", BASE64_STANDARD.encode(bytes)))
+    }
+
     /// Get the S3 object path (without s3:// prefix) for non-encrypted files
     pub fn s3_path(&self) -> Option<&str> {
         self.name.strip_prefix("s3://")
@@ -67,7 +215,7 @@ impl PastaFile {
             ".jpg", ".jpeg", ".png", ".gif", ".bmp", ".webp", ".ico", ".svg", ".tiff", ".tif",
             ".jfif", ".pjpeg", ".pjp", ".avif", ".jxl", ".heif",
         ];
-        extensions.iter().any(|&ext| lowercase_name.ends_with(ext))
+        extensions.iter().any(|&ext| lowercase_name.ends_with(ext)) || self.sniffed_as("image/")
     }
 
     pub fn is_video(&self) -> bool {
@@ -75,11 +223,82 @@ impl PastaFile {
         let extensions = [
             ".mp4", ".mov", ".wmv", ".webm", ".avi", ".flv", ".mkv", ".mts",
         ];
-        extensions.iter().any(|&ext| lowercase_name.ends_with(ext))
+        extensions.iter().any(|&ext| lowercase_name.ends_with(ext)) || self.sniffed_as("video/")
     }
 
+    /// Whether the file is shown inline, as far as the instance embeds
+    /// images and videos
     pub fn embeddable(&self) -> bool {
-        self.is_image() || self.is_video()
+        self.embeddable_with(ARGS.embed_images, ARGS.embed_videos)
+    }
+
+    /// Whether the file is an image and `images` are embedded, or a video
+    /// and `videos` are
+    pub fn embeddable_with(&self, images: bool, videos: bool) -> bool {
+        (images && self.is_image()) || (videos && self.is_video())
+    }
+}
+
+/// Privacy modes offered on the upload form
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrivacyPreset {
+    Public,
+    Unlisted,
+    Readonly,
+    Private,
+    Secret,
+}
+
+/// Flags of a pasta that follow from its privacy preset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrivacyFlags {
+    pub private: bool,
+    pub unlisted: bool,
+    pub readonly: bool,
+    pub encrypt_server: bool,
+    pub encrypt_client: bool,
+    pub editable: bool,
+}
+
+impl PrivacyPreset {
+    pub fn parse(privacy: &str) -> Option<Self> {
+        match privacy {
+            "public" => Some(Self::Public),
+            "unlisted" => Some(Self::Unlisted),
+            "readonly" => Some(Self::Readonly),
+            "private" => Some(Self::Private),
+            "secret" => Some(Self::Secret),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Public => "public",
+            Self::Unlisted => "unlisted",
+            Self::Readonly => "readonly",
+            Self::Private => "private",
+            Self::Secret => "secret",
+        }
+    }
+
+    /// Flags for this preset, `editable` is the instance default
+    pub fn flags(self, editable: bool) -> PrivacyFlags {
+        let (private, unlisted, readonly, encrypt_server, encrypt_client) = match self {
+            Self::Public => (false, false, false, false, false),
+            Self::Unlisted => (false, true, false, false, false),
+            Self::Readonly => (true, false, true, false, false),
+            Self::Private => (true, false, false, true, false),
+            Self::Secret => (true, false, false, true, true),
+        };
+        PrivacyFlags {
+            private,
+            unlisted,
+            readonly,
+            encrypt_server,
+            encrypt_client,
+            editable,
+        }
     }
 }
 
@@ -88,8 +307,14 @@ pub struct Pasta {
     pub id: u64,
     pub content: String,
     pub file: Option<PastaFile>,
+    /// Attachments after the first one, only unencrypted pastas have them
+    #[serde(default)]
+    pub extra_files: Vec<PastaFile>,
     pub extension: String,
     pub private: bool,
+    /// Reachable by its link, but kept off the list and other discovery pages
+    #[serde(default)]
+    pub unlisted: bool,
     pub readonly: bool,
     pub editable: bool,
     pub encrypt_server: bool,
@@ -100,9 +325,27 @@ pub struct Pasta {
     pub last_read: i64,
     pub read_count: u64,
     pub burn_after_reads: u64,
+    /// Downloads of the attachments allowed before the pasta is deleted, 0
+    /// for no limit. Counted apart from `read_count`, which views add to.
+    #[serde(default)]
+    pub download_limit: u64,
+    #[serde(default)]
+    pub download_count: u64,
+    /// Deleted by its first view, and afterwards not found rather than expired
+    #[serde(default)]
+    pub one_time: bool,
+    /// Slug chosen by the uploader, used in links instead of the generated one
+    #[serde(default)]
+    pub custom_slug: Option<String>,
     pub pasta_type: String,
     #[serde(default)]
     pub title: Option<String>,
+    /// Page theme for the view page: auto, light or dark. Empty means the instance default.
+    #[serde(default)]
+    pub theme: String,
+    /// Note shown on the unlock page of protected pastas, stored in plain text
+    #[serde(default)]
+    pub label: String,
 }
 
 impl Pasta {
@@ -111,7 +354,12 @@ impl Pasta {
             return None;
         }
         let line = content.lines().find(|l| !l.trim().is_empty())?;
-        let title = line.trim_start_matches('#').trim();
+        Self::clean_title(line.trim_start_matches('#'))
+    }
+
+    /// Trim a title and cut it to 80 characters, `None` if nothing is left
+    pub fn clean_title(title: &str) -> Option<String> {
+        let title = title.trim();
         if title.is_empty() {
             return None;
         }
@@ -123,11 +371,34 @@ impl Pasta {
         }
     }
 
+    pub fn apply_privacy(&mut self, preset: PrivacyPreset) {
+        let flags = preset.flags(ARGS.editable);
+        self.private = flags.private;
+        self.unlisted = flags.unlisted;
+        self.readonly = flags.readonly;
+        self.encrypt_server = flags.encrypt_server;
+        self.encrypt_client = flags.encrypt_client;
+        self.editable = flags.editable;
+    }
+
+    /// Whether the pasta may show up on the list page
+    pub fn is_listed(&self) -> bool {
+        !self.private && !self.unlisted
+    }
+
+    pub fn theme_or_default(&self) -> &str {
+        if self.theme.is_empty() {
+            &ARGS.default_theme
+        } else {
+            &self.theme
+        }
+    }
+
     pub fn id_as_animals(&self) -> String {
-        if ARGS.hash_ids {
-            to_hashids(self.id)
+        if let Some(slug) = &self.custom_slug {
+            slug.clone()
         } else {
-            to_animal_names(self.id)
+            ids::to_slug(self.id)
         }
     }
 
@@ -135,12 +406,95 @@ impl Pasta {
         self.file.is_some()
     }
 
-    pub fn total_size_as_string(&self) -> String {
-        let total_size_bytes = if self.has_file() {
-            self.file.as_ref().unwrap().size.as_u64() as usize + self.content.as_bytes().len()
+    /// Size of the attachments and the content as uploaded
+    pub fn total_size_bytes(&self) -> usize {
+        self.files()
+            .map(|file| file.size.as_u64() as usize)
+            .sum::<usize>()
+            + compression::original_len(&self.content)
+    }
+
+    /// Size of the attachments and the content as stored, which is smaller
+    /// for compressed content and larger for encrypted files
+    pub fn stored_size_bytes(&self) -> usize {
+        self.files()
+            .map(|file| file.stored_size().as_u64() as usize)
+            .sum::<usize>()
+            + self.content.len()
+    }
+
+    /// The content as uploaded, decompressed if it is stored compressed
+    pub fn text(&self) -> Cow<'_, str> {
+        compression::decompress(&self.content)
+    }
+
+    /// Store the content compressed if it has at least `min_bytes`, 0
+    /// meaning never. Encrypted content would not get any smaller.
+    pub fn compress_content(&mut self, min_bytes: usize) {
+        if min_bytes == 0
+            || self.content.len() < min_bytes
+            || self.encrypt_server
+            || self.encrypt_client
+            || compression::is_compressed(&self.content)
+        {
+            return;
+        }
+        if let Some(compressed) = compression::compress(&self.content) {
+            self.content = compressed;
+        }
+    }
+
+    /// All attachments, the first one being `file`
+    pub fn files(&self) -> impl Iterator<Item = &PastaFile> {
+        self.file.iter().chain(self.extra_files.iter())
+    }
+
+    /// Attachment number `index`, counting from 0
+    pub fn file_at(&self, index: usize) -> Option<&PastaFile> {
+        match index {
+            0 => self.file.as_ref(),
+            index => self.extra_files.get(index - 1),
+        }
+    }
+
+    /// Storage path of the attached file, as expected by the storage module
+    pub fn file_storage_path(&self) -> Option<String> {
+        let file = self.file.as_ref()?;
+        if self.encrypt_server {
+            // Encrypted file - determine if S3, GCS, WebDAV, Azure or local
+            if file.is_s3_encrypted() {
+                Some(format!("s3://attachments/{}/data.enc", self.id_as_animals()))
+            } else if file.is_gcs_encrypted() {
+                Some(format!("gcs://attachments/{}/data.enc", self.id_as_animals()))
+            } else if file.is_webdav_encrypted() {
+                Some(format!(
+                    "webdav://attachments/{}/data.enc",
+                    self.id_as_animals()
+                ))
+            } else if file.is_azure_encrypted() {
+                Some(format!(
+                    "azure://attachments/{}/data.enc",
+                    self.id_as_animals()
+                ))
+            } else {
+                Some("data.enc".to_string())
+            }
         } else {
-            self.content.as_bytes().len()
-        };
+            // Non-encrypted - use stored path directly
+            Some(file.name().to_string())
+        }
+    }
+
+    /// Storage paths of all attachments
+    pub fn file_storage_paths(&self) -> Vec<String> {
+        self.file_storage_path()
+            .into_iter()
+            .chain(self.extra_files.iter().map(|file| file.name().to_string()))
+            .collect()
+    }
+
+    pub fn total_size_as_string(&self) -> String {
+        let total_size_bytes = self.total_size_bytes();
 
         if total_size_bytes < 1024 {
             format!("{} B", total_size_bytes)
@@ -201,6 +555,19 @@ impl Pasta {
         }
     }
 
+    /// Reads left before the pasta is deleted, `None` without a read limit
+    pub fn reads_remaining(&self) -> Option<u64> {
+        if self.one_time {
+            return Some(u64::from(self.read_count == 0));
+        }
+        (self.burn_after_reads != 0).then(|| self.burn_after_reads.saturating_sub(self.read_count))
+    }
+
+    /// Seconds from `timenow` until the pasta expires, `None` if it never does
+    pub fn expires_in(&self, timenow: i64) -> Option<i64> {
+        (self.expiration != 0).then(|| (self.expiration - timenow).max(0))
+    }
+
     pub fn last_read_time_ago_as_string(&self) -> String {
         // get current unix time in seconds
         let timenow: i64 = match SystemTime::now().duration_since(UNIX_EPOCH) {
@@ -292,17 +659,17 @@ impl Pasta {
     }
 
     pub fn content_syntax_highlighted(&self) -> String {
-        html_highlight(&self.content, &self.extension)
+        html_highlight(&self.text(), &self.extension)
     }
 
     pub fn content_not_highlighted(&self) -> String {
-        html_highlight(&self.content, "txt")
+        html_highlight(&self.text(), "txt")
     }
 
     pub fn content_escaped(&self) -> String {
         html_escape::encode_text(
             &self
-                .content
+                .text()
                 .replace('\\', "\\\\")
                 .replace('`', "\\`")
                 .replace('$', "\\$"),
@@ -311,7 +678,7 @@ impl Pasta {
     }
 
     pub fn content_textarea_safe(&self) -> String {
-        html_escape::encode_text(&self.content).to_string()
+        html_escape::encode_text(&self.text()).to_string()
     }
 
     /// Check if content should be rendered as markdown
@@ -332,17 +699,213 @@ impl Pasta {
 
     /// Render content as markdown HTML
     pub fn content_rendered_markdown(&self) -> String {
-        render_markdown(&self.content)
+        render_markdown(
+            &self.text(),
+            &ARGS.markdown_extensions,
+            ARGS.render_math,
+            ARGS.markdown_toc,
+        )
     }
 
     /// Prepare content for HTML iframe (escaped for srcdoc)
     pub fn content_for_html_iframe(&self) -> String {
-        prepare_html_for_iframe(&self.content)
+        prepare_html_for_iframe(&self.text(), ARGS.sanitize_html_pastes)
     }
 }
 
 impl fmt::Display for Pasta {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.content)
+        write!(f, "{}", self.text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::create::new_pasta;
+
+    fn flags(
+        private: bool,
+        unlisted: bool,
+        readonly: bool,
+        encrypt_server: bool,
+        encrypt_client: bool,
+        editable: bool,
+    ) -> PrivacyFlags {
+        PrivacyFlags {
+            private,
+            unlisted,
+            readonly,
+            encrypt_server,
+            encrypt_client,
+            editable,
+        }
+    }
+
+    #[test]
+    fn test_sniff_extensionless_png() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\x08\x06\0\0\0";
+
+        let mut file = PastaFile::from_unsanitized("screenshot").unwrap();
+        assert!(!file.is_image());
+        file.sniff(png);
+        assert_eq!(file.content_type.as_deref(), Some("image/png"));
+        assert!(file.is_image());
+        assert!(file.embeddable());
+
+        // unknown bytes leave the type unknown
+        let mut file = PastaFile::from_unsanitized("notes").unwrap();
+        file.sniff(b"just some text");
+        assert_eq!(file.content_type, None);
+
+        // names that tell a type are not sniffed
+        let mut file = PastaFile::from_unsanitized("image.txt").unwrap();
+        file.sniff(png);
+        assert_eq!(file.content_type, None);
+        assert!(!file.is_image());
+    }
+
+    #[test]
+    fn test_embed_flags() {
+        let video = PastaFile::from_unsanitized("clip.mp4").unwrap();
+        let image = PastaFile::from_unsanitized("cat.png").unwrap();
+        let text = PastaFile::from_unsanitized("notes.txt").unwrap();
+
+        assert!(video.embeddable_with(true, true));
+        assert!(!video.embeddable_with(true, false));
+        assert!(image.embeddable_with(true, false));
+        assert!(!image.embeddable_with(false, true));
+        assert!(!text.embeddable_with(true, true));
+    }
+
+    #[test]
+    fn test_is_allowed_type() {
+        let text = PastaFile::from_unsanitized("notes.TXT").unwrap();
+        let program = PastaFile::from_unsanitized("setup.exe").unwrap();
+        let image = PastaFile::from_unsanitized("cat.png").unwrap();
+        let extensions = vec![String::from("txt"), String::from(" .md")];
+        let mime_types = vec![String::from("image/*")];
+
+        assert!(text.is_allowed_type(&extensions, &[]));
+        assert!(!program.is_allowed_type(&extensions, &mime_types));
+        assert!(image.is_allowed_type(&extensions, &mime_types));
+        assert!(!image.is_allowed_type(&extensions, &[]));
+        assert!(text.is_allowed_type(&[], &[String::from("text/plain")]));
+        // nothing configured allows everything
+        assert!(program.is_allowed_type(&[], &[]));
+        assert!(program.is_allowed_type(&[String::new()], &[]));
+    }
+
+    #[test]
+    fn test_privacy_preset_parse() {
+        assert_eq!(PrivacyPreset::parse("public"), Some(PrivacyPreset::Public));
+        assert_eq!(PrivacyPreset::parse("unlisted"), Some(PrivacyPreset::Unlisted));
+        assert_eq!(PrivacyPreset::parse("readonly"), Some(PrivacyPreset::Readonly));
+        assert_eq!(PrivacyPreset::parse("private"), Some(PrivacyPreset::Private));
+        assert_eq!(PrivacyPreset::parse("secret"), Some(PrivacyPreset::Secret));
+        assert_eq!(PrivacyPreset::parse("Public"), None);
+        assert_eq!(PrivacyPreset::parse(""), None);
+    }
+
+    #[test]
+    fn test_privacy_preset_flags() {
+        for editable in [false, true] {
+            assert_eq!(
+                PrivacyPreset::Public.flags(editable),
+                flags(false, false, false, false, false, editable)
+            );
+            assert_eq!(
+                PrivacyPreset::Unlisted.flags(editable),
+                flags(false, true, false, false, false, editable)
+            );
+            assert_eq!(
+                PrivacyPreset::Readonly.flags(editable),
+                flags(true, false, true, false, false, editable)
+            );
+            assert_eq!(
+                PrivacyPreset::Private.flags(editable),
+                flags(true, false, false, true, false, editable)
+            );
+            assert_eq!(
+                PrivacyPreset::Secret.flags(editable),
+                flags(true, false, false, true, true, editable)
+            );
+        }
+    }
+
+    #[test]
+    fn test_compressed_content() {
+        let log = "GET /upload/ant-eel 200 <3ms>\n".repeat(4096);
+        let mut pasta = Pasta {
+            id: 1,
+            content: log.clone(),
+            editable: false,
+            encrypted_key: None,
+            expiration: 0,
+            pasta_type: String::from("text"),
+            ..new_pasta(0)
+        };
+
+        pasta.compress_content(0);
+        assert_eq!(pasta.content, log);
+        pasta.compress_content(log.len() + 1);
+        assert_eq!(pasta.content, log);
+
+        pasta.compress_content(1024);
+        assert!(pasta.content.len() < log.len() / 10);
+        assert_eq!(pasta.text(), log);
+        assert_eq!(pasta.to_string(), log);
+        assert_eq!(pasta.total_size_bytes(), log.len());
+        assert_eq!(pasta.total_size_as_string(), "120 KB");
+        assert_eq!(pasta.stored_size_bytes(), pasta.content.len());
+        assert!(pasta
+            .content_textarea_safe()
+            .starts_with("GET /upload/ant-eel 200 &lt;3ms&gt;"));
+
+        // compressing again leaves it as it is
+        let compressed = pasta.content.clone();
+        pasta.compress_content(1024);
+        assert_eq!(pasta.content, compressed);
+
+        pasta.content = log.clone();
+        pasta.encrypt_server = true;
+        pasta.compress_content(1024);
+        assert_eq!(pasta.content, log);
+    }
+
+    #[test]
+    fn test_encrypted_file_reports_plaintext_size() {
+        let plaintext = vec![7u8; 3000];
+        let ciphertext = crate::util::misc::encrypt_bytes_with_password(&plaintext, "hunter22");
+        assert!(ciphertext.len() > plaintext.len());
+
+        let mut pasta = Pasta {
+            id: 1,
+            file: Some(PastaFile {
+                name: String::from("report.pdf"),
+                size: ByteSize::b(plaintext.len() as u64),
+                original_name: None,
+                sha256: None,
+                content_type: None,
+                stored_size: Some(ByteSize::b(ciphertext.len() as u64)),
+            }),
+            private: true,
+            editable: false,
+            encrypt_server: true,
+            encrypted_key: None,
+            expiration: 0,
+            pasta_type: String::from("text"),
+            ..new_pasta(0)
+        };
+
+        assert_eq!(pasta.total_size_bytes(), 3000);
+        assert_eq!(pasta.total_size_as_string(), "2 KB");
+        assert_eq!(pasta.stored_size_bytes(), ciphertext.len());
+
+        // files stored as uploaded are the same size either way
+        let file = pasta.file.as_mut().unwrap();
+        file.stored_size = None;
+        assert_eq!(pasta.total_size_bytes(), 3000);
+        assert_eq!(pasta.stored_size_bytes(), 3000);
     }
 }
@@ -0,0 +1,112 @@
+use std::time::Instant;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::Error;
+use chrono::Utc;
+use serde_json::json;
+
+use crate::util::rate_limit;
+
+/// Log target of the access log, whose records are written as they are
+pub const TARGET: &str = "access";
+
+/// Middleware logging every request as one JSON object, for `json_logs`
+pub async fn log_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    log_requests_to(req, next, |entry| log::info!(target: TARGET, "{}", entry)).await
+}
+
+/// `log_requests` handing the entries to `emit`. Only the method, path,
+/// status, client IP, pasta id and latency are logged, never the query,
+/// headers or body, which may carry passwords and keys.
+async fn log_requests_to(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+    emit: fn(String),
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let start = Instant::now();
+
+    let res = next.call(req).await?;
+
+    let request = res.request();
+    // uploads redirect to the pasta they created
+    let created = res
+        .headers()
+        .get(header::LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .and_then(|location| location.split("/upload/").nth(1));
+    let pasta_id = request.match_info().get("id").or(created);
+    emit(
+        json!({
+            "time": Utc::now().to_rfc3339(),
+            "method": request.method().as_str(),
+            "path": request.path(),
+            "status": res.status().as_u16(),
+            "client_ip": rate_limit::client_ip(request),
+            "pasta_id": pasta_id,
+            "latency_ms": start.elapsed().as_secs_f64() * 1000.0,
+        })
+        .to_string(),
+    );
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+    use std::sync::Mutex;
+
+    static ENTRIES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    fn record(entry: String) {
+        ENTRIES.lock().unwrap().push(entry);
+    }
+
+    #[actix_web::test]
+    async fn test_create_request_is_logged_as_json() {
+        let app = init_service(
+            App::new()
+                .wrap(from_fn(|req, next| log_requests_to(req, next, record)))
+                .route(
+                    "/upload",
+                    web::post().to(|| async {
+                        HttpResponse::Found()
+                            .append_header((header::LOCATION, "/upload/cat-dog-bird"))
+                            .finish()
+                    }),
+                ),
+        )
+        .await;
+
+        let request = TestRequest::post()
+            .uri("/upload?plain_key=hunter2")
+            .cookie(actix_web::cookie::Cookie::new("owner_token", "s3cr3t"))
+            .peer_addr("203.0.113.7:4242".parse().unwrap())
+            .set_form([("content", "hello"), ("plain_key", "hunter2")])
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), 302);
+
+        let entries = ENTRIES.lock().unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry: serde_json::Value = serde_json::from_str(&entries[0]).unwrap();
+        assert_eq!(entry["method"], "POST");
+        assert_eq!(entry["path"], "/upload");
+        assert_eq!(entry["status"], 302);
+        assert_eq!(entry["client_ip"], "203.0.113.7");
+        assert_eq!(entry["pasta_id"], "cat-dog-bird");
+        assert!(entry["latency_ms"].as_f64().unwrap() >= 0.0);
+        assert!(entry["time"].is_string());
+        assert!(!entries[0].contains("hunter2"));
+        assert!(!entries[0].contains("s3cr3t"));
+    }
+}
@@ -53,6 +53,12 @@ pub fn to_u64(animal_names: &str) -> Result<u64, &str> {
     Ok(result)
 }
 
+/// Normalize an animal name slug taken from a URL, so that copy-pasted
+/// variants like `Cat-Dog/` resolve the same as `cat-dog`
+pub fn normalize_slug(slug: &str) -> String {
+    slug.trim_end_matches('/').to_lowercase()
+}
+
 #[test]
 fn test_animal_name_to_u64() {
     assert_eq!(to_u64("ant"), Ok(0));
@@ -60,3 +66,10 @@ fn test_animal_name_to_u64() {
     assert_eq!(to_u64("eel-ant"), Ok(64));
     assert_eq!(to_u64("sloth-ant-lion"), Ok(12345));
 }
+
+#[test]
+fn test_normalize_slug() {
+    assert_eq!(to_u64(&normalize_slug("Sloth-Ant-Lion")), Ok(12345));
+    assert_eq!(to_u64(&normalize_slug("sloth-ant-lion/")), Ok(12345));
+    assert_eq!(to_u64(&normalize_slug("SLOTH-ANT-LION/")), Ok(12345));
+}
@@ -1,11 +1,15 @@
 use actix_multipart::Multipart;
 use actix_web::dev::ServiceRequest;
 use actix_web::web::Bytes;
-use actix_web::{error, Error};
+use actix_web::http::header;
+use actix_web::{error, Error, HttpRequest, HttpResponse};
 use actix_web_httpauth::extractors::basic::BasicAuth;
 use futures::TryStreamExt;
+use serde_json::json;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::args::ARGS;
+use crate::args::{ARGS, DEFAULT_ADMIN_PASSWORD};
+use crate::util::hashids::HARSH;
 
 pub async fn auth_validator(
     req: ServiceRequest,
@@ -36,3 +40,178 @@ pub async fn password_from_multipart(mut payload: Multipart) -> Result<String, E
     }
     Ok(password)
 }
+
+/// Compare secrets in time independent of where they differ. The length of
+/// `expected` is not hidden.
+pub fn constant_time_eq(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Token from an `Authorization: Bearer <token>` header, if present
+pub fn bearer_token(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_string())
+}
+
+/// Header carrying the admin password on instances behind basic auth, where
+/// `Authorization` holds the basic auth login
+pub const ADMIN_HEADER: &str = "X-Microbin-Admin";
+
+/// Header carrying the uploader password on instances behind basic auth
+pub const UPLOADER_HEADER: &str = "X-Microbin-Uploader";
+
+/// Secret from header `name`, or else from an `Authorization: Bearer <token>`
+/// header
+pub fn secret_token(req: &HttpRequest, name: &str) -> Option<String> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_string())
+        .or_else(|| bearer_token(req))
+}
+
+/// Whether the request carries the admin password as
+/// `Authorization: Bearer <password>` or in the `X-Microbin-Admin` header
+pub fn is_admin(req: &HttpRequest) -> bool {
+    has_password(req, ADMIN_HEADER, &ARGS.auth_admin_password)
+}
+
+/// Why a request may not use an admin endpoint
+#[derive(Debug, PartialEq, Eq)]
+pub enum AdminRefused {
+    /// The admin password is left at its well known default
+    DefaultPassword,
+    /// The request does not carry the admin password
+    InvalidPassword,
+}
+
+impl AdminRefused {
+    /// JSON error answering the refused request
+    pub fn response(&self) -> HttpResponse {
+        match self {
+            AdminRefused::DefaultPassword => HttpResponse::Forbidden().json(json!({
+                "error": "The admin API is disabled until MICROBIN_ADMIN_PASSWORD is changed."
+            })),
+            AdminRefused::InvalidPassword => {
+                HttpResponse::Unauthorized().json(json!({ "error": "Invalid admin password." }))
+            }
+        }
+    }
+}
+
+/// Let the request use admin endpoints if it carries the admin password,
+/// refusing everyone while that is the default anyone could guess
+pub fn check_admin(req: &HttpRequest) -> Result<(), AdminRefused> {
+    check_admin_password(req, &ARGS.auth_admin_password)
+}
+
+fn check_admin_password(req: &HttpRequest, password: &str) -> Result<(), AdminRefused> {
+    if password == DEFAULT_ADMIN_PASSWORD {
+        Err(AdminRefused::DefaultPassword)
+    } else if has_password(req, ADMIN_HEADER, password) {
+        Ok(())
+    } else {
+        Err(AdminRefused::InvalidPassword)
+    }
+}
+
+fn has_password(req: &HttpRequest, header: &str, password: &str) -> bool {
+    secret_token(req, header).is_some_and(|token| constant_time_eq(&token, password))
+}
+
+/// Whether the request carries an unexpired owner token for pasta `id`.
+/// The token is issued on upload and encodes its expiry and the pasta id.
+pub fn is_owner(req: &HttpRequest, id: u64) -> bool {
+    let cookie = match req.cookie(&ARGS.owner_cookie_name()) {
+        Some(cookie) => cookie,
+        None => return false,
+    };
+
+    // only accept the exact encoding we hand out
+    match HARSH.decode(cookie.value()) {
+        Ok(numbers)
+            if numbers.len() == 2 && constant_time_eq(cookie.value(), &HARSH.encode(&numbers)) =>
+        {
+            let timenow = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            numbers[1] == id && numbers[0] > timenow
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq("s3cr3t-token", "s3cr3t-token"));
+        assert!(constant_time_eq("", ""));
+        assert!(!constant_time_eq("s3cr3t-token", "s3cr3t-tokeN"));
+        assert!(!constant_time_eq("x3cr3t-token", "s3cr3t-token"));
+        assert!(!constant_time_eq("s3cr3t", "s3cr3t-token"));
+        assert!(!constant_time_eq("s3cr3t-token-", "s3cr3t-token"));
+        assert!(!constant_time_eq("", "s3cr3t-token"));
+    }
+
+    #[test]
+    fn test_has_password() {
+        use actix_web::test::TestRequest;
+
+        let request = TestRequest::default()
+            .insert_header((header::AUTHORIZATION, "Bearer s3cr3t"))
+            .to_http_request();
+        assert!(has_password(&request, ADMIN_HEADER, "s3cr3t"));
+        assert!(!has_password(&request, ADMIN_HEADER, "other"));
+
+        let request = TestRequest::default().to_http_request();
+        assert!(!has_password(&request, ADMIN_HEADER, "s3cr3t"));
+
+        let request = TestRequest::default()
+            .insert_header((header::AUTHORIZATION, "Basic czNjcjN0"))
+            .to_http_request();
+        assert!(!has_password(&request, ADMIN_HEADER, "s3cr3t"));
+
+        // next to a basic auth login
+        let request = TestRequest::default()
+            .insert_header((header::AUTHORIZATION, "Basic czNjcjN0"))
+            .insert_header((ADMIN_HEADER, "s3cr3t"))
+            .to_http_request();
+        assert!(has_password(&request, ADMIN_HEADER, "s3cr3t"));
+        assert!(!has_password(&request, UPLOADER_HEADER, "s3cr3t"));
+    }
+
+    #[test]
+    fn test_check_admin_password() {
+        use actix_web::test::TestRequest;
+
+        let request = TestRequest::default()
+            .insert_header((ADMIN_HEADER, DEFAULT_ADMIN_PASSWORD))
+            .to_http_request();
+        assert_eq!(
+            check_admin_password(&request, DEFAULT_ADMIN_PASSWORD),
+            Err(AdminRefused::DefaultPassword)
+        );
+        assert_eq!(
+            check_admin_password(&request, "s3cr3t"),
+            Err(AdminRefused::InvalidPassword)
+        );
+
+        let request = TestRequest::default()
+            .insert_header((ADMIN_HEADER, "s3cr3t"))
+            .to_http_request();
+        assert_eq!(check_admin_password(&request, "s3cr3t"), Ok(()));
+    }
+}
@@ -0,0 +1,368 @@
+use crate::args::ARGS;
+use crate::util::http_client;
+use crate::util::webdav::encode_path;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, StatusCode};
+use sha2::Sha256;
+
+/// Version of the Blob service REST API requests are made against
+const API_VERSION: &str = "2021-08-06";
+
+/// A container in an Azure Blob Storage account attachments are stored in.
+///
+/// Requests are signed with the account's shared key, which never leaves
+/// MicroBin. Each request carries its own date, so the clock of the machine
+/// MicroBin runs on has to be roughly right.
+pub struct AzureBlob {
+    endpoint: String,
+    account: String,
+    key: Vec<u8>,
+    container: String,
+}
+
+impl AzureBlob {
+    /// A container reached at `endpoint`, like
+    /// `https://account.blob.core.windows.net`, with the base64 `key` of
+    /// `account`
+    pub fn new(endpoint: &str, account: &str, key: &str, container: &str) -> Result<Self, String> {
+        let key = BASE64_STANDARD
+            .decode(key.trim())
+            .map_err(|e| format!("Invalid Azure storage key: {}", e))?;
+        Ok(AzureBlob {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            account: account.to_string(),
+            key,
+            container: container.to_string(),
+        })
+    }
+
+    /// The container configured with `azure_account`, `azure_key` and
+    /// `azure_container`, at `azure_endpoint` when set
+    pub fn from_args() -> Result<Self, String> {
+        let account = ARGS.azure_account.as_deref().unwrap_or_default();
+        let endpoint = match ARGS.azure_endpoint.as_deref() {
+            Some(endpoint) => endpoint.to_string(),
+            None => format!("https://{}.blob.core.windows.net", account),
+        };
+        AzureBlob::new(
+            &endpoint,
+            account,
+            ARGS.azure_key.as_deref().unwrap_or_default(),
+            ARGS.azure_container.as_deref().unwrap_or_default(),
+        )
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}/{}", self.endpoint, self.container, encode_path(path))
+    }
+
+    /// The `Authorization` header of a request, see
+    /// https://learn.microsoft.com/rest/api/storageservices/authorize-with-shared-key
+    fn authorization(
+        &self,
+        method: &Method,
+        content_length: usize,
+        ms_headers: &[(&str, &str)],
+        url_path: &str,
+    ) -> String {
+        let string_to_sign = string_to_sign(
+            method,
+            content_length,
+            ms_headers,
+            &format!("/{}{}", self.account, url_path),
+        );
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(string_to_sign.as_bytes());
+        format!(
+            "SharedKey {}:{}",
+            self.account,
+            BASE64_STANDARD.encode(mac.finalize().into_bytes())
+        )
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&[u8]>,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let url = self.url(path);
+        let url_path = reqwest::Url::parse(&url)
+            .map(|url| url.path().to_string())
+            .unwrap_or_default();
+        let date = Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+
+        let mut ms_headers = vec![("x-ms-date", date.as_str()), ("x-ms-version", API_VERSION)];
+        if body.is_some() {
+            ms_headers.push(("x-ms-blob-type", "BlockBlob"));
+        }
+        let content_length = body.map_or(0, <[u8]>::len);
+        let authorization = self.authorization(&method, content_length, &ms_headers, &url_path);
+
+        let mut request = http_client::new_async()
+            .request(method, url)
+            .header("Authorization", authorization);
+        for (name, value) in ms_headers {
+            request = request.header(name, value);
+        }
+        if let Some(body) = body {
+            request = request.body(body.to_vec());
+        }
+        request.send().await
+    }
+
+    pub async fn put(&self, path: &str, data: &[u8]) -> Result<(), String> {
+        let response = self
+            .send(Method::PUT, path, Some(data))
+            .await
+            .map_err(|e| format!("Failed to upload to Azure: {}", e))?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            status => Err(format!("Failed to upload to Azure: status {}", status)),
+        }
+    }
+
+    pub async fn get(&self, path: &str) -> Result<Vec<u8>, String> {
+        let response = self
+            .send(Method::GET, path, None)
+            .await
+            .map_err(|e| format!("Failed to get file from Azure: {}", e))?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Err(format!("File not found in Azure: {}", path)),
+            status if !status.is_success() => {
+                Err(format!("Failed to get file from Azure: status {}", status))
+            }
+            _ => response
+                .bytes()
+                .await
+                .map(|bytes| bytes.to_vec())
+                .map_err(|e| format!("Failed to get file from Azure: {}", e)),
+        }
+    }
+
+    pub async fn exists(&self, path: &str) -> Result<bool, String> {
+        let response = self
+            .send(Method::HEAD, path, None)
+            .await
+            .map_err(|e| format!("Failed to check blob in Azure: {}", e))?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_success() => Ok(true),
+            status => Err(format!("Failed to check blob in Azure: status {}", status)),
+        }
+    }
+
+    /// Delete a blob, blobs that are already gone count as deleted
+    pub async fn delete(&self, path: &str) -> Result<(), String> {
+        let response = self
+            .send(Method::DELETE, path, None)
+            .await
+            .map_err(|e| format!("Failed to delete from Azure: {}", e))?;
+        let status = response.status();
+        if status.is_success() || status == StatusCode::NOT_FOUND {
+            Ok(())
+        } else {
+            Err(format!("Failed to delete from Azure: status {}", status))
+        }
+    }
+
+    /// Copy a blob by downloading and uploading it again. Server side copies
+    /// may still be pending when they return, and the source is deleted
+    /// right after moving.
+    pub async fn copy(&self, from: &str, to: &str) -> Result<(), String> {
+        let data = self.get(from).await?;
+        self.put(to, &data).await
+    }
+}
+
+/// The string a shared key request signature is computed over, with the
+/// standard headers MicroBin never sends left empty
+fn string_to_sign(
+    method: &Method,
+    content_length: usize,
+    ms_headers: &[(&str, &str)],
+    canonicalized_resource: &str,
+) -> String {
+    // an empty body is signed as an empty Content-Length
+    let content_length = match content_length {
+        0 => String::new(),
+        length => length.to_string(),
+    };
+
+    let mut headers: Vec<(String, &str)> = ms_headers
+        .iter()
+        .map(|(name, value)| (name.to_ascii_lowercase(), value.trim()))
+        .collect();
+    headers.sort();
+    let canonicalized_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect();
+
+    format!(
+        "{}\n\n\n{}\n\n\n\n\n\n\n\n\n{}{}",
+        method.as_str(),
+        content_length,
+        canonicalized_headers,
+        canonicalized_resource
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Key of the Azurite emulator's well-known development account
+    const AZURITE_KEY: &str =
+        "Eby8vdM02xNOcqFlqUwJPLlmEtlCDXJ1OUzFT50uSRZ6IFsuFq2UVErCz4I6tq/K1SZFPTOtr/KBHBeksoGMGw==";
+
+    #[test]
+    fn test_shared_key_signature() {
+        let blob = AzureBlob::new(
+            "http://127.0.0.1:10000/devstoreaccount1",
+            "devstoreaccount1",
+            AZURITE_KEY,
+            "microbin",
+        )
+        .unwrap();
+        let headers = [
+            ("x-ms-version", API_VERSION),
+            ("x-ms-date", "Sat, 17 Oct 2026 12:00:00 GMT"),
+        ];
+
+        assert_eq!(
+            string_to_sign(&Method::GET, 0, &headers, "/devstoreaccount1/c/a.txt"),
+            "GET\n\n\n\n\n\n\n\n\n\n\n\n\
+             x-ms-date:Sat, 17 Oct 2026 12:00:00 GMT\nx-ms-version:2021-08-06\n\
+             /devstoreaccount1/c/a.txt"
+        );
+        assert_eq!(
+            blob.authorization(
+                &Method::GET,
+                0,
+                &headers,
+                "/devstoreaccount1/microbin/attachments/cat-dog/a.txt"
+            ),
+            "SharedKey devstoreaccount1:IfPyM38ULB/j7vgbtRTeNcaRryaY03B0Ypc8axhr/M8="
+        );
+        assert!(AzureBlob::new("http://localhost", "a", "not base64!", "c").is_err());
+    }
+
+    /// Method, path and `Authorization` header of a request to the mock server
+    type Seen = Arc<Mutex<Vec<(String, String, Option<String>)>>>;
+
+    /// Blob service on a free port storing blobs in memory, like Azurite
+    fn mock_server() -> (String, Seen) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/devstoreaccount1", listener.local_addr().unwrap());
+        let seen: Seen = Arc::default();
+
+        let requests = Arc::clone(&seen);
+        thread::spawn(move || {
+            let mut blobs: Vec<(String, Vec<u8>)> = Vec::new();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap_or_default().to_string();
+                let path = parts.next().unwrap_or_default().to_string();
+
+                let mut content_length = 0;
+                let mut authorization = None;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    let (name, value) = line.split_once(':').unwrap();
+                    match name.to_ascii_lowercase().as_str() {
+                        "content-length" => content_length = value.trim().parse().unwrap(),
+                        "authorization" => authorization = Some(value.trim().to_string()),
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+
+                let position = blobs.iter().position(|(name, _)| *name == path);
+                let (status, response) = match (method.as_str(), position) {
+                    ("PUT", _) => {
+                        blobs.retain(|(name, _)| *name != path);
+                        blobs.push((path.clone(), body));
+                        (201, vec![])
+                    }
+                    ("GET", Some(index)) => (200, blobs[index].1.clone()),
+                    ("DELETE", Some(index)) => {
+                        blobs.remove(index);
+                        (202, vec![])
+                    }
+                    _ => (404, vec![]),
+                };
+                requests.lock().unwrap().push((method, path, authorization));
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    response.len()
+                )
+                .unwrap();
+                stream.write_all(&response).unwrap();
+            }
+        });
+
+        (url, seen)
+    }
+
+    #[actix_web::test]
+    async fn test_azure_requests() {
+        let (url, seen) = mock_server();
+        let blob = AzureBlob::new(&url, "devstoreaccount1", AZURITE_KEY, "microbin").unwrap();
+
+        blob.put("attachments/cat-dog/my file.txt", b"hello")
+            .await
+            .unwrap();
+        assert_eq!(
+            blob.get("attachments/cat-dog/my file.txt").await.unwrap(),
+            b"hello"
+        );
+        blob.copy(
+            "attachments/cat-dog/my file.txt",
+            "attachments/cow-pig/my file.txt",
+        )
+        .await
+        .unwrap();
+        blob.delete("attachments/cat-dog/my file.txt")
+            .await
+            .unwrap();
+        assert!(blob.get("attachments/cat-dog/my file.txt").await.is_err());
+        // blobs that are gone already count as deleted
+        blob.delete("attachments/cat-dog/my file.txt")
+            .await
+            .unwrap();
+        assert_eq!(
+            blob.get("attachments/cow-pig/my file.txt").await.unwrap(),
+            b"hello"
+        );
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(
+            seen[0].1,
+            "/devstoreaccount1/microbin/attachments/cat-dog/my%20file.txt"
+        );
+        assert!(seen.iter().all(|(_, _, auth)| auth
+            .as_deref()
+            .is_some_and(|auth| auth.starts_with("SharedKey devstoreaccount1:"))));
+    }
+}
@@ -0,0 +1,181 @@
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::web::Bytes;
+use futures::{stream, Stream};
+use tar::{Archive, Builder, Header};
+
+use crate::pasta::Pasta;
+use crate::util::storage;
+
+/// The database in a backup, as the JSON array `db_json` keeps
+pub const DATABASE_ENTRY: &str = "pastas.json";
+
+/// Path in a backup of the file of `pasta` stored at `storage_path`
+pub fn entry_path(pasta: &Pasta, storage_path: &str) -> String {
+    let (_, path) = storage::BackendKind::for_path(storage_path);
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    format!("attachments/{}/{}", pasta.id_as_animals(), file_name)
+}
+
+fn append(builder: &mut Builder<Vec<u8>>, path: &str, data: &[u8]) -> Result<(), String> {
+    let mut header = Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0),
+    );
+    builder
+        .append_data(&mut header, path, data)
+        .map_err(|e| format!("Failed to add {} to backup: {}", path, e))
+}
+
+/// A tar archive of `pastas` and all their files, one file at a time. Files
+/// are fetched from whichever backend holds them and added as stored, so
+/// encrypted files stay encrypted. Files that can not be fetched are logged
+/// and left out.
+pub fn export(pastas: Vec<Pasta>) -> Result<impl Stream<Item = Bytes>, String> {
+    let database = serde_json::to_vec_pretty(&pastas)
+        .map_err(|e| format!("Failed to serialize pastas: {}", e))?;
+    let mut builder = Builder::new(Vec::new());
+    append(&mut builder, DATABASE_ENTRY, &database)?;
+
+    let files: Vec<(String, String, String)> = pastas
+        .iter()
+        .flat_map(|pasta| {
+            pasta.file_storage_paths().into_iter().map(|storage_path| {
+                (
+                    pasta.id_as_animals(),
+                    entry_path(pasta, &storage_path),
+                    storage_path,
+                )
+            })
+        })
+        .collect();
+
+    Ok(stream::unfold(
+        Some((builder, files.into_iter())),
+        |state| async move {
+            let (mut builder, mut files) = state?;
+            match files.next() {
+                Some((pasta_id, entry, storage_path)) => {
+                    let added = match storage::get_file(&pasta_id, &storage_path).await {
+                        Ok(data) => append(&mut builder, &entry, &data),
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = added {
+                        log::error!("Leaving {} out of backup: {}", storage_path, e);
+                    }
+                    let chunk = std::mem::take(builder.get_mut());
+                    Some((Bytes::from(chunk), Some((builder, files))))
+                }
+                // finishing writes the blocks marking the end of the archive
+                None => {
+                    let end = builder.into_inner().unwrap_or_default();
+                    Some((Bytes::from(end), None))
+                }
+            }
+        },
+    ))
+}
+
+/// Unpack a backup into `dir` and read the pastas in it. Blocks, so run it
+/// off the async workers.
+pub fn unpack(archive: impl Read, dir: &Path) -> Result<Vec<Pasta>, String> {
+    Archive::new(archive)
+        .unpack(dir)
+        .map_err(|e| format!("Failed to unpack backup: {}", e))?;
+    let database = fs::read(dir.join(DATABASE_ENTRY))
+        .map_err(|e| format!("Backup has no {}: {}", DATABASE_ENTRY, e))?;
+    serde_json::from_slice(&database)
+        .map_err(|e| format!("Failed to read {}: {}", DATABASE_ENTRY, e))
+}
+
+/// Save the files of `pastas` unpacked to `dir` back to the storage paths
+/// they had, so files from S3 go back to S3. Files missing from the backup
+/// are logged and skipped. Returns how many files were restored.
+pub async fn restore_files(pastas: &[Pasta], dir: &Path) -> Result<usize, String> {
+    let mut restored = 0;
+    for pasta in pastas {
+        let pasta_id = pasta.id_as_animals();
+        for storage_path in pasta.file_storage_paths() {
+            let data = match fs::read(dir.join(entry_path(pasta, &storage_path))) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Backup has no file {} of {}: {}", storage_path, pasta_id, e);
+                    continue;
+                }
+            };
+            storage::save_file(&pasta_id, &storage_path, &data).await?;
+            restored += 1;
+        }
+    }
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::create::new_pasta;
+    use crate::pasta::PastaFile;
+    use bytesize::ByteSize;
+    use futures::StreamExt;
+
+    fn pasta(id: u64) -> Pasta {
+        Pasta {
+            id,
+            content: String::from("backed up"),
+            file: Some(PastaFile {
+                name: String::from("notes.txt"),
+                size: ByteSize::b(11),
+                original_name: None,
+                sha256: None,
+                content_type: None,
+                stored_size: None,
+            }),
+            editable: false,
+            encrypted_key: None,
+            expiration: 0,
+            pasta_type: String::from("text"),
+            ..new_pasta(0)
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_export_then_import() {
+        // an id no other test stores files under
+        let original = pasta(4_012_345_678);
+        let pasta_id = original.id_as_animals();
+        storage::save_file(&pasta_id, "notes.txt", b"hello world")
+            .await
+            .unwrap();
+
+        let archive: Vec<u8> = export(vec![original.clone()])
+            .unwrap()
+            .collect::<Vec<Bytes>>()
+            .await
+            .concat();
+        storage::delete_file(&pasta_id, "notes.txt").await.unwrap();
+
+        let dir = std::env::temp_dir().join(format!("microbin-backup-{}", std::process::id()));
+        let pastas = unpack(archive.as_slice(), &dir).unwrap();
+        assert_eq!(pastas.len(), 1);
+        assert_eq!(pastas[0].id, original.id);
+        assert_eq!(pastas[0].content, "backed up");
+        assert_eq!(pastas[0].file, original.file);
+
+        assert_eq!(restore_files(&pastas, &dir).await, Ok(1));
+        assert_eq!(
+            storage::get_file(&pasta_id, "notes.txt").await.unwrap(),
+            b"hello world"
+        );
+
+        storage::delete_file(&pasta_id, "notes.txt").await.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+const ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const BASE: u64 = ALPHABET.len() as u64;
+
+pub fn to_base62(number: u64) -> String {
+    let mut digits: Vec<u8> = Vec::new();
+    let mut value = number;
+
+    loop {
+        digits.push(ALPHABET[(value % BASE) as usize]);
+        value /= BASE;
+        if value == 0 {
+            break;
+        }
+    }
+
+    digits.reverse();
+    String::from_utf8(digits).unwrap()
+}
+
+/// Only the canonical form of a number is accepted, so every pasta has a
+/// single link
+pub fn to_u64(base62: &str) -> Result<u64, &str> {
+    if base62.is_empty() || (base62.len() > 1 && base62.starts_with('0')) {
+        return Err("Failed to decode base62 ID");
+    }
+
+    let mut result: u64 = 0;
+    for digit in base62.bytes() {
+        let value = ALPHABET
+            .iter()
+            .position(|&c| c == digit)
+            .ok_or("Failed to decode base62 ID")?;
+        result = result
+            .checked_mul(BASE)
+            .and_then(|result| result.checked_add(value as u64))
+            .ok_or("Base62 ID out of range")?;
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_base62() {
+        assert_eq!(to_base62(0), "0");
+        assert_eq!(to_base62(61), "z");
+        assert_eq!(to_base62(62), "10");
+        assert_eq!(to_base62(u16::MAX as u64), "H31");
+        assert_eq!(to_base62(u64::MAX), "LygHa16AHYF");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut samples: Vec<u64> = vec![0, 1, 61, 62, 3843, 3844, u16::MAX as u64, u64::MAX];
+        samples.extend((0..64).map(|shift| 1u64 << shift));
+        samples.extend((1..64).map(|shift| (1u64 << shift) - 1));
+        samples.extend((0..1000u64).map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15)));
+
+        for number in samples {
+            assert_eq!(to_u64(&to_base62(number)), Ok(number));
+        }
+    }
+
+    #[test]
+    fn test_to_u64_rejects_invalid_ids() {
+        assert!(to_u64("").is_err());
+        assert!(to_u64("007").is_err());
+        assert!(to_u64("cat-dog").is_err());
+        // one past u64::MAX
+        assert!(to_u64("LygHa16AHYG").is_err());
+        assert!(to_u64("zzzzzzzzzzzz").is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Largest chunk sent to clamd at once
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How long clamd may take to connect, accept a chunk or answer
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Outcome of scanning a file
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Clean,
+    /// Infected, with the name of the signature clamd found
+    Infected(String),
+}
+
+/// Stream everything `data` yields to the clamd listening on `address` with
+/// the INSTREAM command. Blocks, so run it off the async workers.
+pub fn scan(address: &str, mut data: impl Read) -> Result<Verdict, String> {
+    let mut stream = connect(address)?;
+    let write_error = |e: std::io::Error| format!("Failed to send file to clamd: {}", e);
+
+    stream.write_all(b"zINSTREAM\0").map_err(write_error)?;
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let len = data
+            .read(&mut chunk)
+            .map_err(|e| format!("Failed to read file for scanning: {}", e))?;
+        if len == 0 {
+            break;
+        }
+        stream
+            .write_all(&(len as u32).to_be_bytes())
+            .and_then(|_| stream.write_all(&chunk[..len]))
+            .map_err(write_error)?;
+    }
+    // a chunk of length zero ends the stream
+    stream.write_all(&0u32.to_be_bytes()).map_err(write_error)?;
+
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .map_err(|e| format!("Failed to read answer of clamd: {}", e))?;
+    parse_response(&response)
+}
+
+fn connect(address: &str) -> Result<TcpStream, String> {
+    let addrs = address
+        .to_socket_addrs()
+        .map_err(|e| format!("Failed to resolve clamd address {}: {}", address, e))?;
+
+    let mut last_error = format!("No address found for clamd at {}", address);
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, TIMEOUT) {
+            Ok(stream) => {
+                stream
+                    .set_read_timeout(Some(TIMEOUT))
+                    .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+                    .map_err(|e| e.to_string())?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = format!("Failed to connect to clamd at {}: {}", addr, e),
+        }
+    }
+    Err(last_error)
+}
+
+/// Read an answer like `stream: OK` or `stream: Eicar-Signature FOUND`.
+/// Anything else, like size limit errors, is a failed scan.
+fn parse_response(response: &str) -> Result<Verdict, String> {
+    let response = response.trim_end_matches('\0').trim();
+    let result = response
+        .strip_prefix("stream:")
+        .map(str::trim)
+        .unwrap_or(response);
+
+    if result == "OK" {
+        Ok(Verdict::Clean)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(Verdict::Infected(signature.trim().to_string()))
+    } else {
+        Err(format!("Unexpected answer from clamd: {}", response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// A clamd answering `answer` to one INSTREAM scan, returning the bytes
+    /// it was sent
+    fn mock_clamd(answer: &'static str) -> (String, thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut command = [0; 10];
+            stream.read_exact(&mut command).unwrap();
+            assert_eq!(&command, b"zINSTREAM\0");
+
+            let mut received = Vec::new();
+            loop {
+                let mut len = [0; 4];
+                stream.read_exact(&mut len).unwrap();
+                let len = u32::from_be_bytes(len) as usize;
+                if len == 0 {
+                    break;
+                }
+                let mut chunk = vec![0; len];
+                stream.read_exact(&mut chunk).unwrap();
+                received.extend(chunk);
+            }
+            stream.write_all(answer.as_bytes()).unwrap();
+            received
+        });
+        (address, handle)
+    }
+
+    #[test]
+    fn test_scan_clean() {
+        let (address, clamd) = mock_clamd("stream: OK\0");
+        let data = vec![7u8; CHUNK_SIZE + 100];
+
+        assert_eq!(scan(&address, data.as_slice()), Ok(Verdict::Clean));
+        assert_eq!(clamd.join().unwrap(), data);
+    }
+
+    #[test]
+    fn test_scan_infected() {
+        let (address, clamd) = mock_clamd("stream: Win.Test.EICAR_HDB-1 FOUND\0");
+
+        assert_eq!(
+            scan(&address, &b"X5O!P%@AP"[..]),
+            Ok(Verdict::Infected(String::from("Win.Test.EICAR_HDB-1")))
+        );
+        clamd.join().unwrap();
+    }
+
+    #[test]
+    fn test_parse_response() {
+        assert_eq!(parse_response("stream: OK\0"), Ok(Verdict::Clean));
+        assert!(parse_response("INSTREAM size limit exceeded. ERROR\0").is_err());
+        assert!(parse_response("").is_err());
+    }
+}
@@ -0,0 +1,98 @@
+//! Compression of large text pastas at rest. Compressed content is stored as
+//! `PREFIX`, the original length, `:` and the base64 of the gzip stream, so
+//! the version in the prefix tells how to read it back and the original
+//! size is known without decompressing.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+/// Marks content compressed with gzip. Starts with a control character, which
+/// text pastas do not start with.
+const PREFIX: &str = "\u{1}gz1:";
+
+pub fn is_compressed(content: &str) -> bool {
+    content.starts_with(PREFIX)
+}
+
+/// `text` compressed, unless that would not make it any smaller
+pub fn compress(text: &str) -> Option<String> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(text.as_bytes()).ok()?;
+    let compressed = encoder.finish().ok()?;
+
+    let encoded = format!("{}{}:{}", PREFIX, text.len(), BASE64.encode(compressed));
+    (encoded.len() < text.len()).then_some(encoded)
+}
+
+/// The text of `content`, decompressed if needed
+pub fn decompress(content: &str) -> Cow<'_, str> {
+    if !is_compressed(content) {
+        return Cow::Borrowed(content);
+    }
+
+    match try_decompress(content) {
+        Some(text) => Cow::Owned(text),
+        None => {
+            log::error!("Failed to decompress pasta content");
+            Cow::Borrowed("")
+        }
+    }
+}
+
+fn try_decompress(content: &str) -> Option<String> {
+    let (length, encoded) = content[PREFIX.len()..].split_once(':')?;
+    let compressed = BASE64.decode(encoded).ok()?;
+
+    let mut text = String::with_capacity(length.parse().unwrap_or(0));
+    GzDecoder::new(compressed.as_slice())
+        .read_to_string(&mut text)
+        .ok()?;
+    Some(text)
+}
+
+/// Length of the text of `content` in bytes, without decompressing it
+pub fn original_len(content: &str) -> usize {
+    if !is_compressed(content) {
+        return content.len();
+    }
+
+    content[PREFIX.len()..]
+        .split_once(':')
+        .and_then(|(length, _)| length.parse().ok())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let log = "2024-01-01T00:00:00 [INFO] - request served in 3ms\n".repeat(500);
+        let compressed = compress(&log).unwrap();
+
+        assert!(is_compressed(&compressed));
+        assert!(compressed.len() < log.len() / 10);
+        assert_eq!(decompress(&compressed), log);
+        assert_eq!(original_len(&compressed), log.len());
+
+        let unicode = "Grüße, 世界! ".repeat(200);
+        assert_eq!(decompress(&compress(&unicode).unwrap()), unicode);
+    }
+
+    #[test]
+    fn test_plain_content_is_left_alone() {
+        // too short or too random to get any smaller
+        assert_eq!(compress("short note"), None);
+        assert_eq!(decompress("short note"), "short note");
+        assert_eq!(original_len("short note"), 10);
+
+        assert!(!is_compressed("gz1:not compressed"));
+        assert_eq!(decompress("\u{1}gz1:12:not base64"), "");
+    }
+}
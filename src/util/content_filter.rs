@@ -0,0 +1,101 @@
+use lazy_static::lazy_static;
+use regex::RegexSet;
+
+use crate::args::ARGS;
+
+/// Upper bound for the compiled size of the blocked content patterns
+const SIZE_LIMIT: usize = 10 * 1024 * 1024;
+
+lazy_static! {
+    /// The regex crate matches in linear time, so no pattern can make
+    /// matching backtrack catastrophically on crafted content
+    static ref BLOCKED_PATTERNS: Result<RegexSet, regex::Error> =
+        regex::RegexSetBuilder::new(ARGS.blocked_content_patterns.iter().filter(|p| !p.is_empty()))
+            .size_limit(SIZE_LIMIT)
+            .build();
+}
+
+/// Compile the blocked content patterns, so invalid ones are reported at startup
+pub fn init() -> Result<(), String> {
+    BLOCKED_PATTERNS
+        .as_ref()
+        .map(|_| ())
+        .map_err(|e| format!("Invalid blocked content pattern: {}", e))
+}
+
+/// Returns the first blocked content pattern matching the content, if any
+pub fn blocked_pattern(content: &str) -> Option<&'static str> {
+    let patterns = BLOCKED_PATTERNS.as_ref().ok()?;
+    let index = patterns.matches(content).into_iter().next()?;
+    Some(patterns.patterns()[index].as_str())
+}
+
+/// Whether a URL pasta may redirect to `url` according to `url_allowlist`
+/// and `url_blocklist`
+pub fn url_allowed(url: &str) -> bool {
+    if ARGS.url_allowlist.is_empty() && ARGS.url_blocklist.is_empty() {
+        return true;
+    }
+    let Ok(url) = reqwest::Url::parse(url) else {
+        return false;
+    };
+    match url.host_str() {
+        Some(host) => domain_allowed(host, &ARGS.url_allowlist, &ARGS.url_blocklist),
+        None => false,
+    }
+}
+
+/// Whether `host` is not in `blocklist` and, unless it is empty, in
+/// `allowlist`. Listed domains include their subdomains.
+fn domain_allowed(host: &str, allowlist: &[String], blocklist: &[String]) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let listed = |list: &[String]| {
+        list.iter()
+            .map(|domain| domain.trim().trim_matches('.').to_ascii_lowercase())
+            .filter(|domain| !domain.is_empty())
+            .any(|domain| {
+                host == domain
+                    || host
+                        .strip_suffix(domain.as_str())
+                        .is_some_and(|sub| sub.ends_with('.'))
+            })
+    };
+
+    let allowed = allowlist.iter().all(|d| d.trim().is_empty()) || listed(allowlist);
+    allowed && !listed(blocklist)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(domains: &[&str]) -> Vec<String> {
+        domains.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn test_domain_allowed() {
+        assert!(domain_allowed("example.com", &[], &[]));
+
+        let blocklist = list(&["bad.example", " Phish.example "]);
+        assert!(!domain_allowed("bad.example", &[], &blocklist));
+        assert!(!domain_allowed("www.bad.example", &[], &blocklist));
+        assert!(!domain_allowed("login.PHISH.example.", &[], &blocklist));
+        assert!(domain_allowed("notbad.example", &[], &blocklist));
+        assert!(domain_allowed("bad.example.org", &[], &blocklist));
+
+        let allowlist = list(&["github.com", "example.org"]);
+        assert!(domain_allowed("github.com", &allowlist, &[]));
+        assert!(domain_allowed("gist.github.com", &allowlist, &[]));
+        assert!(!domain_allowed("evilgithub.com", &allowlist, &[]));
+        assert!(!domain_allowed("github.com.evil.net", &allowlist, &[]));
+
+        // the blocklist wins over the allowlist
+        let blocklist = list(&["gist.github.com"]);
+        assert!(!domain_allowed("gist.github.com", &allowlist, &blocklist));
+        assert!(domain_allowed("docs.github.com", &allowlist, &blocklist));
+
+        // empty entries from trailing commas do not restrict anything
+        assert!(domain_allowed("example.com", &list(&[""]), &list(&[""])));
+    }
+}
@@ -1,7 +1,21 @@
+use crate::args::ARGS;
+use crate::util::math::render_math_spans;
 use ammonia::Builder;
-use comrak::{markdown_to_html, Options};
+use comrak::{markdown_to_html, ExtensionOptions, Options};
+use lazy_static::lazy_static;
+use regex::Regex;
 use std::collections::HashSet;
 
+lazy_static! {
+    /// Headings as comrak renders them with `header_ids`, the anchor first
+    static ref HEADING: Regex =
+        Regex::new(r##"(?s)<h([1-6])><a href="#([^"]*)"[^>]*></a>(.*?)</h[1-6]>"##).unwrap();
+    static ref TAG: Regex = Regex::new(r"<[^>]*>").unwrap();
+}
+
+/// Paragraph standing for the table of contents
+const TOC_MARKER: &str = "<p>[[TOC]]</p>";
+
 /// Extract frontmatter from content if present
 /// Frontmatter must be at the very beginning: ---\n...content...\n---\n
 fn extract_frontmatter(content: &str) -> (Option<String>, &str) {
@@ -38,24 +52,67 @@ fn extract_frontmatter(content: &str) -> (Option<String>, &str) {
     (None, content)
 }
 
-/// Render markdown to safe HTML
-pub fn render_markdown(content: &str) -> String {
+/// Markdown extensions `markdown_extensions` may name
+pub const MARKDOWN_EXTENSIONS: &[&str] = &[
+    "strikethrough",
+    "table",
+    "autolink",
+    "tasklist",
+    "tagfilter",
+    "footnotes",
+    "superscript",
+    "description_lists",
+    "multiline_block_quotes",
+    "underline",
+    "spoiler",
+    "greentext",
+];
+
+/// Turn on the comrak option of the extension `name`. Returns false for
+/// names not in `MARKDOWN_EXTENSIONS`.
+fn enable_extension(extension: &mut ExtensionOptions, name: &str) -> bool {
+    match name.trim() {
+        "strikethrough" => extension.strikethrough = true,
+        "table" => extension.table = true,
+        "autolink" => extension.autolink = true,
+        "tasklist" => extension.tasklist = true,
+        "tagfilter" => extension.tagfilter = true,
+        "footnotes" => extension.footnotes = true,
+        "superscript" => extension.superscript = true,
+        "description_lists" => extension.description_lists = true,
+        "multiline_block_quotes" => extension.multiline_block_quotes = true,
+        "underline" => extension.underline = true,
+        "spoiler" => extension.spoiler = true,
+        "greentext" => extension.greentext = true,
+        _ => return false,
+    }
+    true
+}
+
+/// Warn at startup about `markdown_extensions` that do not exist
+pub fn check_markdown_extensions() {
+    for name in &ARGS.markdown_extensions {
+        if !name.trim().is_empty() && !MARKDOWN_EXTENSIONS.contains(&name.trim()) {
+            log::warn!(
+                "Unknown Markdown extension {:?}, ignoring it. Available extensions: {}",
+                name,
+                MARKDOWN_EXTENSIONS.join(", ")
+            );
+        }
+    }
+}
+
+/// Render markdown to safe HTML with the named comrak `extensions`, and
+/// `$...$` and `$$...$$` as MathML if `math` is set. A table of contents goes
+/// where `[[TOC]]` is, or at the top if `toc` is set.
+pub fn render_markdown(content: &str, extensions: &[String], math: bool, toc: bool) -> String {
     let mut options = Options::default();
 
-    // Extension options (GFM and more)
-    options.extension.strikethrough = true;
-    options.extension.table = true;
-    options.extension.autolink = true;
-    options.extension.tasklist = true;
-    options.extension.superscript = true;
-    options.extension.footnotes = true;
-    options.extension.description_lists = true;
-    options.extension.tagfilter = true;
+    for name in extensions {
+        enable_extension(&mut options.extension, name);
+    }
     options.extension.header_ids = Some("".to_owned());
-    options.extension.multiline_block_quotes = true;
-    options.extension.underline = true;
-    options.extension.spoiler = true;
-    options.extension.greentext = true;
+    options.extension.math_dollars = math;
 
     // Render options
     options.render.unsafe_ = false; // Don't allow raw HTML in markdown
@@ -76,8 +133,128 @@ pub fn render_markdown(content: &str) -> String {
     // Render the rest of the markdown
     html.push_str(&markdown_to_html(remaining_content, &options));
 
+    if math {
+        html = render_math_spans(&html);
+    }
+
+    html = mermaid_blocks(&html);
+    html = insert_toc(&html, toc);
+
     // Sanitize output
-    sanitize_html(&html)
+    unescape_mermaid_arrows(&sanitize_html(&html))
+}
+
+/// Put a table of contents of the headings of `html` in place of the
+/// `[[TOC]]` markers, or at the top if there are none and `toc` is set
+fn insert_toc(html: &str, toc: bool) -> String {
+    let has_marker = html.contains(TOC_MARKER);
+    if !has_marker && !toc {
+        return html.to_string();
+    }
+
+    let headings: Vec<(usize, &str, String)> = HEADING
+        .captures_iter(html)
+        .map(|heading| {
+            let level = heading[1].parse().unwrap_or(1);
+            let id = heading.get(2).map_or("", |id| id.as_str());
+            (level, id, TAG.replace_all(&heading[3], "").into_owned())
+        })
+        .collect();
+    let contents = toc_html(&headings);
+
+    if has_marker {
+        html.replace(TOC_MARKER, &contents)
+    } else {
+        contents + html
+    }
+}
+
+/// Nested lists of links to `headings`, given as their level, anchor and text
+fn toc_html(headings: &[(usize, &str, String)]) -> String {
+    let Some(top) = headings.iter().map(|(level, _, _)| *level).min() else {
+        return String::new();
+    };
+
+    let mut html = String::from("<nav class=\"toc\">\n<ul>\n");
+    let mut depth = 1;
+    for (i, (level, id, text)) in headings.iter().enumerate() {
+        let level = level - top + 1;
+        if level > depth {
+            // skipped levels get an empty item to nest in
+            while depth < level {
+                html.push_str("\n<ul>\n");
+                depth += 1;
+                if depth < level {
+                    html.push_str("<li>");
+                }
+            }
+        } else {
+            if i > 0 {
+                html.push_str("</li>\n");
+            }
+            while depth > level {
+                html.push_str("</ul>\n</li>\n");
+                depth -= 1;
+            }
+        }
+        html.push_str(&format!("<li><a href=\"#{}\">{}</a>", id, text));
+    }
+    html.push_str("</li>\n");
+    while depth > 1 {
+        html.push_str("</ul>\n</li>\n");
+        depth -= 1;
+    }
+    html.push_str("</ul>\n</nav>\n");
+    html
+}
+
+const MERMAID_OPEN: &str = "<pre class=\"mermaid\">";
+
+/// Turn ```mermaid code blocks into `<pre class="mermaid">` holding the bare
+/// diagram source, so they are left to mermaid instead of the highlighter
+fn mermaid_blocks(html: &str) -> String {
+    const OPEN: &str = "<pre lang=\"mermaid\"><code>";
+    const CLOSE: &str = "</code></pre>";
+
+    let mut output = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(at) = rest.find(OPEN) {
+        let after_open = &rest[at + OPEN.len()..];
+        let Some(end) = after_open.find(CLOSE) else {
+            break;
+        };
+
+        output.push_str(&rest[..at]);
+        output.push_str(MERMAID_OPEN);
+        output.push_str(&after_open[..end]);
+        output.push_str("</pre>");
+        rest = &after_open[end + CLOSE.len()..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// The sanitizer escapes `>` in text, put it back in mermaid blocks so the
+/// diagram source reads `A-->B`. Harmless as `<` and `&` stay escaped.
+fn unescape_mermaid_arrows(html: &str) -> String {
+    let mut output = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(at) = rest.find(MERMAID_OPEN) {
+        let start = at + MERMAID_OPEN.len();
+        let Some(end) = rest[start..].find("</pre>") else {
+            break;
+        };
+
+        output.push_str(&rest[..start]);
+        output.push_str(&rest[start..start + end].replace("&gt;", ">"));
+        rest = &rest[start + end..];
+    }
+
+    output.push_str(rest);
+    output
 }
 
 /// Sanitize HTML for safe display
@@ -105,6 +282,20 @@ pub fn sanitize_html(content: &str) -> String {
             "section",
             "ol",
             "li",
+            // For math
+            "math",
+            "mrow",
+            "mi",
+            "mn",
+            "mo",
+            "mtext",
+            "mspace",
+            "msub",
+            "msup",
+            "msubsup",
+            "mfrac",
+            "msqrt",
+            "mroot",
         ])
         .add_tag_attributes("a", &["href", "title", "id", "class"]) // id for footnote refs
         .add_tag_attributes("img", &["src", "alt", "title"])
@@ -112,41 +303,290 @@ pub fn sanitize_html(content: &str) -> String {
         .add_tag_attributes("input", &["type", "checked", "disabled"]) // For task lists
         .add_tag_attributes("li", &["id"]) // For footnote definitions
         .add_tag_attributes("section", &["class"]) // For footnotes section
+        .add_tag_attributes("nav", &["class"]) // For the table of contents
         .add_tag_attributes("sup", &["class", "id"]) // For footnote refs
+        .add_tag_attributes("math", &["display"])
+        .add_tag_attributes("mi", &["mathvariant"])
+        .add_tag_attributes("mspace", &["width"])
+        .add_allowed_classes("pre", &["mermaid"])
         .url_schemes(HashSet::from(["http", "https", "mailto"]))
         .link_rel(Some("noopener noreferrer"))
         .clean(content)
         .to_string()
 }
 
-/// Prepare HTML content for iframe display (escape for srcdoc attribute)
-pub fn prepare_html_for_iframe(content: &str) -> String {
-    html_escape::encode_double_quoted_attribute(content).to_string()
+/// Sanitize an HTML pasta, a whole document rather than a Markdown
+/// fragment. Scripts, event handlers and forms go, while layout and styling,
+/// stylesheets included, stay.
+pub fn sanitize_html_document(content: &str) -> String {
+    Builder::default()
+        .add_tags(&[
+            "article",
+            "aside",
+            "blockquote",
+            "caption",
+            "center",
+            "col",
+            "colgroup",
+            "details",
+            "figcaption",
+            "figure",
+            "footer",
+            "header",
+            "main",
+            "mark",
+            "nav",
+            "pre",
+            "section",
+            "style",
+            "summary",
+            "table",
+            "tbody",
+            "td",
+            "tfoot",
+            "th",
+            "thead",
+            "tr",
+        ])
+        .rm_clean_content_tags(&["style"])
+        .add_generic_attributes(&["class", "id", "style", "align", "width", "height"])
+        .add_tag_attributes("img", &["src", "alt", "title"])
+        .add_tag_attributes("td", &["colspan", "rowspan"])
+        .add_tag_attributes("th", &["colspan", "rowspan"])
+        .url_schemes(HashSet::from(["http", "https", "mailto"]))
+        .link_rel(Some("noopener noreferrer"))
+        .clean(content)
+        .to_string()
+}
+
+/// Prepare HTML content for iframe display (escape for srcdoc attribute),
+/// sanitized first if `sanitize` is set
+pub fn prepare_html_for_iframe(content: &str, sanitize: bool) -> String {
+    if sanitize {
+        html_escape::encode_double_quoted_attribute(&sanitize_html_document(content)).to_string()
+    } else {
+        html_escape::encode_double_quoted_attribute(content).to_string()
+    }
+}
+
+/// Kind of text a pasta holds, as far as rendering is concerned
+#[derive(Debug, PartialEq)]
+pub enum ContentType {
+    Markdown,
+    Html,
+    PlainText,
+}
+
+/// Guess whether content is a Markdown or HTML document. Markdown needs at
+/// least two different Markdown constructs, so code with `#` comments or a
+/// single list stays plain text.
+pub fn detect_content_type(content: &str) -> ContentType {
+    let trimmed = content.trim_start();
+    let start = trimmed
+        .get(..trimmed.len().min(15))
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        return ContentType::Html;
+    }
+
+    let lower = content.to_ascii_lowercase();
+    let html_blocks = [
+        "<div", "<p>", "<table", "<ul", "<ol", "<h1", "<h2", "<h3", "<section",
+    ]
+    .iter()
+    .filter(|tag| lower.contains(*tag))
+    .count();
+    if trimmed.starts_with('<') && html_blocks >= 2 {
+        return ContentType::Html;
+    }
+
+    let lines: Vec<&str> = content.lines().map(str::trim_end).collect();
+    let is_heading = |line: &str| {
+        let hashes = line.chars().take_while(|&c| c == '#').count();
+        (1..=6).contains(&hashes) && line[hashes..].starts_with(' ')
+    };
+    let is_list_item = |line: &str| {
+        let line = line.trim_start();
+        line.starts_with("- ")
+            || line.starts_with("* ")
+            || line
+                .split_once(". ")
+                .is_some_and(|(n, _)| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+    };
+    let is_table_separator = |line: &str| {
+        line.starts_with('|')
+            && line.contains("---")
+            && line.chars().all(|c| matches!(c, '|' | '-' | ':' | ' '))
+    };
+
+    let features = [
+        lines.iter().any(|line| is_heading(line)),
+        lines
+            .iter()
+            .any(|line| line.trim_start().starts_with("```")),
+        lines.iter().filter(|line| is_list_item(line)).count() >= 2,
+        lines.iter().any(|line| is_table_separator(line)),
+        content.contains("](") && content.contains('['),
+        content.contains("**"),
+    ];
+    if features.iter().filter(|&&found| found).count() >= 2 {
+        ContentType::Markdown
+    } else {
+        ContentType::PlainText
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::args::DEFAULT_MARKDOWN_EXTENSIONS;
+
+    fn gfm() -> Vec<String> {
+        DEFAULT_MARKDOWN_EXTENSIONS
+            .split(',')
+            .map(String::from)
+            .collect()
+    }
 
     #[test]
     fn test_markdown_xss_prevention() {
         let malicious = "# Test\n<script>alert('xss')</script>";
-        let rendered = render_markdown(malicious);
+        let rendered = render_markdown(malicious, &gfm(), false, false);
         assert!(!rendered.contains("<script>"));
     }
 
+    #[test]
+    fn test_markdown_math() {
+        let rendered = render_markdown("Area $x^2$ and\n\n$$\\frac{1}{2}$$", &gfm(), true, false);
+        assert!(rendered.contains("<math><mrow><msup><mi>x</mi><mn>2</mn></msup></mrow></math>"));
+        assert!(rendered.contains("<math display=\"block\">"));
+        assert!(rendered.contains("<mfrac>"));
+
+        let rendered = render_markdown(
+            "$x$ <script>alert('xss')</script> $\\text{<script>}$",
+            &gfm(),
+            true,
+            false,
+        );
+        assert!(!rendered.contains("<script>"));
+        assert!(rendered.contains("<math>"));
+
+        let rendered = render_markdown("$x^2$", &gfm(), false, false);
+        assert!(!rendered.contains("<math>"));
+    }
+
+    #[test]
+    fn test_markdown_mermaid() {
+        let rendered = render_markdown("```mermaid\ngraph TD; A-->B\n```", &gfm(), false, false);
+        assert_eq!(rendered, "<pre class=\"mermaid\">graph TD; A-->B\n</pre>\n");
+
+        let rendered = render_markdown(
+            "```mermaid\nA-->B <script>alert(1)</script>\n```",
+            &gfm(),
+            false,
+            false,
+        );
+        assert!(!rendered.contains("<script>"));
+        assert!(rendered.contains("A-->B &lt;script>"));
+
+        let rendered = render_markdown("```rust\nlet a = b > c;\n```", &gfm(), false, false);
+        assert!(!rendered.contains("mermaid"));
+        assert!(rendered.contains("b &gt; c"));
+    }
+
     #[test]
     fn test_markdown_table() {
         let md = "| Header 1 | Header 2 |\n|----------|----------|\n| Cell 1 | Cell 2 |";
-        let rendered = render_markdown(md);
+        let rendered = render_markdown(md, &gfm(), false, false);
         assert!(rendered.contains("<table>"));
         assert!(rendered.contains("<th>"));
     }
 
+    #[test]
+    fn test_markdown_extensions() {
+        let rendered = render_markdown("~~x~~", &gfm(), false, false);
+        assert_eq!(rendered, "<p><del>x</del></p>\n");
+
+        let without = vec![String::from("table"), String::from("autolink")];
+        let rendered = render_markdown("~~x~~", &without, false, false);
+        assert_eq!(rendered, "<p>~~x~~</p>\n");
+
+        // unknown names change nothing
+        let mut extensions = gfm();
+        extensions.push(String::from("blink"));
+        assert!(!enable_extension(&mut ExtensionOptions::default(), "blink"));
+        assert_eq!(
+            render_markdown("~~x~~", &extensions, false, false),
+            "<p><del>x</del></p>\n"
+        );
+    }
+
+    #[test]
+    fn test_markdown_toc() {
+        let md = "# Guide\n\n[[TOC]]\n\n## Install `it`\n\ntext\n\n### From source\n\n## Use\n";
+        let rendered = render_markdown(md, &gfm(), false, false);
+        let toc = &rendered[rendered.find("<nav class=\"toc\">").unwrap()..];
+        let toc = &toc[..toc.find("</nav>").unwrap()];
+        assert_eq!(
+            toc,
+            "<nav class=\"toc\">\n<ul>\n<li><a href=\"#guide\" rel=\"noopener noreferrer\">Guide</a>\n<ul>\n\
+             <li><a href=\"#install-it\" rel=\"noopener noreferrer\">Install it</a>\n<ul>\n\
+             <li><a href=\"#from-source\" rel=\"noopener noreferrer\">From source</a></li>\n</ul>\n</li>\n\
+             <li><a href=\"#use\" rel=\"noopener noreferrer\">Use</a></li>\n</ul>\n</li>\n</ul>\n"
+        );
+        // every link leads to a heading anchor
+        for id in ["guide", "install-it", "from-source", "use"] {
+            assert!(rendered.contains(&format!("id=\"{}\"", id)));
+        }
+        assert!(!rendered.contains("[[TOC]]"));
+
+        // without a marker it only shows up when asked for
+        let md = "# One\n\n## Two\n";
+        assert!(!render_markdown(md, &gfm(), false, false).contains("<nav"));
+        assert!(render_markdown(md, &gfm(), false, true).starts_with("<nav class=\"toc\">"));
+    }
+
+    #[test]
+    fn test_detect_content_type() {
+        let md = "# Notes\n\nSome **bold** text.\n\n- one\n- two\n";
+        assert_eq!(detect_content_type(md), ContentType::Markdown);
+        let md = "## Usage\n\n```sh\nmake dev\n```\n";
+        assert_eq!(detect_content_type(md), ContentType::Markdown);
+
+        let html = "<!DOCTYPE html>\n<html><body>Hi</body></html>";
+        assert_eq!(detect_content_type(html), ContentType::Html);
+        let html = "<div>\n  <p>Hello</p>\n  <table></table>\n</div>";
+        assert_eq!(detect_content_type(html), ContentType::Html);
+
+        let code = "# set up the path\nexport PATH=$HOME/bin:$PATH\n";
+        assert_eq!(detect_content_type(code), ContentType::PlainText);
+        let code = "fn main() {\n    println!(\"<div>\");\n}\n";
+        assert_eq!(detect_content_type(code), ContentType::PlainText);
+        assert_eq!(detect_content_type(""), ContentType::PlainText);
+    }
+
     #[test]
     fn test_html_iframe_escape() {
         let html = r#"<div class="test">Hello "world"</div>"#;
-        let escaped = prepare_html_for_iframe(html);
+        let escaped = prepare_html_for_iframe(html, false);
         assert!(escaped.contains("&quot;"));
     }
+
+    #[test]
+    fn test_html_iframe_sanitize() {
+        let html = r#"<html><head><style>p { color: red; }</style></head>
+<body><p style="margin: 0" onclick="steal()">Hello</p><script>alert('xss')</script></body></html>"#;
+
+        let sanitized = prepare_html_for_iframe(html, true);
+        assert!(!sanitized.contains("script"));
+        assert!(!sanitized.contains("onclick"));
+        assert!(sanitized.contains("p { color: red; }"));
+        assert!(sanitized.contains("style=&quot;margin: 0&quot;"));
+        assert!(sanitized.contains("Hello"));
+
+        let raw = prepare_html_for_iframe(html, false);
+        assert!(raw.contains("&lt;script&gt;alert"));
+        assert!(raw.contains("onclick"));
+    }
 }
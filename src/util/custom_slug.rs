@@ -0,0 +1,174 @@
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use crate::util::animalnumbers;
+use crate::util::base62;
+use crate::util::hashids::HARSH;
+use crate::Pasta;
+
+/// Shortest and longest custom slugs
+pub const MIN_LENGTH: usize = 3;
+pub const MAX_LENGTH: usize = 64;
+
+/// Names of pages and routes, which would make links confusing as slugs
+const RESERVED: &[&str] = &[
+    "admin",
+    "api",
+    "auth",
+    "auth_admin",
+    "edit",
+    "file",
+    "guide",
+    "incorrect",
+    "list",
+    "login",
+    "logout",
+    "metrics",
+    "new",
+    "oembed",
+    "qr",
+    "raw",
+    "remove",
+    "rotate-slug",
+    "secure_file",
+    "static",
+    "tus",
+    "upload",
+    "url",
+];
+
+lazy_static! {
+    /// Custom slugs of the pastas, so links with them resolve to the pasta id
+    static ref SLUGS: RwLock<HashMap<String, u64>> = RwLock::new(HashMap::new());
+}
+
+/// Normalize a slug chosen by an uploader and check that it can be used.
+/// Slugs that look like generated ids are refused, as they would point to
+/// another pasta.
+pub fn validate(slug: &str) -> Result<String, &'static str> {
+    let slug = slug.trim().to_lowercase();
+
+    if slug.len() < MIN_LENGTH || slug.len() > MAX_LENGTH {
+        return Err("Custom slugs must be between 3 and 64 characters long.");
+    }
+    if !slug
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+        || !slug.starts_with(|c: char| c.is_ascii_alphanumeric())
+        || !slug.ends_with(|c: char| c.is_ascii_alphanumeric())
+    {
+        return Err(
+            "Custom slugs may only contain letters, digits, - and _, and must start and end with a letter or digit.",
+        );
+    }
+    if RESERVED.contains(&slug.as_str()) {
+        return Err("This custom slug is reserved.");
+    }
+    // generated ids are 16 bit, at most three characters in base62
+    let is_base62_id = base62::to_u64(&slug).is_ok_and(|id| id <= u16::MAX as u64);
+    if animalnumbers::to_u64(&slug).is_ok() || HARSH.decode(&slug).is_ok() || is_base62_id {
+        return Err("This custom slug looks like a generated one.");
+    }
+
+    Ok(slug)
+}
+
+/// Whether a pasta in the collection already uses `slug`
+pub fn is_taken(pastas: &[Pasta], slug: &str) -> bool {
+    pastas
+        .iter()
+        .any(|pasta| pasta.custom_slug.as_deref() == Some(slug))
+}
+
+/// Make links with `slug` resolve to pasta `id`
+pub fn register(slug: &str, id: u64) {
+    SLUGS
+        .write()
+        .unwrap_or_else(|error| error.into_inner())
+        .insert(slug.to_string(), id);
+}
+
+/// Register the custom slugs of all pastas, at startup
+pub fn load(pastas: &[Pasta]) {
+    for pasta in pastas {
+        if let Some(slug) = &pasta.custom_slug {
+            register(slug, pasta.id);
+        }
+    }
+}
+
+/// Id of the pasta with the custom slug, if there was one. The pasta may
+/// have been removed since.
+pub fn lookup(slug: &str) -> Option<u64> {
+    SLUGS
+        .read()
+        .unwrap_or_else(|error| error.into_inner())
+        .get(slug)
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(validate(" Meeting-Notes ").unwrap(), "meeting-notes");
+        assert_eq!(validate("release_2024").unwrap(), "release_2024");
+
+        assert!(validate("ab").is_err());
+        assert!(validate(&"a".repeat(65)).is_err());
+        assert!(validate("meeting notes").is_err());
+        assert!(validate("-notes").is_err());
+        assert!(validate("notes/../etc").is_err());
+        assert!(validate("notes.txt").is_err());
+    }
+
+    #[test]
+    fn test_validate_rejects_reserved_words() {
+        for slug in ["list", "login", "upload", "Admin", "raw"] {
+            assert_eq!(validate(slug), Err("This custom slug is reserved."));
+        }
+        // they would shadow the generated ids of other pastas
+        assert!(validate("cat-dog").is_err());
+        let hash_id = (0..u64::from(u16::MAX))
+            .map(|id| HARSH.encode(&[id]))
+            .find(|hash_id| *hash_id == hash_id.to_lowercase())
+            .unwrap();
+        assert!(validate(&hash_id).is_err());
+        assert!(validate("9zz").is_err());
+        assert!(validate("abc").is_ok());
+    }
+
+    #[test]
+    fn test_register_and_collisions() {
+        register("weekly-sync", 4242);
+        assert_eq!(lookup("weekly-sync"), Some(4242));
+        assert_eq!(lookup("other-sync"), None);
+
+        let mut pasta: Pasta = serde_json::from_value(serde_json::json!({
+            "id": 4242,
+            "content": "",
+            "file": null,
+            "extension": "",
+            "private": false,
+            "readonly": false,
+            "editable": false,
+            "encrypt_server": false,
+            "encrypt_client": false,
+            "encrypted_key": null,
+            "created": 0,
+            "expiration": 0,
+            "last_read": 0,
+            "read_count": 0,
+            "burn_after_reads": 0,
+            "pasta_type": "text",
+        }))
+        .unwrap();
+        pasta.custom_slug = Some(String::from("weekly-sync"));
+        let pastas = vec![pasta];
+        assert!(is_taken(&pastas, "weekly-sync"));
+        assert!(!is_taken(&pastas, "monthly-sync"));
+    }
+}
@@ -21,6 +21,19 @@ pub fn read_all() -> Vec<Pasta> {
     }
 }
 
+/// Whether the database can be written to, for readiness probes
+pub fn check_writable() -> Result<(), String> {
+    if ARGS.json_db {
+        super::db_json::check_writable()
+    } else {
+        #[cfg(feature = "default")]
+        return super::db_sqlite::check_writable()
+            .map_err(|error| format!("SQLite database is not writable: {error}"));
+        #[cfg(not(feature = "default"))]
+        panic!("{}", PANIC_MSG);
+    }
+}
+
 #[allow(unused)]
 pub fn insert(pastas: Option<&Vec<Pasta>>, pasta: Option<&Pasta>) -> Result<(), String> {
     if ARGS.json_db {
@@ -36,19 +49,24 @@ pub fn insert(pastas: Option<&Vec<Pasta>>, pasta: Option<&Pasta>) -> Result<(),
 
 #[allow(unused)]
 pub fn update(pastas: Option<&Vec<Pasta>>, pasta: Option<&Pasta>) {
+    if let Err(error) = try_update(pastas, pasta) {
+        log::error!("{}", error);
+    }
+}
+
+/// Like `update`, but leaves a failure to the caller, for changes that must
+/// be undone elsewhere when they cannot be saved
+#[allow(unused)]
+pub fn try_update(pastas: Option<&Vec<Pasta>>, pasta: Option<&Pasta>) -> Result<(), String> {
     if ARGS.json_db {
-        if let Err(error) = super::db_json::update_all(
-            pastas.expect("Called update() without passing Pasta vector"),
-        ) {
-            log::error!("Failed to update JSON database: {}", error);
-        }
+        super::db_json::update_all(pastas.expect("Called update() without passing Pasta vector"))
+            .map_err(|error| format!("Failed to update JSON database: {error}"))
     } else {
         #[cfg(feature = "default")]
-        if let Err(error) = super::db_sqlite::update(
+        return super::db_sqlite::update(
             pasta.expect("Called insert() without passing Pasta to update"),
-        ) {
-            log::error!("Failed to update pasta in SQLite: {}", error);
-        }
+        )
+        .map_err(|error| format!("Failed to update pasta in SQLite: {error}"));
         #[cfg(not(feature = "default"))]
         panic!("{}", PANIC_MSG);
     }
@@ -14,6 +14,15 @@ pub fn update_all(pastas: &[Pasta]) -> Result<(), String> {
     save_to_file(pastas).map_err(|error| format!("Failed to save JSON database: {error}"))
 }
 
+/// Check the database directory can be written to, with a probe file next
+/// to the database
+pub fn check_writable() -> Result<(), String> {
+    let probe_path = DATABASE_PATH.to_string() + ".probe";
+    std::fs::write(&probe_path, b"")
+        .and_then(|_| std::fs::remove_file(&probe_path))
+        .map_err(|error| format!("JSON database is not writable: {error}"))
+}
+
 fn save_to_file(pasta_data: &[Pasta]) -> io::Result<()> {
     // This uses a two stage write. First we write to a new file, if this fails
     // only the new pasta's are lost. Then we replace the current database with
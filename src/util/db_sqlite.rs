@@ -41,7 +41,19 @@ pub fn rewrite_all_to_db(pasta_data: &[Pasta]) -> rusqlite::Result<()> {
             read_count INTEGER NOT NULL,
             burn_after_reads INTEGER NOT NULL,
             pasta_type TEXT NOT NULL,
-            title TEXT
+            title TEXT,
+            file_original_name TEXT,
+            theme TEXT,
+            file_sha256 TEXT,
+            unlisted INTEGER,
+            extra_files TEXT,
+            one_time INTEGER,
+            custom_slug TEXT,
+            file_content_type TEXT,
+            download_limit INTEGER,
+            download_count INTEGER,
+            label TEXT,
+            file_stored_size INTEGER
         );",
         params![],
     )?;
@@ -66,8 +78,20 @@ pub fn rewrite_all_to_db(pasta_data: &[Pasta]) -> rusqlite::Result<()> {
                 read_count,
                 burn_after_reads,
                 pasta_type,
-                title
-            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
+                title,
+                file_original_name,
+                theme,
+                file_sha256,
+                unlisted,
+                extra_files,
+                one_time,
+                custom_slug,
+                file_content_type,
+                download_limit,
+                download_count,
+                label,
+                file_stored_size
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29, ?30)",
             params![
                 pasta.id,
                 pasta.content,
@@ -87,6 +111,18 @@ pub fn rewrite_all_to_db(pasta_data: &[Pasta]) -> rusqlite::Result<()> {
                 pasta.burn_after_reads,
                 pasta.pasta_type,
                 pasta.title.as_deref().unwrap_or(""),
+                pasta.file.as_ref().and_then(|f| f.original_name.as_deref()),
+                pasta.theme,
+                pasta.file.as_ref().and_then(|f| f.sha256.as_deref()),
+                pasta.unlisted as i32,
+                (!pasta.extra_files.is_empty()).then(|| serde_json::to_string(&pasta.extra_files).unwrap_or_default()),
+                pasta.one_time as i32,
+            pasta.custom_slug.as_deref(),
+            pasta.file.as_ref().and_then(|f| f.content_type.as_deref()),
+            pasta.download_limit,
+            pasta.download_count,
+            pasta.label,
+            pasta.file.as_ref().and_then(|f| f.stored_size).map(|size| size.as_u64()),
             ],
         )?;
     }
@@ -118,7 +154,19 @@ pub fn select_all_from_db() -> Vec<Pasta> {
             read_count INTEGER NOT NULL,
             burn_after_reads INTEGER NOT NULL,
             pasta_type TEXT NOT NULL,
-            title TEXT
+            title TEXT,
+            file_original_name TEXT,
+            theme TEXT,
+            file_sha256 TEXT,
+            unlisted INTEGER,
+            extra_files TEXT,
+            one_time INTEGER,
+            custom_slug TEXT,
+            file_content_type TEXT,
+            download_limit INTEGER,
+            download_count INTEGER,
+            label TEXT,
+            file_stored_size INTEGER
         );",
         params![],
     )
@@ -126,9 +174,21 @@ pub fn select_all_from_db() -> Vec<Pasta> {
 
     // Add title column if it doesn't exist (migration for existing databases)
     let _ = conn.execute("ALTER TABLE pasta ADD COLUMN title TEXT", params![]);
+    let _ = conn.execute("ALTER TABLE pasta ADD COLUMN file_original_name TEXT", params![]);
+    let _ = conn.execute("ALTER TABLE pasta ADD COLUMN theme TEXT", params![]);
+    let _ = conn.execute("ALTER TABLE pasta ADD COLUMN file_sha256 TEXT", params![]);
+    let _ = conn.execute("ALTER TABLE pasta ADD COLUMN unlisted INTEGER", params![]);
+    let _ = conn.execute("ALTER TABLE pasta ADD COLUMN extra_files TEXT", params![]);
+    let _ = conn.execute("ALTER TABLE pasta ADD COLUMN one_time INTEGER", params![]);
+    let _ = conn.execute("ALTER TABLE pasta ADD COLUMN custom_slug TEXT", params![]);
+    let _ = conn.execute("ALTER TABLE pasta ADD COLUMN file_content_type TEXT", params![]);
+    let _ = conn.execute("ALTER TABLE pasta ADD COLUMN download_limit INTEGER", params![]);
+    let _ = conn.execute("ALTER TABLE pasta ADD COLUMN download_count INTEGER", params![]);
+    let _ = conn.execute("ALTER TABLE pasta ADD COLUMN label TEXT", params![]);
+    let _ = conn.execute("ALTER TABLE pasta ADD COLUMN file_stored_size INTEGER", params![]);
 
     let mut stmt = conn
-        .prepare("SELECT id, content, file_name, file_size, extension, read_only, private, editable, encrypt_server, encrypt_client, encrypted_key, created, expiration, last_read, read_count, burn_after_reads, pasta_type, title FROM pasta ORDER BY created ASC")
+        .prepare("SELECT id, content, file_name, file_size, extension, read_only, private, editable, encrypt_server, encrypt_client, encrypted_key, created, expiration, last_read, read_count, burn_after_reads, pasta_type, title, file_original_name, theme, file_sha256, unlisted, extra_files, one_time, custom_slug, file_content_type, download_limit, download_count, label, file_stored_size FROM pasta ORDER BY created ASC")
         .expect("Failed to prepare SQL statement to load pastas");
 
     let pasta_iter = stmt
@@ -143,6 +203,10 @@ pub fn select_all_from_db() -> Vec<Pasta> {
                         Some(PastaFile {
                             name: file_name,
                             size: ByteSize::b(file_size),
+                            original_name: row.get(18)?,
+                            sha256: row.get(20)?,
+                            content_type: row.get(25)?,
+                            stored_size: row.get::<_, Option<u64>>(29)?.map(ByteSize::b),
                         })
                     } else {
                         None
@@ -150,9 +214,14 @@ pub fn select_all_from_db() -> Vec<Pasta> {
                 } else {
                     None
                 },
+                extra_files: row
+                    .get::<_, Option<String>>(22)?
+                    .and_then(|files| serde_json::from_str(&files).ok())
+                    .unwrap_or_default(),
                 extension: row.get(4)?,
                 readonly: row.get(5)?,
                 private: row.get(6)?,
+                unlisted: row.get::<_, Option<bool>>(21)?.unwrap_or(false),
                 editable: row.get(7)?,
                 encrypt_server: row.get(8)?,
                 encrypt_client: row.get(9)?,
@@ -162,8 +231,14 @@ pub fn select_all_from_db() -> Vec<Pasta> {
                 last_read: row.get(13)?,
                 read_count: row.get(14)?,
                 burn_after_reads: row.get(15)?,
+                download_limit: row.get::<_, Option<u64>>(26)?.unwrap_or(0),
+                download_count: row.get::<_, Option<u64>>(27)?.unwrap_or(0),
+                one_time: row.get::<_, Option<bool>>(23)?.unwrap_or(false),
+                custom_slug: row.get(24)?,
                 pasta_type: row.get(16)?,
                 title: title.filter(|s| !s.is_empty()),
+                theme: row.get::<_, Option<String>>(19)?.unwrap_or_default(),
+                label: row.get::<_, Option<String>>(28)?.unwrap_or_default(),
             })
         })
         .expect("Failed to select Pastas from SQLite database.");
@@ -196,7 +271,19 @@ pub fn insert(pasta: &Pasta) -> rusqlite::Result<()> {
             read_count INTEGER NOT NULL,
             burn_after_reads INTEGER NOT NULL,
             pasta_type TEXT NOT NULL,
-            title TEXT
+            title TEXT,
+            file_original_name TEXT,
+            theme TEXT,
+            file_sha256 TEXT,
+            unlisted INTEGER,
+            extra_files TEXT,
+            one_time INTEGER,
+            custom_slug TEXT,
+            file_content_type TEXT,
+            download_limit INTEGER,
+            download_count INTEGER,
+            label TEXT,
+            file_stored_size INTEGER
         );",
         params![],
     )?;
@@ -220,8 +307,20 @@ pub fn insert(pasta: &Pasta) -> rusqlite::Result<()> {
                 read_count,
                 burn_after_reads,
                 pasta_type,
-                title
-            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
+                title,
+                file_original_name,
+                theme,
+                file_sha256,
+                unlisted,
+                extra_files,
+                one_time,
+                custom_slug,
+                file_content_type,
+                download_limit,
+                download_count,
+                label,
+                file_stored_size
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29, ?30)",
         params![
             pasta.id,
             pasta.content,
@@ -241,6 +340,18 @@ pub fn insert(pasta: &Pasta) -> rusqlite::Result<()> {
             pasta.burn_after_reads,
             pasta.pasta_type,
             pasta.title.as_deref().unwrap_or(""),
+            pasta.file.as_ref().and_then(|f| f.original_name.as_deref()),
+            pasta.theme,
+            pasta.file.as_ref().and_then(|f| f.sha256.as_deref()),
+            pasta.unlisted as i32,
+            (!pasta.extra_files.is_empty()).then(|| serde_json::to_string(&pasta.extra_files).unwrap_or_default()),
+            pasta.one_time as i32,
+            pasta.custom_slug.as_deref(),
+            pasta.file.as_ref().and_then(|f| f.content_type.as_deref()),
+            pasta.download_limit,
+            pasta.download_count,
+            pasta.label,
+            pasta.file.as_ref().and_then(|f| f.stored_size).map(|size| size.as_u64()),
         ],
     )?;
 
@@ -268,7 +379,19 @@ pub fn update(pasta: &Pasta) -> rusqlite::Result<()> {
             read_count = ?15,
             burn_after_reads = ?16,
             pasta_type = ?17,
-            title = ?18
+            title = ?18,
+            file_original_name = ?19,
+            theme = ?20,
+            file_sha256 = ?21,
+            unlisted = ?22,
+            extra_files = ?23,
+            one_time = ?24,
+            custom_slug = ?25,
+            file_content_type = ?26,
+            download_limit = ?27,
+            download_count = ?28,
+            label = ?29,
+            file_stored_size = ?30
         WHERE id = ?1;",
         params![
             pasta.id,
@@ -289,6 +412,18 @@ pub fn update(pasta: &Pasta) -> rusqlite::Result<()> {
             pasta.burn_after_reads,
             pasta.pasta_type,
             pasta.title.as_deref().unwrap_or(""),
+            pasta.file.as_ref().and_then(|f| f.original_name.as_deref()),
+            pasta.theme,
+            pasta.file.as_ref().and_then(|f| f.sha256.as_deref()),
+            pasta.unlisted as i32,
+            (!pasta.extra_files.is_empty()).then(|| serde_json::to_string(&pasta.extra_files).unwrap_or_default()),
+            pasta.one_time as i32,
+            pasta.custom_slug.as_deref(),
+            pasta.file.as_ref().and_then(|f| f.content_type.as_deref()),
+            pasta.download_limit,
+            pasta.download_count,
+            pasta.label,
+            pasta.file.as_ref().and_then(|f| f.stored_size).map(|size| size.as_u64()),
         ],
     )?;
 
@@ -306,3 +441,55 @@ pub fn delete_by_id(id: u64) -> rusqlite::Result<()> {
 
     Ok(())
 }
+
+/// Check the database can be written to, by creating a table in a
+/// transaction that is rolled back
+pub fn check_writable() -> rusqlite::Result<()> {
+    let conn = Connection::open(format!("{}/database.sqlite", ARGS.data_dir))?;
+    conn.execute_batch("BEGIN; CREATE TABLE readiness_probe (id INTEGER); ROLLBACK;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::create::new_pasta;
+    use std::fs;
+
+    #[test]
+    fn test_label_round_trip() {
+        let pasta = Pasta {
+            // an id no other test stores
+            id: 4_056_789_012,
+            content: String::from("encrypted"),
+            private: true,
+            editable: false,
+            encrypt_server: true,
+            encrypted_key: None,
+            expiration: 0,
+            pasta_type: String::from("text"),
+            label: String::from("Q3 payroll <draft>"),
+            ..new_pasta(0)
+        };
+        fs::create_dir_all(&ARGS.data_dir).unwrap();
+        // adds the label column to databases from before it existed
+        select_all_from_db();
+        insert(&pasta).unwrap();
+
+        let stored = select_all_from_db()
+            .into_iter()
+            .find(|p| p.id == pasta.id)
+            .unwrap();
+        assert_eq!(stored.label, "Q3 payroll <draft>");
+
+        let mut relabeled = stored;
+        relabeled.label = String::from("");
+        update(&relabeled).unwrap();
+        let stored = select_all_from_db()
+            .into_iter()
+            .find(|p| p.id == pasta.id)
+            .unwrap();
+        assert_eq!(stored.label, "");
+
+        delete_by_id(pasta.id).unwrap();
+    }
+}
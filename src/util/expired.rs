@@ -0,0 +1,57 @@
+use lazy_static::lazy_static;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Maximum number of recently removed pasta ids that are remembered
+const CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryReason {
+    Expired,
+    ReadLimit,
+    Inactive,
+    Rotated,
+}
+
+impl ExpiryReason {
+    pub fn message(&self) -> &'static str {
+        match self {
+            ExpiryReason::Expired => "This upload has expired.",
+            ExpiryReason::ReadLimit => "This upload has reached its read limit.",
+            ExpiryReason::Inactive => "This upload was removed after a period of inactivity.",
+            ExpiryReason::Rotated => "This link was rotated and is no longer valid.",
+        }
+    }
+}
+
+lazy_static! {
+    static ref RECENTLY_EXPIRED: Mutex<VecDeque<(u64, ExpiryReason)>> =
+        Mutex::new(VecDeque::with_capacity(CAPACITY));
+}
+
+/// Remember that a pasta was removed, evicting the least recently used entry when full
+pub fn record(id: u64, reason: ExpiryReason) {
+    let mut expired = RECENTLY_EXPIRED
+        .lock()
+        .unwrap_or_else(|error| error.into_inner());
+
+    expired.retain(|(expired_id, _)| *expired_id != id);
+    expired.push_back((id, reason));
+
+    while expired.len() > CAPACITY {
+        expired.pop_front();
+    }
+}
+
+/// Look up why a pasta was removed, if it was removed recently
+pub fn reason(id: u64) -> Option<ExpiryReason> {
+    let mut expired = RECENTLY_EXPIRED
+        .lock()
+        .unwrap_or_else(|error| error.into_inner());
+
+    let index = expired.iter().position(|(expired_id, _)| *expired_id == id)?;
+    let entry = expired.remove(index)?;
+    expired.push_back(entry);
+
+    Some(entry.1)
+}
@@ -0,0 +1,282 @@
+use crate::args::ARGS;
+use crate::util::http_client;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use lazy_static::lazy_static;
+use reqwest::StatusCode;
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::signature::{SignatureEncoding, Signer};
+use rsa::RsaPrivateKey;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::Sha256;
+use std::fs;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const API_URL: &str = "https://storage.googleapis.com/storage/v1";
+const UPLOAD_URL: &str = "https://storage.googleapis.com/upload/storage/v1";
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+const SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+
+/// Refresh access tokens this many seconds before they expire
+const TOKEN_MARGIN_SECONDS: u64 = 60;
+
+lazy_static! {
+    /// Access token and its expiry as unix timestamp
+    static ref TOKEN: Mutex<Option<(String, u64)>> = Mutex::new(None);
+}
+
+#[derive(Deserialize)]
+struct ServiceAccount {
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Signed JWT asking the token endpoint for an access token of the service account
+fn service_account_assertion(account: &ServiceAccount, issued_at: u64) -> Result<String, String> {
+    let header = json!({ "alg": "RS256", "typ": "JWT" });
+    let claims = json!({
+        "iss": account.client_email,
+        "scope": SCOPE,
+        "aud": account.token_uri,
+        "iat": issued_at,
+        "exp": issued_at + 3600,
+    });
+    let message = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(header.to_string()),
+        URL_SAFE_NO_PAD.encode(claims.to_string())
+    );
+
+    let key = RsaPrivateKey::from_pkcs8_pem(&account.private_key)
+        .map_err(|e| format!("Invalid service account key: {}", e))?;
+    let signature = SigningKey::<Sha256>::new(key).sign(message.as_bytes());
+
+    Ok(format!(
+        "{}.{}",
+        message,
+        URL_SAFE_NO_PAD.encode(signature.to_bytes())
+    ))
+}
+
+/// Get a new access token, from the service account in `gcs_credentials` if
+/// set, otherwise from the metadata server of the GCP instance we run on
+async fn fetch_token(client: &reqwest::Client) -> Result<TokenResponse, String> {
+    let request = match ARGS.gcs_credentials.as_ref() {
+        Some(path) => {
+            let account: ServiceAccount = serde_json::from_str(
+                &fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read GCS credentials: {}", e))?,
+            )
+            .map_err(|e| format!("Invalid GCS credentials: {}", e))?;
+            let assertion = service_account_assertion(&account, now())?;
+            client.post(&account.token_uri).form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", assertion.as_str()),
+            ])
+        }
+        None => client
+            .get(METADATA_TOKEN_URL)
+            .header("Metadata-Flavor", "Google"),
+    };
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("Failed to get GCS access token: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "Failed to get GCS access token: status {}",
+            response.status()
+        ));
+    }
+    response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid GCS token response: {}", e))
+}
+
+async fn access_token(client: &reqwest::Client) -> Result<String, String> {
+    if let Some((token, expires_at)) = TOKEN.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        if now() + TOKEN_MARGIN_SECONDS < *expires_at {
+            return Ok(token.clone());
+        }
+    }
+
+    let response = fetch_token(client).await?;
+    *TOKEN.lock().unwrap_or_else(|e| e.into_inner()) =
+        Some((response.access_token.clone(), now() + response.expires_in));
+    Ok(response.access_token)
+}
+
+/// Percent-encode an object name for use as a single URL path segment
+fn encode_object_name(name: &str) -> String {
+    name.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn bucket() -> &'static str {
+    ARGS.gcs_bucket.as_deref().unwrap_or_default()
+}
+
+pub async fn put_object(object: &str, data: &[u8]) -> Result<(), String> {
+    let client = http_client::new_async();
+    let token = access_token(&client).await?;
+
+    let response = client
+        .post(format!("{}/b/{}/o", UPLOAD_URL, bucket()))
+        .query(&[("uploadType", "media"), ("name", object)])
+        .bearer_auth(token)
+        .header("Content-Type", "application/octet-stream")
+        .body(data.to_vec())
+        .send()
+        .await
+        .map_err(|e| format!("Failed to upload to GCS: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "Failed to upload to GCS: status {}",
+            response.status()
+        ));
+    }
+    Ok(())
+}
+
+pub async fn get_object(object: &str) -> Result<Vec<u8>, String> {
+    let client = http_client::new_async();
+    let token = access_token(&client).await?;
+
+    let response = client
+        .get(format!(
+            "{}/b/{}/o/{}",
+            API_URL,
+            bucket(),
+            encode_object_name(object)
+        ))
+        .query(&[("alt", "media")])
+        .bearer_auth(token)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to get file from GCS: {}", e))?;
+    match response.status() {
+        StatusCode::NOT_FOUND => Err(format!("File not found in GCS: {}", object)),
+        status if !status.is_success() => {
+            Err(format!("Failed to get file from GCS: status {}", status))
+        }
+        _ => response
+            .bytes()
+            .await
+            .map(|bytes| bytes.to_vec())
+            .map_err(|e| format!("Failed to get file from GCS: {}", e)),
+    }
+}
+
+/// Whether an object exists, going by its metadata
+pub async fn object_exists(object: &str) -> Result<bool, String> {
+    let client = http_client::new_async();
+    let token = access_token(&client).await?;
+
+    let response = client
+        .get(format!(
+            "{}/b/{}/o/{}",
+            API_URL,
+            bucket(),
+            encode_object_name(object)
+        ))
+        .bearer_auth(token)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to check file in GCS: {}", e))?;
+    match response.status() {
+        StatusCode::NOT_FOUND => Ok(false),
+        status if status.is_success() => Ok(true),
+        status => Err(format!("Failed to check file in GCS: status {}", status)),
+    }
+}
+
+pub async fn copy_object(from: &str, to: &str) -> Result<(), String> {
+    let client = http_client::new_async();
+    let token = access_token(&client).await?;
+
+    let response = client
+        .post(format!(
+            "{}/b/{}/o/{}/copyTo/b/{}/o/{}",
+            API_URL,
+            bucket(),
+            encode_object_name(from),
+            bucket(),
+            encode_object_name(to)
+        ))
+        .bearer_auth(token)
+        .header("Content-Length", "0")
+        .send()
+        .await
+        .map_err(|e| format!("Failed to copy in GCS: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "Failed to copy in GCS: status {}",
+            response.status()
+        ));
+    }
+    Ok(())
+}
+
+/// Delete an object, objects that are already gone count as deleted
+pub async fn delete_object(object: &str) -> Result<(), String> {
+    let client = http_client::new_async();
+    let token = access_token(&client).await?;
+
+    let response = client
+        .delete(format!(
+            "{}/b/{}/o/{}",
+            API_URL,
+            bucket(),
+            encode_object_name(object)
+        ))
+        .bearer_auth(token)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to delete from GCS: {}", e))?;
+    let status = response.status();
+    if status.is_success() || status == StatusCode::NOT_FOUND {
+        Ok(())
+    } else {
+        Err(format!("Failed to delete from GCS: status {}", status))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_object_name() {
+        assert_eq!(
+            encode_object_name("attachments/cat-dog/my file.txt"),
+            "attachments%2Fcat-dog%2Fmy%20file.txt"
+        );
+        assert_eq!(encode_object_name("data.enc"), "data.enc");
+    }
+}
@@ -2,6 +2,12 @@
 compile_error! {"You must either have the default feature enabled (remove
 the no-default-features rust argument) or the no-c-deps feature"}
 
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Time limit of whole requests made with `new_async_pinned`
+const PINNED_TIMEOUT: Duration = Duration::from_secs(300);
+
 #[cfg(feature = "default")]
 pub fn new() -> reqwest::blocking::Client {
     reqwest::blocking::Client::new()
@@ -12,6 +18,19 @@ pub fn new_async() -> reqwest::Client {
     reqwest::Client::new()
 }
 
+/// Async client that connects to `host` at `addr` only, not through a proxy,
+/// and does not follow redirects, for fetching URLs given by users
+#[cfg(feature = "default")]
+pub fn new_async_pinned(host: &str, addr: SocketAddr) -> reqwest::Client {
+    reqwest::Client::builder()
+        .resolve(host, addr)
+        .no_proxy()
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(PINNED_TIMEOUT)
+        .build()
+        .expect("Could not create HTTP client.")
+}
+
 #[cfg(feature = "__rustcrypto-tls")]
 pub fn new() -> reqwest::blocking::Client {
     reqwest::blocking::Client::builder()
@@ -28,6 +47,18 @@ pub fn new_async() -> reqwest::Client {
         .expect("Could not create HTTP client.")
 }
 
+#[cfg(feature = "__rustcrypto-tls")]
+pub fn new_async_pinned(host: &str, addr: SocketAddr) -> reqwest::Client {
+    reqwest::Client::builder()
+        .use_preconfigured_tls(tls_config())
+        .resolve(host, addr)
+        .no_proxy()
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(PINNED_TIMEOUT)
+        .build()
+        .expect("Could not create HTTP client.")
+}
+
 #[cfg(feature = "__rustcrypto-tls")]
 fn tls_config() -> rustls::ClientConfig {
     use std::sync::Arc;
@@ -0,0 +1,69 @@
+use crate::args::{IdScheme, ARGS};
+use crate::util::animalnumbers::{self, normalize_slug, to_animal_names};
+use crate::util::base62::{self, to_base62};
+use crate::util::custom_slug;
+use crate::util::hashids::{self, to_hashids};
+
+/// The configured id scheme. `MICROBIN_HASH_IDS` is still honoured when no
+/// other scheme was chosen.
+pub fn scheme() -> IdScheme {
+    if ARGS.hash_ids && ARGS.id_scheme == IdScheme::Animals {
+        IdScheme::HashIds
+    } else {
+        ARGS.id_scheme
+    }
+}
+
+pub fn encode(scheme: IdScheme, id: u64) -> String {
+    match scheme {
+        IdScheme::Animals => to_animal_names(id),
+        IdScheme::HashIds => to_hashids(id),
+        IdScheme::Base62 => to_base62(id),
+    }
+}
+
+pub fn decode(scheme: IdScheme, slug: &str) -> Result<u64, &str> {
+    match scheme {
+        IdScheme::Animals => animalnumbers::to_u64(slug),
+        IdScheme::HashIds => hashids::to_u64(slug),
+        IdScheme::Base62 => base62::to_u64(slug),
+    }
+}
+
+/// Slug of pasta `id` in links
+pub fn to_slug(id: u64) -> String {
+    encode(scheme(), id)
+}
+
+/// Id of the pasta a link points to, by its custom slug or its generated
+/// one. Trailing slashes are ignored, and so is case for animal names.
+pub fn to_u64(slug: &str) -> Result<u64, &str> {
+    let slug = slug.trim_end_matches('/');
+
+    if let Some(id) = custom_slug::lookup(&slug.to_lowercase()) {
+        return Ok(id);
+    }
+
+    match scheme() {
+        IdScheme::Animals => decode(IdScheme::Animals, &normalize_slug(slug))
+            .map_err(|_| "Failed to convert animal name to u64!"),
+        scheme => decode(scheme, slug),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schemes_round_trip() {
+        let samples = [0, 1, 63, 64, 12345, u16::MAX as u64, 1 << 40];
+        for scheme in [IdScheme::Animals, IdScheme::HashIds, IdScheme::Base62] {
+            for id in samples {
+                assert_eq!(decode(scheme, &encode(scheme, id)), Ok(id));
+            }
+        }
+        assert_eq!(encode(IdScheme::Animals, 12345), "sloth-ant-lion");
+        assert_eq!(encode(IdScheme::Base62, 12345), "3D7");
+    }
+}
@@ -0,0 +1,65 @@
+use crate::args::ARGS;
+use crate::pasta::Pasta;
+use crate::util::db::delete;
+use crate::util::metrics;
+use crate::util::storage;
+use crate::util::webhook;
+use std::path::Path;
+
+/// Check that every pasta with a local attachment still has its file, so a
+/// wrong volume mount or lost files show up at boot instead of at the first
+/// failed download. Pastas with missing files are removed when
+/// `prune_missing_files` is set. Attachments on S3, GCS, WebDAV or Azure are not checked.
+pub fn check_files(pastas: &mut Vec<Pasta>) {
+    let mut checked = 0;
+    let mut missing = Vec::new();
+
+    for pasta in pastas.iter() {
+        for storage_path in pasta.file_storage_paths() {
+            if storage::is_remote(&storage_path) {
+                continue;
+            }
+            checked += 1;
+
+            let file_path = storage::local_file_path(&pasta.id_as_animals(), &storage_path);
+            if !Path::new(&file_path).is_file() {
+                log::warn!(
+                    "File of pasta {} is missing: {}",
+                    pasta.id_as_animals(),
+                    file_path
+                );
+                if !missing.contains(&pasta.id) {
+                    missing.push(pasta.id);
+                }
+            }
+        }
+    }
+
+    log::info!(
+        "Loaded {} pastas from {}, {} of {} local attachments missing",
+        pastas.len(),
+        ARGS.data_dir,
+        missing.len(),
+        checked
+    );
+
+    if missing.is_empty() {
+        return;
+    }
+
+    if ARGS.prune_missing_files {
+        for pasta in pastas.iter().filter(|p| missing.contains(&p.id)) {
+            metrics::record(webhook::Event::Pruned);
+            webhook::notify(webhook::Event::Pruned, pasta);
+        }
+        pastas.retain(|p| !missing.contains(&p.id));
+        for id in missing.iter() {
+            delete(Some(pastas), Some(*id));
+        }
+        log::warn!("Removed {} pastas whose files are missing", missing.len());
+    } else {
+        log::warn!(
+            "Pastas with missing files are kept, set MICROBIN_PRUNE_MISSING_FILES to remove them"
+        );
+    }
+}
@@ -0,0 +1,524 @@
+//! Conversion of the LaTeX math people write in notes to MathML, which
+//! browsers render without any JavaScript. Only a common subset is
+//! understood: scripts, fractions, roots, text, greek letters and the usual
+//! operators. Unknown commands are shown as they were written.
+
+/// Nesting deeper than this is shown as plain text instead of recursing
+const MAX_DEPTH: usize = 32;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Command(String),
+    /// Content of `\text{...}` and friends, kept verbatim
+    Text(String, String),
+    Open,
+    Close,
+    Sup,
+    Sub,
+    Number(String),
+    Letter(char),
+    Other(char),
+}
+
+/// Commands whose braced argument is text rather than math
+const TEXT_COMMANDS: [&str; 5] = ["text", "textrm", "mbox", "mathrm", "operatorname"];
+
+fn tokenize(tex: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = tex.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let mut name = String::new();
+                while let Some(&next) = chars.peek() {
+                    if !next.is_ascii_alphabetic() {
+                        break;
+                    }
+                    name.push(next);
+                    chars.next();
+                }
+                if name.is_empty() {
+                    // escaped character like \{ or a spacing command like \,
+                    if let Some(next) = chars.next() {
+                        name.push(next);
+                    }
+                }
+
+                if TEXT_COMMANDS.contains(&name.as_str()) {
+                    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                        chars.next();
+                    }
+                    if chars.peek() == Some(&'{') {
+                        chars.next();
+                        let mut text = String::new();
+                        let mut depth = 0;
+                        for c in chars.by_ref() {
+                            match c {
+                                '{' => depth += 1,
+                                '}' if depth == 0 => break,
+                                '}' => depth -= 1,
+                                _ => {}
+                            }
+                            text.push(c);
+                        }
+                        tokens.push(Token::Text(name, text));
+                        continue;
+                    }
+                }
+                tokens.push(Token::Command(name));
+            }
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            '^' => tokens.push(Token::Sup),
+            '_' => tokens.push(Token::Sub),
+            c if c.is_ascii_digit() => {
+                let mut number = String::from(c);
+                while let Some(&next) = chars.peek() {
+                    if !(next.is_ascii_digit() || next == '.') {
+                        break;
+                    }
+                    number.push(next);
+                    chars.next();
+                }
+                tokens.push(Token::Number(number));
+            }
+            c if c.is_whitespace() => {}
+            c if c.is_alphabetic() => tokens.push(Token::Letter(c)),
+            c => tokens.push(Token::Other(c)),
+        }
+    }
+
+    tokens
+}
+
+fn escape(text: &str) -> String {
+    html_escape::encode_text(text).to_string()
+}
+
+fn greek_letter(name: &str) -> Option<char> {
+    let letter = match name {
+        "alpha" => 'α',
+        "beta" => 'β',
+        "gamma" => 'γ',
+        "delta" => 'δ',
+        "epsilon" => 'ϵ',
+        "varepsilon" => 'ε',
+        "zeta" => 'ζ',
+        "eta" => 'η',
+        "theta" => 'θ',
+        "vartheta" => 'ϑ',
+        "iota" => 'ι',
+        "kappa" => 'κ',
+        "lambda" => 'λ',
+        "mu" => 'μ',
+        "nu" => 'ν',
+        "xi" => 'ξ',
+        "pi" => 'π',
+        "rho" => 'ρ',
+        "sigma" => 'σ',
+        "tau" => 'τ',
+        "upsilon" => 'υ',
+        "phi" => 'ϕ',
+        "varphi" => 'φ',
+        "chi" => 'χ',
+        "psi" => 'ψ',
+        "omega" => 'ω',
+        "Gamma" => 'Γ',
+        "Delta" => 'Δ',
+        "Theta" => 'Θ',
+        "Lambda" => 'Λ',
+        "Xi" => 'Ξ',
+        "Pi" => 'Π',
+        "Sigma" => 'Σ',
+        "Upsilon" => 'Υ',
+        "Phi" => 'Φ',
+        "Psi" => 'Ψ',
+        "Omega" => 'Ω',
+        "infty" => '∞',
+        "partial" => '∂',
+        "nabla" => '∇',
+        "ell" => 'ℓ',
+        "hbar" => 'ℏ',
+        "emptyset" => '∅',
+        _ => return None,
+    };
+    Some(letter)
+}
+
+fn operator(name: &str) -> Option<&'static str> {
+    let operator = match name {
+        "times" => "×",
+        "cdot" => "⋅",
+        "pm" => "±",
+        "mp" => "∓",
+        "div" => "÷",
+        "ast" => "∗",
+        "star" => "⋆",
+        "circ" => "∘",
+        "leq" | "le" => "≤",
+        "geq" | "ge" => "≥",
+        "neq" | "ne" => "≠",
+        "approx" => "≈",
+        "equiv" => "≡",
+        "sim" => "∼",
+        "propto" => "∝",
+        "in" => "∈",
+        "notin" => "∉",
+        "subset" => "⊂",
+        "subseteq" => "⊆",
+        "supset" => "⊃",
+        "supseteq" => "⊇",
+        "cup" => "∪",
+        "cap" => "∩",
+        "setminus" => "∖",
+        "to" | "rightarrow" => "→",
+        "leftarrow" | "gets" => "←",
+        "leftrightarrow" => "↔",
+        "Rightarrow" => "⇒",
+        "Leftarrow" => "⇐",
+        "Leftrightarrow" => "⇔",
+        "implies" => "⟹",
+        "iff" => "⟺",
+        "mapsto" => "↦",
+        "forall" => "∀",
+        "exists" => "∃",
+        "neg" | "lnot" => "¬",
+        "land" | "wedge" => "∧",
+        "lor" | "vee" => "∨",
+        "sum" => "∑",
+        "prod" => "∏",
+        "int" => "∫",
+        "iint" => "∬",
+        "oint" => "∮",
+        "ldots" | "dots" => "…",
+        "cdots" => "⋯",
+        "langle" => "⟨",
+        "rangle" => "⟩",
+        "lfloor" => "⌊",
+        "rfloor" => "⌋",
+        "lceil" => "⌈",
+        "rceil" => "⌉",
+        "mid" => "∣",
+        "{" => "{",
+        "}" => "}",
+        "|" => "‖",
+        "%" => "%",
+        "$" => "$",
+        "#" => "#",
+        "&" => "&",
+        "_" => "_",
+        _ => return None,
+    };
+    Some(operator)
+}
+
+fn function_name(name: &str) -> bool {
+    matches!(
+        name,
+        "sin"
+            | "cos"
+            | "tan"
+            | "cot"
+            | "sec"
+            | "csc"
+            | "arcsin"
+            | "arccos"
+            | "arctan"
+            | "sinh"
+            | "cosh"
+            | "tanh"
+            | "log"
+            | "ln"
+            | "lg"
+            | "exp"
+            | "lim"
+            | "max"
+            | "min"
+            | "sup"
+            | "inf"
+            | "det"
+            | "dim"
+            | "ker"
+            | "arg"
+            | "deg"
+            | "gcd"
+            | "mod"
+    )
+}
+
+fn space(name: &str) -> Option<&'static str> {
+    let width = match name {
+        "," => "0.167em",
+        ":" | ">" => "0.222em",
+        ";" => "0.278em",
+        " " => "0.25em",
+        "quad" => "1em",
+        "qquad" => "2em",
+        _ => return None,
+    };
+    Some(width)
+}
+
+fn double_struck(c: char) -> char {
+    match c {
+        'C' => 'ℂ',
+        'N' => 'ℕ',
+        'P' => 'ℙ',
+        'Q' => 'ℚ',
+        'R' => 'ℝ',
+        'Z' => 'ℤ',
+        c => c,
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    /// Atoms with their scripts until the end of the current group
+    fn row(&mut self) -> String {
+        let mut row = String::new();
+        while let Some(token) = self.peek() {
+            if *token == Token::Close {
+                break;
+            }
+            let base = self.atom();
+            row.push_str(&self.scripts(base));
+        }
+        row
+    }
+
+    /// A single atom as one element, for arguments and scripts
+    fn argument(&mut self) -> String {
+        let atom = self.atom();
+        if atom.is_empty() {
+            String::from("<mrow></mrow>")
+        } else {
+            atom
+        }
+    }
+
+    fn scripts(&mut self, base: String) -> String {
+        let mut sub = None;
+        let mut sup = None;
+        loop {
+            match self.peek() {
+                Some(Token::Sub) if sub.is_none() => {
+                    self.next();
+                    sub = Some(self.argument());
+                }
+                Some(Token::Sup) if sup.is_none() => {
+                    self.next();
+                    sup = Some(self.argument());
+                }
+                _ => break,
+            }
+        }
+
+        let base = if base.is_empty() {
+            String::from("<mrow></mrow>")
+        } else {
+            base
+        };
+        match (sub, sup) {
+            (None, None) => base,
+            (Some(sub), None) => format!("<msub>{}{}</msub>", base, sub),
+            (None, Some(sup)) => format!("<msup>{}{}</msup>", base, sup),
+            (Some(sub), Some(sup)) => format!("<msubsup>{}{}{}</msubsup>", base, sub, sup),
+        }
+    }
+
+    fn atom(&mut self) -> String {
+        let Some(token) = self.next() else {
+            return String::new();
+        };
+
+        if self.depth >= MAX_DEPTH {
+            return String::from("<mtext>…</mtext>");
+        }
+        self.depth += 1;
+        let atom = match token {
+            Token::Open => {
+                let row = self.row();
+                // the closing brace, if there is one
+                self.next();
+                format!("<mrow>{}</mrow>", row)
+            }
+            Token::Close => String::new(),
+            Token::Sup | Token::Sub => {
+                // a script without a base
+                self.pos -= 1;
+                String::new()
+            }
+            Token::Number(number) => format!("<mn>{}</mn>", number),
+            Token::Letter(letter) => format!("<mi>{}</mi>", escape(&letter.to_string())),
+            Token::Other('-') => String::from("<mo>−</mo>"),
+            Token::Other('\'') => String::from("<mo>′</mo>"),
+            Token::Other(other) => format!("<mo>{}</mo>", escape(&other.to_string())),
+            Token::Text(kind, text) => {
+                if kind == "mathrm" || kind == "operatorname" {
+                    format!("<mi mathvariant=\"normal\">{}</mi>", escape(&text))
+                } else {
+                    format!("<mtext>{}</mtext>", escape(&text))
+                }
+            }
+            Token::Command(name) => self.command(&name),
+        };
+        self.depth -= 1;
+        atom
+    }
+
+    fn command(&mut self, name: &str) -> String {
+        if let Some(letter) = greek_letter(name) {
+            return format!("<mi>{}</mi>", letter);
+        }
+        if let Some(operator) = operator(name) {
+            return format!("<mo>{}</mo>", escape(operator));
+        }
+        if function_name(name) {
+            return format!("<mi>{}</mi>", name);
+        }
+        if let Some(width) = space(name) {
+            return format!("<mspace width=\"{}\"></mspace>", width);
+        }
+
+        match name {
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.argument();
+                let denominator = self.argument();
+                format!("<mfrac>{}{}</mfrac>", numerator, denominator)
+            }
+            "sqrt" => {
+                if self.peek() == Some(&Token::Other('[')) {
+                    self.next();
+                    let mut index = String::new();
+                    while let Some(token) = self.peek() {
+                        if *token == Token::Other(']') {
+                            self.next();
+                            break;
+                        }
+                        let base = self.atom();
+                        index.push_str(&self.scripts(base));
+                    }
+                    let radicand = self.argument();
+                    format!("<mroot>{}<mrow>{}</mrow></mroot>", radicand, index)
+                } else {
+                    format!("<msqrt>{}</msqrt>", self.argument())
+                }
+            }
+            "mathbb" => match self.peek() {
+                Some(Token::Letter(letter)) => {
+                    let letter = double_struck(*letter);
+                    self.next();
+                    format!("<mi>{}</mi>", letter)
+                }
+                _ => self.argument(),
+            },
+            // styles are not supported, the argument is shown as is
+            "mathbf" | "mathit" | "mathcal" | "boldsymbol" | "displaystyle" => self.argument(),
+            // delimiters are shown as they are
+            "left" | "right" | "big" | "Big" | "bigg" | "Bigg" => match self.peek() {
+                Some(Token::Other('.')) => {
+                    self.next();
+                    String::new()
+                }
+                Some(_) => self.atom(),
+                None => String::new(),
+            },
+            "!" | "\\" => String::new(),
+            unknown => format!("<mtext>\\{}</mtext>", escape(unknown)),
+        }
+    }
+}
+
+/// Convert LaTeX math to a MathML `<math>` element
+pub fn tex_to_mathml(tex: &str, display: bool) -> String {
+    let mut parser = Parser {
+        tokens: tokenize(tex),
+        pos: 0,
+        depth: 0,
+    };
+
+    let mut row = String::new();
+    while parser.peek().is_some() {
+        row.push_str(&parser.row());
+        // skip unbalanced closing braces
+        parser.next();
+    }
+
+    if display {
+        format!("<math display=\"block\"><mrow>{}</mrow></math>", row)
+    } else {
+        format!("<math><mrow>{}</mrow></math>", row)
+    }
+}
+
+/// Replace the math spans comrak emits for `$...$` and `$$...$$` with MathML
+pub fn render_math_spans(html: &str) -> String {
+    const INLINE: &str = "<span data-math-style=\"inline\">";
+    const DISPLAY: &str = "<span data-math-style=\"display\">";
+
+    let mut output = String::with_capacity(html.len());
+    let mut rest = html;
+
+    loop {
+        let next = [(INLINE, false), (DISPLAY, true)]
+            .into_iter()
+            .filter_map(|(marker, display)| rest.find(marker).map(|at| (at, marker, display)))
+            .min_by_key(|(at, _, _)| *at);
+        let Some((at, marker, display)) = next else {
+            break;
+        };
+        let after_marker = &rest[at + marker.len()..];
+        let Some(end) = after_marker.find("</span>") else {
+            break;
+        };
+
+        output.push_str(&rest[..at]);
+        let tex = html_escape::decode_html_entities(&after_marker[..end]);
+        output.push_str(&tex_to_mathml(tex.trim(), display));
+        rest = &after_marker[end + "</span>".len()..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tex_to_mathml() {
+        assert_eq!(
+            tex_to_mathml("x^2", false),
+            "<math><mrow><msup><mi>x</mi><mn>2</mn></msup></mrow></math>"
+        );
+        assert_eq!(
+            tex_to_mathml("\\frac{a}{b} \\leq \\sqrt[3]{x}", true),
+            "<math display=\"block\"><mrow><mfrac><mrow><mi>a</mi></mrow><mrow><mi>b</mi></mrow></mfrac>\
+             <mo>≤</mo><mroot><mrow><mi>x</mi></mrow><mrow><mn>3</mn></mrow></mroot></mrow></math>"
+        );
+        assert_eq!(
+            tex_to_mathml("\\text{a < b}", false),
+            "<math><mrow><mtext>a &lt; b</mtext></mrow></math>"
+        );
+        // unbalanced braces and deep nesting do not break out of the element
+        assert!(tex_to_mathml("}}{{x", false).ends_with("</math>"));
+        assert!(tex_to_mathml(&"{".repeat(10_000), false).ends_with("</math>"));
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use lazy_static::lazy_static;
+
+use crate::util::webhook::Event;
+use crate::Pasta;
+
+lazy_static! {
+    /// Counters since start. Kept globally like the expired pasta records,
+    /// as pastas are removed in places that only see the pasta collection.
+    static ref METRICS: Metrics = Metrics::default();
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    created: AtomicU64,
+    deleted_manual: AtomicU64,
+    deleted_expired: AtomicU64,
+    deleted_burned: AtomicU64,
+    decrypt_failures: AtomicU64,
+}
+
+impl Metrics {
+    fn record(&self, event: Event) {
+        let counter = match event {
+            Event::Created => &self.created,
+            Event::Deleted => &self.deleted_manual,
+            Event::Expired | Event::Inactive | Event::Pruned => &self.deleted_expired,
+            Event::ReadLimit => &self.deleted_burned,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The counters and the state of `pastas` in the Prometheus text format
+    fn render(&self, pastas: &[Pasta]) -> String {
+        let stored_bytes: u64 = pastas
+            .iter()
+            .map(|pasta| pasta.total_size_bytes() as u64)
+            .sum();
+
+        let mut output = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, values: &[(&str, u64)]| {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} {}", name, kind);
+            for (labels, value) in values {
+                let _ = writeln!(output, "{}{} {}", name, labels, value);
+            }
+        };
+
+        metric(
+            "microbin_pastas_created_total",
+            "counter",
+            "Pastas created since start.",
+            &[("", self.created.load(Ordering::Relaxed))],
+        );
+        metric(
+            "microbin_pastas_deleted_total",
+            "counter",
+            "Pastas removed since start, by reason.",
+            &[
+                (
+                    "{reason=\"manual\"}",
+                    self.deleted_manual.load(Ordering::Relaxed),
+                ),
+                (
+                    "{reason=\"expired\"}",
+                    self.deleted_expired.load(Ordering::Relaxed),
+                ),
+                (
+                    "{reason=\"burned\"}",
+                    self.deleted_burned.load(Ordering::Relaxed),
+                ),
+            ],
+        );
+        metric(
+            "microbin_pastas",
+            "gauge",
+            "Pastas currently stored.",
+            &[("", pastas.len() as u64)],
+        );
+        metric(
+            "microbin_stored_bytes",
+            "gauge",
+            "Bytes of content and attachments currently stored.",
+            &[("", stored_bytes)],
+        );
+        metric(
+            "microbin_decrypt_failures_total",
+            "counter",
+            "Failed attempts to decrypt an encrypted file since start.",
+            &[("", self.decrypt_failures.load(Ordering::Relaxed))],
+        );
+
+        output
+    }
+}
+
+/// Count a pasta being created or removed
+pub fn record(event: Event) {
+    METRICS.record(event);
+}
+
+/// Count a failed attempt to decrypt a file
+pub fn record_decrypt_failure() {
+    METRICS.decrypt_failures.fetch_add(1, Ordering::Relaxed);
+}
+
+/// All metrics in the Prometheus text format
+pub fn render(pastas: &[Pasta]) -> String {
+    METRICS.render(pastas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::create::new_pasta;
+
+    fn pasta(content: &str) -> Pasta {
+        Pasta {
+            id: 1,
+            content: String::from(content),
+            editable: false,
+            encrypted_key: None,
+            expiration: 0,
+            pasta_type: String::from("text"),
+            ..new_pasta(0)
+        }
+    }
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = Metrics::default();
+        metrics.record(Event::Created);
+        metrics.record(Event::Created);
+        metrics.record(Event::ReadLimit);
+        metrics.record(Event::Inactive);
+
+        let scraped = metrics.render(&[pasta("hello")]);
+        assert!(scraped.contains("# TYPE microbin_pastas_created_total counter\n"));
+        assert!(scraped.contains("\nmicrobin_pastas_created_total 2\n"));
+        assert!(scraped.contains("\nmicrobin_pastas_deleted_total{reason=\"manual\"} 0\n"));
+        assert!(scraped.contains("\nmicrobin_pastas_deleted_total{reason=\"expired\"} 1\n"));
+        assert!(scraped.contains("\nmicrobin_pastas_deleted_total{reason=\"burned\"} 1\n"));
+        assert!(scraped.contains("\nmicrobin_pastas 1\n"));
+        assert!(scraped.contains("\nmicrobin_stored_bytes 5\n"));
+        assert!(scraped.contains("\nmicrobin_decrypt_failures_total 0\n"));
+    }
+}
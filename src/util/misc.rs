@@ -1,8 +1,21 @@
 use crate::args::ARGS;
+use crate::util::expired::{self, ExpiryReason};
+use crate::util::metrics;
+use crate::util::pending;
 use crate::util::storage;
+use crate::util::store::MemoryStore;
+use crate::util::thumbnail;
+use crate::util::webhook;
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::Argon2;
+use base64::prelude::{Engine, BASE64_STANDARD};
 use linkify::{LinkFinder, LinkKind};
 use magic_crypt::{new_magic_crypt, MagicCryptTrait};
+use mime_guess::Mime;
 use qrcode_generator::QrCodeEcc;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
 use std::fs::{self, File};
 use std::io::{BufReader, Read, Write};
 use std::path::Path;
@@ -10,9 +23,9 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::Pasta;
 
-use super::db::delete;
+use super::db::{delete, update};
 
-pub fn remove_expired(pastas: &mut Vec<Pasta>) {
+pub fn remove_expired(pastas: &mut MemoryStore) {
     // get current time - this will be needed to check which pastas have expired
     let timenow: i64 = match SystemTime::now().duration_since(UNIX_EPOCH) {
         Ok(n) => n.as_secs(),
@@ -23,72 +36,150 @@ pub fn remove_expired(pastas: &mut Vec<Pasta>) {
     } as i64;
 
     pastas.retain(|p| {
-        // keep if:
-        //  expiration is `never` or not reached
-        //  AND
-        //  read count is less than burn limit, or no limit set
-        //  AND
-        //  has been read in the last N days where N is the arg --gc-days OR N is 0 (no GC)
-        if (p.expiration == 0 || p.expiration > timenow)
-            && (p.read_count < p.burn_after_reads || p.burn_after_reads == 0)
-            && (p.last_read_days_ago() < ARGS.gc_days || ARGS.gc_days == 0)
-        {
+        if !is_expired(p, timenow, ARGS.gc_days) {
             // keep
             true
         } else {
+            // remember why it was removed so visitors can be told it expired
+            let reason = if p.expiration != 0 && p.expiration <= timenow {
+                ExpiryReason::Expired
+            } else if is_used_up(p) {
+                ExpiryReason::ReadLimit
+            } else {
+                ExpiryReason::Inactive
+            };
+            // one-time pastas are gone without a trace
+            if !(p.one_time && reason == ExpiryReason::ReadLimit) {
+                expired::record(p.id, reason);
+            }
+            metrics::record(webhook::Event::removed(reason));
+            webhook::notify(webhook::Event::removed(reason), p);
+
             // remove from database
             delete(None, Some(p.id));
 
-            // remove the file
-            if let Some(file) = &p.file {
-                let pasta_id = p.id_as_animals();
-
-                // Determine storage path based on file metadata
-                let storage_path = if p.encrypt_server {
-                    // Encrypted file
-                    if file.is_s3_encrypted() {
-                        format!("s3://attachments/{}/data.enc", pasta_id)
-                    } else {
-                        "data.enc".to_string()
-                    }
-                } else {
-                    // Non-encrypted - use stored path
-                    file.name().to_string()
-                };
-
-                if storage_path.starts_with("s3://") {
-                    // S3 file - spawn async task for deletion
-                    let pasta_id_clone = pasta_id.clone();
-                    let storage_path_clone = storage_path.clone();
-                    actix_web::rt::spawn(async move {
-                        if let Err(e) = storage::delete_file(&pasta_id_clone, &storage_path_clone).await {
-                            log::error!("Failed to delete S3 file {}: {}", storage_path_clone, e);
-                        }
-                    });
-                } else {
-                    // Local filesystem deletion
-                    let file_path = format!(
-                        "{}/attachments/{}/{}",
-                        ARGS.data_dir,
-                        pasta_id,
-                        storage_path
-                    );
-                    if fs::remove_file(&file_path).is_err() {
-                        log::error!("Failed to delete file {}!", file_path);
-                    }
-
-                    // and remove the containing directory
-                    let dir_path = format!("{}/attachments/{}/", ARGS.data_dir, pasta_id);
-                    let _ = fs::remove_dir(&dir_path);
-                }
-            }
+            // remove the files
+            delete_files(p);
             false
         }
     });
 }
 
-pub fn string_to_qr_svg(str: &str) -> String {
-    qrcode_generator::to_svg_to_string(str, QrCodeEcc::Low, 256, None::<&str>).unwrap()
+/// Whether a pasta is due to be removed at `timenow`. Pastas are kept if:
+///  expiration is `never` or not reached
+///  AND
+///  read count is less than burn limit, or no limit set, and a one-time
+///  pasta was not read yet
+///  AND
+///  has been read in the last `gc_days` days OR `gc_days` is 0 (no GC)
+pub fn is_expired(p: &Pasta, timenow: i64, gc_days: u16) -> bool {
+    !((p.expiration == 0 || p.expiration > timenow)
+        && !is_used_up(p)
+        && (p.last_read_days_ago() < gc_days || gc_days == 0))
+}
+
+/// Delete the attachments of a pasta that was removed from the collection
+fn delete_files(p: &Pasta) {
+    for storage_path in p.file_storage_paths() {
+        let pasta_id = p.id_as_animals();
+
+        if storage::is_remote(&storage_path) {
+            // S3 or GCS file - spawn async task for deletion, awaited on shutdown
+            let pasta_id_clone = pasta_id.clone();
+            let storage_path_clone = storage_path.clone();
+            pending::spawn(async move {
+                if let Err(e) = storage::delete_file(&pasta_id_clone, &storage_path_clone).await {
+                    log::error!("Failed to delete remote file {}: {}", storage_path_clone, e);
+                }
+            });
+        } else {
+            // Local filesystem deletion
+            let file_path = format!(
+                "{}/attachments/{}/{}",
+                ARGS.data_dir, pasta_id, storage_path
+            );
+            if fs::remove_file(&file_path).is_err() {
+                log::error!("Failed to delete file {}!", file_path);
+            }
+
+            // and remove the containing directory
+            thumbnail::delete_cached(&pasta_id);
+            let dir_path = format!("{}/attachments/{}/", ARGS.data_dir, pasta_id);
+            let _ = fs::remove_dir(&dir_path);
+        }
+    }
+}
+
+/// Whether the pasta has been read or downloaded as often as its limits
+/// allow, or read at all if it is a one-time pasta
+fn is_used_up(pasta: &Pasta) -> bool {
+    (pasta.burn_after_reads != 0 && pasta.read_count >= pasta.burn_after_reads)
+        || (pasta.download_limit != 0 && pasta.download_count >= pasta.download_limit)
+        || (pasta.one_time && pasta.read_count > 0)
+}
+
+/// Take `pastas[index]` out of the collection if its last allowed read was
+/// just counted, so no request that locks the collection after this one can
+/// read it again
+fn take_if_used_up(pastas: &mut MemoryStore, index: usize) -> Option<Pasta> {
+    if is_used_up(&pastas[index]) {
+        Some(pastas.remove(index))
+    } else {
+        None
+    }
+}
+
+/// Save `pastas[index]` after a read was counted. When that was its last
+/// allowed read, the pasta and its files are deleted right away instead, to
+/// be called while still holding the lock on `pastas`.
+pub fn save_or_burn(pastas: &mut MemoryStore, index: usize) {
+    match take_if_used_up(pastas, index) {
+        Some(pasta) => {
+            delete(Some(pastas), Some(pasta.id));
+            // one-time pastas are gone without a trace
+            if !pasta.one_time {
+                expired::record(pasta.id, ExpiryReason::ReadLimit);
+            }
+            metrics::record(webhook::Event::ReadLimit);
+            webhook::notify(webhook::Event::ReadLimit, &pasta);
+            delete_files(&pasta);
+        }
+        None => update(Some(pastas), Some(&pastas[index])),
+    }
+}
+
+/// Smallest and largest QR code images, in pixels
+pub const MIN_QR_SIZE: usize = 64;
+pub const MAX_QR_SIZE: usize = 2048;
+
+/// Error correction level named `low`, `medium`, `quartile` or `high`, or
+/// by its initial
+pub fn parse_qr_ecc(ecc: &str) -> Option<QrCodeEcc> {
+    match ecc.trim().to_ascii_lowercase().as_str() {
+        "low" | "l" => Some(QrCodeEcc::Low),
+        "medium" | "m" => Some(QrCodeEcc::Medium),
+        "quartile" | "q" => Some(QrCodeEcc::Quartile),
+        "high" | "h" => Some(QrCodeEcc::High),
+        _ => None,
+    }
+}
+
+/// The configured `qr_ecc`, checked at startup
+pub fn default_qr_ecc() -> QrCodeEcc {
+    parse_qr_ecc(&ARGS.qr_ecc).unwrap_or(QrCodeEcc::Low)
+}
+
+pub fn clamp_qr_size(size: usize) -> usize {
+    size.clamp(MIN_QR_SIZE, MAX_QR_SIZE)
+}
+
+pub fn string_to_qr_svg(str: &str, ecc: QrCodeEcc, size: usize) -> String {
+    qrcode_generator::to_svg_to_string(str, ecc, clamp_qr_size(size), None::<&str>).unwrap()
+}
+
+/// PNG counterpart of `string_to_qr_svg`, for clients that cannot show SVG
+pub fn string_to_qr_png(str: &str, ecc: QrCodeEcc, size: usize) -> Vec<u8> {
+    qrcode_generator::to_png_to_vec(str, ecc, clamp_qr_size(size)).unwrap()
 }
 
 pub fn is_valid_url(url: &str) -> bool {
@@ -97,24 +188,190 @@ pub fn is_valid_url(url: &str) -> bool {
     spans[0].as_str() == url && Some(&LinkKind::Url) == spans[0].kind()
 }
 
+/// Content type for a file, preferring the configured `mime_overrides` over
+/// the type guessed from the file extension
+pub fn content_type_for(file_name: &str) -> Mime {
+    content_type_with_overrides(file_name, ARGS.mime_overrides.as_deref())
+}
+
+fn content_type_with_overrides(file_name: &str, overrides: Option<&str>) -> Mime {
+    let extension = Path::new(file_name)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase());
+
+    if let (Some(extension), Some(overrides)) = (extension, overrides) {
+        // overrides look like "md=text/plain,log=text/plain"
+        for (ext, content_type) in overrides.split(',').filter_map(|o| o.split_once('=')) {
+            if ext.trim().trim_start_matches('.').eq_ignore_ascii_case(&extension) {
+                match content_type.trim().parse::<Mime>() {
+                    Ok(mime) => return mime,
+                    Err(e) => log::error!("Invalid content type override {}: {}", content_type, e),
+                }
+            }
+        }
+    }
+
+    mime_guess::from_path(file_name).first_or_octet_stream()
+}
+
+/// Bytes from the start of a file looked at by `sniff_content_type`
+pub const SNIFF_LENGTH: usize = 8192;
+
+/// Content type told by the magic bytes at the start of a file, if known
+pub fn sniff_content_type(head: &[u8]) -> Option<&'static str> {
+    infer::get(&head[..head.len().min(SNIFF_LENGTH)]).map(|kind| kind.mime_type())
+}
+
+/// Length in seconds of a duration like `30min`, `2h`, `14d`, `6mo` or `2 hours`.
+/// A month is 30 days and a year 365 days.
+pub fn parse_duration(duration: &str) -> Option<i64> {
+    let duration = duration.trim().to_lowercase();
+    let unit_start = duration.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = duration.split_at(unit_start);
+    let amount: i64 = amount.parse().ok()?;
+
+    let unit_seconds = match unit.trim_start() {
+        "s" | "sec" | "secs" | "second" | "seconds" => 1,
+        "m" | "min" | "mins" | "minute" | "minutes" => 60,
+        "h" | "hr" | "hrs" | "hour" | "hours" => 60 * 60,
+        "d" | "day" | "days" => 60 * 60 * 24,
+        "w" | "week" | "weeks" => 60 * 60 * 24 * 7,
+        "mo" | "month" | "months" => 60 * 60 * 24 * 30,
+        "y" | "year" | "years" => 60 * 60 * 24 * 365,
+        _ => return None,
+    };
+
+    amount.checked_mul(unit_seconds).filter(|&seconds| seconds > 0)
+}
+
+/// Why a blob could not be decrypted
+#[derive(Debug)]
+pub enum DecryptError {
+    /// Wrong password, or the blob was corrupted or tampered with
+    AuthenticationFailed,
+    /// Blob in the unauthenticated MagicCrypt format that did not decrypt
+    Legacy(magic_crypt::MagicCryptError),
+    /// Decrypted text that is not UTF-8
+    InvalidText,
+}
+
+impl std::fmt::Display for DecryptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecryptError::AuthenticationFailed => write!(f, "authentication failed"),
+            DecryptError::Legacy(error) => write!(f, "failed to decrypt: {}", error),
+            DecryptError::InvalidText => write!(f, "decrypted text is not valid UTF-8"),
+        }
+    }
+}
+
+impl From<magic_crypt::MagicCryptError> for DecryptError {
+    fn from(error: magic_crypt::MagicCryptError) -> Self {
+        DecryptError::Legacy(error)
+    }
+}
+
+/// Header of blobs encrypted with AES-256-GCM. It is followed by the key
+/// kind, the salt, the nonce and the ciphertext with its tag. Older blobs are
+/// unauthenticated AES-CBC from MagicCrypt, either bare or after
+/// `KDF_VERSION` and a salt.
+const GCM_HEADER: &[u8] = b"MBGCM";
+/// Key kinds of AES-GCM blobs: SHA-256 of salt and password, or Argon2id
+const GCM_KEY_SHA256: u8 = 0;
+const GCM_KEY_ARGON2: u8 = 1;
+const GCM_NONCE_LEN: usize = 12;
+const GCM_TAG_LEN: usize = 16;
+
+/// Version byte of blobs encrypted with a key derived by Argon2id
+const KDF_VERSION: u8 = 1;
+const KDF_SALT_LEN: usize = 16;
+
+fn derive_key(password: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .expect("Argon2 rejected its default parameters");
+    key
+}
+
+fn is_gcm(blob: &[u8]) -> bool {
+    blob.starts_with(GCM_HEADER)
+        && blob.len() >= GCM_HEADER.len() + 1 + KDF_SALT_LEN + GCM_NONCE_LEN + GCM_TAG_LEN
+}
+
+/// Encrypt with AES-256-GCM under a key derived from `password` and a random
+/// salt, by Argon2id if `kdf` is set and by SHA-256 otherwise
+fn encrypt_gcm(data: &[u8], password: &str, kdf: bool) -> Vec<u8> {
+    let mut salt = [0u8; KDF_SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let mut nonce = [0u8; GCM_NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let key_kind = if kdf { GCM_KEY_ARGON2 } else { GCM_KEY_SHA256 };
+    let cipher = Aes256Gcm::new(&gcm_key(key_kind, password, &salt).into());
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), data)
+        .expect("AES-GCM cannot fail to encrypt data in memory");
+
+    let mut blob = GCM_HEADER.to_vec();
+    blob.push(key_kind);
+    blob.extend_from_slice(&salt);
+    blob.extend_from_slice(&nonce);
+    blob.extend_from_slice(&ciphertext);
+    blob
+}
+
+/// Decrypt a blob from `encrypt_gcm`, checking that it is unchanged
+fn decrypt_gcm(blob: &[u8], password: &str) -> Result<Vec<u8>, DecryptError> {
+    let rest = &blob[GCM_HEADER.len()..];
+    let (key_kind, rest) = (rest[0], &rest[1..]);
+    let (salt, rest) = rest.split_at(KDF_SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(GCM_NONCE_LEN);
+
+    if key_kind != GCM_KEY_SHA256 && key_kind != GCM_KEY_ARGON2 {
+        return Err(DecryptError::AuthenticationFailed);
+    }
+    let cipher = Aes256Gcm::new(&gcm_key(key_kind, password, salt).into());
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| DecryptError::AuthenticationFailed)
+}
+
+fn gcm_key(key_kind: u8, password: &str, salt: &[u8]) -> [u8; 32] {
+    if key_kind == GCM_KEY_ARGON2 {
+        derive_key(password, salt)
+    } else {
+        let mut hasher = Sha256::new();
+        hasher.update(salt);
+        hasher.update(password.as_bytes());
+        hasher.finalize().into()
+    }
+}
+
+/// Encrypt text with AES-256-GCM, encoded as base64
 pub fn encrypt(text_str: &str, key_str: &str) -> String {
     if text_str.is_empty() {
         return String::from("");
     }
 
-    let mc = new_magic_crypt!(key_str, 256);
-
-    mc.encrypt_str_to_base64(text_str)
+    BASE64_STANDARD.encode(encrypt_gcm(text_str.as_bytes(), key_str, false))
 }
 
-pub fn decrypt(text_str: &str, key_str: &str) -> Result<String, magic_crypt::MagicCryptError> {
+/// Decrypt text from `encrypt`, or from MagicCrypt before AES-GCM was used
+pub fn decrypt(text_str: &str, key_str: &str) -> Result<String, DecryptError> {
     if text_str.is_empty() {
         return Ok(String::from(""));
     }
 
-    let mc = new_magic_crypt!(key_str, 256);
-
-    mc.decrypt_base64_to_string(text_str)
+    match BASE64_STANDARD.decode(text_str) {
+        Ok(blob) if is_gcm(&blob) => {
+            String::from_utf8(decrypt_gcm(&blob, key_str)?).map_err(|_| DecryptError::InvalidText)
+        }
+        _ => {
+            let mc = new_magic_crypt!(key_str, 256);
+            Ok(mc.decrypt_base64_to_string(text_str)?)
+        }
+    }
 }
 
 pub fn encrypt_file(
@@ -148,14 +405,75 @@ pub fn encrypt_file(
     Ok(())
 }
 
+/// Encrypt with AES-256-GCM under a key hashed from `passphrase`
 pub fn encrypt_bytes(data: &[u8], passphrase: &str) -> Vec<u8> {
-    let mc = new_magic_crypt!(passphrase, 256);
-    mc.encrypt_bytes_to_bytes(data)
+    encrypt_gcm(data, passphrase, false)
 }
 
-pub fn decrypt_bytes(data: &[u8], passphrase: &str) -> Result<Vec<u8>, magic_crypt::MagicCryptError> {
+/// Decrypt a blob from `encrypt_bytes`, or from MagicCrypt before AES-GCM
+/// was used
+pub fn decrypt_bytes(data: &[u8], passphrase: &str) -> Result<Vec<u8>, DecryptError> {
+    if is_gcm(data) {
+        return decrypt_gcm(data, passphrase);
+    }
     let mc = new_magic_crypt!(passphrase, 256);
-    mc.decrypt_bytes_to_bytes(data)
+    Ok(mc.decrypt_bytes_to_bytes(data)?)
+}
+
+/// Encrypt with AES-256-GCM under a key derived from `password` by Argon2id
+/// and a random salt
+pub fn encrypt_bytes_with_kdf(data: &[u8], password: &str) -> Vec<u8> {
+    encrypt_gcm(data, password, true)
+}
+
+/// Decrypt a blob from `encrypt_bytes_with_kdf` or `encrypt_bytes`, or one
+/// in the older MagicCrypt formats with or without a derived key
+pub fn decrypt_bytes_with_kdf(data: &[u8], password: &str) -> Result<Vec<u8>, DecryptError> {
+    // legacy ciphertext is whole AES blocks, so it never has the extra version byte
+    if !is_gcm(data) && data.len() % 16 == 1 && data[0] == KDF_VERSION {
+        let (salt, ciphertext) = data[1..].split_at(KDF_SALT_LEN);
+        let mc = new_magic_crypt!(derive_key(password, salt), 256);
+        return Ok(mc.decrypt_bytes_to_bytes(ciphertext)?);
+    }
+    decrypt_bytes(data, password)
+}
+
+/// Text counterpart of `encrypt_bytes_with_kdf`, encoded as base64
+pub fn encrypt_with_kdf(text_str: &str, password: &str) -> String {
+    if text_str.is_empty() {
+        return String::from("");
+    }
+
+    BASE64_STANDARD.encode(encrypt_bytes_with_kdf(text_str.as_bytes(), password))
+}
+
+/// Text counterpart of `decrypt_bytes_with_kdf`, falls back to `decrypt`
+pub fn decrypt_with_kdf(text_str: &str, password: &str) -> Result<String, DecryptError> {
+    match BASE64_STANDARD.decode(text_str) {
+        Ok(data) if data.len() % 16 == 1 && data[0] == KDF_VERSION => {
+            let plaintext = decrypt_bytes_with_kdf(&data, password)?;
+            String::from_utf8(plaintext).map_err(|_| DecryptError::InvalidText)
+        }
+        _ => decrypt(text_str, password),
+    }
+}
+
+/// Encrypt with a derived key when `strong_kdf` is set, with the raw password otherwise
+pub fn encrypt_with_password(text_str: &str, password: &str) -> String {
+    if ARGS.strong_kdf {
+        encrypt_with_kdf(text_str, password)
+    } else {
+        encrypt(text_str, password)
+    }
+}
+
+/// Byte counterpart of `encrypt_with_password`
+pub fn encrypt_bytes_with_password(data: &[u8], password: &str) -> Vec<u8> {
+    if ARGS.strong_kdf {
+        encrypt_bytes_with_kdf(data, password)
+    } else {
+        encrypt_bytes(data, password)
+    }
 }
 
 pub fn decrypt_file(
@@ -178,3 +496,181 @@ pub fn decrypt_file(
 
     Ok(res.unwrap())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::create::new_pasta;
+
+    fn burn_after_one_pasta() -> Pasta {
+        Pasta {
+            id: 1,
+            content: String::from("one-time secret"),
+            editable: false,
+            encrypted_key: None,
+            expiration: 0,
+            burn_after_reads: 1,
+            pasta_type: String::from("text"),
+            ..new_pasta(0)
+        }
+    }
+
+    #[test]
+    fn test_one_time_view() {
+        let mut pasta = burn_after_one_pasta();
+        pasta.burn_after_reads = 0;
+        pasta.one_time = true;
+        let mut pastas = MemoryStore::new(vec![pasta]);
+
+        // the owner's own view right after the upload is not counted
+        assert!(take_if_used_up(&mut pastas, 0).is_none());
+
+        pastas[0].read_count += 1;
+        let burnt = take_if_used_up(&mut pastas, 0).unwrap();
+        assert!(burnt.one_time);
+        assert!(pastas.iter().all(|p| p.id != burnt.id));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        // the presets of the upload form
+        assert_eq!(parse_duration("1min"), Some(60));
+        assert_eq!(parse_duration("10min"), Some(600));
+        assert_eq!(parse_duration("1hour"), Some(3600));
+        assert_eq!(parse_duration("24hour"), Some(86400));
+        assert_eq!(parse_duration("3days"), Some(3 * 86400));
+        assert_eq!(parse_duration("1week"), Some(7 * 86400));
+
+        assert_eq!(parse_duration("30min"), Some(1800));
+        assert_eq!(parse_duration("2h"), Some(7200));
+        assert_eq!(parse_duration("2 hours"), Some(7200));
+        assert_eq!(parse_duration("14d"), Some(14 * 86400));
+        assert_eq!(parse_duration("6mo"), Some(6 * 30 * 86400));
+        assert_eq!(parse_duration("1Y"), Some(365 * 86400));
+
+        assert_eq!(parse_duration("never"), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("0d"), None);
+        assert_eq!(parse_duration("-1d"), None);
+        assert_eq!(parse_duration("5 fortnights"), None);
+        assert_eq!(parse_duration("99999999999999999999y"), None);
+        assert_eq!(parse_duration("9999999999999999y"), None);
+    }
+
+    #[test]
+    fn test_kdf_round_trip() {
+        let blob = encrypt_with_kdf("secret text", "hunter2");
+        assert_ne!(blob, encrypt_with_kdf("secret text", "hunter2"));
+        assert_eq!(decrypt_with_kdf(&blob, "hunter2").unwrap(), "secret text");
+        assert!(decrypt_with_kdf(&blob, "hunter3").is_err());
+
+        let data = vec![0u8, 1, 2, 254, 255];
+        let blob = encrypt_bytes_with_kdf(&data, "hunter2");
+        assert_eq!(decrypt_bytes_with_kdf(&blob, "hunter2").unwrap(), data);
+    }
+
+    #[test]
+    fn test_kdf_decrypts_legacy_blobs() {
+        let blob = encrypt("secret text", "hunter2");
+        assert_eq!(decrypt_with_kdf(&blob, "hunter2").unwrap(), "secret text");
+        assert!(decrypt_with_kdf(&blob, "hunter3").is_err());
+
+        // MagicCrypt blobs from before AES-GCM, with the raw password
+        let mc = new_magic_crypt!("hunter2", 256);
+        let blob = mc.encrypt_str_to_base64("secret text");
+        assert_eq!(decrypt_with_kdf(&blob, "hunter2").unwrap(), "secret text");
+        let data = vec![1u8; 33];
+        let blob = mc.encrypt_bytes_to_bytes(&data);
+        assert_eq!(decrypt_bytes_with_kdf(&blob, "hunter2").unwrap(), data);
+
+        // and with a key derived by Argon2id
+        let salt = [7u8; KDF_SALT_LEN];
+        let mc = new_magic_crypt!(derive_key("hunter2", &salt), 256);
+        let mut blob = vec![KDF_VERSION];
+        blob.extend_from_slice(&salt);
+        blob.extend_from_slice(&mc.encrypt_bytes_to_bytes(&data));
+        assert_eq!(decrypt_bytes_with_kdf(&blob, "hunter2").unwrap(), data);
+        let mut blob = vec![KDF_VERSION];
+        blob.extend_from_slice(&salt);
+        blob.extend_from_slice(&mc.encrypt_str_to_bytes("secret text"));
+        let text = BASE64_STANDARD.encode(&blob);
+        assert_eq!(decrypt_with_kdf(&text, "hunter2").unwrap(), "secret text");
+    }
+
+    #[test]
+    fn test_gcm_detects_tampering() {
+        let data = b"attack at dawn".to_vec();
+        for blob in [
+            encrypt_bytes(&data, "hunter2"),
+            encrypt_bytes_with_kdf(&data, "hunter2"),
+        ] {
+            assert!(blob.starts_with(GCM_HEADER));
+            assert_eq!(decrypt_bytes_with_kdf(&blob, "hunter2").unwrap(), data);
+            assert!(matches!(
+                decrypt_bytes_with_kdf(&blob, "hunter3"),
+                Err(DecryptError::AuthenticationFailed)
+            ));
+
+            let mut tampered = blob.clone();
+            *tampered.last_mut().unwrap() ^= 1;
+            assert!(matches!(
+                decrypt_bytes_with_kdf(&tampered, "hunter2"),
+                Err(DecryptError::AuthenticationFailed)
+            ));
+            tampered = blob.clone();
+            tampered[GCM_HEADER.len() + 1] ^= 1;
+            assert!(decrypt_bytes_with_kdf(&tampered, "hunter2").is_err());
+        }
+    }
+
+    #[test]
+    fn test_qr_svg_size() {
+        let small = string_to_qr_svg("https://example.com/upload/cat", QrCodeEcc::Low, 128);
+        let large = string_to_qr_svg("https://example.com/upload/cat", QrCodeEcc::Low, 512);
+        assert!(small.contains(r#"width="128" height="128""#));
+        assert!(large.contains(r#"width="512" height="512""#));
+
+        let huge = string_to_qr_svg("https://example.com", QrCodeEcc::High, 1_000_000);
+        assert!(huge.contains(&format!(r#"width="{}""#, MAX_QR_SIZE)));
+
+        // more error correction needs more modules for the same data
+        let high = string_to_qr_svg("https://example.com/upload/cat", QrCodeEcc::High, 512);
+        assert!(high.len() > large.len());
+
+        assert!(matches!(parse_qr_ecc("Quartile"), Some(QrCodeEcc::Quartile)));
+        assert!(matches!(parse_qr_ecc("h"), Some(QrCodeEcc::High)));
+        assert!(parse_qr_ecc("extreme").is_none());
+    }
+
+    #[test]
+    fn test_qr_png() {
+        let png = string_to_qr_png("https://example.com/upload/cat", QrCodeEcc::Low, 256);
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        // width and height in the IHDR chunk
+        assert_eq!(&png[16..24], &[0, 0, 1, 0, 0, 0, 1, 0]);
+
+        let tiny = string_to_qr_png("https://example.com", QrCodeEcc::Low, 1);
+        assert_eq!(&tiny[16..20], &(MIN_QR_SIZE as u32).to_be_bytes());
+    }
+
+    #[test]
+    fn test_content_type_override() {
+        let overrides = Some("md=text/plain; charset=utf-8, .LOG=text/plain");
+        assert_eq!(
+            content_type_with_overrides("README.md", overrides).to_string(),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            content_type_with_overrides("server.log", overrides).to_string(),
+            "text/plain"
+        );
+        assert_eq!(
+            content_type_with_overrides("image.png", overrides).to_string(),
+            "image/png"
+        );
+        assert_eq!(
+            content_type_with_overrides("README.md", None).to_string(),
+            "text/markdown"
+        );
+    }
+}
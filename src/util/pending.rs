@@ -0,0 +1,111 @@
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use actix_web::rt::task::JoinHandle;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    /// Storage operations running in the background. Kept globally like the
+    /// metrics, as remote files are deleted in places that only see the
+    /// pasta collection.
+    static ref PENDING: PendingTasks = PendingTasks::default();
+}
+
+/// Longest the shutdown waits for background storage operations
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Background tasks that should complete before the process exits
+#[derive(Default)]
+pub struct PendingTasks {
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl PendingTasks {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<JoinHandle<()>>> {
+        self.tasks.lock().unwrap_or_else(|error| error.into_inner())
+    }
+
+    /// Run `task` in the background and keep track of it until it completes
+    pub fn spawn(&self, task: impl Future<Output = ()> + 'static) {
+        let handle = actix_web::rt::spawn(task);
+        let mut tasks = self.lock();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(handle);
+    }
+
+    /// Number of tracked tasks that have not completed yet
+    pub fn len(&self) -> usize {
+        self.lock().iter().filter(|task| !task.is_finished()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Wait for every tracked task, also those spawned while waiting
+    pub async fn wait(&self) {
+        loop {
+            let tasks = std::mem::take(&mut *self.lock());
+            if tasks.is_empty() {
+                return;
+            }
+            for task in tasks {
+                if let Err(error) = task.await {
+                    log::error!("Background storage operation did not complete: {}", error);
+                }
+            }
+        }
+    }
+}
+
+/// Run a storage operation in the background, awaited on shutdown
+pub fn spawn(task: impl Future<Output = ()> + 'static) {
+    PENDING.spawn(task);
+}
+
+/// Wait up to `SHUTDOWN_TIMEOUT` for the background storage operations
+pub async fn wait_for_pending() {
+    let pending = PENDING.len();
+    if pending == 0 {
+        return;
+    }
+
+    log::info!(
+        "Waiting for {} background storage operation(s) to complete",
+        pending
+    );
+    if actix_web::rt::time::timeout(SHUTDOWN_TIMEOUT, PENDING.wait())
+        .await
+        .is_err()
+    {
+        log::warn!(
+            "Background storage operations still running after {} seconds, exiting anyway",
+            SHUTDOWN_TIMEOUT.as_secs()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[actix_web::test]
+    async fn test_pending_delete_is_awaited() {
+        let pending = PendingTasks::default();
+        let deleted = Rc::new(AtomicBool::new(false));
+
+        let flag = deleted.clone();
+        pending.spawn(async move {
+            actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+            flag.store(true, Ordering::SeqCst);
+        });
+        assert_eq!(pending.len(), 1);
+
+        pending.wait().await;
+        assert!(deleted.load(Ordering::SeqCst));
+        assert!(pending.is_empty());
+    }
+}
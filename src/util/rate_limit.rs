@@ -0,0 +1,370 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::{HttpRequest, HttpResponse};
+
+use crate::args::ARGS;
+use crate::AppState;
+
+/// Buckets kept before full ones are dropped, full buckets are the same as
+/// no bucket
+const PRUNE_AT: usize = 10_000;
+
+/// Failed logins older than this are forgotten
+const FAILURE_WINDOW: Duration = Duration::from_secs(15 * 60);
+/// Lockout after reaching the failure limit, doubled with every further failure
+const FIRST_LOCKOUT: Duration = Duration::from_secs(30);
+const MAX_LOCKOUT: Duration = Duration::from_secs(60 * 60);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets per client and endpoint. Every bucket holds up to
+/// `per_minute` requests and refills at `per_minute` tokens per minute.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<(&'static str, String), Bucket>>,
+}
+
+impl RateLimiter {
+    /// Take a token from the bucket of `client` for `scope`. Returns the
+    /// seconds until the next token if the bucket is empty.
+    pub fn check(
+        &self,
+        scope: &'static str,
+        client: &str,
+        per_minute: u32,
+        now: Instant,
+    ) -> Result<(), u64> {
+        let capacity = per_minute as f64;
+        let per_second = capacity / 60.0;
+        let refilled = |bucket: &Bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            (bucket.tokens + elapsed * per_second).min(capacity)
+        };
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if buckets.len() >= PRUNE_AT {
+            buckets.retain(|_, bucket| refilled(bucket) < capacity);
+        }
+
+        let bucket = buckets
+            .entry((scope, client.to_string()))
+            .or_insert(Bucket {
+                tokens: capacity,
+                updated: now,
+            });
+        bucket.tokens = refilled(bucket);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / per_second).ceil() as u64)
+        }
+    }
+}
+
+struct Failures {
+    count: u32,
+    first: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Failed login attempts per client, locking a client out with
+/// exponential backoff once it failed too often within `FAILURE_WINDOW`
+#[derive(Default)]
+pub struct FailedLogins {
+    clients: Mutex<HashMap<String, Failures>>,
+}
+
+impl FailedLogins {
+    /// Seconds until `client` may try again, if it is locked out
+    pub fn locked_out(&self, client: &str, now: Instant) -> Option<u64> {
+        let clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        let locked_until = clients.get(client)?.locked_until?;
+        (locked_until > now).then(|| (locked_until - now).as_secs().max(1))
+    }
+
+    /// Count a failed attempt, returns the failures within the window so far
+    pub fn failed(&self, client: &str, max_failures: u32, now: Instant) -> u32 {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+
+        if clients.len() >= PRUNE_AT {
+            clients.retain(|_, failures| {
+                now.saturating_duration_since(failures.first) < FAILURE_WINDOW
+                    || failures.locked_until.is_some_and(|until| until > now)
+            });
+        }
+
+        let failures = clients.entry(client.to_string()).or_insert(Failures {
+            count: 0,
+            first: now,
+            locked_until: None,
+        });
+        let locked = failures.locked_until.is_some_and(|until| until > now);
+        if !locked && now.saturating_duration_since(failures.first) >= FAILURE_WINDOW {
+            *failures = Failures {
+                count: 0,
+                first: now,
+                locked_until: None,
+            };
+        }
+
+        failures.count += 1;
+        if failures.count >= max_failures {
+            let doublings = (failures.count - max_failures).min(16);
+            let lockout = FIRST_LOCKOUT
+                .saturating_mul(1 << doublings)
+                .min(MAX_LOCKOUT);
+            failures.locked_until = Some(now + lockout);
+        }
+        failures.count
+    }
+
+    /// Forget the failures of a client that logged in successfully
+    pub fn succeeded(&self, client: &str) {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        clients.remove(client);
+    }
+}
+
+/// 429 response with `Retry-After` if the client sent more than
+/// `rate_limit_per_minute` requests to `scope`, None when it may go ahead
+pub fn limit(req: &HttpRequest, data: &AppState, scope: &'static str) -> Option<HttpResponse> {
+    if ARGS.rate_limit_per_minute == 0 {
+        return None;
+    }
+
+    let client = client_ip(req);
+
+    match data
+        .rate_limiter
+        .check(scope, &client, ARGS.rate_limit_per_minute, Instant::now())
+    {
+        Ok(()) => None,
+        Err(retry_after) => {
+            log::warn!("Rate limited {} on {}", client, scope);
+            Some(too_many_requests(retry_after))
+        }
+    }
+}
+
+/// Address the rate limits and login lockouts are kept for, and logs name.
+/// Forwarding headers are only believed from `trusted_proxies`.
+pub fn client_ip(req: &HttpRequest) -> String {
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    resolve_client_ip(
+        req.peer_addr().map(|addr| addr.ip()),
+        header("X-Forwarded-For"),
+        header("X-Real-IP"),
+        &ARGS.trusted_proxies,
+    )
+}
+
+/// The client behind `peer`. `X-Forwarded-For` is walked from the right,
+/// past the trusted proxies that appended to it, to the first address not
+/// one of them.
+fn resolve_client_ip(
+    peer: Option<IpAddr>,
+    forwarded_for: Option<&str>,
+    real_ip: Option<&str>,
+    trusted_proxies: &[String],
+) -> String {
+    let peer = match peer {
+        Some(peer) => peer,
+        None => return String::from("unknown"),
+    };
+    let trusted = |ip: &IpAddr| trusted_proxies.iter().any(|proxy| in_range(ip, proxy));
+    if !trusted(&peer) {
+        return peer.to_string();
+    }
+
+    if let Some(forwarded_for) = forwarded_for {
+        let mut hops = forwarded_for
+            .rsplit(',')
+            .map(|hop| hop.trim().parse::<IpAddr>());
+        let mut client = None;
+        for hop in &mut hops {
+            match hop {
+                Ok(ip) if trusted(&ip) => client = Some(ip),
+                Ok(ip) => return ip.to_string(),
+                // garbage in the header can not be told apart from spoofing
+                Err(_) => break,
+            }
+        }
+        if let Some(client) = client {
+            return client.to_string();
+        }
+    } else if let Some(real_ip) = real_ip.and_then(|ip| ip.trim().parse::<IpAddr>().ok()) {
+        return real_ip.to_string();
+    }
+    peer.to_string()
+}
+
+/// Whether `ip` is `range`, an address or a CIDR range like `10.0.0.0/8`
+fn in_range(ip: &IpAddr, range: &str) -> bool {
+    let range = range.trim();
+    let (network, prefix) = match range.split_once('/') {
+        Some((network, prefix)) => match prefix.parse::<u32>() {
+            Ok(prefix) => (network, Some(prefix)),
+            Err(_) => return false,
+        },
+        None => (range, None),
+    };
+    let network = match network.parse::<IpAddr>() {
+        Ok(network) => network,
+        Err(_) => return false,
+    };
+
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            prefix_matches(u32::from(*ip).into(), u32::from(network).into(), 32, prefix)
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            prefix_matches(u128::from(*ip), u128::from(network), 128, prefix)
+        }
+        _ => false,
+    }
+}
+
+fn prefix_matches(ip: u128, network: u128, bits: u32, prefix: Option<u32>) -> bool {
+    let prefix = prefix.unwrap_or(bits);
+    if prefix > bits {
+        return false;
+    }
+    let shift = bits - prefix;
+    // shifting by the full width overflows, and /0 matches everything
+    shift >= bits || ip >> shift == network >> shift
+}
+
+/// 429 response asking to come back in `retry_after` seconds
+pub fn too_many_requests(retry_after: u64) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header(("Retry-After", retry_after.to_string()))
+        .content_type("text/plain; charset=utf-8")
+        .body("Too many requests, please try again later.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::default();
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.check("create", "1.2.3.4", 3, start), Ok(()));
+        }
+        assert_eq!(limiter.check("create", "1.2.3.4", 3, start), Err(20));
+
+        // other clients and endpoints have their own buckets
+        assert_eq!(limiter.check("create", "5.6.7.8", 3, start), Ok(()));
+        assert_eq!(limiter.check("login", "1.2.3.4", 3, start), Ok(()));
+
+        // one token comes back every 20 seconds
+        let later = start + Duration::from_secs(20);
+        assert_eq!(limiter.check("create", "1.2.3.4", 3, later), Ok(()));
+        assert!(limiter.check("create", "1.2.3.4", 3, later).is_err());
+    }
+
+    #[test]
+    fn test_failed_logins() {
+        let logins = FailedLogins::default();
+        let start = Instant::now();
+
+        assert_eq!(logins.failed("1.2.3.4", 3, start), 1);
+        assert_eq!(logins.failed("1.2.3.4", 3, start), 2);
+        assert_eq!(logins.locked_out("1.2.3.4", start), None);
+        assert_eq!(logins.failed("1.2.3.4", 3, start), 3);
+        assert_eq!(logins.locked_out("1.2.3.4", start), Some(30));
+        assert_eq!(logins.locked_out("5.6.7.8", start), None);
+
+        // every further failure doubles the lockout
+        let later = start + Duration::from_secs(30);
+        assert_eq!(logins.locked_out("1.2.3.4", later), None);
+        assert_eq!(logins.failed("1.2.3.4", 3, later), 4);
+        assert_eq!(logins.locked_out("1.2.3.4", later), Some(60));
+
+        // a successful login starts over
+        logins.succeeded("1.2.3.4");
+        assert_eq!(logins.locked_out("1.2.3.4", later), None);
+        assert_eq!(logins.failed("1.2.3.4", 3, later), 1);
+
+        // failures outside the window are forgotten
+        let much_later = later + FAILURE_WINDOW;
+        assert_eq!(logins.failed("1.2.3.4", 3, much_later), 1);
+    }
+
+    #[test]
+    fn test_untrusted_peer_is_the_client() {
+        let peer = "203.0.113.7".parse().ok();
+        let proxies = [String::from("10.0.0.0/8")];
+
+        // anyone can send these headers
+        assert_eq!(
+            resolve_client_ip(peer, Some("1.2.3.4"), Some("5.6.7.8"), &proxies),
+            "203.0.113.7"
+        );
+        assert_eq!(
+            resolve_client_ip(peer, Some("1.2.3.4"), None, &[]),
+            "203.0.113.7"
+        );
+        assert_eq!(
+            resolve_client_ip(None, Some("1.2.3.4"), None, &[]),
+            "unknown"
+        );
+    }
+
+    #[test]
+    fn test_trusted_proxy_forwards_the_client() {
+        let peer = "10.0.0.2".parse().ok();
+        let proxies = [String::from("10.0.0.0/8"), String::from("192.0.2.1")];
+
+        assert_eq!(
+            resolve_client_ip(peer, Some("1.2.3.4"), None, &proxies),
+            "1.2.3.4"
+        );
+        assert_eq!(
+            resolve_client_ip(peer, None, Some("1.2.3.4"), &proxies),
+            "1.2.3.4"
+        );
+        // addresses the client made up come before the ones proxies added
+        assert_eq!(
+            resolve_client_ip(peer, Some("6.6.6.6, 1.2.3.4, 192.0.2.1"), None, &proxies),
+            "1.2.3.4"
+        );
+        // no usable header leaves the proxy itself
+        assert_eq!(
+            resolve_client_ip(peer, Some("bogus"), None, &proxies),
+            "10.0.0.2"
+        );
+        assert_eq!(resolve_client_ip(peer, None, None, &proxies), "10.0.0.2");
+    }
+
+    #[test]
+    fn test_in_range() {
+        let ip = "10.1.2.3".parse().unwrap();
+        assert!(in_range(&ip, "10.0.0.0/8"));
+        assert!(in_range(&ip, "10.1.2.3"));
+        assert!(in_range(&ip, "0.0.0.0/0"));
+        assert!(!in_range(&ip, "10.1.2.4"));
+        assert!(!in_range(&ip, "11.0.0.0/8"));
+        assert!(!in_range(&ip, "10.0.0.0/33"));
+        assert!(!in_range(&ip, "::/0"));
+        assert!(in_range(&"fd00::1".parse().unwrap(), "fd00::/8"));
+    }
+}
@@ -0,0 +1,278 @@
+use crate::util::http_client;
+use actix_web::web;
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use reqwest::Url;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+
+/// Redirects followed before a fetch is given up
+const MAX_REDIRECTS: usize = 5;
+
+/// Body of a fetched URL, text for text content types that are valid UTF-8
+#[derive(Debug)]
+pub enum Fetched {
+    Text(String),
+    File { name: String, data: Vec<u8> },
+}
+
+/// The IPv4 address carried by an IPv4-mapped, IPv4-compatible, NAT64 or
+/// 6to4 address, which routes to that IPv4 address in the end
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let v4 = |high: u16, low: u16| Ipv4Addr::from((u32::from(high) << 16) | u32::from(low));
+    match ip.segments() {
+        // ::ffff:a.b.c.d and ::a.b.c.d
+        [0, 0, 0, 0, 0, 0 | 0xffff, high, low] => Some(v4(high, low)),
+        // 64:ff9b::a.b.c.d
+        [0x64, 0xff9b, 0, 0, 0, 0, high, low] => Some(v4(high, low)),
+        // 2002:AABB:CCDD::/48
+        [0x2002, high, low, ..] => Some(v4(high, low)),
+        _ => None,
+    }
+}
+
+/// Whether `ip` is reachable on the public internet. Loopback, private,
+/// link-local, shared and other special ranges are refused, also when
+/// carried in an IPv6 address, so fetches cannot reach services running
+/// next to MicroBin.
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                // shared address space of carrier-grade NAT
+                || (a == 100 && (64..128).contains(&b))
+                // benchmarking
+                || (a == 198 && (b == 18 || b == 19))
+                // reserved
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = embedded_ipv4(ip) {
+                return is_public_ip(IpAddr::V4(ip));
+            }
+            let [first, second, ..] = ip.segments();
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // local-use NAT64 and the rest of 64:ff9b::/32
+                || (first == 0x64 && second == 0xff9b)
+                // unique local
+                || (first & 0xfe00) == 0xfc00
+                // link-local
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Check the scheme of `url` and resolve its host, refusing hosts with any
+/// address that is not public
+async fn resolve(url: &Url) -> Result<SocketAddr, String> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(format!("Unsupported URL scheme {}.", url.scheme()));
+    }
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    let host = url.host_str().ok_or("URL has no host.")?;
+    // IPv6 hosts come in brackets
+    let addrs: Vec<SocketAddr> = match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => {
+            let host = host.to_string();
+            web::block(move || (host.as_str(), port).to_socket_addrs())
+                .await
+                .map_err(|e| e.to_string())?
+                .map_err(|e| format!("Failed to resolve host: {}", e))?
+                .collect()
+        }
+    };
+
+    // the client only connects to the checked address, so the host cannot
+    // resolve to another one in between
+    match addrs.first() {
+        Some(addr) if addrs.iter().all(|addr| is_public_ip(addr.ip())) => Ok(*addr),
+        Some(_) => Err(String::from("URL points to a private or local address.")),
+        None => Err(String::from("Failed to resolve host.")),
+    }
+}
+
+/// Whether a content type is text that can become the content of a pasta
+fn is_text_type(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json" | "application/xml" | "application/javascript"
+        )
+}
+
+/// Last segment of the URL path, the name fetched files are stored under
+fn file_name(url: &Url) -> String {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("download")
+        .to_string()
+}
+
+/// Download `url` for a new pasta, following redirects and checking every
+/// hop. Text may be up to `max_text_bytes` long and files up to
+/// `max_file_bytes`, bodies are cut off as soon as they go over.
+pub async fn fetch(
+    url: &str,
+    max_text_bytes: usize,
+    max_file_bytes: usize,
+) -> Result<Fetched, String> {
+    let mut url = Url::parse(url).map_err(|e| format!("Invalid URL: {}", e))?;
+
+    for _ in 0..=MAX_REDIRECTS {
+        let addr = resolve(&url).await?;
+        let client = http_client::new_async_pinned(url.host_str().unwrap_or_default(), addr);
+        let mut response = client
+            .get(url.clone())
+            .send()
+            .await
+            .map_err(|e| format!("Failed to fetch URL: {}", e))?;
+
+        let status = response.status();
+        if status.is_redirection() {
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .ok_or("Redirect without a location.")?;
+            url = url
+                .join(location)
+                .map_err(|e| format!("Invalid redirect: {}", e))?;
+            continue;
+        }
+        if !status.is_success() {
+            return Err(format!("Remote server answered with status {}.", status));
+        }
+
+        let is_text = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .is_some_and(is_text_type);
+        let limit = if is_text {
+            max_text_bytes
+        } else {
+            max_file_bytes
+        };
+        if response
+            .content_length()
+            .is_some_and(|length| length > limit as u64)
+        {
+            return Err(String::from("Remote file exceeded size limit."));
+        }
+
+        let mut data = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| format!("Failed to fetch URL: {}", e))?
+        {
+            data.extend_from_slice(&chunk);
+            if data.len() > limit {
+                return Err(String::from("Remote file exceeded size limit."));
+            }
+        }
+
+        let name = file_name(&url);
+        return Ok(match is_text {
+            true => match String::from_utf8(data) {
+                Ok(text) => Fetched::Text(text),
+                Err(e) => Fetched::File {
+                    name,
+                    data: e.into_bytes(),
+                },
+            },
+            false => Fetched::File { name, data },
+        });
+    }
+
+    Err(String::from("Too many redirects."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_public_ip() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+            "::127.0.0.1",
+            "::192.168.1.1",
+            "64:ff9b::127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "64:ff9b:1::1",
+            "2002:7f00:1::1",
+            "2002:a00:1::",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{} is not public", ip);
+        }
+        for ip in [
+            "1.1.1.1",
+            "93.184.216.34",
+            "100.128.0.1",
+            "2606:4700::1111",
+            "::ffff:1.1.1.1",
+            "64:ff9b::1.1.1.1",
+            "2002:101:101::1",
+        ] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{} is public", ip);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_fetch_refuses_local_and_other_schemes() {
+        for url in [
+            "http://127.0.0.1:1/secret",
+            "http://[::1]/",
+            "http://localhost/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://0x7f000001/",
+            "file:///etc/passwd",
+            "ftp://example.com/file",
+            "not a url",
+        ] {
+            assert!(fetch(url, 1024, 1024).await.is_err(), "{} was fetched", url);
+        }
+    }
+
+    #[test]
+    fn test_is_text_type() {
+        assert!(is_text_type("text/plain; charset=utf-8"));
+        assert!(is_text_type("application/JSON"));
+        assert!(is_text_type("application/ld+json"));
+        assert!(!is_text_type("image/png"));
+        assert!(!is_text_type("application/octet-stream"));
+    }
+}
@@ -0,0 +1,38 @@
+use std::time::Instant;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::Error;
+
+use crate::args::ARGS;
+
+/// Middleware warning about requests whose handler took longer than
+/// `slow_request_ms`. Only the time until the handler returns its response is
+/// measured, streaming the body afterwards is not included.
+pub async fn log_slow_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let start = Instant::now();
+    let method = req.method().clone();
+
+    let res = next.call(req).await?;
+
+    let elapsed = start.elapsed();
+    if elapsed.as_millis() >= ARGS.slow_request_ms as u128 {
+        let request = res.request();
+        let route = request
+            .match_pattern()
+            .unwrap_or_else(|| request.path().to_string());
+        log::warn!(
+            "Slow request: {} {} (pasta id: {}) took {} ms",
+            method,
+            route,
+            request.match_info().get("id").unwrap_or("-"),
+            elapsed.as_millis()
+        );
+    }
+
+    Ok(res)
+}
@@ -1,10 +1,42 @@
-use crate::args::ARGS;
+use crate::args::{FileNameCollisions, ARGS};
+use crate::util::azure::AzureBlob;
+use crate::util::gcs;
+use crate::util::thumbnail;
+use crate::util::webdav::WebDav;
+use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+use lazy_static::lazy_static;
 use s3::creds::Credentials;
 use s3::error::S3Error;
 use s3::{Bucket, Region};
+use sha2::{Digest, Sha256};
 use std::fs;
-use std::io::Write;
+use std::io::{Read, Write};
+use std::collections::VecDeque;
 use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long after an upload a missing S3 object is considered a transient error
+const RECENT_SAVE_WINDOW: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    /// S3 paths written recently, to tell read-after-write lag apart from genuine 404s
+    static ref RECENTLY_SAVED: Mutex<VecDeque<(String, Instant)>> = Mutex::new(VecDeque::new());
+}
+
+fn record_saved(storage_path: &str) {
+    let mut saved = RECENTLY_SAVED.lock().unwrap_or_else(|e| e.into_inner());
+    saved.retain(|(_, at)| at.elapsed() < RECENT_SAVE_WINDOW);
+    saved.push_back((storage_path.to_string(), Instant::now()));
+}
+
+fn was_recently_saved(storage_path: &str) -> bool {
+    let saved = RECENTLY_SAVED.lock().unwrap_or_else(|e| e.into_inner());
+    saved
+        .iter()
+        .any(|(path, at)| path == storage_path && at.elapsed() < RECENT_SAVE_WINDOW)
+}
 
 fn get_s3_bucket() -> Result<Box<Bucket>, S3Error> {
     let region = Region::Custom {
@@ -26,37 +58,64 @@ fn get_s3_bucket() -> Result<Box<Bucket>, S3Error> {
     Ok(bucket)
 }
 
-/// Generate the storage path for a file.
-/// Returns (storage_name, is_s3) where storage_name includes s3:// prefix if using S3.
-pub fn generate_storage_path(pasta_id: &str, filename: &str) -> String {
-    if ARGS.s3_enabled() {
-        format!("s3://attachments/{}/{}", pasta_id, filename)
-    } else {
-        filename.to_string()
+/// A place attachments are kept. `path` is the path of a file within the
+/// backend, that is its storage path without the scheme prefix.
+#[async_trait(?Send)]
+pub trait StorageBackend: Sync {
+    async fn save(&self, pasta_id: &str, path: &str, data: &[u8]) -> Result<(), String>;
+
+    async fn get(&self, pasta_id: &str, path: &str) -> Result<Vec<u8>, String>;
+
+    /// The `length` bytes of a file from `start` on. Backends that can't
+    /// fetch part of a file get all of it and cut the range out.
+    async fn get_range(
+        &self,
+        pasta_id: &str,
+        path: &str,
+        start: u64,
+        length: u64,
+    ) -> Result<Vec<u8>, String> {
+        let data = self.get(pasta_id, path).await?;
+        Ok(data
+            .into_iter()
+            .skip(start as usize)
+            .take(length as usize)
+            .collect())
     }
-}
 
-/// Save a file. The `storage_path` should be the value returned by `generate_storage_path`
-/// or the `name` field from PastaFile.
-pub async fn save_file(pasta_id: &str, storage_path: &str, data: &[u8]) -> Result<(), String> {
-    if let Some(s3_path) = storage_path.strip_prefix("s3://") {
-        // S3 storage
-        let bucket = get_s3_bucket().map_err(|e| format!("Failed to get S3 bucket: {}", e))?;
+    /// Whether a file is stored at `path`
+    async fn exists(&self, pasta_id: &str, path: &str) -> Result<bool, String>;
 
-        bucket
-            .put_object(s3_path, data)
-            .await
-            .map_err(|e| format!("Failed to upload to S3: {}", e))?;
+    /// Files that are already gone count as deleted
+    async fn delete(&self, pasta_id: &str, path: &str) -> Result<(), String>;
 
-        log::info!("Uploaded file to S3: {}", s3_path);
+    /// Move a file to another pasta id and return its new path. Remote
+    /// backends copy it, and leave the old file for the caller to delete
+    /// once the move is committed.
+    async fn move_to(
+        &self,
+        old_pasta_id: &str,
+        new_pasta_id: &str,
+        path: &str,
+    ) -> Result<String, String>;
+
+    /// Whether the backend can be reached, for readiness probes. Backends
+    /// without a cheap check count as reachable.
+    async fn check(&self) -> Result<(), String> {
         Ok(())
-    } else {
-        // Local storage
+    }
+}
+
+/// Attachments in `data_dir`, in one directory per pasta
+pub struct LocalBackend;
+
+#[async_trait(?Send)]
+impl StorageBackend for LocalBackend {
+    async fn save(&self, pasta_id: &str, path: &str, data: &[u8]) -> Result<(), String> {
         let dir_path = format!("{}/attachments/{}", ARGS.data_dir, pasta_id);
-        fs::create_dir_all(&dir_path)
-            .map_err(|e| format!("Failed to create directory: {}", e))?;
+        fs::create_dir_all(&dir_path).map_err(|e| format!("Failed to create directory: {}", e))?;
 
-        let file_path = format!("{}/{}", dir_path, storage_path);
+        let file_path = local_file_path(pasta_id, path);
         let mut file =
             fs::File::create(&file_path).map_err(|e| format!("Failed to create file: {}", e))?;
 
@@ -65,54 +124,676 @@ pub async fn save_file(pasta_id: &str, storage_path: &str, data: &[u8]) -> Resul
 
         Ok(())
     }
+
+    async fn get(&self, pasta_id: &str, path: &str) -> Result<Vec<u8>, String> {
+        let file_path = local_file_path(pasta_id, path);
+        fs::read(&file_path).map_err(|e| format!("Failed to read file: {}", e))
+    }
+
+    async fn exists(&self, pasta_id: &str, path: &str) -> Result<bool, String> {
+        Ok(Path::new(&local_file_path(pasta_id, path)).exists())
+    }
+
+    async fn delete(&self, pasta_id: &str, path: &str) -> Result<(), String> {
+        let file_path = local_file_path(pasta_id, path);
+
+        if Path::new(&file_path).exists() {
+            fs::remove_file(&file_path).map_err(|e| format!("Failed to delete file: {}", e))?;
+        }
+
+        remove_empty_dir(pasta_id);
+
+        Ok(())
+    }
+
+    /// Moves the whole directory of the pasta, so all its files at once
+    async fn move_to(
+        &self,
+        old_pasta_id: &str,
+        new_pasta_id: &str,
+        path: &str,
+    ) -> Result<String, String> {
+        let old_dir = format!("{}/attachments/{}", ARGS.data_dir, old_pasta_id);
+        let new_dir = format!("{}/attachments/{}", ARGS.data_dir, new_pasta_id);
+
+        if Path::new(&new_dir).exists() {
+            return Err(format!("Directory already exists: {}", new_dir));
+        }
+        fs::rename(&old_dir, &new_dir).map_err(|e| format!("Failed to move file: {}", e))?;
+
+        Ok(path.to_string())
+    }
 }
 
-/// Get a file by its storage path.
-pub async fn get_file(pasta_id: &str, storage_path: &str) -> Result<Vec<u8>, String> {
-    if let Some(s3_path) = storage_path.strip_prefix("s3://") {
-        // S3 storage
+/// List at most one object of `bucket`, as a cheap check that it is reachable
+async fn check_bucket(bucket: &Bucket) -> Result<(), String> {
+    let (_, status) = bucket
+        .list_page(String::from("attachments/"), None, None, None, Some(1))
+        .await
+        .map_err(|e| format!("S3 bucket unreachable: {}", e))?;
+    if !(200..300).contains(&status) {
+        return Err(format!("S3 bucket unreachable (status {})", status));
+    }
+    Ok(())
+}
+
+/// Attachments in the S3 compatible bucket configured in `ARGS`
+pub struct S3Backend;
+
+#[async_trait(?Send)]
+impl StorageBackend for S3Backend {
+    async fn save(&self, _pasta_id: &str, path: &str, data: &[u8]) -> Result<(), String> {
         let bucket = get_s3_bucket().map_err(|e| format!("Failed to get S3 bucket: {}", e))?;
 
+        bucket
+            .put_object(path, data)
+            .await
+            .map_err(|e| format!("Failed to upload to S3: {}", e))?;
+
+        log::info!("Uploaded file to S3: {}", path);
+        record_saved(path);
+        Ok(())
+    }
+
+    async fn get(&self, _pasta_id: &str, path: &str) -> Result<Vec<u8>, String> {
+        let bucket = get_s3_bucket().map_err(|e| format!("Failed to get S3 bucket: {}", e))?;
+
+        // Some S3 compatible providers report freshly written objects as
+        // missing for a short while, so retry those with a backoff before
+        // treating them as really gone
+        let retries = if was_recently_saved(path) {
+            ARGS.s3_read_retry
+        } else {
+            0
+        };
+
+        let mut attempt = 0;
+        loop {
+            let status = match bucket.get_object(path).await {
+                Ok(response) if response.status_code() == 404 => 404,
+                Ok(response) if response.status_code() >= 300 => {
+                    return Err(format!(
+                        "Failed to get file from S3: status {}",
+                        response.status_code()
+                    ))
+                }
+                Ok(response) => return Ok(response.to_vec()),
+                Err(S3Error::HttpFailWithBody(404, _)) => 404,
+                Err(e) => return Err(format!("Failed to get file from S3: {}", e)),
+            };
+
+            if attempt >= retries {
+                return Err(format!("File not found in S3 (status {}): {}", status, path));
+            }
+
+            attempt += 1;
+            let delay = Duration::from_millis(100 * 2u64.pow(attempt - 1));
+            log::warn!(
+                "S3 object {} not visible yet, retrying in {} ms ({}/{})",
+                path,
+                delay.as_millis(),
+                attempt,
+                retries
+            );
+            actix_web::rt::time::sleep(delay).await;
+        }
+    }
+
+    async fn get_range(
+        &self,
+        _pasta_id: &str,
+        path: &str,
+        start: u64,
+        length: u64,
+    ) -> Result<Vec<u8>, String> {
+        let bucket = get_s3_bucket().map_err(|e| format!("Failed to get S3 bucket: {}", e))?;
+
+        // The end is inclusive and must lie after the start, so single bytes
+        // are fetched as two and cut down below
+        let end = start + length.max(2) - 1;
         let response = bucket
-            .get_object(s3_path)
+            .get_object_range(path, start, Some(end))
             .await
-            .map_err(|e| format!("Failed to get file from S3: {}", e))?;
+            .map_err(|e| format!("Failed to get file range from S3: {}", e))?;
+        if response.status_code() >= 300 {
+            return Err(format!(
+                "Failed to get file range from S3: status {}",
+                response.status_code()
+            ));
+        }
 
-        Ok(response.to_vec())
-    } else {
-        // Local storage
-        let file_path = format!("{}/attachments/{}/{}", ARGS.data_dir, pasta_id, storage_path);
-        fs::read(&file_path).map_err(|e| format!("Failed to read file: {}", e))
+        let mut data = response.to_vec();
+        data.truncate(length as usize);
+        Ok(data)
     }
-}
 
-/// Delete a file by its storage path.
-pub async fn delete_file(pasta_id: &str, storage_path: &str) -> Result<(), String> {
-    if let Some(s3_path) = storage_path.strip_prefix("s3://") {
-        // S3 storage
+    async fn exists(&self, _pasta_id: &str, path: &str) -> Result<bool, String> {
+        let bucket = get_s3_bucket().map_err(|e| format!("Failed to get S3 bucket: {}", e))?;
+
+        match bucket.head_object(path).await {
+            Ok((_, 404)) | Err(S3Error::HttpFailWithBody(404, _)) => Ok(false),
+            Ok((_, status)) if status >= 300 => {
+                Err(format!("Failed to check file in S3: status {}", status))
+            }
+            Ok(_) => Ok(true),
+            Err(e) => Err(format!("Failed to check file in S3: {}", e)),
+        }
+    }
+
+    async fn delete(&self, _pasta_id: &str, path: &str) -> Result<(), String> {
         let bucket = get_s3_bucket().map_err(|e| format!("Failed to get S3 bucket: {}", e))?;
 
         bucket
-            .delete_object(s3_path)
+            .delete_object(path)
             .await
             .map_err(|e| format!("Failed to delete from S3: {}", e))?;
 
-        log::info!("Deleted file from S3: {}", s3_path);
+        log::info!("Deleted file from S3: {}", path);
         Ok(())
-    } else {
-        // Local storage
-        let file_path = format!("{}/attachments/{}/{}", ARGS.data_dir, pasta_id, storage_path);
+    }
 
-        if Path::new(&file_path).exists() {
-            fs::remove_file(&file_path)
-                .map_err(|e| format!("Failed to delete file: {}", e))?;
-        }
+    async fn move_to(
+        &self,
+        old_pasta_id: &str,
+        new_pasta_id: &str,
+        path: &str,
+    ) -> Result<String, String> {
+        let new_path = moved_object_path(old_pasta_id, new_pasta_id, path)?;
 
-        let dir_path = format!("{}/attachments/{}", ARGS.data_dir, pasta_id);
-        if Path::new(&dir_path).exists() {
-            let _ = fs::remove_dir(&dir_path);
+        let bucket = get_s3_bucket().map_err(|e| format!("Failed to get S3 bucket: {}", e))?;
+
+        let status = bucket
+            .copy_object_internal(path, &new_path)
+            .await
+            .map_err(|e| format!("Failed to copy in S3: {}", e))?;
+        if status >= 300 {
+            return Err(format!("Failed to copy in S3: status {}", status));
         }
 
+        log::info!("Copied file in S3: {} -> {}", path, new_path);
+        Ok(new_path)
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        let bucket = get_s3_bucket().map_err(|e| format!("Failed to get S3 bucket: {}", e))?;
+        check_bucket(&bucket).await
+    }
+}
+
+/// Attachments in the Google Cloud Storage bucket configured in `ARGS`
+pub struct GcsBackend;
+
+#[async_trait(?Send)]
+impl StorageBackend for GcsBackend {
+    async fn save(&self, _pasta_id: &str, path: &str, data: &[u8]) -> Result<(), String> {
+        gcs::put_object(path, data).await?;
+        log::info!("Uploaded file to GCS: {}", path);
+        Ok(())
+    }
+
+    async fn get(&self, _pasta_id: &str, path: &str) -> Result<Vec<u8>, String> {
+        gcs::get_object(path).await
+    }
+
+    async fn exists(&self, _pasta_id: &str, path: &str) -> Result<bool, String> {
+        gcs::object_exists(path).await
+    }
+
+    async fn delete(&self, _pasta_id: &str, path: &str) -> Result<(), String> {
+        gcs::delete_object(path).await?;
+        log::info!("Deleted file from GCS: {}", path);
+        Ok(())
+    }
+
+    async fn move_to(
+        &self,
+        old_pasta_id: &str,
+        new_pasta_id: &str,
+        path: &str,
+    ) -> Result<String, String> {
+        let new_path = moved_object_path(old_pasta_id, new_pasta_id, path)?;
+        gcs::copy_object(path, &new_path).await?;
+        log::info!("Copied file in GCS: {} -> {}", path, new_path);
+        Ok(new_path)
+    }
+}
+
+/// Attachments on the WebDAV server configured in `ARGS`
+pub struct WebdavBackend;
+
+#[async_trait(?Send)]
+impl StorageBackend for WebdavBackend {
+    async fn save(&self, _pasta_id: &str, path: &str, data: &[u8]) -> Result<(), String> {
+        WebDav::from_args().put(path, data).await?;
+        log::info!("Uploaded file to WebDAV: {}", path);
+        Ok(())
+    }
+
+    async fn get(&self, _pasta_id: &str, path: &str) -> Result<Vec<u8>, String> {
+        WebDav::from_args().get(path).await
+    }
+
+    async fn exists(&self, _pasta_id: &str, path: &str) -> Result<bool, String> {
+        WebDav::from_args().exists(path).await
+    }
+
+    async fn delete(&self, _pasta_id: &str, path: &str) -> Result<(), String> {
+        WebDav::from_args().delete(path).await?;
+        log::info!("Deleted file from WebDAV: {}", path);
+        Ok(())
+    }
+
+    async fn move_to(
+        &self,
+        old_pasta_id: &str,
+        new_pasta_id: &str,
+        path: &str,
+    ) -> Result<String, String> {
+        let new_path = moved_object_path(old_pasta_id, new_pasta_id, path)?;
+        WebDav::from_args().copy(path, &new_path).await?;
+        log::info!("Copied file in WebDAV: {} -> {}", path, new_path);
+        Ok(new_path)
+    }
+}
+
+/// Attachments in the Azure Blob Storage container configured in `ARGS`
+pub struct AzureBackend;
+
+#[async_trait(?Send)]
+impl StorageBackend for AzureBackend {
+    async fn save(&self, _pasta_id: &str, path: &str, data: &[u8]) -> Result<(), String> {
+        AzureBlob::from_args()?.put(path, data).await?;
+        log::info!("Uploaded file to Azure: {}", path);
         Ok(())
     }
+
+    async fn get(&self, _pasta_id: &str, path: &str) -> Result<Vec<u8>, String> {
+        AzureBlob::from_args()?.get(path).await
+    }
+
+    async fn exists(&self, _pasta_id: &str, path: &str) -> Result<bool, String> {
+        AzureBlob::from_args()?.exists(path).await
+    }
+
+    async fn delete(&self, _pasta_id: &str, path: &str) -> Result<(), String> {
+        AzureBlob::from_args()?.delete(path).await?;
+        log::info!("Deleted file from Azure: {}", path);
+        Ok(())
+    }
+
+    async fn move_to(
+        &self,
+        old_pasta_id: &str,
+        new_pasta_id: &str,
+        path: &str,
+    ) -> Result<String, String> {
+        let new_path = moved_object_path(old_pasta_id, new_pasta_id, path)?;
+        AzureBlob::from_args()?.copy(path, &new_path).await?;
+        log::info!("Copied file in Azure: {} -> {}", path, new_path);
+        Ok(new_path)
+    }
+}
+
+/// Path of a remote object after moving it from one pasta id to another
+fn moved_object_path(old_pasta_id: &str, new_pasta_id: &str, path: &str) -> Result<String, String> {
+    let file_name = path
+        .strip_prefix(&format!("attachments/{}/", old_pasta_id))
+        .ok_or_else(|| format!("Unexpected object path: {}", path))?;
+    Ok(format!("attachments/{}/{}", new_pasta_id, file_name))
+}
+
+/// The kinds of storage backends. Files in remote backends are referred to by
+/// storage paths starting with the backend's scheme, like `s3://`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+    Local,
+    S3,
+    Gcs,
+    Webdav,
+    Azure,
+}
+
+impl BackendKind {
+    /// The backend new uploads are saved to
+    pub fn configured() -> Self {
+        if ARGS.s3_enabled() {
+            BackendKind::S3
+        } else if ARGS.gcs_enabled() {
+            BackendKind::Gcs
+        } else if ARGS.webdav_enabled() {
+            BackendKind::Webdav
+        } else if ARGS.azure_enabled() {
+            BackendKind::Azure
+        } else {
+            BackendKind::Local
+        }
+    }
+
+    /// The backend holding a stored file, and the path of the file within it
+    pub fn for_path(storage_path: &str) -> (Self, &str) {
+        if let Some(path) = storage_path.strip_prefix("s3://") {
+            (BackendKind::S3, path)
+        } else if let Some(path) = storage_path.strip_prefix("gcs://") {
+            (BackendKind::Gcs, path)
+        } else if let Some(path) = storage_path.strip_prefix("webdav://") {
+            (BackendKind::Webdav, path)
+        } else if let Some(path) = storage_path.strip_prefix("azure://") {
+            (BackendKind::Azure, path)
+        } else {
+            (BackendKind::Local, storage_path)
+        }
+    }
+
+    /// Scheme of the storage paths of remote backends
+    pub fn scheme(self) -> Option<&'static str> {
+        match self {
+            BackendKind::Local => None,
+            BackendKind::S3 => Some("s3"),
+            BackendKind::Gcs => Some("gcs"),
+            BackendKind::Webdav => Some("webdav"),
+            BackendKind::Azure => Some("azure"),
+        }
+    }
+
+    /// Storage path of a file at `path` within this backend
+    fn storage_path(self, path: &str) -> String {
+        match self.scheme() {
+            Some(scheme) => format!("{}://{}", scheme, path),
+            None => path.to_string(),
+        }
+    }
+
+    fn backend(self) -> &'static dyn StorageBackend {
+        match self {
+            BackendKind::Local => &LocalBackend,
+            BackendKind::S3 => &S3Backend,
+            BackendKind::Gcs => &GcsBackend,
+            BackendKind::Webdav => &WebdavBackend,
+            BackendKind::Azure => &AzureBackend,
+        }
+    }
+}
+
+/// Whether the configured backend can be reached
+pub async fn check() -> Result<(), String> {
+    BackendKind::configured().backend().check().await
+}
+
+/// Whether a storage path points to a remote backend instead of the local disk
+pub fn is_remote(storage_path: &str) -> bool {
+    BackendKind::for_path(storage_path).0 != BackendKind::Local
+}
+
+/// Generate the storage path for a file. For remote backends, this is the
+/// full object path with the backend's scheme, e.g. `s3://attachments/...`.
+pub fn generate_storage_path(pasta_id: &str, filename: &str) -> String {
+    let kind = BackendKind::configured();
+    match kind {
+        BackendKind::Local => filename.to_string(),
+        _ => kind.storage_path(&format!("attachments/{}/{}", pasta_id, filename)),
+    }
+}
+
+/// Most names tried for a file before giving up
+const MAX_NAME_ATTEMPTS: u32 = 100;
+
+/// Generate a storage path for a file like `generate_storage_path`, but one
+/// no file of the pasta is stored at yet. When `filename` is taken, a number
+/// or a short hash is added to it as set by `filename_collisions`, so the
+/// caller has to use the name in the returned path.
+pub async fn free_storage_path(pasta_id: &str, filename: &str) -> Result<String, String> {
+    let kind = BackendKind::configured();
+    for attempt in 0..MAX_NAME_ATTEMPTS {
+        let name = collision_name(filename, attempt, ARGS.filename_collisions);
+        let storage_path = generate_storage_path(pasta_id, &name);
+        let (_, path) = BackendKind::for_path(&storage_path);
+        if !kind.backend().exists(pasta_id, path).await? {
+            return Ok(storage_path);
+        }
+    }
+    Err(format!(
+        "No free name left for {} in {}",
+        filename, pasta_id
+    ))
+}
+
+/// Name to try for `filename` after `attempt` collisions. The number or
+/// hash goes before the extension, so the file keeps its type.
+fn collision_name(filename: &str, attempt: u32, naming: FileNameCollisions) -> String {
+    if attempt == 0 {
+        return filename.to_string();
+    }
+    let tag = match naming {
+        FileNameCollisions::Number => attempt.to_string(),
+        FileNameCollisions::Hash => {
+            let digest = Sha256::digest(format!("{}/{}", attempt, filename));
+            format!("{:x}", digest)[..8].to_string()
+        }
+    };
+    match filename.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => format!("{}_{}.{}", stem, tag, extension),
+        _ => format!("{}_{}", filename, tag),
+    }
+}
+
+/// Save a file. The `storage_path` should be the value returned by `generate_storage_path`
+/// or the `name` field from PastaFile.
+pub async fn save_file(pasta_id: &str, storage_path: &str, data: &[u8]) -> Result<(), String> {
+    let (kind, path) = BackendKind::for_path(storage_path);
+    kind.backend().save(pasta_id, path, data).await
+}
+
+/// Save a file to local storage from a stream of chunks, writing each chunk
+/// as it arrives instead of holding the whole file in memory. The partially
+/// written file is removed if the stream yields an error. Returns the number
+/// of bytes written.
+pub async fn save_file_streaming<S, B>(
+    pasta_id: &str,
+    storage_path: &str,
+    mut chunks: S,
+) -> Result<u64, String>
+where
+    S: Stream<Item = Result<B, String>> + Unpin,
+    B: AsRef<[u8]>,
+{
+    let dir_path = format!("{}/attachments/{}", ARGS.data_dir, pasta_id);
+    fs::create_dir_all(&dir_path).map_err(|e| format!("Failed to create directory: {}", e))?;
+
+    let file_path = local_file_path(pasta_id, storage_path);
+    let mut file =
+        fs::File::create(&file_path).map_err(|e| format!("Failed to create file: {}", e))?;
+
+    let mut written: u64 = 0;
+    while let Some(chunk) = chunks.next().await {
+        let result = chunk.and_then(|chunk| {
+            file.write_all(chunk.as_ref())
+                .map_err(|e| format!("Failed to write file: {}", e))?;
+            Ok(chunk.as_ref().len() as u64)
+        });
+        match result {
+            Ok(len) => written += len,
+            Err(e) => {
+                drop(file);
+                delete_local_file(pasta_id, storage_path);
+                return Err(e);
+            }
+        }
+    }
+
+    Ok(written)
+}
+
+/// Rename a file in local storage within the directory of its pasta
+pub fn rename_local_file(pasta_id: &str, from: &str, to: &str) -> Result<(), String> {
+    fs::rename(
+        local_file_path(pasta_id, from),
+        local_file_path(pasta_id, to),
+    )
+    .map_err(|e| format!("Failed to rename file: {}", e))
+}
+
+/// Move a file from elsewhere on disk into local storage, as a file of the
+/// pasta `pasta_id`
+pub fn move_to_local_file(pasta_id: &str, from: &Path, storage_path: &str) -> Result<(), String> {
+    let dir_path = format!("{}/attachments/{}", ARGS.data_dir, pasta_id);
+    fs::create_dir_all(&dir_path).map_err(|e| format!("Failed to create directory: {}", e))?;
+
+    fs::rename(from, local_file_path(pasta_id, storage_path))
+        .map_err(|e| format!("Failed to move file: {}", e))
+}
+
+/// Read up to `length` bytes from the start of a file in local storage
+pub fn read_local_file_head(
+    pasta_id: &str,
+    storage_path: &str,
+    length: usize,
+) -> Result<Vec<u8>, String> {
+    let file = fs::File::open(local_file_path(pasta_id, storage_path))
+        .map_err(|e| format!("Failed to open file: {}", e))?;
+    let mut head = Vec::with_capacity(length);
+    file.take(length as u64)
+        .read_to_end(&mut head)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    Ok(head)
+}
+
+/// Get a file by its storage path.
+pub async fn get_file(pasta_id: &str, storage_path: &str) -> Result<Vec<u8>, String> {
+    let (kind, path) = BackendKind::for_path(storage_path);
+    kind.backend().get(pasta_id, path).await
+}
+
+/// Get `length` bytes of a file from `start` on
+pub async fn get_file_range(
+    pasta_id: &str,
+    storage_path: &str,
+    start: u64,
+    length: u64,
+) -> Result<Vec<u8>, String> {
+    let (kind, path) = BackendKind::for_path(storage_path);
+    kind.backend().get_range(pasta_id, path, start, length).await
+}
+
+/// Move a stored file to another pasta id and return its new storage path.
+/// Local files are moved by renaming the pasta's directory. Remote objects
+/// are copied, and the old object is left for the caller to delete once the
+/// move is committed.
+pub async fn move_file(
+    old_pasta_id: &str,
+    new_pasta_id: &str,
+    storage_path: &str,
+) -> Result<String, String> {
+    let (kind, path) = BackendKind::for_path(storage_path);
+    let new_path = kind
+        .backend()
+        .move_to(old_pasta_id, new_pasta_id, path)
+        .await?;
+    Ok(kind.storage_path(&new_path))
+}
+
+/// Delete a file by its storage path.
+pub async fn delete_file(pasta_id: &str, storage_path: &str) -> Result<(), String> {
+    // thumbnails are cached on the local disk whichever backend is used
+    thumbnail::delete_cached(pasta_id);
+    let (kind, path) = BackendKind::for_path(storage_path);
+    kind.backend().delete(pasta_id, path).await
+}
+
+/// Best effort removal of a file in local storage, for cleaning up after
+/// failed uploads where there is no one to report an error to
+pub fn delete_local_file(pasta_id: &str, storage_path: &str) {
+    let _ = fs::remove_file(local_file_path(pasta_id, storage_path));
+    remove_empty_dir(pasta_id);
+}
+
+/// Remove the attachment directory of a pasta once its last file is gone
+fn remove_empty_dir(pasta_id: &str) {
+    let dir_path = format!("{}/attachments/{}", ARGS.data_dir, pasta_id);
+    if Path::new(&dir_path).exists() {
+        let _ = fs::remove_dir(&dir_path);
+    }
+}
+
+/// Path of a file in local storage, relative to the working directory
+pub fn local_file_path(pasta_id: &str, storage_path: &str) -> String {
+    format!("{}/attachments/{}/{}", ARGS.data_dir, pasta_id, storage_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collision_name() {
+        let number = FileNameCollisions::Number;
+        assert_eq!(collision_name("a.txt", 0, number), "a.txt");
+        assert_eq!(collision_name("a.txt", 1, number), "a_1.txt");
+        assert_eq!(collision_name("a.tar.gz", 2, number), "a.tar_2.gz");
+        assert_eq!(collision_name("README", 1, number), "README_1");
+        assert_eq!(collision_name(".env", 1, number), ".env_1");
+
+        let hash = FileNameCollisions::Hash;
+        assert_eq!(collision_name("a.txt", 0, hash), "a.txt");
+        let renamed = collision_name("a.txt", 1, hash);
+        assert!(renamed.starts_with("a_") && renamed.ends_with(".txt"));
+        assert_eq!(renamed.len(), "a_.txt".len() + 8);
+        assert_ne!(renamed, collision_name("a.txt", 2, hash));
+    }
+
+    #[test]
+    fn test_backend_for_path() {
+        assert_eq!(
+            BackendKind::for_path("s3://attachments/cat-dog/a.txt"),
+            (BackendKind::S3, "attachments/cat-dog/a.txt")
+        );
+        assert_eq!(
+            BackendKind::for_path("gcs://attachments/cat-dog/a.txt"),
+            (BackendKind::Gcs, "attachments/cat-dog/a.txt")
+        );
+        assert_eq!(
+            BackendKind::for_path("webdav://attachments/cat-dog/a.txt"),
+            (BackendKind::Webdav, "attachments/cat-dog/a.txt")
+        );
+        assert_eq!(
+            BackendKind::for_path("azure://attachments/cat-dog/a.txt"),
+            (BackendKind::Azure, "attachments/cat-dog/a.txt")
+        );
+        assert_eq!(
+            BackendKind::for_path("data.enc"),
+            (BackendKind::Local, "data.enc")
+        );
+        assert!(!is_remote("s3:a.txt"));
+        assert!(!is_remote("webdav:a.txt"));
+        assert!(!is_remote("azure:a.txt"));
+        assert_eq!(
+            BackendKind::Gcs.storage_path("attachments/cat-dog/a.txt"),
+            "gcs://attachments/cat-dog/a.txt"
+        );
+    }
+
+    #[test]
+    fn test_moved_object_path() {
+        assert_eq!(
+            moved_object_path("cat-dog", "owl-bee", "attachments/cat-dog/a.txt"),
+            Ok(String::from("attachments/owl-bee/a.txt"))
+        );
+        assert!(moved_object_path("cat-dog", "owl-bee", "attachments/fox/a.txt").is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_check_unreachable_bucket() {
+        // nothing listens on port 1
+        let region = Region::Custom {
+            region: String::from("us-east-1"),
+            endpoint: String::from("http://127.0.0.1:1"),
+        };
+        let credentials =
+            Credentials::new(Some("access"), Some("secret"), None, None, None).unwrap();
+        let bucket = Bucket::new("microbin", region, credentials)
+            .unwrap()
+            .with_path_style();
+
+        let error = check_bucket(&bucket).await.unwrap_err();
+        assert!(error.starts_with("S3 bucket unreachable"), "{}", error);
+    }
 }
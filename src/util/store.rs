@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::ops::{Deref, Index, IndexMut};
+
+use crate::pasta::Pasta;
+
+/// The pastas of the instance, found by id without scanning all of them
+pub trait PastaStore {
+    /// Where the pasta with `id` is in the collection
+    fn position(&self, id: u64) -> Option<usize>;
+
+    fn get(&self, id: u64) -> Option<&Pasta>;
+
+    fn get_mut(&mut self, id: u64) -> Option<&mut Pasta>;
+}
+
+/// Pastas held in memory, persisted by `db` like before. Handlers still read
+/// the list of pastas through `Deref` and change single pastas by index, while
+/// adding, removing and reordering goes through the methods below, which keep
+/// an index of positions by id up to date. Ids must not be changed in place.
+#[derive(Default)]
+pub struct MemoryStore {
+    pastas: Vec<Pasta>,
+    /// Position of the first pasta with each id in `pastas`
+    positions: HashMap<u64, usize>,
+}
+
+impl MemoryStore {
+    pub fn new(pastas: Vec<Pasta>) -> Self {
+        let mut store = MemoryStore {
+            pastas,
+            positions: HashMap::new(),
+        };
+        store.reindex();
+        store
+    }
+
+    fn reindex(&mut self) {
+        self.positions.clear();
+        for (i, pasta) in self.pastas.iter().enumerate() {
+            // the first pasta with an id wins, like scanning the list did
+            self.positions.entry(pasta.id).or_insert(i);
+        }
+    }
+
+    pub fn push(&mut self, pasta: Pasta) {
+        self.positions.entry(pasta.id).or_insert(self.pastas.len());
+        self.pastas.push(pasta);
+    }
+
+    pub fn pop(&mut self) -> Option<Pasta> {
+        let pasta = self.pastas.pop()?;
+        if self.positions.get(&pasta.id) == Some(&self.pastas.len()) {
+            self.positions.remove(&pasta.id);
+        }
+        Some(pasta)
+    }
+
+    pub fn extend(&mut self, pastas: impl IntoIterator<Item = Pasta>) {
+        for pasta in pastas {
+            self.push(pasta);
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> Pasta {
+        let pasta = self.pastas.remove(index);
+        self.reindex();
+        pasta
+    }
+
+    pub fn retain(&mut self, keep: impl FnMut(&Pasta) -> bool) {
+        let before = self.pastas.len();
+        self.pastas.retain(keep);
+        if self.pastas.len() != before {
+            self.reindex();
+        }
+    }
+
+    pub fn sort_by(&mut self, compare: impl FnMut(&Pasta, &Pasta) -> std::cmp::Ordering) {
+        self.pastas.sort_by(compare);
+        self.reindex();
+    }
+
+    /// Take all pastas out, leaving the store empty
+    pub fn take(&mut self) -> Vec<Pasta> {
+        self.positions.clear();
+        std::mem::take(&mut self.pastas)
+    }
+}
+
+impl PastaStore for MemoryStore {
+    fn position(&self, id: u64) -> Option<usize> {
+        self.positions.get(&id).copied()
+    }
+
+    fn get(&self, id: u64) -> Option<&Pasta> {
+        let i = self.position(id)?;
+        self.pastas.get(i)
+    }
+
+    fn get_mut(&mut self, id: u64) -> Option<&mut Pasta> {
+        let i = self.position(id)?;
+        self.pastas.get_mut(i)
+    }
+}
+
+impl Deref for MemoryStore {
+    type Target = Vec<Pasta>;
+
+    fn deref(&self) -> &Vec<Pasta> {
+        &self.pastas
+    }
+}
+
+impl Index<usize> for MemoryStore {
+    type Output = Pasta;
+
+    fn index(&self, index: usize) -> &Pasta {
+        &self.pastas[index]
+    }
+}
+
+impl IndexMut<usize> for MemoryStore {
+    fn index_mut(&mut self, index: usize) -> &mut Pasta {
+        &mut self.pastas[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::create::new_pasta;
+
+    fn pasta(id: u64) -> Pasta {
+        Pasta {
+            id,
+            editable: false,
+            encrypted_key: None,
+            expiration: 0,
+            pasta_type: String::from("text"),
+            ..new_pasta(0)
+        }
+    }
+
+    #[test]
+    fn test_lookup_by_id() {
+        let mut store = MemoryStore::new(vec![pasta(10), pasta(20), pasta(30)]);
+        assert_eq!(store.position(20), Some(1));
+        assert_eq!(store.get(30).map(|p| p.id), Some(30));
+        assert_eq!(store.position(40), None);
+
+        store.get_mut(10).unwrap().read_count = 3;
+        assert_eq!(store[0].read_count, 3);
+    }
+
+    #[test]
+    fn test_lookup_after_changes() {
+        let mut store = MemoryStore::new(vec![pasta(10), pasta(20), pasta(30)]);
+
+        // removing shifts the pastas after it
+        store.remove(0);
+        assert_eq!(store.position(10), None);
+        assert_eq!(store.position(30), Some(1));
+
+        store.push(pasta(40));
+        assert_eq!(store.get(40).map(|p| p.id), Some(40));
+
+        store.retain(|p| p.id != 20);
+        store.push(pasta(50));
+        assert_eq!(store.position(20), None);
+        assert_eq!(store.position(30), Some(0));
+        assert_eq!(store.position(50), Some(2));
+
+        store.sort_by(|a, b| b.id.cmp(&a.id));
+        assert_eq!(store.position(50), Some(0));
+
+        let taken = store.take();
+        assert_eq!(taken.len(), 3);
+        assert_eq!(store.position(50), None);
+        store.extend(taken);
+
+        assert_eq!(store.pop().map(|p| p.id), Some(30));
+        assert_eq!(store.position(30), None);
+
+        // every pasta is found where it really is
+        for i in 0..store.len() {
+            let id = store[i].id;
+            assert_eq!(store.position(id), Some(i));
+        }
+    }
+
+    #[test]
+    fn test_duplicate_ids_find_the_first() {
+        let mut first = pasta(10);
+        first.read_count = 1;
+        let mut store = MemoryStore::new(vec![first, pasta(20), pasta(10)]);
+        assert_eq!(store.position(10), Some(0));
+
+        store.push(pasta(20));
+        assert_eq!(store.position(20), Some(1));
+
+        // the next one is found once the first is gone
+        store.remove(0);
+        assert_eq!(store.position(10), Some(1));
+        assert_eq!(store.get(10).map(|p| p.read_count), Some(0));
+
+        assert_eq!(store.pop().map(|p| p.id), Some(20));
+        assert_eq!(store.position(20), Some(0));
+    }
+}
@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use actix_web::web;
+
+use crate::args::ARGS;
+use crate::util::misc::remove_expired;
+use crate::util::store::MemoryStore;
+use crate::AppState;
+
+/// Start removing expired pastas in the background every `gc_interval_secs`,
+/// so they are gone even if no request comes along to remove them
+pub fn start(data: web::Data<AppState>) {
+    if ARGS.gc_interval_secs == 0 {
+        return;
+    }
+
+    log::info!(
+        "Removing expired pastas every {} seconds",
+        ARGS.gc_interval_secs
+    );
+    actix_web::rt::spawn(run(
+        data,
+        Duration::from_secs(ARGS.gc_interval_secs),
+        remove_expired,
+    ));
+}
+
+/// Run `sweep` on the pasta collection every `interval`. Remote files are
+/// deleted by tasks `sweep` spawns, so the lock is only held for the sweep.
+async fn run(data: web::Data<AppState>, interval: Duration, sweep: fn(&mut MemoryStore)) {
+    let mut ticks = actix_web::rt::time::interval(interval);
+    // the first tick completes right away
+    ticks.tick().await;
+
+    loop {
+        ticks.tick().await;
+
+        let mut pastas = data.lock_pastas();
+        let before = pastas.len();
+        sweep(&mut pastas);
+        let removed = before - pastas.len();
+        drop(pastas);
+
+        if removed > 0 {
+            log::info!("Removed {} expired pastas", removed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::create::new_pasta;
+    use crate::util::misc::is_expired;
+    use crate::util::rate_limit::{FailedLogins, RateLimiter};
+    use crate::Pasta;
+    use std::sync::Mutex;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn now() -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64
+    }
+
+    fn pasta(id: u64, expiration: i64) -> Pasta {
+        Pasta {
+            id,
+            content: String::from("short lived"),
+            editable: false,
+            encrypted_key: None,
+            expiration,
+            pasta_type: String::from("text"),
+            ..new_pasta(now())
+        }
+    }
+
+    /// `remove_expired` without the database and file cleanup
+    fn remove_expired_in_memory(pastas: &mut MemoryStore) {
+        let timenow = now();
+        pastas.retain(|p| !is_expired(p, timenow, 30));
+    }
+
+    #[actix_web::test]
+    async fn test_sweeper_removes_expired_pastas() {
+        let data = web::Data::new(AppState {
+            pastas: Mutex::new(MemoryStore::new(vec![pasta(1, now() + 1), pasta(2, 0)])),
+            rate_limiter: RateLimiter::default(),
+            failed_logins: FailedLogins::default(),
+        });
+
+        actix_web::rt::spawn(run(
+            data.clone(),
+            Duration::from_millis(100),
+            remove_expired_in_memory,
+        ));
+
+        // no request touches the collection while the first pasta expires
+        for _ in 0..40 {
+            actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+            if data.lock_pastas().len() == 1 {
+                break;
+            }
+        }
+
+        let pastas = data.lock_pastas();
+        assert_eq!(pastas.len(), 1);
+        assert_eq!(pastas[0].id, 2);
+    }
+}
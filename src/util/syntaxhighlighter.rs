@@ -1,3 +1,5 @@
+use crate::args::ARGS;
+use lazy_static::lazy_static;
 use syntect::easy::HighlightLines;
 use syntect::highlighting::{Style, ThemeSet};
 use syntect::html::append_highlighted_html_for_styled_line;
@@ -5,15 +7,171 @@ use syntect::html::IncludeBackground::No;
 use syntect::parsing::SyntaxSet;
 use syntect::util::LinesWithEndings;
 
+/// Theme used when `highlight_theme` names a theme syntect does not have
+pub const DEFAULT_THEME: &str = "InspiredGitHub";
+
+/// Names of the bundled highlighting themes, sorted
+pub fn theme_names() -> Vec<String> {
+    ThemeSet::load_defaults().themes.into_keys().collect()
+}
+
+lazy_static! {
+    static ref THEME_NAME: &'static str = if theme_names().contains(&ARGS.highlight_theme) {
+        &ARGS.highlight_theme
+    } else {
+        DEFAULT_THEME
+    };
+}
+
+/// The configured theme if it exists, otherwise the default one
+pub fn theme_name() -> &'static str {
+    &THEME_NAME
+}
+
+/// Warn at startup about a `highlight_theme` that does not exist
+pub fn check_theme() {
+    if theme_name() != ARGS.highlight_theme {
+        log::warn!(
+            "Unknown highlight theme {:?}, using {}. Available themes: {}",
+            ARGS.highlight_theme,
+            DEFAULT_THEME,
+            theme_names().join(", ")
+        );
+    }
+}
+
+/// Languages `detect_language` knows, with markers at the start of a line
+/// and markers anywhere in the text
+const LANGUAGE_MARKERS: &[(&str, &[&str], &[&str])] = &[
+    (
+        "rs",
+        &[
+            "fn ",
+            "pub fn ",
+            "let mut ",
+            "impl ",
+            "use std::",
+            "#[derive(",
+            "pub struct ",
+        ],
+        &["println!(", "-> Result<", "&mut self", "::new("],
+    ),
+    (
+        "py",
+        &["def ", "import ", "from ", "elif ", "class ", "if __name__"],
+        &["self.", "print(", "\"\"\"", "None"],
+    ),
+    (
+        "js",
+        &["function ", "const ", "let ", "export ", "module.exports"],
+        &["console.log(", "=> {", "require(", "document."],
+    ),
+    (
+        "go",
+        &["package ", "func ", "import (", "type "],
+        &[":= ", "fmt.", "err != nil"],
+    ),
+    (
+        "java",
+        &[
+            "public class ",
+            "private ",
+            "public static void main",
+            "import java.",
+        ],
+        &["System.out.", "@Override"],
+    ),
+    (
+        "c",
+        &["#include <", "#define ", "int main(", "typedef struct"],
+        &["printf(", "malloc(", "->"],
+    ),
+    (
+        "sh",
+        &["if [ ", "echo ", "export ", "case "],
+        &["$(", "${", "; then", "; do"],
+    ),
+    (
+        "sql",
+        &[
+            "SELECT ",
+            "INSERT INTO ",
+            "CREATE TABLE ",
+            "UPDATE ",
+            "DELETE FROM ",
+        ],
+        &[" FROM ", " WHERE ", "PRIMARY KEY"],
+    ),
+];
+
+/// Extension of the language a code snippet is most likely written in.
+/// Shebangs and file signatures decide on their own, otherwise a language
+/// needs markers on at least two lines and more than any other language, so
+/// prose and short snippets give `None`.
+pub fn detect_language(content: &str) -> Option<&'static str> {
+    let trimmed = content.trim_start();
+    let first_line = trimmed.lines().next()?;
+    // `#![` starts inner attributes in Rust, not a shebang
+    if let Some(shebang) = first_line
+        .strip_prefix("#!")
+        .filter(|s| !s.starts_with('['))
+    {
+        let interpreter = shebang.split_whitespace().last()?;
+        let interpreter = interpreter.rsplit('/').next()?;
+        return match interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+            "python" => Some("py"),
+            "bash" | "sh" | "zsh" | "dash" => Some("sh"),
+            "node" => Some("js"),
+            "ruby" => Some("rb"),
+            "perl" => Some("pl"),
+            "php" => Some("php"),
+            _ => None,
+        };
+    }
+    if trimmed.starts_with("<?php") {
+        return Some("php");
+    }
+    if trimmed.starts_with("<?xml") {
+        return Some("xml");
+    }
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(trimmed).is_ok()
+    {
+        return Some("json");
+    }
+
+    let lines: Vec<&str> = content.lines().map(str::trim).collect();
+    let mut scores: Vec<(&'static str, usize)> = LANGUAGE_MARKERS
+        .iter()
+        .map(|(extension, line_starts, anywhere)| {
+            let score = lines
+                .iter()
+                .filter(|line| {
+                    line_starts.iter().any(|marker| line.starts_with(marker))
+                        || anywhere.iter().any(|marker| line.contains(marker))
+                })
+                .count();
+            (*extension, score)
+        })
+        .collect();
+    scores.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+
+    match scores.as_slice() {
+        [(extension, best), (_, second), ..] if *best >= 2 && best > second => Some(extension),
+        _ => None,
+    }
+}
+
 pub fn html_highlight(text: &str, extension: &str) -> String {
     let ps = SyntaxSet::load_defaults_newlines();
     let ts = ThemeSet::load_defaults();
+    let theme = theme_name();
 
     let syntax = ps
         .find_syntax_by_extension(extension)
         .or_else(|| Option::from(ps.find_syntax_plain_text()))
         .unwrap();
-    let mut h = HighlightLines::new(syntax, &ts.themes["InspiredGitHub"]);
+    let mut h = HighlightLines::new(syntax, &ts.themes[theme]);
 
     let mut highlighted_content: String = String::from("");
 
@@ -28,10 +186,38 @@ pub fn html_highlight(text: &str, extension: &str) -> String {
         highlighted_content2 += &*format!("<code-line>{}</code-line>\n", line);
     }
 
-    // Rewrite colours to ones that are compatible with water.css and both light/dark modes
-    highlighted_content2 = highlighted_content2.replace("style=\"color:#323232;\"", "");
-    highlighted_content2 =
-        highlighted_content2.replace("style=\"color:#183691;\"", "style=\"color:blue;\"");
+    // Rewrite colours of the default theme to ones that are compatible with
+    // water.css and both light/dark modes
+    if theme == DEFAULT_THEME {
+        highlighted_content2 = highlighted_content2.replace("style=\"color:#323232;\"", "");
+        highlighted_content2 =
+            highlighted_content2.replace("style=\"color:#183691;\"", "style=\"color:blue;\"");
+    }
 
     highlighted_content2
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_language() {
+        let python = "import os\n\ndef main():\n    print(os.getcwd())\n";
+        assert_eq!(detect_language(python), Some("py"));
+        let rust = "use std::fs;\n\nfn main() {\n    let mut s = String::new();\n    println!(\"{}\", s);\n}\n";
+        assert_eq!(detect_language(rust), Some("rs"));
+
+        assert_eq!(
+            detect_language("#!/usr/bin/env python3\nx = 1\n"),
+            Some("py")
+        );
+        assert_eq!(detect_language("#!/bin/bash\nls\n"), Some("sh"));
+        assert_eq!(detect_language("{\"a\": [1, 2]}"), Some("json"));
+
+        let prose = "Hi all,\n\nthe meeting moved to Friday. Let me know if that\ndoes not work for you, then we find another date.\n";
+        assert_eq!(detect_language(prose), None);
+        assert_eq!(detect_language("fn"), None);
+        assert_eq!(detect_language(""), None);
+    }
+}
@@ -0,0 +1,112 @@
+use std::fs;
+use std::io::Cursor;
+
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+
+use crate::args::ARGS;
+
+/// Width of a thumbnail when none is asked for
+pub const DEFAULT_WIDTH: u32 = 256;
+/// Narrowest thumbnail served
+pub const MIN_WIDTH: u32 = 16;
+/// Widest thumbnail served, wider requests get this one
+pub const MAX_WIDTH: u32 = 1024;
+
+/// Prefix of the thumbnails cached next to the attachments of a pasta
+const PREFIX: &str = "thumb_";
+
+/// The width to make a thumbnail for a request asking for `width`
+pub fn clamp_width(width: Option<u32>) -> u32 {
+    width.unwrap_or(DEFAULT_WIDTH).clamp(MIN_WIDTH, MAX_WIDTH)
+}
+
+/// Name of the cached thumbnail of `width` pixels
+pub fn cache_name(width: u32) -> String {
+    format!("{}{}", PREFIX, width)
+}
+
+/// Path of a cached thumbnail on the local disk, where thumbnails are kept
+/// whichever backend stores the attachments
+pub fn cache_path(pasta_id: &str, width: u32) -> String {
+    format!(
+        "{}/attachments/{}/{}",
+        ARGS.data_dir,
+        pasta_id,
+        cache_name(width)
+    )
+}
+
+/// A JPEG of the image in `data`, scaled down to `width` pixels wide.
+/// Narrower images keep their size.
+pub fn generate(data: &[u8], width: u32) -> Result<Vec<u8>, String> {
+    let image =
+        image::load_from_memory(data).map_err(|e| format!("Failed to decode image: {}", e))?;
+
+    let image = if image.width() > width {
+        let height = (image.height() as u64 * width as u64 / image.width() as u64).max(1);
+        image.resize_exact(width, height as u32, FilterType::Triangle)
+    } else {
+        image
+    };
+
+    // JPEG has no alpha channel
+    let image = DynamicImage::ImageRgb8(image.to_rgb8());
+    let mut thumbnail = Cursor::new(Vec::new());
+    image
+        .write_to(&mut thumbnail, ImageFormat::Jpeg)
+        .map_err(|e| format!("Failed to encode thumbnail: {}", e))?;
+    Ok(thumbnail.into_inner())
+}
+
+/// Remove the cached thumbnails of a pasta, to be called when its files go
+pub fn delete_cached(pasta_id: &str) {
+    let dir_path = format!("{}/attachments/{}", ARGS.data_dir, pasta_id);
+    let Ok(entries) = fs::read_dir(&dir_path) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let is_thumbnail = name.to_str().is_some_and(|name| {
+            name.strip_prefix(PREFIX)
+                .is_some_and(|width| !width.is_empty() && width.bytes().all(|b| b.is_ascii_digit()))
+        });
+        if is_thumbnail {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+    // only goes once nothing else is left in it
+    let _ = fs::remove_dir(&dir_path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, RgbaImage};
+
+    #[test]
+    fn test_png_thumbnail() {
+        let mut png = Cursor::new(Vec::new());
+        RgbaImage::from_fn(800, 600, |x, y| {
+            image::Rgba([(x % 256) as u8, (y % 256) as u8, 128, 255])
+        })
+        .write_to(&mut png, ImageFormat::Png)
+        .unwrap();
+        let png = png.into_inner();
+
+        let thumbnail = generate(&png, 200).unwrap();
+        assert!(thumbnail.len() < png.len());
+        assert_eq!(image::guess_format(&thumbnail).unwrap(), ImageFormat::Jpeg);
+        let decoded = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!(decoded.dimensions(), (200, 150));
+
+        // images narrower than asked for are not scaled up
+        let decoded = image::load_from_memory(&generate(&png, 1000).unwrap()).unwrap();
+        assert_eq!(decoded.dimensions(), (800, 600));
+
+        assert!(generate(b"not an image", 200).is_err());
+        assert_eq!(clamp_width(None), DEFAULT_WIDTH);
+        assert_eq!(clamp_width(Some(5000)), MAX_WIDTH);
+        assert_eq!(clamp_width(Some(0)), MIN_WIDTH);
+    }
+}
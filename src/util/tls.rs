@@ -0,0 +1,84 @@
+use std::sync::Arc;
+
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::version::{TLS12, TLS13};
+use rustls::{ServerConfig, SupportedProtocolVersion};
+
+use crate::args::ARGS;
+
+#[cfg(feature = "__rustcrypto-tls")]
+fn crypto_provider() -> CryptoProvider {
+    rustls_rustcrypto::provider()
+}
+
+#[cfg(not(feature = "__rustcrypto-tls"))]
+fn crypto_provider() -> CryptoProvider {
+    rustls::crypto::ring::default_provider()
+}
+
+static TLS12_AND_UP: &[&SupportedProtocolVersion] = &[&TLS13, &TLS12];
+static TLS13_ONLY: &[&SupportedProtocolVersion] = &[&TLS13];
+
+fn protocol_versions(
+    min_version: &str,
+) -> Result<&'static [&'static SupportedProtocolVersion], String> {
+    match min_version {
+        "1.2" => Ok(TLS12_AND_UP),
+        "1.3" => Ok(TLS13_ONLY),
+        other => Err(format!(
+            "Unsupported minimum TLS version \"{}\", expected 1.2 or 1.3",
+            other
+        )),
+    }
+}
+
+/// Build the rustls server config from the TLS arguments.
+/// Returns `Ok(None)` when TLS is not configured.
+pub fn server_config() -> Result<Option<ServerConfig>, String> {
+    let (cert_path, key_path) = match (ARGS.tls_cert.as_ref(), ARGS.tls_key.as_ref()) {
+        (Some(cert), Some(key)) => (cert, key),
+        (None, None) => return Ok(None),
+        _ => return Err(String::from("Both TLS certificate and key must be set")),
+    };
+
+    let mut provider = crypto_provider();
+
+    // keep only the cipher suites that were asked for, in the order they were given
+    if let Some(ciphers) = ARGS.tls_ciphers.as_ref() {
+        let mut selected = Vec::new();
+        for name in ciphers.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let suite = provider
+                .cipher_suites
+                .iter()
+                .find(|s| format!("{:?}", s.suite()).eq_ignore_ascii_case(name))
+                .ok_or_else(|| format!("Unsupported TLS cipher suite \"{}\"", name))?;
+            selected.push(*suite);
+        }
+        if selected.is_empty() {
+            return Err(String::from("No TLS cipher suites selected"));
+        }
+        provider.cipher_suites = selected;
+    }
+
+    let cert_chain = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed to read TLS certificate {}: {}", cert_path, e))?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| format!("Failed to read TLS key {}: {}", key_path, e))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(protocol_versions(&ARGS.tls_min_version)?)
+        .map_err(|e| {
+            format!(
+                "Unsupported TLS configuration (minimum version {}): {}",
+                ARGS.tls_min_version, e
+            )
+        })?
+        .with_no_client_auth()
+        .with_single_cert(cert_chain, key)
+        .map_err(|e| format!("Invalid TLS certificate or key: {}", e))?;
+
+    Ok(Some(config))
+}
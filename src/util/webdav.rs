@@ -0,0 +1,320 @@
+use crate::args::ARGS;
+use crate::util::http_client;
+use reqwest::{Method, StatusCode};
+
+/// A WebDAV collection attachments are stored in, like a Nextcloud folder
+/// or an Apache/nginx share with WebDAV enabled.
+///
+/// Requests authenticate with HTTP Basic auth when a user is configured, so
+/// the password is sent with every request and the server should be reached
+/// over HTTPS. Servers without authentication work with no user set.
+pub struct WebDav {
+    base_url: String,
+    user: Option<String>,
+    pass: Option<String>,
+}
+
+impl WebDav {
+    pub fn new(base_url: &str, user: Option<String>, pass: Option<String>) -> Self {
+        WebDav {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            user,
+            pass,
+        }
+    }
+
+    /// The server configured with `webdav_url`, `webdav_user` and `webdav_pass`
+    pub fn from_args() -> Self {
+        WebDav::new(
+            ARGS.webdav_url.as_deref().unwrap_or_default(),
+            ARGS.webdav_user.clone(),
+            ARGS.webdav_pass.clone(),
+        )
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, encode_path(path))
+    }
+
+    fn request(
+        &self,
+        client: &reqwest::Client,
+        method: Method,
+        path: &str,
+    ) -> reqwest::RequestBuilder {
+        let request = client.request(method, self.url(path));
+        match self.user.as_deref() {
+            Some(user) => request.basic_auth(user, self.pass.as_deref()),
+            None => request,
+        }
+    }
+
+    /// Create the collections `path` lies in, leaving existing ones alone
+    async fn make_parents(&self, client: &reqwest::Client, path: &str) -> Result<(), String> {
+        let mkcol = Method::from_bytes(b"MKCOL").expect("MKCOL is a valid method");
+        let segments: Vec<&str> = path.split('/').collect();
+
+        for end in 1..segments.len() {
+            let collection = format!("{}/", segments[..end].join("/"));
+            let response = self
+                .request(client, mkcol.clone(), &collection)
+                .send()
+                .await
+                .map_err(|e| format!("Failed to create WebDAV collection: {}", e))?;
+            // 405 is the answer for collections that already exist
+            let status = response.status();
+            if !status.is_success() && status != StatusCode::METHOD_NOT_ALLOWED {
+                return Err(format!(
+                    "Failed to create WebDAV collection {}: status {}",
+                    collection, status
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Upload a file, creating the collections it goes in if needed
+    pub async fn put(&self, path: &str, data: &[u8]) -> Result<(), String> {
+        let client = http_client::new_async();
+
+        for attempt in 0..2 {
+            let response = self
+                .request(&client, Method::PUT, path)
+                .body(data.to_vec())
+                .send()
+                .await
+                .map_err(|e| format!("Failed to upload to WebDAV: {}", e))?;
+            match response.status() {
+                // the parent collection is missing
+                StatusCode::CONFLICT if attempt == 0 => self.make_parents(&client, path).await?,
+                status if status.is_success() => return Ok(()),
+                status => return Err(format!("Failed to upload to WebDAV: status {}", status)),
+            }
+        }
+        Err(String::from(
+            "Failed to upload to WebDAV: parent collection missing",
+        ))
+    }
+
+    pub async fn get(&self, path: &str) -> Result<Vec<u8>, String> {
+        let client = http_client::new_async();
+
+        let response = self
+            .request(&client, Method::GET, path)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to get file from WebDAV: {}", e))?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Err(format!("File not found in WebDAV: {}", path)),
+            status if !status.is_success() => {
+                Err(format!("Failed to get file from WebDAV: status {}", status))
+            }
+            _ => response
+                .bytes()
+                .await
+                .map(|bytes| bytes.to_vec())
+                .map_err(|e| format!("Failed to get file from WebDAV: {}", e)),
+        }
+    }
+
+    pub async fn exists(&self, path: &str) -> Result<bool, String> {
+        let client = http_client::new_async();
+
+        let response = self
+            .request(&client, Method::HEAD, path)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to check file in WebDAV: {}", e))?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_success() => Ok(true),
+            status => Err(format!("Failed to check file in WebDAV: status {}", status)),
+        }
+    }
+
+    /// Delete a file, files that are already gone count as deleted
+    pub async fn delete(&self, path: &str) -> Result<(), String> {
+        let client = http_client::new_async();
+
+        let response = self
+            .request(&client, Method::DELETE, path)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to delete from WebDAV: {}", e))?;
+        let status = response.status();
+        if status.is_success() || status == StatusCode::NOT_FOUND {
+            Ok(())
+        } else {
+            Err(format!("Failed to delete from WebDAV: status {}", status))
+        }
+    }
+
+    /// Copy a file on the server, creating the collections of `to` if needed
+    pub async fn copy(&self, from: &str, to: &str) -> Result<(), String> {
+        let client = http_client::new_async();
+        let copy = Method::from_bytes(b"COPY").expect("COPY is a valid method");
+
+        for attempt in 0..2 {
+            let response = self
+                .request(&client, copy.clone(), from)
+                .header("Destination", self.url(to))
+                .header("Overwrite", "F")
+                .send()
+                .await
+                .map_err(|e| format!("Failed to copy in WebDAV: {}", e))?;
+            match response.status() {
+                StatusCode::CONFLICT if attempt == 0 => self.make_parents(&client, to).await?,
+                status if status.is_success() => return Ok(()),
+                status => return Err(format!("Failed to copy in WebDAV: status {}", status)),
+            }
+        }
+        Err(String::from(
+            "Failed to copy in WebDAV: parent collection missing",
+        ))
+    }
+}
+
+/// Percent-encode every segment of a path, keeping the slashes between them
+pub fn encode_path(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Method, path and `Authorization` header of a request to the mock server
+    type Seen = Arc<Mutex<Vec<(String, String, Option<String>)>>>;
+
+    /// Status and body of the answer to a method, path and body
+    type Respond = fn(&str, &str, &[u8]) -> (u16, Vec<u8>);
+
+    /// WebDAV server on a free port answering requests with `respond`
+    fn mock_server(respond: Respond) -> (String, Seen) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/dav", listener.local_addr().unwrap());
+        let seen: Seen = Arc::default();
+
+        let requests = Arc::clone(&seen);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap_or_default().to_string();
+                let path = parts.next().unwrap_or_default().to_string();
+
+                let mut content_length = 0;
+                let mut authorization = None;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    let (name, value) = line.split_once(':').unwrap();
+                    match name.to_ascii_lowercase().as_str() {
+                        "content-length" => content_length = value.trim().parse().unwrap(),
+                        "authorization" => authorization = Some(value.trim().to_string()),
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+
+                let (status, response) = respond(&method, &path, &body);
+                requests.lock().unwrap().push((method, path, authorization));
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    response.len()
+                )
+                .unwrap();
+                stream.write_all(&response).unwrap();
+            }
+        });
+
+        (url, seen)
+    }
+
+    #[actix_web::test]
+    async fn test_webdav_requests() {
+        let (url, seen) = mock_server(|method, path, body| match (method, path) {
+            ("PUT", "/dav/attachments/cat-dog/my%20file.txt") if body == b"hello" => (201, vec![]),
+            ("GET", "/dav/attachments/cat-dog/my%20file.txt") => (200, b"hello".to_vec()),
+            ("DELETE", _) => (404, vec![]),
+            _ => (500, vec![]),
+        });
+        let dav = WebDav::new(&url, Some("user".into()), Some("pass".into()));
+
+        dav.put("attachments/cat-dog/my file.txt", b"hello")
+            .await
+            .unwrap();
+        assert_eq!(
+            dav.get("attachments/cat-dog/my file.txt").await.unwrap(),
+            b"hello"
+        );
+        assert!(dav.get("attachments/cat-dog/other.txt").await.is_err());
+        // files that are gone already count as deleted
+        dav.delete("attachments/cat-dog/my file.txt").await.unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 4);
+        // basic auth with user:pass
+        assert!(seen
+            .iter()
+            .all(|(_, _, auth)| auth.as_deref() == Some("Basic dXNlcjpwYXNz")));
+    }
+
+    #[actix_web::test]
+    async fn test_webdav_put_creates_collections() {
+        let (url, seen) = mock_server(|method, path, _| match (method, path) {
+            ("PUT", _) if path.ends_with("a.txt") => {
+                // 409 until the collection exists, like real servers
+                static CREATED: Mutex<bool> = Mutex::new(false);
+                let mut created = CREATED.lock().unwrap();
+                let status = if *created { 201 } else { 409 };
+                *created = true;
+                (status, vec![])
+            }
+            ("MKCOL", "/dav/attachments/") => (405, vec![]),
+            ("MKCOL", "/dav/attachments/cat-dog/") => (201, vec![]),
+            _ => (500, vec![]),
+        });
+        let dav = WebDav::new(&format!("{}/", url), None, None);
+
+        dav.put("attachments/cat-dog/a.txt", b"a").await.unwrap();
+
+        let seen = seen.lock().unwrap();
+        let requests: Vec<(&str, &str)> = seen
+            .iter()
+            .map(|(method, path, _)| (method.as_str(), path.as_str()))
+            .collect();
+        assert_eq!(
+            requests,
+            vec![
+                ("PUT", "/dav/attachments/cat-dog/a.txt"),
+                ("MKCOL", "/dav/attachments/"),
+                ("MKCOL", "/dav/attachments/cat-dog/"),
+                ("PUT", "/dav/attachments/cat-dog/a.txt"),
+            ]
+        );
+        assert!(seen.iter().all(|(_, _, auth)| auth.is_none()));
+    }
+}
@@ -0,0 +1,222 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::args::ARGS;
+use crate::util::expired::ExpiryReason;
+use crate::util::http_client;
+use crate::Pasta;
+
+/// Delay before the one retry of a failed delivery
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// What happened to a pasta
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Created,
+    Deleted,
+    Expired,
+    ReadLimit,
+    Inactive,
+    /// Removed at startup because its files are missing
+    Pruned,
+}
+
+impl Event {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Event::Created => "created",
+            Event::Deleted => "deleted",
+            Event::Expired => "expired",
+            Event::ReadLimit => "read_limit",
+            Event::Inactive => "inactive",
+            Event::Pruned => "pruned",
+        }
+    }
+
+    /// The event for a pasta removed for `reason`
+    pub fn removed(reason: ExpiryReason) -> Self {
+        match reason {
+            ExpiryReason::Expired => Event::Expired,
+            ExpiryReason::ReadLimit => Event::ReadLimit,
+            ExpiryReason::Inactive => Event::Inactive,
+            ExpiryReason::Rotated => Event::Deleted,
+        }
+    }
+}
+
+/// Tell the `webhook_url` about `event` in the background. Failed
+/// deliveries are retried once and then only logged, they never fail the
+/// operation that caused the event.
+pub fn notify(event: Event, pasta: &Pasta) {
+    let Some(url) = ARGS.webhook_url.clone() else {
+        return;
+    };
+
+    let timenow = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(_) => {
+            log::error!("SystemTime before UNIX EPOCH!");
+            0
+        }
+    } as i64;
+
+    let payload = payload(event, pasta, &pasta.id_as_animals(), timenow);
+    actix_web::rt::spawn(async move {
+        send(&url, &payload, RETRY_DELAY).await;
+    });
+}
+
+/// The JSON sent for `event`. Only metadata, never content, file names or keys.
+fn payload(event: Event, pasta: &Pasta, slug: &str, timenow: i64) -> Value {
+    json!({
+        "event": event.as_str(),
+        "id": slug,
+        "created": pasta.created,
+        "expiration": pasta.expiration,
+        "has_file": pasta.has_file(),
+        "timestamp": timenow,
+    })
+}
+
+/// POST `payload` to `url`, trying once more after `retry_delay` if that fails
+async fn send(url: &str, payload: &Value, retry_delay: Duration) -> bool {
+    let client = http_client::new_async();
+
+    for attempt in 0..2 {
+        if attempt > 0 {
+            actix_web::rt::time::sleep(retry_delay).await;
+        }
+
+        let result = client.post(url).json(payload).send().await;
+        match result {
+            Ok(response) if response.status().is_success() => return true,
+            Ok(response) => log::warn!(
+                "Webhook for {} event answered with status {}",
+                payload["event"],
+                response.status()
+            ),
+            Err(e) => log::warn!("Webhook for {} event failed: {}", payload["event"], e),
+        }
+    }
+
+    log::error!(
+        "Giving up on webhook for {} event of pasta {}",
+        payload["event"],
+        payload["id"]
+    );
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::create::new_pasta;
+    use crate::pasta::PastaFile;
+    use bytesize::ByteSize;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    fn pasta() -> Pasta {
+        Pasta {
+            id: 1,
+            content: String::from("secret content"),
+            file: Some(PastaFile {
+                name: String::from("secret.txt"),
+                size: ByteSize::b(10),
+                original_name: None,
+                sha256: None,
+                content_type: None,
+                stored_size: None,
+            }),
+            editable: false,
+            encrypted_key: Some(String::from("secret key")),
+            expiration: 200,
+            pasta_type: String::from("text"),
+            ..new_pasta(100)
+        }
+    }
+
+    /// Receiver on a free port answering with `statuses` in turn, keeping
+    /// the bodies it got
+    fn mock_receiver(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let bodies: Arc<Mutex<Vec<String>>> = Arc::default();
+
+        let received = Arc::clone(&bodies);
+        thread::spawn(move || {
+            for (stream, status) in listener.incoming().zip(statuses) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                received
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8(body).unwrap());
+
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Mock\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+        });
+
+        (url, bodies)
+    }
+
+    #[test]
+    fn test_payload() {
+        let payload = payload(Event::Created, &pasta(), "cat-dog", 150);
+        assert_eq!(
+            payload,
+            json!({
+                "event": "created",
+                "id": "cat-dog",
+                "created": 100,
+                "expiration": 200,
+                "has_file": true,
+                "timestamp": 150,
+            })
+        );
+        assert!(!payload.to_string().contains("secret"));
+
+        assert_eq!(Event::removed(ExpiryReason::ReadLimit), Event::ReadLimit);
+    }
+
+    #[actix_web::test]
+    async fn test_send_retries_once() {
+        let payload = payload(Event::Deleted, &pasta(), "cat-dog", 150);
+
+        let (url, bodies) = mock_receiver(vec![500, 200]);
+        assert!(send(&url, &payload, Duration::ZERO).await);
+        let received = bodies.lock().unwrap().clone();
+        assert_eq!(received.len(), 2);
+        let received: Value = serde_json::from_str(&received[1]).unwrap();
+        assert_eq!(received, payload);
+
+        let (url, bodies) = mock_receiver(vec![500, 500, 200]);
+        assert!(!send(&url, &payload, Duration::ZERO).await);
+        assert_eq!(bodies.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,8 @@
+// Highlight the link of the current page in the site navigation
+(function() {
+    var path = window.location.pathname.replace(/\/+$/, '');
+    document.querySelectorAll('.site-nav a').forEach(function(a) {
+        var href = a.pathname.replace(/\/+$/, '');
+        if (path === href) a.classList.add('active');
+    });
+})();